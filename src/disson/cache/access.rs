@@ -0,0 +1,98 @@
+//! Restrictions on how another cache may be used
+
+use std::iter;
+
+use super::{Cache, CacheEntry, CacheKey, CacheValue, Values};
+use crate::error::prelude::*;
+
+/// How the values of a cache may be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Read and write the cache as usual
+    ReadWrite,
+    /// Ignore stored values, replacing each entry that is written to
+    Refresh,
+    /// Read stored values, but never write to the cache
+    ReadOnly,
+}
+
+/// Applies an [`Access`] mode to another cache
+pub struct AccessCache<C> {
+    pub inner: C,
+    pub access: Access,
+}
+
+pub struct AccessCacheEntry<E> {
+    inner: E,
+    access: Access,
+    /// Whether the stored values have been dropped yet, in refresh mode
+    replaced: bool,
+}
+
+impl<C> AccessCache<C> {
+    pub fn new(inner: C, access: Access) -> Self { Self { inner, access } }
+
+    /// Fail if the cache may not be written to
+    pub fn check_writable(&self) -> Result<()> {
+        if self.access == Access::ReadOnly {
+            Err(anyhow!("the cache is read-only"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a, C: Cache<'a>> Cache<'a> for AccessCache<C> {
+    type Entry = AccessCacheEntry<C::Entry>;
+
+    fn entry_impl(&'a self, key: CacheKey) -> Result<Self::Entry> {
+        Ok(AccessCacheEntry {
+            inner: self.inner.entry_impl(key)?,
+            access: self.access,
+            replaced: false,
+        })
+    }
+
+    fn clean(&self) -> Result<()> {
+        self.check_writable()?;
+        self.inner.clean()
+    }
+
+    fn enforce_quota(&self) -> Result<()> {
+        match self.access {
+            Access::ReadOnly => Ok(()),
+            Access::ReadWrite | Access::Refresh => self.inner.enforce_quota(),
+        }
+    }
+}
+
+impl<E: CacheEntry> CacheEntry for AccessCacheEntry<E> {
+    fn read_impl(&mut self) -> Values<'_> {
+        match self.access {
+            Access::Refresh => Box::new(iter::empty()),
+            Access::ReadWrite | Access::ReadOnly => self.inner.read_impl(),
+        }
+    }
+
+    fn append_impl(&mut self, val: &CacheValue) -> Result<()> {
+        match self.access {
+            Access::ReadOnly => return Ok(()),
+            // Replace the stale values rather than appending after them
+            Access::Refresh if !self.replaced => self.truncate()?,
+            Access::ReadWrite | Access::Refresh => (),
+        }
+
+        self.inner.append_impl(val)
+    }
+
+    fn truncate(&mut self) -> Result<()> {
+        if self.access == Access::ReadOnly {
+            return Ok(());
+        }
+
+        self.replaced = true;
+        self.inner.truncate()
+    }
+
+    fn flush(&mut self) -> Result<()> { self.inner.flush() }
+}
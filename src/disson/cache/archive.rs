@@ -0,0 +1,169 @@
+//! Transfer of cache entries between machines as .tar.zst archives
+
+use std::{
+    fs,
+    fs::{DirBuilder, File},
+    path::{Component, Path},
+    time::SystemTime,
+};
+
+use log::{info, warn};
+
+use super::{
+    file::{EntryInfo, FileCache, Health},
+    index,
+};
+use crate::{
+    cli::{ByteSize, ExportOpts, ImportOpts},
+    error::prelude::*,
+};
+
+/// Recover the hash of an entry from its path in an archive, rejecting
+/// anything that isn't laid out like the cache directory
+fn hash_of(path: &Path) -> Option<String> {
+    let parts = path
+        .components()
+        .map(|c| match c {
+            Component::Normal(s) => s.to_str(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    match *parts.as_slice() {
+        [dir, file] if dir.len() == 2 && file.len() == 62 => {
+            let hash = format!("{dir}{file}").to_lowercase();
+
+            if hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                Some(hash)
+            } else {
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+fn selected(entry: &EntryInfo, opts: &ExportOpts) -> bool {
+    if !opts.hashes.is_empty()
+        && !opts
+            .hashes
+            .iter()
+            .any(|h| entry.hash.starts_with(&h.to_lowercase()))
+    {
+        return false;
+    }
+
+    match opts.matching {
+        Some(ref re) => entry.read_key().is_ok_and(|k| re.is_match(&k.to_string())),
+        None => true,
+    }
+}
+
+pub(super) fn export(cache: &FileCache, opts: &ExportOpts) -> Result<()> {
+    let entries: Vec<_> = cache
+        .entries()?
+        .into_iter()
+        .filter(|e| selected(e, opts))
+        .collect();
+
+    let file = File::create(&opts.out).context("failed to create archive")?;
+    let mut tar = tar::Builder::new(
+        zstd::Encoder::new(file, 0).context("failed to open zstd encoder on archive")?,
+    );
+
+    for entry in &entries {
+        tar.append_path_with_name(
+            &entry.path,
+            Path::new(&entry.hash[..2]).join(&entry.hash[2..]),
+        )
+        .with_context(|| format!("failed to add entry {} to archive", entry.hash))?;
+    }
+
+    tar.into_inner()
+        .context("failed to finish archive")?
+        .finish()
+        .context("failed to close zstd encoder")?;
+
+    info!(
+        "Exported {} entries ({}) to {}",
+        entries.len(),
+        ByteSize(entries.iter().map(|e| e.len).sum()),
+        opts.out.to_string_lossy()
+    );
+
+    Ok(())
+}
+
+pub(super) fn import(cache: &FileCache, opts: &ImportOpts) -> Result<()> {
+    let cache_dir = cache.locate_cache()?;
+    let file = File::open(&opts.input).context("failed to open archive")?;
+    let mut archive = tar::Archive::new(
+        zstd::Decoder::new(file).context("failed to open zstd decoder on archive")?,
+    );
+
+    let mut imported = 0_usize;
+    let mut existing = 0_usize;
+    let mut rejected = 0_usize;
+
+    for item in archive.entries().context("failed to read archive")? {
+        let mut item = item.context("failed to read archive entry")?;
+        let name = item.path().context("invalid path in archive")?.into_owned();
+
+        let Some(hash) = hash_of(&name) else {
+            warn!("Skipping unexpected file {} in archive", name.display());
+            rejected += 1;
+            continue;
+        };
+
+        let dest = cache_dir.join(&hash[..2]).join(&hash[2..]);
+
+        if dest.exists() && !opts.overwrite {
+            existing += 1;
+            continue;
+        }
+
+        DirBuilder::new()
+            .recursive(true)
+            .create(dest.parent().unwrap())
+            .context("failed to create cache (sub)directory")?;
+
+        // Unpack next to the destination, and only move valid entries into place
+        let part = dest.with_extension("part");
+
+        item.unpack(&part)
+            .with_context(|| format!("failed to unpack entry {hash}"))?;
+
+        let entry = EntryInfo {
+            hash,
+            path: part,
+            len: item.header().size().unwrap_or(0),
+            accessed: SystemTime::now(),
+        };
+
+        match entry.check(false) {
+            Ok(Health::Good) => {
+                fs::rename(&entry.path, &dest)
+                    .with_context(|| format!("failed to move entry {} into place", entry.hash))?;
+
+                let indexed = File::open(&dest)
+                    .context("failed to open imported entry")
+                    .and_then(|mut f| index::record(&cache_dir, &mut f));
+
+                if let Err(e) = indexed {
+                    warn!("Failed to update cache index: {e:?}");
+                }
+
+                imported += 1;
+            },
+            health => {
+                warn!("Rejecting entry {}: {:?}", entry.hash, health);
+                entry.delete()?;
+                rejected += 1;
+            },
+        }
+    }
+
+    info!("Imported {imported} entries ({existing} already present, {rejected} rejected)");
+
+    Ok(())
+}
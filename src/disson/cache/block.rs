@@ -0,0 +1,581 @@
+//! Framing and compression of blocks of cache values.
+//!
+//! Each block starts with a byte naming its codec and the length of its
+//! compressed data, followed by the compressed values and a `None` sentinel.
+//! The length is only filled in once the block is finished, so a block left
+//! unfinished by a crash can be told apart from a complete one.
+
+use std::{
+    convert::TryFrom,
+    io::{self, prelude::*, BufReader, SeekFrom},
+};
+
+use bincode::Options;
+use log::warn;
+
+use super::{file::val_bin_opts, CacheValue};
+use crate::{cli::CacheCodec, error::prelude::*};
+
+/// The length of the codec tag and compressed length preceding each block
+pub(super) const BLOCK_HEADER_LEN: u64 = HEADER_BYTES as u64;
+/// [`BLOCK_HEADER_LEN`], for indexing into buffers
+const HEADER_BYTES: usize = 9;
+
+/// Blocks whose first value is smaller than this many bytes are left
+/// uncompressed by [`CacheCodec::Auto`]
+const SMALL_BLOCK: u64 = 4096;
+
+/// The compression applied to a single block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Codec {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Codec {
+    /// Choose a codec for a new block, given the first value it will hold
+    pub fn choose(cfg: CacheCodec, first: &CacheValue) -> Self {
+        match cfg {
+            CacheCodec::Auto => match val_bin_opts().serialized_size(&Some(first)) {
+                Ok(n) if n < SMALL_BLOCK => Self::None,
+                _ => Self::Zstd,
+            },
+            CacheCodec::None => Self::None,
+            CacheCodec::Lz4 => Self::Lz4,
+            CacheCodec::Zstd => Self::Zstd,
+        }
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Lz4 => 1,
+            Self::Zstd => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        Some(match tag {
+            0 => Self::None,
+            1 => Self::Lz4,
+            2 => Self::Zstd,
+            _ => return None,
+        })
+    }
+}
+
+enum Encoder<W: Write> {
+    None(W),
+    Lz4(lz4::Encoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::None(w) => w.write(buf),
+            Self::Lz4(e) => e.write(buf),
+            Self::Zstd(e) => e.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::None(w) => w.flush(),
+            Self::Lz4(e) => e.flush(),
+            Self::Zstd(e) => e.flush(),
+        }
+    }
+}
+
+enum Decoder<R: Read> {
+    None(R),
+    Lz4(lz4::Decoder<R>),
+    Zstd(zstd::Decoder<'static, BufReader<R>>),
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::None(r) => r.read(buf),
+            Self::Lz4(d) => d.read(buf),
+            Self::Zstd(d) => d.read(buf),
+        }
+    }
+}
+
+/// A block being written to the end of a stream
+pub(super) struct BlockWriter<W: Write + Seek> {
+    start: u64,
+    enc: Encoder<W>,
+}
+
+impl<W: Write + Seek> BlockWriter<W> {
+    pub fn new(mut inner: W, codec: Codec) -> Result<Self> {
+        let start = inner
+            .stream_position()
+            .context("failed to get block position")?;
+
+        inner
+            .write_all(&[codec.tag()])
+            .and_then(|()| inner.write_all(&0_u64.to_le_bytes()))
+            .context("failed to write block header")?;
+
+        let enc = match codec {
+            Codec::None => Encoder::None(inner),
+            Codec::Lz4 => Encoder::Lz4(
+                lz4::EncoderBuilder::new()
+                    .build(inner)
+                    .context("failed to open lz4 encoder")?,
+            ),
+            Codec::Zstd => {
+                Encoder::Zstd(zstd::Encoder::new(inner, 0).context("failed to open zstd encoder")?)
+            },
+        };
+
+        Ok(Self { start, enc })
+    }
+
+    pub fn write(&mut self, val: &CacheValue) -> Result<()> {
+        val_bin_opts()
+            .serialize_into(&mut self.enc, &Some(val))
+            .context("failed to write cache value")
+    }
+
+    /// Write the end-of-block sentinel and fill in the length of the block,
+    /// returning the underlying stream positioned after it
+    pub fn finish(mut self) -> Result<W> {
+        val_bin_opts()
+            .serialize_into(&mut self.enc, &None::<CacheValue<'static>>)
+            .context("failed to serialize sentinel")?;
+
+        let start = self.start;
+        let mut inner = match self.enc {
+            Encoder::None(w) => w,
+            Encoder::Lz4(e) => {
+                let (w, res) = e.finish();
+                res.context("failed to close lz4 encoder")?;
+                w
+            },
+            Encoder::Zstd(e) => e.finish().context("failed to close zstd encoder")?,
+        };
+
+        let end = inner
+            .stream_position()
+            .context("failed to get block position")?;
+
+        inner
+            .seek(SeekFrom::Start(start + 1))
+            .and_then(|_| inner.write_all(&(end - start - BLOCK_HEADER_LEN).to_le_bytes()))
+            .and_then(|()| inner.seek(SeekFrom::Start(end)))
+            .context("failed to write block length")?;
+
+        Ok(inner)
+    }
+}
+
+/// Check whether a stream is at its end, returning its position and length
+fn is_at_eof(file: &mut impl Seek) -> Result<(bool, u64, u64)> {
+    let pos = file
+        .stream_position()
+        .context("failed to get file position")?;
+    let len = file
+        .seek(SeekFrom::End(0))
+        .context("failed to get file length")?;
+    let eof = pos == len;
+
+    if !eof {
+        file.seek(SeekFrom::Start(pos))
+            .context("failed to restore file position")?;
+    }
+
+    Ok((eof, pos, len))
+}
+
+pub(super) enum Block {
+    /// A block was successfully read, and more blocks may be available
+    Good(Vec<CacheValue<'static>>),
+    /// A block may have been partially read, but the rest of the file is not
+    /// recoverable - the file should be truncated to the length given and the
+    /// data should be recovered
+    Corrupt(Vec<CacheValue<'static>>, u64),
+    /// No more blocks are available
+    Eof,
+}
+
+/// Reads part of a shared buffer, such as a memory-mapped file
+struct Slice<B> {
+    buf: B,
+    pos: usize,
+    end: usize,
+}
+
+impl<B: AsRef<[u8]>> Read for Slice<B> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let n = (&self.buf.as_ref()[self.pos..self.end]).read(out)?;
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// Decodes every value of every block in a buffer one at a time, so only one
+/// value needs to be held in memory
+pub(super) struct Values<B: AsRef<[u8]>> {
+    buf: B,
+    /// The offset of the current block into the buffer
+    pos: usize,
+    /// The decoder for the current block, and where the next block starts if
+    /// the current one is complete
+    dec: Option<(Decoder<Slice<B>>, Option<usize>)>,
+    /// The offset of the first corrupted block, if one was found
+    corrupt: Option<usize>,
+    done: bool,
+}
+
+impl<B: AsRef<[u8]> + Clone> Values<B> {
+    pub fn new(buf: B, start: usize) -> Self {
+        Self {
+            buf,
+            pos: start,
+            dec: None,
+            corrupt: None,
+            done: false,
+        }
+    }
+
+    /// The offset of the first corrupted block, once every readable value has
+    /// been decoded
+    pub fn corrupt(&self) -> Option<u64> { self.corrupt.map(|p| p as u64) }
+
+    /// Decode the readable values of the first corrupted block again, so they
+    /// can be recovered
+    pub fn salvage(&self) -> Vec<CacheValue<'static>> {
+        match self.corrupt {
+            Some(p) => Values::new(self.buf.clone(), p).collect(),
+            None => vec![],
+        }
+    }
+
+    fn fail(&mut self) {
+        self.corrupt = Some(self.pos);
+        self.done = true;
+    }
+
+    fn open_block(&mut self) {
+        let len = self.buf.as_ref().len();
+        let header_end = self.pos + HEADER_BYTES;
+
+        if header_end > len {
+            warn!("Cache block header was cut off");
+            self.fail();
+            return;
+        }
+
+        let header = &self.buf.as_ref()[self.pos..header_end];
+
+        let Some(codec) = Codec::from_tag(header[0]) else {
+            warn!("Unknown cache block codec {}", header[0]);
+            self.fail();
+            return;
+        };
+
+        let mut data_len = [0_u8; 8];
+        data_len.copy_from_slice(&header[1..]);
+        let data_len = usize::try_from(u64::from_le_bytes(data_len)).unwrap_or(usize::MAX);
+
+        // Unfinished or cut-off blocks are read to the end of the buffer, to
+        // salvage what's left of them
+        let next = if data_len != 0 && data_len <= len - header_end {
+            Some(header_end + data_len)
+        } else {
+            None
+        };
+        let data = Slice {
+            buf: self.buf.clone(),
+            pos: header_end,
+            end: next.unwrap_or(len),
+        };
+
+        let dec = match codec {
+            Codec::None => Ok(Decoder::None(data)),
+            Codec::Lz4 => lz4::Decoder::new(data).map(Decoder::Lz4),
+            Codec::Zstd => zstd::Decoder::new(data).map(Decoder::Zstd),
+        };
+
+        match dec {
+            Ok(d) => self.dec = Some((d, next)),
+            Err(e) => {
+                warn!("Failed to open {codec:?} decoder on cache file: {e:?}");
+                self.fail();
+            },
+        }
+    }
+}
+
+impl<B: AsRef<[u8]> + Clone> Iterator for Values<B> {
+    type Item = CacheValue<'static>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.done {
+                return None;
+            }
+
+            let (dec, next) = match self.dec {
+                Some((ref mut d, n)) => (d, n),
+                None if self.pos == self.buf.as_ref().len() => {
+                    self.done = true;
+                    return None;
+                },
+                None => {
+                    self.open_block();
+                    continue;
+                },
+            };
+
+            match val_bin_opts().deserialize_from(dec) {
+                Ok(Some(val)) => return Some(val),
+                Ok(None) => {
+                    if let Some(n) = next {
+                        self.dec = None;
+                        self.pos = n;
+                    } else {
+                        warn!("Found unfinished cache block");
+                        self.fail();
+                        return None;
+                    }
+                },
+                Err(e) => {
+                    warn!("Failed to read cache value: {e:?}");
+                    self.fail();
+                    return None;
+                },
+            }
+        }
+    }
+}
+
+pub(super) fn read_block<R: Read + Seek>(file: &mut R) -> Block {
+    let (pos, file_len) = match is_at_eof(file) {
+        Ok((true, ..)) => return Block::Eof,
+        Ok((false, p, l)) => (p, l),
+        Err(e) => {
+            warn!("Failed to check for end of cache file: {e:?}");
+            return Block::Eof; // Can't return Corrupt because it requires pos
+        },
+    };
+
+    let mut header = [0_u8; HEADER_BYTES];
+
+    if let Err(e) = file.read_exact(&mut header) {
+        warn!("Failed to read cache block header: {e:?}");
+        return Block::Corrupt(vec![], pos);
+    }
+
+    let Some(codec) = Codec::from_tag(header[0]) else {
+        warn!("Unknown cache block codec {}", header[0]);
+        return Block::Corrupt(vec![], pos);
+    };
+
+    let mut len = [0_u8; 8];
+    len.copy_from_slice(&header[1..]);
+    let len = u64::from_le_bytes(len);
+
+    // Unfinished blocks have no length, so salvage what's left of the file
+    let limit = if len == 0 { u64::MAX } else { len };
+    let data = file.by_ref().take(limit);
+
+    let dec = match codec {
+        Codec::None => Ok(Decoder::None(data)),
+        Codec::Lz4 => lz4::Decoder::new(data).map(Decoder::Lz4),
+        Codec::Zstd => zstd::Decoder::new(data).map(Decoder::Zstd),
+    };
+
+    let mut dec = match dec {
+        Ok(d) => d,
+        Err(e) => {
+            warn!("Failed to open {codec:?} decoder on cache file: {e:?}");
+            return Block::Corrupt(vec![], pos);
+        },
+    };
+
+    let mut ret = vec![];
+
+    loop {
+        match val_bin_opts().deserialize_from(&mut dec) {
+            Ok(Some(val)) => ret.push(val),
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Failed to read cache value: {e:?}");
+
+                return Block::Corrupt(ret, pos);
+            },
+        }
+    }
+
+    drop(dec);
+
+    if len == 0 {
+        warn!("Found unfinished cache block");
+
+        return Block::Corrupt(ret, pos);
+    }
+
+    // A block cut off at the end of its data may still decode in full
+    if len > file_len - pos - BLOCK_HEADER_LEN {
+        warn!("Cache block was cut off");
+
+        return Block::Corrupt(ret, pos);
+    }
+
+    match file.seek(SeekFrom::Start(pos + BLOCK_HEADER_LEN + len)) {
+        Ok(_) => Block::Good(ret),
+        Err(e) => {
+            warn!("Failed to seek past cache block: {e:?}");
+
+            Block::Corrupt(ret, pos)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, io::Cursor};
+
+    use nalgebra::Vector2;
+
+    use super::{read_block, Block, BlockWriter, Codec, Values};
+    use crate::{cache::CacheValue, cli::CacheCodec, disson::map, tile_renderer::TileRange};
+
+    /// A map tile whose position identifies it
+    fn tile(x: u32, len: usize) -> CacheValue<'static> {
+        let range = TileRange {
+            pos: Vector2::new(x, 0),
+            size: Vector2::new(1, 1),
+        };
+
+        CacheValue::Map(map::CacheValue::Block(range, Cow::Owned(vec![f64::from(x); len])))
+    }
+
+    fn positions(vals: &[CacheValue]) -> Vec<u32> {
+        vals.iter()
+            .map(|v| match v {
+                CacheValue::Map(map::CacheValue::Block(r, _)) => r.pos.x,
+                v => panic!("unexpected value {:?}", v),
+            })
+            .collect()
+    }
+
+    /// Write each group of values as its own finished block
+    fn write_blocks(codec: Codec, blocks: &[&[CacheValue]]) -> Vec<u8> {
+        let mut buf = Cursor::new(vec![]);
+
+        for blk in blocks {
+            let mut w = BlockWriter::new(&mut buf, codec).unwrap();
+
+            for val in *blk {
+                w.write(val).unwrap();
+            }
+
+            w.finish().unwrap();
+        }
+
+        buf.into_inner()
+    }
+
+    /// A finished block holding tile 1, followed by an unfinished block
+    /// holding tiles 2 and 3.  Returns the buffer and the offset of the
+    /// unfinished block.
+    fn unfinished_block() -> (Vec<u8>, u64) {
+        let good = write_blocks(Codec::None, &[&[tile(1, 4)]]);
+        let good_len = good.len() as u64;
+        let mut file = Cursor::new(good);
+        file.set_position(good_len);
+
+        // Never finished, so the block has no length or sentinel
+        let mut w = BlockWriter::new(&mut file, Codec::None).unwrap();
+        w.write(&tile(2, 4)).unwrap();
+        w.write(&tile(3, 4)).unwrap();
+        drop(w);
+
+        (file.into_inner(), good_len)
+    }
+
+    #[test]
+    fn blocks_round_trip_with_every_codec() {
+        for &codec in &[Codec::None, Codec::Lz4, Codec::Zstd] {
+            let buf = write_blocks(codec, &[&[tile(1, 64), tile(2, 64)], &[tile(3, 64)]]);
+            let mut file = Cursor::new(&buf);
+
+            match (read_block(&mut file), read_block(&mut file)) {
+                (Block::Good(a), Block::Good(b)) => {
+                    assert_eq!(positions(&a), [1, 2]);
+                    assert_eq!(positions(&b), [3]);
+                },
+                _ => panic!("{:?} blocks didn't read back", codec),
+            }
+
+            assert!(matches!(read_block(&mut file), Block::Eof));
+        }
+    }
+
+    #[test]
+    fn auto_codec_leaves_small_blocks_uncompressed() {
+        assert_eq!(Codec::choose(CacheCodec::Auto, &tile(0, 4)), Codec::None);
+        assert_eq!(Codec::choose(CacheCodec::Auto, &tile(0, 4096)), Codec::Zstd);
+    }
+
+    #[test]
+    fn unfinished_block_is_salvaged() {
+        let (buf, good_len) = unfinished_block();
+        let mut file = Cursor::new(&buf);
+
+        assert!(matches!(read_block(&mut file), Block::Good(_)));
+        match read_block(&mut file) {
+            Block::Corrupt(vals, pos) => {
+                assert_eq!(positions(&vals), [2, 3]);
+                assert_eq!(pos, good_len);
+            },
+            _ => panic!("unfinished block wasn't reported as corrupt"),
+        }
+    }
+
+    #[test]
+    fn cut_off_and_unknown_blocks_are_corrupt() {
+        let mut buf = write_blocks(Codec::Lz4, &[&[tile(1, 64)], &[tile(2, 64)]]);
+        let first_len = write_blocks(Codec::Lz4, &[&[tile(1, 64)]]).len();
+        buf.truncate(buf.len() - 4);
+
+        let mut file = Cursor::new(&buf);
+        assert!(matches!(read_block(&mut file), Block::Good(_)));
+        assert!(matches!(read_block(&mut file), Block::Corrupt(_, p) if p == first_len as u64));
+
+        buf[0] = 0xff;
+        assert!(matches!(
+            read_block(&mut Cursor::new(&buf)),
+            Block::Corrupt(v, 0) if v.is_empty()
+        ));
+    }
+
+    #[test]
+    fn values_decode_every_block() {
+        let buf = write_blocks(Codec::Zstd, &[&[tile(1, 64), tile(2, 64)], &[tile(3, 64)]]);
+        let mut vals = Values::new(&buf[..], 0);
+
+        assert_eq!(positions(&vals.by_ref().collect::<Vec<_>>()), [1, 2, 3]);
+        assert_eq!(vals.corrupt(), None);
+        assert!(vals.salvage().is_empty());
+    }
+
+    #[test]
+    fn values_salvage_unfinished_block() {
+        let (buf, good_len) = unfinished_block();
+        let mut vals = Values::new(&buf[..], 0);
+
+        assert_eq!(positions(&vals.by_ref().collect::<Vec<_>>()), [1, 2, 3]);
+        assert_eq!(vals.corrupt(), Some(good_len));
+        assert_eq!(positions(&vals.salvage()), [2, 3]);
+    }
+}
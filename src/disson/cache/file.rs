@@ -2,24 +2,48 @@ use std::{
     convert::TryFrom,
     fs,
     fs::{DirBuilder, File, OpenOptions},
-    io::{prelude::*, SeekFrom},
-    marker::PhantomData,
-    mem,
+    io::{self, prelude::*, SeekFrom},
+    iter, mem,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
 };
 
 use bincode::Options;
+use filetime::FileTime;
 use fs2::FileExt;
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
+use memmap2::Mmap;
 use sha2::{Digest, Sha256};
 
-use super::{Cache, CacheEntry, CacheKey, CacheValue};
-use crate::error::prelude::*;
+use super::{
+    block::{self, read_block, Block, BlockWriter, Codec},
+    index::{self, INDEX_FILE},
+    Cache, CacheEntry, CacheKey, CacheValue, KeyInfo, Values,
+};
+use crate::{
+    cli::{ByteSize, CacheCodec},
+    error::prelude::*,
+};
 
 const GLOBAL_MAGIC: &str = "\x00diss";
+const LOCK_POLL: Duration = Duration::from_millis(50);
+/// Revision of the layout of cache files, bumped whenever it changes
+const FORMAT_REVISION: u32 = 2;
+
+/// The version stored in the magic number of every cache file.  Files with any
+/// other version are not read.
+pub(super) fn version() -> String {
+    format!("{}+format{}", env!("CARGO_PKG_VERSION"), FORMAT_REVISION)
+}
 
-fn magic() -> Vec<u8> {
-    let ver = env!("CARGO_PKG_VERSION").as_bytes();
+pub(super) fn magic() -> Vec<u8> {
+    let ver = version();
+    let ver = ver.as_bytes();
 
     let mut out = vec![];
 
@@ -31,7 +55,7 @@ fn magic() -> Vec<u8> {
     out
 }
 
-fn file_name(hash: impl AsRef<[u8]>) -> (PathBuf, PathBuf) {
+pub(super) fn file_name(hash: impl AsRef<[u8]>) -> (PathBuf, PathBuf) {
     let hash = hash.as_ref();
     (
         format!("{:02x}", hash[0]).into(),
@@ -46,21 +70,49 @@ fn file_name(hash: impl AsRef<[u8]>) -> (PathBuf, PathBuf) {
     )
 }
 
-fn key_bin_opts() -> impl bincode::Options {
+/// The hex-encoded hash of a cache key, which names its file in a file cache
+pub fn key_hash(key: &CacheKey) -> Result<String> {
+    let key_bytes = key_bin_opts()
+        .serialize(key)
+        .context("failed to serialize cache key")?;
+    let (dir, name) = file_name(Sha256::digest(&key_bytes));
+
+    Ok(format!("{}{}", dir.to_string_lossy(), name.to_string_lossy()))
+}
+
+pub(super) fn key_bin_opts() -> impl bincode::Options {
     bincode::options()
         .with_varint_encoding()
         .reject_trailing_bytes()
 }
 
-fn val_bin_opts() -> impl bincode::Options {
+pub(super) fn val_bin_opts() -> impl bincode::Options {
     bincode::options()
         .with_fixint_encoding()
         .reject_trailing_bytes()
 }
 
-pub struct FileCache(pub Option<PathBuf>);
+pub struct FileCache {
+    pub dir: Option<PathBuf>,
+    /// The maximum total size of all cache files, in bytes
+    pub quota: Option<u64>,
+    /// How long to wait for other processes to release an entry
+    pub lock_timeout: Duration,
+    pub codec: CacheCodec,
+    /// Open existing files without write access, and never modify them
+    pub read_only: bool,
+    gc: Mutex<Gc>,
+}
+
+/// State of the background eviction of entries when the cache exceeds its
+/// quota
+#[derive(Default)]
+struct Gc {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
 
-pub struct FileCacheEntry<'a>(Entry, PhantomData<&'a FileCache>);
+pub struct FileCacheEntry<'a>(Entry, &'a FileCache);
 
 enum Entry {
     Unopened {
@@ -70,9 +122,11 @@ enum Entry {
     Open {
         file: File,
         header_len: usize,
+        /// Whether the file is locked for writing, rather than just reading
+        exclusive: bool,
     },
     Streaming {
-        stream: zstd::Encoder<'static, File>,
+        stream: BlockWriter<File>,
         header_len: usize,
     },
     Closed,
@@ -83,8 +137,104 @@ impl Default for Entry {
 }
 
 impl FileCache {
-    fn locate_cache(&self) -> Result<PathBuf> {
-        self.0
+    pub fn new(
+        dir: Option<PathBuf>,
+        quota: Option<u64>,
+        lock_timeout: Duration,
+        codec: CacheCodec,
+        read_only: bool,
+    ) -> Self {
+        Self {
+            dir,
+            quota,
+            lock_timeout,
+            codec,
+            read_only,
+            gc: Mutex::default(),
+        }
+    }
+
+    /// Choose the codec for a new block, given the first value in it
+    fn codec_for(&self, first: &CacheValue) -> Codec { Codec::choose(self.codec, first) }
+
+    /// Start evicting entries in the background if the cache is over its
+    /// quota.  Does nothing if no quota is set or eviction is already running.
+    fn collect_garbage(&self) {
+        let Some(quota) = self.quota else { return };
+        let cache_dir = match self.locate_cache() {
+            Ok(d) => d,
+            Err(e) => {
+                warn!("Failed to locate cache for eviction: {e:?}");
+                return;
+            },
+        };
+
+        let mut gc = self.gc.lock().unwrap();
+
+        if gc.running.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        if let Some(thread) = gc.thread.take() {
+            thread.join().ok();
+        }
+
+        let running = gc.running.clone();
+
+        gc.thread = Some(thread::spawn(move || {
+            match evict(&cache_dir, quota) {
+                Ok(Eviction { evicted: 0, .. }) => (),
+                Ok(Eviction {
+                    evicted,
+                    freed,
+                    total,
+                }) => info!(
+                    "Cache exceeded its quota of {}; evicted {} file(s), freeing {} (now {}).",
+                    ByteSize(quota),
+                    evicted,
+                    ByteSize(freed),
+                    ByteSize(total)
+                ),
+                Err(e) => warn!("Failed to evict cache entries: {e:?}"),
+            }
+
+            running.store(false, Ordering::Release);
+        }));
+    }
+
+    /// List every entry in the cache directory
+    pub fn entries(&self) -> Result<Vec<EntryInfo>> {
+        let cache_dir = self.locate_cache()?;
+
+        if cache_dir.exists() {
+            list_files(&cache_dir)
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    /// List the directories a full clean would remove, i.e. those holding
+    /// nothing but cache files and other such directories, children first
+    pub fn clean_dirs(&self) -> Result<Vec<PathBuf>> {
+        let cache_dir = self.locate_cache()?;
+        let mut ret = vec![];
+
+        if cache_dir.exists() {
+            find_clean_dirs(&cache_dir, &mut ret)?;
+        }
+
+        Ok(ret)
+    }
+
+    /// Bring the index up to date with a file that was just written
+    fn index(&self, file: &mut File) {
+        if let Err(e) = self.locate_cache().and_then(|d| index::record(&d, file)) {
+            warn!("Failed to update cache index: {e:?}");
+        }
+    }
+
+    pub(super) fn locate_cache(&self) -> Result<PathBuf> {
+        self.dir
             .as_ref()
             .map_or_else(
                 || dirs::cache_dir().map(|d| d.join("disson-rs")),
@@ -115,7 +265,7 @@ impl<'a> Cache<'a> for FileCache {
                 path: cache_dir.join(dir).join(file),
                 key_bytes,
             },
-            PhantomData,
+            self,
         ))
     }
 
@@ -133,7 +283,12 @@ impl<'a> Cache<'a> for FileCache {
             return Ok(());
         }
 
-        let mut magic_buf = vec![0_u8; GLOBAL_MAGIC.len()];
+        match fs::remove_file(cache_dir.join(INDEX_FILE)) {
+            Ok(()) => info!("Removing file {}...", cache_dir.join(INDEX_FILE).to_string_lossy()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e).context("failed to delete cache index"),
+        }
+
         let mut stack = vec![(QType::Explore, cache_dir)];
 
         while let Some((ty, dir)) = stack.pop() {
@@ -159,21 +314,7 @@ impl<'a> Cache<'a> for FileCache {
                 let ty = entry.file_type()?;
 
                 if ty.is_file() {
-                    let mut file = File::open(&path).with_context(|| {
-                        format!(
-                            "failed to open possible cache file {:?}",
-                            path.to_string_lossy()
-                        )
-                    })?;
-
-                    file.read_exact(magic_buf.as_mut()).with_context(|| {
-                        format!(
-                            "failed to check possible cache file {:?}",
-                            path.to_string_lossy()
-                        )
-                    })?;
-
-                    if magic_buf == GLOBAL_MAGIC.as_bytes() {
+                    if is_cache_file(&path)? {
                         let s = path.to_string_lossy();
 
                         info!("Removing file {}...", s);
@@ -198,25 +339,464 @@ impl<'a> Cache<'a> for FileCache {
 
         Ok(())
     }
+
+    fn enforce_quota(&self) -> Result<()> {
+        let quota = self.quota.ok_or_else(|| {
+            anyhow!("no cache quota configured, please specify one with --cache-quota")
+        })?;
+        let cache_dir = self.locate_cache()?;
+
+        if !cache_dir.exists() {
+            warn!("Cache directory doesn't exist, nothing to do.");
+
+            return Ok(());
+        }
+
+        let Eviction {
+            evicted,
+            freed,
+            total,
+        } = evict(&cache_dir, quota)?;
+
+        if evicted == 0 && total <= quota {
+            info!(
+                "Cache size {} is within quota of {}, nothing to do.",
+                ByteSize(total),
+                ByteSize(quota)
+            );
+
+            return Ok(());
+        }
+
+        info!(
+            "Evicted {} cache file(s), freeing {}; cache is now {}.",
+            evicted,
+            ByteSize(freed),
+            ByteSize(total)
+        );
+
+        if total > quota {
+            warn!(
+                "Cache is still over its quota of {}; some files could not be evicted.",
+                ByteSize(quota)
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for FileCache {
+    fn drop(&mut self) {
+        // Let any eviction in progress finish before exiting
+        if let Some(thread) = self.gc.get_mut().unwrap().thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// The outcome of evicting entries to bring the cache within its quota
+struct Eviction {
+    evicted: usize,
+    freed: u64,
+    /// The size of the cache after eviction
+    total: u64,
+}
+
+/// Delete least-recently-used cache files until the cache fits within the
+/// quota, skipping any that are in use
+fn evict(cache_dir: &Path, quota: u64) -> Result<Eviction> {
+    let mut files = list_files(cache_dir)?;
+    let mut ret = Eviction {
+        evicted: 0,
+        freed: 0,
+        total: files.iter().map(|f| f.len).sum(),
+    };
+
+    if ret.total <= quota {
+        return Ok(ret);
+    }
+
+    files.sort_by_key(|f| f.accessed);
+
+    for file in files {
+        if ret.total <= quota {
+            break;
+        }
+
+        let s = file.path.to_string_lossy();
+
+        // Don't pull files out from under another running instance
+        let lock = File::open(&file.path).and_then(|f| f.try_lock_exclusive().map(|()| f));
+        let _lock = match lock {
+            Ok(f) => f,
+            Err(e) => {
+                debug!("Skipping cache file {s} in use: {e}");
+                continue;
+            },
+        };
+
+        debug!("Evicting file {s}...");
+
+        file.delete()?;
+
+        ret.total -= file.len;
+        ret.freed += file.len;
+        ret.evicted += 1;
+    }
+
+    Ok(ret)
+}
+
+/// A cache file found on disk
+#[derive(Debug)]
+pub struct EntryInfo {
+    /// The hex-encoded hash of the entry's key
+    pub hash: String,
+    pub path: PathBuf,
+    pub len: u64,
+    /// The last time the entry was read or written, as recorded by [`touch`]
+    pub accessed: SystemTime,
+}
+
+/// The condition of a cache file, as found by [`EntryInfo::check`]
+#[derive(Debug)]
+pub enum Health {
+    /// Every block was read in full
+    Good,
+    /// The file was written by the given version of the program, and can't be
+    /// read by this one
+    Stale(String),
+    /// The header couldn't be read, or doesn't match the name of the file
+    BadHeader(Error),
+    /// The block starting at the given offset couldn't be read in full
+    Corrupt { values: usize, offset: u64 },
+    /// The file was corrupted, and has been rewritten to keep only the values
+    /// that could be read
+    Repaired {
+        /// The number of values kept
+        values: usize,
+        /// How many of the kept values were salvaged from the corrupted block
+        salvaged: usize,
+        /// The number of bytes dropped from the file
+        dropped: u64,
+    },
+}
+
+impl EntryInfo {
+    /// Decode the key stored in the header of this entry.  Fails if the entry
+    /// was written by a different version of the program.
+    pub fn read_key(&self) -> Result<KeyInfo> {
+        let mut file = File::open(&self.path).context("failed to open cache file")?;
+        let ver = read_version(&mut file)?;
+
+        if ver != version() {
+            return Err(anyhow!("entry was written by version {}", ver));
+        }
+
+        read_key(&mut file).map(|(k, _)| k)
+    }
+
+    /// Read the version of the program that wrote this entry
+    pub fn version(&self) -> Result<String> {
+        let mut file = File::open(&self.path).context("failed to open cache file")?;
+
+        read_version(&mut file)
+    }
+
+    /// Check the header of this entry and decode every block in it.  If
+    /// `repair` is set, corrupted blocks are dropped from the file, keeping any
+    /// values that could be read from them.
+    pub fn check(&self, repair: bool) -> Result<Health> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(repair)
+            .open(&self.path)
+            .context("failed to open cache file")?;
+
+        lock(&file, repair, Duration::default())?;
+
+        let key_bytes = match read_version(&mut file) {
+            Ok(v) if v != version() => return Ok(Health::Stale(v)),
+            Ok(_) => read_key(&mut file).map(|(_, b)| b),
+            Err(e) => Err(e),
+        };
+
+        let key_bytes = match key_bytes {
+            Ok(b) => b,
+            Err(e) => return Ok(Health::BadHeader(e)),
+        };
+
+        let (dir, name) = file_name(Sha256::digest(&key_bytes));
+
+        if dir.join(name) != Path::new(&self.hash[..2]).join(&self.hash[2..]) {
+            return Ok(Health::BadHeader(anyhow!(
+                "stored key doesn't match the name of the file"
+            )));
+        }
+
+        file.seek(SeekFrom::Start((magic().len() + key_bytes.len()) as u64))
+            .context("failed to seek past file header")?;
+
+        let mut values = 0;
+
+        loop {
+            match read_block(&mut file) {
+                Block::Good(b) => values += b.len(),
+                Block::Corrupt(b, offset) if repair => {
+                    let old_len = file
+                        .metadata()
+                        .context("failed to get file length")?
+                        .len();
+                    let mut file = recover(file, offset, &b, Codec::Zstd)?.finish()?;
+                    let new_len = file
+                        .stream_position()
+                        .context("failed to get file length")?;
+
+                    if let Err(e) = index::record(self.cache_dir(), &mut file) {
+                        warn!("Failed to update cache index: {e:?}");
+                    }
+
+                    break Ok(Health::Repaired {
+                        values: values + b.len(),
+                        salvaged: b.len(),
+                        dropped: old_len.saturating_sub(new_len),
+                    });
+                },
+                Block::Corrupt(b, offset) => {
+                    break Ok(Health::Corrupt {
+                        values: values + b.len(),
+                        offset,
+                    })
+                },
+                Block::Eof => break Ok(Health::Good),
+            }
+        }
+    }
+
+    /// Delete this entry, along with its directory if it is left empty
+    pub fn delete(&self) -> Result<()> {
+        fs::remove_file(&self.path).with_context(|| {
+            format!("failed to delete cache file {:?}", self.path.to_string_lossy())
+        })?;
+
+        // Fails harmlessly if the directory still has other entries in it
+        fs::remove_dir(self.path.parent().unwrap()).ok();
+
+        if let Err(e) = index::remove(self.cache_dir(), &self.hash) {
+            warn!("Failed to update cache index: {e:?}");
+        }
+
+        Ok(())
+    }
+
+    /// The root of the cache containing this entry
+    pub fn cache_dir(&self) -> &Path {
+        self.path
+            .parent()
+            .and_then(Path::parent)
+            .unwrap_or_else(|| Path::new(""))
+    }
+}
+
+/// Decode the key following the magic number of a cache file, returning it
+/// along with its encoded bytes
+pub(super) fn read_key(file: &mut File) -> Result<(KeyInfo, Vec<u8>)> {
+    let key: KeyInfo = key_bin_opts()
+        .allow_trailing_bytes()
+        .deserialize_from(file)
+        .context("failed to decode cache key")?;
+    let bytes = key_bin_opts()
+        .serialize(&key)
+        .context("failed to re-encode cache key")?;
+
+    Ok((key, bytes))
+}
+
+/// Read the program version from the magic number at the start of a cache
+/// file
+pub(super) fn read_version(file: &mut File) -> Result<String> {
+    let mut magic_buf = vec![0_u8; GLOBAL_MAGIC.len()];
+    let mut len = [0_u8];
+
+    file.read_exact(magic_buf.as_mut())
+        .and_then(|()| file.read_exact(&mut len))
+        .context("failed to read cache magic number")?;
+
+    if magic_buf != GLOBAL_MAGIC.as_bytes() {
+        return Err(anyhow!("not a cache file"));
+    }
+
+    let mut ver = vec![0_u8; len[0].into()];
+
+    file.read_exact(ver.as_mut())
+        .context("failed to read cache version")?;
+
+    String::from_utf8(ver).context("cache version was not valid UTF-8")
+}
+
+fn is_cache_file(path: &Path) -> Result<bool> {
+    let s = path.to_string_lossy();
+    let mut magic_buf = vec![0_u8; GLOBAL_MAGIC.len()];
+
+    let mut file =
+        File::open(path).with_context(|| format!("failed to open possible cache file {s:?}"))?;
+
+    match file.read_exact(magic_buf.as_mut()) {
+        Ok(()) => Ok(magic_buf == GLOBAL_MAGIC.as_bytes()),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("failed to check possible cache file {s:?}")),
+    }
+}
+
+/// Add every directory under and including `dir` that would be left empty by
+/// deleting all cache files to `out`.  Returns whether `dir` was added.
+fn find_clean_dirs(dir: &Path, out: &mut Vec<PathBuf>) -> Result<bool> {
+    let mut empty = true;
+
+    for entry in fs::read_dir(dir)
+        .with_context(|| format!("failed to open directory {:?}", dir.to_string_lossy()))?
+    {
+        let entry = entry.with_context(|| {
+            format!("failed to read from directory {:?}", dir.to_string_lossy())
+        })?;
+        let path = dir.join(entry.file_name());
+        let ty = entry.file_type()?;
+
+        if ty.is_dir() {
+            empty &= find_clean_dirs(&path, out)?;
+        } else {
+            empty &= ty.is_file() && (entry.file_name() == INDEX_FILE || is_cache_file(&path)?);
+        }
+    }
+
+    if empty {
+        out.push(dir.to_owned());
+    }
+
+    Ok(empty)
+}
+
+/// Recursively collect every cache file under the given directory
+fn list_files(cache_dir: &Path) -> Result<Vec<EntryInfo>> {
+    let mut ret = vec![];
+    let mut stack = vec![cache_dir.to_owned()];
+
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(&dir)
+            .with_context(|| format!("failed to open directory {:?}", dir.to_string_lossy()))?
+        {
+            let entry = entry.with_context(|| {
+                format!("failed to read from directory {:?}", dir.to_string_lossy())
+            })?;
+            let path = dir.join(entry.file_name());
+            let ty = entry.file_type()?;
+
+            if ty.is_file() {
+                if !is_cache_file(&path)? {
+                    continue;
+                }
+
+                let meta = entry.metadata().with_context(|| {
+                    format!("failed to stat cache file {:?}", path.to_string_lossy())
+                })?;
+
+                ret.push(EntryInfo {
+                    hash: format!(
+                        "{}{}",
+                        dir.file_name().unwrap_or_default().to_string_lossy(),
+                        entry.file_name().to_string_lossy()
+                    ),
+                    path,
+                    len: meta.len(),
+                    accessed: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                });
+            } else if ty.is_dir() {
+                stack.push(path);
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Mark a cache file as recently used.  Access times are unreliable on most
+/// filesystems, so the modification time is bumped instead.
+fn touch(path: &Path) {
+    if let Err(e) = filetime::set_file_mtime(path, FileTime::now()) {
+        warn!("Failed to update cache file access time: {e:?}");
+    }
+}
+
+/// Lock a file, waiting up to `timeout` for other processes to release it
+pub(super) fn lock(file: &File, exclusive: bool, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        // Newer versions of std have an inherent File::try_lock_shared, which
+        // would shadow the fs2 one
+        let res = if exclusive {
+            FileExt::try_lock_exclusive(file)
+        } else {
+            FileExt::try_lock_shared(file)
+        };
+
+        match res {
+            Ok(()) => break Ok(()),
+            Err(e)
+                if e.raw_os_error() == fs2::lock_contended_error().raw_os_error()
+                    && Instant::now() < deadline =>
+            {
+                thread::sleep(LOCK_POLL);
+            },
+            Err(e) => break Err(e).context("failed to acquire file lock"),
+        }
+    }
 }
 
-fn open_file(path: impl AsRef<Path>, key_bytes: &[u8]) -> Result<(File, usize)> {
+/// Trade a shared lock on a file for an exclusive one.  The shared lock is
+/// released first, since not every platform can convert locks in place, so
+/// the file may have grown in the meantime.
+fn upgrade_lock(file: &File, timeout: Duration) -> Result<()> {
+    FileExt::unlock(file).context("failed to release shared file lock")?;
+
+    lock(file, true, timeout)
+}
+
+/// Open an existing cache file for reading, with a shared lock.  Unless
+/// `write` is set the file can't be written to, even after upgrading the lock.
+fn open_file(
+    path: impl AsRef<Path>,
+    key_bytes: &[u8],
+    timeout: Duration,
+    write: bool,
+) -> Result<(File, usize)> {
     let mut file = OpenOptions::new()
         .read(true)
-        .write(true)
+        .write(write)
         .create(false)
         .open(path)
         .context("failed to open file")?;
 
-    file.try_lock_exclusive()
-        .context("failed to acquire file lock")?;
+    lock(&file, false, timeout)?;
 
     let header_len = check_header(&mut file, &key_bytes).context("failed to check file header")?;
 
     Ok((file, header_len))
 }
 
-fn create_file(path: impl AsRef<Path>, key_bytes: &[u8]) -> Result<(File, usize)> {
+/// Whether opening a cache file failed because it doesn't exist
+fn is_not_found(err: &Error) -> bool {
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+}
+
+/// Create an empty cache file, with an exclusive lock
+fn create_file(
+    path: impl AsRef<Path>,
+    key_bytes: &[u8],
+    timeout: Duration,
+) -> Result<(File, usize)> {
     DirBuilder::new()
         .recursive(true)
         .create(path.as_ref().parent().unwrap())
@@ -226,19 +806,20 @@ fn create_file(path: impl AsRef<Path>, key_bytes: &[u8]) -> Result<(File, usize)
         .read(true)
         .write(true)
         .create(true)
-        .truncate(true)
         .open(path)
         .context("failed to create file")?;
 
-    file.try_lock_exclusive()
-        .context("failed to acquire file lock")?;
+    // Don't truncate the file until any other process is done with it
+    lock(&file, true, timeout)?;
+
+    file.set_len(0).context("failed to truncate file")?;
 
     let header_len = write_header(&mut file, &key_bytes).context("failed to write file header")?;
 
     Ok((file, header_len))
 }
 
-fn check_header(file: &mut File, key_bytes: &[u8]) -> Result<usize> {
+pub(super) fn check_header(file: &mut impl Read, key_bytes: &[u8]) -> Result<usize> {
     let magic = magic();
     let mut file_magic = vec![0_u8; magic.len()];
 
@@ -263,7 +844,7 @@ fn check_header(file: &mut File, key_bytes: &[u8]) -> Result<usize> {
     Ok(magic.len() + key_bytes.len())
 }
 
-fn write_header(file: &mut File, key_bytes: &[u8]) -> Result<usize> {
+pub(super) fn write_header(file: &mut impl Write, key_bytes: &[u8]) -> Result<usize> {
     let magic = magic();
 
     file.write_all(magic.as_ref())
@@ -275,102 +856,129 @@ fn write_header(file: &mut File, key_bytes: &[u8]) -> Result<usize> {
     Ok(magic.len() + key_bytes.len())
 }
 
-fn is_at_eof(mut file: &File) -> Result<(bool, u64)> {
-    let pos = file
-        .stream_position()
-        .context("failed to get file position")?;
-    let len = file
-        .seek(SeekFrom::End(0))
-        .context("failed to get file length")?;
-    let eof = pos == len;
+/// Truncate a file at the start of a corrupted block and begin a new block
+/// containing the values recovered from it
+fn recover(
+    mut file: File,
+    pos: u64,
+    blk: &[CacheValue],
+    codec: Codec,
+) -> Result<BlockWriter<File>> {
+    file.set_len(pos).context("failed to truncate file")?;
 
-    if !eof {
-        file.seek(SeekFrom::Start(pos))
-            .context("failed to restore file position")?;
-    }
+    file.seek(SeekFrom::End(0))
+        .context("failed to seek to end-of-file")?;
 
-    Ok((eof, pos))
-}
+    let mut stream = BlockWriter::new(file, codec)?;
 
-enum Block {
-    /// A block was successfully read, and more blocks may be available
-    Good(Vec<CacheValue<'static>>),
-    /// A block may have been partially read, but the rest of the file is not
-    /// recoverable - the file should be truncated to the length given and the
-    /// data should be recovered
-    Corrupt(Vec<CacheValue<'static>>, u64),
-    /// No more blocks are available
-    Eof,
-}
+    for val in blk {
+        stream.write(val).context("failed to write recovered value")?;
+    }
 
-fn read_block(file: &File) -> Block {
-    let pos = match is_at_eof(file) {
-        Ok((true, _)) => return Block::Eof,
-        Ok((false, p)) => p,
-        Err(e) => {
-            warn!("Failed to check for end of cache file: {:?}", e);
-            return Block::Eof; // Can't return Corrupt because it requires pos
-        },
-    };
+    Ok(stream)
+}
 
-    let mut dec = match zstd::Decoder::new(file) {
-        Ok(d) => d,
-        Err(e) => {
-            warn!("Failed to open zstd decoder on cache file: {:?}", e);
-            return Block::Corrupt(vec![], pos);
-        },
-    };
+impl FileCacheEntry<'_> {
+    /// Truncate the file at the corrupted block starting at `pos`, and begin
+    /// a new block holding the values salvaged from it
+    fn recover_from(&mut self, pos: u64, blk: &[CacheValue]) {
+        let timeout = self.1.lock_timeout;
 
-    let mut ret = vec![];
+        if self.1.read_only {
+            warn!("Found a corrupted block in a read-only cache, leaving it as-is");
+            return;
+        }
 
-    loop {
-        match val_bin_opts().deserialize_from(&mut dec) {
-            Ok(Some(val)) => ret.push(val),
-            Ok(None) => return Block::Good(ret),
-            Err(e) => {
-                // TODO: either mark the file as partially unreadable or
-                // attempt to stream a corrupted block back into the file
+        if let Entry::Open {
+            file,
+            header_len,
+            exclusive,
+        } = mem::take(&mut self.0)
+        {
+            let res = if exclusive {
+                Ok(())
+            } else {
+                upgrade_lock(&file, timeout)
+            };
 
-                warn!("Failed to read cache value: {:?}", e);
+            let codec = blk.first().map_or(Codec::None, |v| self.1.codec_for(v));
 
-                return Block::Corrupt(ret, pos);
-            },
+            match res.and_then(|()| recover(file, pos, blk, codec)) {
+                Ok(stream) => self.0 = Entry::Streaming { stream, header_len },
+                Err(e) => {
+                    warn!("Failed to recover corrupted cache block: {e:?}");
+                },
+            }
         }
     }
 }
 
-fn make_stream(file: File) -> Result<zstd::Encoder<'static, File>> {
-    zstd::Encoder::new(file, 0).context("failed to open zstd encoder on cache file")
+/// A memory map shared between a value decoder and the blocks it opens
+#[derive(Clone)]
+struct SharedMap(Arc<Mmap>);
+
+impl AsRef<[u8]> for SharedMap {
+    fn as_ref(&self) -> &[u8] { &self.0 }
 }
 
-impl<'a> CacheEntry for FileCacheEntry<'a> {
-    fn read_impl(&mut self) -> Vec<CacheValue<'static>> {
-        fn recover(
-            mut file: File,
-            pos: u64,
-            blk: &[CacheValue],
-        ) -> Result<zstd::Encoder<'static, File>> {
-            file.set_len(pos).context("failed to truncate file")?;
+/// Values decoded lazily from a memory-mapped cache file, which is recovered
+/// once a corrupted block is reached
+struct FileValues<'e, 'a> {
+    entry: &'e mut FileCacheEntry<'a>,
+    values: Option<block::Values<SharedMap>>,
+}
 
-            file.seek(SeekFrom::End(0))
-                .context("failed to seek to end-of-file")?;
+impl Iterator for FileValues<'_, '_> {
+    type Item = CacheValue<'static>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(val) = self.values.as_mut()?.next() {
+            return Some(val);
+        }
 
-            let mut stream = make_stream(file)?;
+        let values = self.values.take().unwrap();
 
-            for val in blk {
-                val_bin_opts()
-                    .serialize_into(&mut stream, &Some(val))
-                    .context("failed to write recovered value")?;
-            }
+        if let Some(pos) = values.corrupt() {
+            let blk = values.salvage();
+
+            // Unmap the file before truncating it
+            mem::drop(values);
 
-            Ok(stream)
+            self.entry.recover_from(pos, &blk);
         }
 
+        None
+    }
+}
+
+impl CacheEntry for FileCacheEntry<'_> {
+    fn read_impl(&mut self) -> Values<'_> {
+        let timeout = self.1.lock_timeout;
+        let read_only = self.1.read_only;
+
         self.0 = match mem::take(&mut self.0) {
-            Entry::Unopened { path, key_bytes } => match open_file(&path, &key_bytes) {
-                Ok((file, header_len)) => Entry::Open { file, header_len },
+            Entry::Unopened { path, key_bytes } => match open_file(
+                &path,
+                &key_bytes,
+                timeout,
+                !read_only,
+            ) {
+                Ok((file, header_len)) => {
+                    if !read_only {
+                        touch(&path);
+                    }
+
+                    Entry::Open {
+                        file,
+                        header_len,
+                        exclusive: false,
+                    }
+                },
                 Err(e) => {
-                    warn!("Failed to open cache file: {:?}", e);
+                    // A missing file is just a cache miss
+                    if !is_not_found(&e) {
+                        warn!("Failed to open cache file: {e:?}");
+                    }
 
                     Entry::Unopened { path, key_bytes }
                 },
@@ -379,84 +987,139 @@ impl<'a> CacheEntry for FileCacheEntry<'a> {
             Entry::Closed => unreachable!("Attempted to read from dropped entry"),
         };
 
-        if let Entry::Open { ref mut file, .. } = self.0 {
-            let mut ret = vec![];
-
-            let recover_from = loop {
-                match read_block(file) {
-                    Block::Good(mut b) => ret.append(&mut b),
-                    Block::Corrupt(mut b, p) => {
-                        let i = ret.len();
-                        ret.append(&mut b);
-                        break Some((p, &ret[i..]));
-                    },
-                    Block::Eof => break None,
-                }
-            };
+        let (map, start) = if let Entry::Open {
+            ref file,
+            header_len,
+            ..
+        } = self.0
+        {
+            // The file stays locked for as long as it's mapped, so other
+            // processes can't truncate it out from under the map
+            match unsafe { Mmap::map(file) } {
+                Ok(m) => (SharedMap(Arc::new(m)), header_len),
+                Err(e) => {
+                    warn!("Failed to map cache file: {e:?}");
 
-            if let Some((pos, blk)) = recover_from {
-                if let Entry::Open { file, header_len } = mem::take(&mut self.0) {
-                    match recover(file, pos, blk) {
-                        Ok(stream) => self.0 = Entry::Streaming { stream, header_len },
-                        Err(e) => {
-                            warn!("Failed to recover corrupted cache block: {:?}", e);
-                        },
-                    }
-                }
+                    return Box::new(iter::empty());
+                },
             }
-
-            ret
         } else {
             debug_assert!(!matches!(self.0, Entry::Closed));
 
-            vec![]
-        }
+            return Box::new(iter::empty());
+        };
+
+        Box::new(FileValues {
+            values: Some(block::Values::new(map, start)),
+            entry: self,
+        })
     }
 
     #[allow(clippy::shadow_unrelated)] // TODO: ?????
     fn append_impl(&mut self, val: &CacheValue) -> Result<()> {
+        let timeout = self.1.lock_timeout;
+        let started = !matches!(self.0, Entry::Streaming { .. });
+
         self.0 = match mem::take(&mut self.0) {
             Entry::Unopened { path, key_bytes } => {
-                let (file, header_len) = create_file(path, &key_bytes)?;
+                // Keep the existing values if this entry was never read, e.g.
+                // because they were served by a faster cache layer.  Only a
+                // missing file may be created, since a file that's locked or
+                // has a bad header may still hold another process's values.
+                let opened = match open_file(&path, &key_bytes, timeout, true) {
+                    Ok((file, header_len)) => {
+                        upgrade_lock(&file, timeout).map(|()| (file, header_len))
+                    },
+                    Err(e) if is_not_found(&e) => create_file(&path, &key_bytes, timeout),
+                    Err(e) => Err(e),
+                };
+
+                let (mut file, header_len) = match opened {
+                    Ok(f) => f,
+                    Err(e) => {
+                        self.0 = Entry::Unopened { path, key_bytes };
+
+                        return Err(e);
+                    },
+                };
+
+                file.seek(SeekFrom::End(0))
+                    .context("failed to seek to end-of-file")?;
 
                 Entry::Streaming {
-                    stream: make_stream(file)?,
+                    stream: BlockWriter::new(file, self.1.codec_for(val))?,
                     header_len,
                 }
             },
-            Entry::Open { file, header_len } => Entry::Streaming {
-                stream: make_stream(file)?,
+            Entry::Open {
+                mut file,
                 header_len,
+                exclusive,
+            } => {
+                if !exclusive {
+                    upgrade_lock(&file, timeout)?;
+                }
+
+                file.seek(SeekFrom::End(0))
+                    .context("failed to seek to end-of-file")?;
+
+                Entry::Streaming {
+                    stream: BlockWriter::new(file, self.1.codec_for(val))?,
+                    header_len,
+                }
             },
             e @ Entry::Streaming { .. } => e,
             Entry::Closed => unreachable!("Attempted to write to dropped entry"),
         };
 
         if let Entry::Streaming { ref mut stream, .. } = self.0 {
-            val_bin_opts()
-                .serialize_into(stream, &Some(val))
-                .context("failed to write cache value")?;
+            stream.write(val)?;
         } else {
             unreachable!();
         }
 
+        if started {
+            self.1.collect_garbage();
+        }
+
         Ok(())
     }
 
     fn truncate(&mut self) -> Result<()> {
+        let timeout = self.1.lock_timeout;
+
         self.0 = match mem::take(&mut self.0) {
             Entry::Unopened { path, key_bytes } => {
-                let (file, header_len) = create_file(path, &key_bytes)?;
+                let (file, header_len) = create_file(path, &key_bytes, timeout)?;
+
+                Entry::Open {
+                    file,
+                    header_len,
+                    exclusive: true,
+                }
+            },
+            Entry::Open {
+                file,
+                header_len,
+                exclusive,
+            } => {
+                if !exclusive {
+                    upgrade_lock(&file, timeout)?;
+                }
 
-                Entry::Open { file, header_len }
+                Entry::Open {
+                    file,
+                    header_len,
+                    exclusive: true,
+                }
             },
-            e @ Entry::Open { .. } => e,
             Entry::Streaming { stream, header_len } => {
                 warn!("Truncating cache file that was open for streaming - this is wasteful!");
 
                 Entry::Open {
-                    file: stream.finish().context("failed to close zstd encoder")?,
+                    file: stream.finish()?,
                     header_len,
+                    exclusive: true,
                 }
             },
             Entry::Closed => unreachable!("Attempted to truncate dropped entry"),
@@ -465,6 +1128,7 @@ impl<'a> CacheEntry for FileCacheEntry<'a> {
         if let Entry::Open {
             ref mut file,
             header_len,
+            ..
         } = self.0
         {
             file.set_len(header_len as u64)
@@ -482,15 +1146,103 @@ impl<'a> CacheEntry for FileCacheEntry<'a> {
 
 impl<'a> Drop for FileCacheEntry<'a> {
     fn drop(&mut self) {
-        if let Entry::Streaming { mut stream, .. } = mem::take(&mut self.0) {
-            match val_bin_opts()
-                .serialize_into(&mut stream, &None::<CacheValue<'static>>)
-                .context("failed to serialize sentinel")
-                .and_then(|()| stream.finish().context("failed to close zstd encoder"))
-            {
-                Ok(_) => (),
-                Err(e) => error!("Failed to write cache block sentinel: {:?}", e),
+        if let Entry::Streaming { stream, .. } = mem::take(&mut self.0) {
+            match stream.finish() {
+                Ok(mut file) => {
+                    self.1.index(&mut file);
+                    self.1.collect_garbage();
+                },
+                Err(e) => error!("Failed to finish cache block: {e:?}"),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process, time::Duration};
+
+    use super::{EntryInfo, FileCache, Health};
+    use crate::{
+        cache::prelude::*,
+        cli::CacheCodec,
+        disson::map::{self, tests::key},
+    };
+
+    /// Cache two values in a new cache, returning the cache and its entry
+    fn cache_entry(name: &str) -> (FileCache, EntryInfo) {
+        let dir = env::temp_dir().join(format!("disson-{}-{}", name, process::id()));
+        let cache = FileCache::new(
+            Some(dir),
+            None,
+            Duration::from_secs(1),
+            CacheCodec::None,
+            false,
+        );
+
+        {
+            let mut entry = cache.entry(key(2)).unwrap();
+
+            entry.append(map::CacheValue::<f64>::Histogram(())).unwrap();
+            entry.append(map::CacheValue::<f64>::Histogram(())).unwrap();
+        }
+
+        let mut entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+
+        (cache, entries.remove(0))
+    }
+
+    /// Cut the end-of-block sentinel off a cache file, leaving every value
+    /// readable
+    fn cut_off(entry: &EntryInfo) {
+        let len = fs::metadata(&entry.path).unwrap().len();
+
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&entry.path)
+            .and_then(|f| f.set_len(len - 1))
+            .unwrap();
+    }
+
+    #[test]
+    fn check_finds_corrupt_entries() {
+        let (cache, entry) = cache_entry("verify");
+        let good = entry.check(false);
+
+        cut_off(&entry);
+        let corrupt = entry.check(false);
+
+        fs::write(&entry.path, b"not a cache file").unwrap();
+        let bad = entry.check(false);
+
+        fs::remove_dir_all(cache.locate_cache().unwrap()).unwrap();
+
+        assert!(matches!(good.unwrap(), Health::Good));
+        assert!(matches!(corrupt.unwrap(), Health::Corrupt { .. }));
+        assert!(matches!(bad.unwrap(), Health::BadHeader(_)));
+    }
+
+    #[test]
+    fn repair_keeps_salvaged_values() {
+        let (cache, entry) = cache_entry("repair");
+
+        cut_off(&entry);
+        let repaired = entry.check(true);
+        let checked = entry.check(false);
+        let values = cache
+            .entry(key(2))
+            .and_then(|mut e| e.read::<map::CacheValue, _>().map(|v| v.len()));
+
+        fs::remove_dir_all(cache.locate_cache().unwrap()).unwrap();
+
+        match repaired.unwrap() {
+            Health::Repaired {
+                values, salvaged, ..
+            } => assert_eq!((values, salvaged), (2, 2)),
+            h => panic!("entry wasn't repaired: {:?}", h),
+        }
+        assert!(matches!(checked.unwrap(), Health::Good));
+        assert_eq!(values.unwrap(), 2);
+    }
+}
@@ -0,0 +1,211 @@
+//! A cache shared over HTTP.  Entries are stored in the same format as cache
+//! files, under the hex-encoded hash of their key, so any server that supports
+//! GET and PUT on arbitrary paths can host one.
+
+use std::{
+    io::{prelude::*, Cursor},
+    marker::PhantomData,
+    time::Duration,
+};
+
+use bincode::Options;
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+
+use super::{
+    block::{read_block, Block, BlockWriter, Codec},
+    file::{check_header, key_bin_opts, write_header},
+    Cache, CacheEntry, CacheKey, CacheValue, Values,
+};
+use crate::error::prelude::*;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct HttpCache {
+    agent: ureq::Agent,
+    url: String,
+}
+
+pub struct HttpCacheEntry<'a> {
+    agent: ureq::Agent,
+    url: String,
+    key_bytes: Vec<u8>,
+    /// Every value in the entry, or `None` if it hasn't been downloaded yet
+    values: Option<Vec<CacheValue<'static>>>,
+    /// Whether the entry couldn't be downloaded.  Its values are then
+    /// unknown, so it is never uploaded unless it's truncated first.
+    failed: bool,
+    /// Whether the entry has changed since it was downloaded or uploaded
+    dirty: bool,
+    _p: PhantomData<&'a HttpCache>,
+}
+
+impl HttpCache {
+    pub fn new(url: String) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+            url,
+        }
+    }
+}
+
+impl<'a> Cache<'a> for HttpCache {
+    type Entry = HttpCacheEntry<'a>;
+
+    fn entry_impl(&'a self, key: CacheKey) -> Result<Self::Entry> {
+        let key_bytes = key_bin_opts()
+            .serialize(&key)
+            .context("failed to serialize cache key")?;
+
+        let hash = Sha256::digest(&key_bytes)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .fold(String::new(), |mut s, h| {
+                s.push_str(&h);
+                s
+            });
+
+        Ok(HttpCacheEntry {
+            agent: self.agent.clone(),
+            url: format!("{}/{}", self.url, hash),
+            key_bytes,
+            values: None,
+            failed: false,
+            dirty: false,
+            _p: PhantomData,
+        })
+    }
+
+    fn clean(&self) -> Result<()> { Err(anyhow!("remote caches cannot be cleaned")) }
+
+    fn enforce_quota(&self) -> Result<()> {
+        Err(anyhow!("remote caches manage their own quota"))
+    }
+}
+
+fn decode(body: Vec<u8>, key_bytes: &[u8]) -> Result<Vec<CacheValue<'static>>> {
+    let mut body = Cursor::new(body);
+
+    check_header(&mut body, key_bytes).context("failed to check entry header")?;
+
+    let mut ret = vec![];
+
+    loop {
+        match read_block(&mut body) {
+            Block::Good(mut b) => ret.append(&mut b),
+            Block::Corrupt(..) => return Err(anyhow!("entry is corrupted")),
+            Block::Eof => break Ok(ret),
+        }
+    }
+}
+
+fn encode(key_bytes: &[u8], values: &[CacheValue]) -> Result<Vec<u8>> {
+    let mut body = Cursor::new(vec![]);
+
+    write_header(&mut body, key_bytes).context("failed to write entry header")?;
+
+    let mut stream = BlockWriter::new(body, Codec::Zstd)?;
+
+    for val in values {
+        stream.write(val)?;
+    }
+
+    Ok(stream.finish()?.into_inner())
+}
+
+impl HttpCacheEntry<'_> {
+    fn download(&self) -> Result<Vec<CacheValue<'static>>> {
+        debug!("Downloading cache entry {}...", self.url);
+
+        let resp = match self.agent.get(&self.url).call() {
+            Ok(r) => r,
+            Err(ureq::Error::Status(404, _)) => return Ok(vec![]),
+            Err(e) => return Err(e).context("request failed"),
+        };
+
+        let mut body = vec![];
+        resp.into_reader()
+            .read_to_end(&mut body)
+            .context("failed to read response body")?;
+
+        decode(body, &self.key_bytes)
+    }
+
+    fn values(&mut self) -> &mut Vec<CacheValue<'static>> {
+        if self.values.is_none() {
+            self.values = Some(self.download().unwrap_or_else(|e| {
+                warn!("Failed to download cache entry: {e:?}");
+                self.failed = true;
+
+                vec![]
+            }));
+        }
+
+        self.values.as_mut().unwrap()
+    }
+
+    fn upload(&self, values: &[CacheValue]) -> Result<()> {
+        debug!("Uploading cache entry {}...", self.url);
+
+        let body = encode(&self.key_bytes, values)?;
+
+        self.agent
+            .put(&self.url)
+            .set("Content-Type", "application/octet-stream")
+            .send_bytes(&body)
+            .context("request failed")?;
+
+        Ok(())
+    }
+}
+
+impl CacheEntry for HttpCacheEntry<'_> {
+    fn read_impl(&mut self) -> Values<'_> { Box::new(self.values().iter().cloned()) }
+
+    fn append_impl(&mut self, val: &CacheValue) -> Result<()> {
+        // Download the existing values first so they aren't overwritten
+        self.values().push(val.clone().into_owned());
+        self.dirty = true;
+
+        Ok(())
+    }
+
+    fn truncate(&mut self) -> Result<()> {
+        // The remote values are replaced, so it no longer matters whether
+        // they could be downloaded
+        self.values = Some(vec![]);
+        self.failed = false;
+        self.dirty = true;
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        if self.failed {
+            return Err(anyhow!(
+                "not uploading cache entry {}, since its stored values couldn't be downloaded",
+                self.url
+            ));
+        }
+
+        if let Some(values) = &self.values {
+            self.upload(values).context("failed to upload cache entry")?;
+        }
+
+        self.dirty = false;
+
+        Ok(())
+    }
+}
+
+impl Drop for HttpCacheEntry<'_> {
+    fn drop(&mut self) {
+        if self.dirty {
+            warn!("Discarding unsaved changes to cache entry {}", self.url);
+        }
+    }
+}
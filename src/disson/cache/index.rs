@@ -0,0 +1,141 @@
+//! A summary of every entry in a file cache, kept as JSON in the root of the
+//! cache so entries can be listed without decoding each file.
+
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{self, prelude::*, BufWriter, SeekFrom},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::file::{file_name, lock, read_key, read_version, version};
+use crate::error::prelude::*;
+
+/// The name of the index file in the cache directory
+pub const INDEX_FILE: &str = "index.json";
+
+/// How long to wait for other processes to finish updating the index
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Every indexed entry, by the hex-encoded hash of its key
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index(pub BTreeMap<String, IndexEntry>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// The key summary, as printed by cache ls
+    pub key: String,
+    /// When the entry was first indexed, in seconds since the Unix epoch
+    pub created: u64,
+    /// The size of the cache file in bytes
+    pub size: u64,
+}
+
+fn open(cache_dir: &Path, write: bool) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(write)
+        .create(write)
+        .open(cache_dir.join(INDEX_FILE))
+}
+
+fn parse(file: &mut File) -> Index {
+    let mut buf = vec![];
+
+    if let Err(e) = file.read_to_end(&mut buf) {
+        warn!("Failed to read cache index: {e:?}");
+        return Index::default();
+    }
+
+    if buf.is_empty() {
+        return Index::default();
+    }
+
+    serde_json::from_slice(&buf).unwrap_or_else(|e| {
+        warn!("Cache index is corrupted, rebuilding it: {e:?}");
+        Index::default()
+    })
+}
+
+/// Read the index of the cache in the given directory, or `None` if it has
+/// none
+pub fn read(cache_dir: &Path) -> Result<Option<Index>> {
+    let mut file = match open(cache_dir, false) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context("failed to open cache index"),
+    };
+
+    lock(&file, false, LOCK_TIMEOUT)?;
+
+    Ok(Some(parse(&mut file)))
+}
+
+/// Modify the index of the cache in the given directory, creating it if
+/// necessary
+fn update(cache_dir: &Path, f: impl FnOnce(&mut Index)) -> Result<()> {
+    let mut file = open(cache_dir, true).context("failed to open cache index")?;
+
+    lock(&file, true, LOCK_TIMEOUT)?;
+
+    let mut index = parse(&mut file);
+
+    f(&mut index);
+
+    file.seek(SeekFrom::Start(0))
+        .and_then(|_| file.set_len(0))
+        .context("failed to truncate cache index")?;
+
+    let mut out = BufWriter::new(file);
+
+    serde_json::to_writer_pretty(&mut out, &index).context("failed to write cache index")?;
+    out.flush().context("failed to write cache index")
+}
+
+/// Add or refresh the index entry for the given cache file
+pub(super) fn record(cache_dir: &Path, file: &mut File) -> Result<()> {
+    file.seek(SeekFrom::Start(0))
+        .context("failed to seek to start of cache file")?;
+
+    if read_version(file)? != version() {
+        return Ok(());
+    }
+
+    let (key, key_bytes) = read_key(file)?;
+    let (dir, name) = file_name(Sha256::digest(&key_bytes));
+    let hash = format!("{}{}", dir.to_string_lossy(), name.to_string_lossy());
+    let size = file
+        .metadata()
+        .context("failed to get cache file length")?
+        .len();
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    update(cache_dir, |index| {
+        let entry = index.0.entry(hash).or_insert_with(|| IndexEntry {
+            key: key.to_string(),
+            created: now,
+            size,
+        });
+
+        entry.size = size;
+    })
+}
+
+/// Drop the entry with the given hash from the index
+pub(super) fn remove(cache_dir: &Path, hash: &str) -> Result<()> {
+    if !cache_dir.join(INDEX_FILE).exists() {
+        return Ok(());
+    }
+
+    update(cache_dir, |index| {
+        index.0.remove(hash);
+    })
+}
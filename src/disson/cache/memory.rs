@@ -0,0 +1,141 @@
+//! An in-memory layer in front of another cache
+
+use std::{collections::HashMap, sync::Mutex};
+
+use super::{Cache, CacheEntry, CacheKey, CacheValue, Values};
+use crate::error::prelude::*;
+
+/// Keeps the values of the most recently used entries of another cache in
+/// memory, so that reading them again doesn't require decoding them again
+pub struct MemoryCache<C> {
+    inner: C,
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+pub struct MemoryCacheEntry<'a, E> {
+    inner: E,
+    key: Vec<u8>,
+    capacity: usize,
+    entries: &'a Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    values: HashMap<Vec<u8>, Vec<CacheValue<'static>>>,
+    /// Keys of every stored entry, least recently used first
+    order: Vec<Vec<u8>>,
+}
+
+impl Entries {
+    fn touch(&mut self, key: &[u8]) {
+        if let Some(i) = self.order.iter().position(|k| k == key) {
+            let key = self.order.remove(i);
+            self.order.push(key);
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<CacheValue<'static>>> {
+        let ret = self.values.get(key)?.clone();
+        self.touch(key);
+
+        Some(ret)
+    }
+
+    fn insert(&mut self, key: Vec<u8>, values: Vec<CacheValue<'static>>, capacity: usize) {
+        if self.values.insert(key.clone(), values).is_some() {
+            self.touch(&key);
+        } else {
+            self.order.push(key);
+        }
+
+        while self.order.len() > capacity {
+            let key = self.order.remove(0);
+            self.values.remove(&key);
+        }
+    }
+}
+
+impl<C> MemoryCache<C> {
+    /// Wrap a cache, keeping up to `capacity` entries in memory
+    pub fn new(inner: C, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            entries: Mutex::default(),
+        }
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn inner(&self) -> &C { &self.inner }
+
+    /// Drop every entry kept in memory, e.g. after entries of the inner cache
+    /// were deleted without going through this one
+    #[cfg(feature = "gui")]
+    pub fn forget(&self) { *self.entries.lock().unwrap() = Entries::default(); }
+}
+
+impl<'a, C: Cache<'a>> Cache<'a> for MemoryCache<C> {
+    type Entry = MemoryCacheEntry<'a, C::Entry>;
+
+    fn entry_impl(&'a self, key: CacheKey) -> Result<Self::Entry> {
+        let key_bytes = bincode::serialize(&key).context("failed to serialize cache key")?;
+
+        Ok(MemoryCacheEntry {
+            inner: self.inner.entry_impl(key)?,
+            key: key_bytes,
+            capacity: self.capacity,
+            entries: &self.entries,
+        })
+    }
+
+    fn clean(&self) -> Result<()> {
+        *self.entries.lock().unwrap() = Entries::default();
+
+        self.inner.clean()
+    }
+
+    fn enforce_quota(&self) -> Result<()> { self.inner.enforce_quota() }
+}
+
+impl<E: CacheEntry> CacheEntry for MemoryCacheEntry<'_, E> {
+    fn read_impl(&mut self) -> Values<'_> {
+        if let Some(vals) = self.entries.lock().unwrap().get(&self.key) {
+            return Box::new(vals.into_iter());
+        }
+
+        let vals: Vec<_> = self.inner.read_impl().collect();
+
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(self.key.clone(), vals.clone(), self.capacity);
+
+        Box::new(vals.into_iter())
+    }
+
+    fn append_impl(&mut self, val: &CacheValue) -> Result<()> {
+        self.inner.append_impl(val)?;
+
+        // Entries that were never read are left to be loaded from the inner
+        // cache in full
+        if let Some(vals) = self.entries.lock().unwrap().values.get_mut(&self.key) {
+            vals.push(val.clone().into_owned());
+        }
+
+        Ok(())
+    }
+
+    fn truncate(&mut self) -> Result<()> {
+        self.inner.truncate()?;
+
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(self.key.clone(), vec![], self.capacity);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> { self.inner.flush() }
+}
@@ -0,0 +1,129 @@
+//! Process-wide counters of cache activity
+
+use std::{
+    fmt,
+    ops::Sub,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::Serialize;
+
+use crate::cli::ByteSize;
+
+struct Counters {
+    entries_opened: AtomicU64,
+    blocks_preloaded: AtomicU64,
+    blocks_rendered: AtomicU64,
+    values_read: AtomicU64,
+    bytes_read: AtomicU64,
+    values_written: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+static COUNTERS: Counters = Counters {
+    entries_opened: AtomicU64::new(0),
+    blocks_preloaded: AtomicU64::new(0),
+    blocks_rendered: AtomicU64::new(0),
+    values_read: AtomicU64::new(0),
+    bytes_read: AtomicU64::new(0),
+    values_written: AtomicU64::new(0),
+    bytes_written: AtomicU64::new(0),
+};
+
+/// A snapshot of the cache counters.  Subtract two snapshots to get the
+/// activity between them.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Metrics {
+    pub entries_opened: u64,
+    /// Map blocks loaded from the cache instead of being rendered
+    pub blocks_preloaded: u64,
+    /// Map blocks rendered because they were missing from the cache
+    pub blocks_rendered: u64,
+    pub values_read: u64,
+    /// Uncompressed size of every value read
+    pub bytes_read: u64,
+    pub values_written: u64,
+    /// Uncompressed size of every value written
+    pub bytes_written: u64,
+}
+
+impl Metrics {
+    /// The fraction of map blocks served from the cache, if any were needed
+    #[allow(clippy::cast_precision_loss)]
+    pub fn hit_rate(&self) -> Option<f64> {
+        match self.blocks_preloaded + self.blocks_rendered {
+            0 => None,
+            n => Some(self.blocks_preloaded as f64 / n as f64),
+        }
+    }
+}
+
+impl Sub for Metrics {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self {
+            entries_opened: self.entries_opened - rhs.entries_opened,
+            blocks_preloaded: self.blocks_preloaded - rhs.blocks_preloaded,
+            blocks_rendered: self.blocks_rendered - rhs.blocks_rendered,
+            values_read: self.values_read - rhs.values_read,
+            bytes_read: self.bytes_read - rhs.bytes_read,
+            values_written: self.values_written - rhs.values_written,
+            bytes_written: self.bytes_written - rhs.bytes_written,
+        }
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Cache: {} entries opened, {} blocks preloaded, {} rendered",
+            self.entries_opened, self.blocks_preloaded, self.blocks_rendered
+        )?;
+
+        if let Some(rate) = self.hit_rate() {
+            write!(f, " ({:.1}% hit rate)", rate * 100.0)?;
+        }
+
+        write!(
+            f,
+            "; read {} values ({}), wrote {} values ({})",
+            self.values_read,
+            ByteSize(self.bytes_read),
+            self.values_written,
+            ByteSize(self.bytes_written)
+        )
+    }
+}
+
+/// Read the current value of every counter
+pub fn snapshot() -> Metrics {
+    let c = &COUNTERS;
+
+    Metrics {
+        entries_opened: c.entries_opened.load(Ordering::Relaxed),
+        blocks_preloaded: c.blocks_preloaded.load(Ordering::Relaxed),
+        blocks_rendered: c.blocks_rendered.load(Ordering::Relaxed),
+        values_read: c.values_read.load(Ordering::Relaxed),
+        bytes_read: c.bytes_read.load(Ordering::Relaxed),
+        values_written: c.values_written.load(Ordering::Relaxed),
+        bytes_written: c.bytes_written.load(Ordering::Relaxed),
+    }
+}
+
+pub(super) fn entry_opened() { COUNTERS.entries_opened.fetch_add(1, Ordering::Relaxed); }
+
+pub(super) fn read(values: u64, bytes: u64) {
+    COUNTERS.values_read.fetch_add(values, Ordering::Relaxed);
+    COUNTERS.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+}
+
+pub(super) fn written(bytes: u64) {
+    COUNTERS.values_written.fetch_add(1, Ordering::Relaxed);
+    COUNTERS.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+}
+
+pub fn blocks_preloaded(n: u64) { COUNTERS.blocks_preloaded.fetch_add(n, Ordering::Relaxed); }
+
+pub fn block_rendered() { COUNTERS.blocks_rendered.fetch_add(1, Ordering::Relaxed); }
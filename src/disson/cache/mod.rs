@@ -1,16 +1,35 @@
 use std::{
+    cmp::Reverse,
     convert::{TryFrom, TryInto},
     error::Error as StdError,
+    fmt, iter,
     ops::{Deref, DerefMut},
+    time::{Duration, SystemTime},
 };
 
-use file::{FileCache, FileCacheEntry};
+pub use access::{Access, AccessCache};
+use file::{EntryInfo, FileCache, FileCacheEntry, Health};
+use http::{HttpCache, HttpCacheEntry};
+pub use memory::MemoryCache;
+use bincode::Options;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{cli::CacheMode, disson::map, error::prelude::*};
+use crate::{
+    cli::{Age, ByteSize, CacheCmd, CacheMode, CacheOpts, CleanOpts, ShowOpts, VerifyOpts},
+    disson::{self, map},
+    error::prelude::*,
+};
 
+pub mod access;
+mod archive;
+mod block;
 pub mod file;
+pub mod http;
+pub mod index;
+pub mod memory;
+pub mod metrics;
 
 pub mod prelude {
     pub use super::{Cache, CacheEntry, CacheEntryExt, CacheExt};
@@ -97,7 +116,7 @@ macro_rules! cache_enum {
     };
 
     (@process_body Value <$lt:lifetime> {} { $($body:tt)* } { $($impls:item)* }) => {
-        #[derive(Debug, Serialize, Deserialize)]
+        #[derive(Debug, Clone, Serialize, Deserialize)]
         pub enum CacheValue<$lt> { $($body)* }
 
         $($impls)*
@@ -197,10 +216,41 @@ macro_rules! cache_enum {
 cache_enum! {
     enum Key<'a> {
         Map(map::CacheKey),
+        MapLayouts(map::LayoutsKey),
     }
 
     enum Value<'a> {
         Map(map::CacheValue<'a>),
+        MapSingle(map::CacheValue<'a, f32>),
+        MapLayout(map::Layout),
+    }
+}
+
+impl CacheValue<'_> {
+    /// Copy any borrowed data out of this value
+    pub fn into_owned(self) -> CacheValue<'static> {
+        match self {
+            Self::Map(v) => CacheValue::Map(v.into_owned()),
+            Self::MapSingle(v) => CacheValue::MapSingle(v.into_owned()),
+            Self::MapLayout(l) => CacheValue::MapLayout(l),
+        }
+    }
+}
+
+/// A cache key decoded from the header of a cache file.  Variants must be
+/// declared in the same order as those of [`CacheKey`].
+#[derive(Debug, Serialize, Deserialize)]
+pub enum KeyInfo {
+    Map(map::KeyInfo),
+    MapLayouts(map::LayoutsKeyInfo),
+}
+
+impl fmt::Display for KeyInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Map(k) => write!(f, "map: {k}"),
+            Self::MapLayouts(k) => write!(f, "map layouts: {k}"),
+        }
     }
 }
 
@@ -210,6 +260,10 @@ pub trait Cache<'a>: Send + Sync {
     fn entry_impl(&'a self, key: CacheKey) -> Result<Self::Entry>;
 
     fn clean(&self) -> Result<()>;
+
+    /// Evict least-recently-used entries until the cache fits within its
+    /// configured size quota
+    fn enforce_quota(&self) -> Result<()>;
 }
 
 impl<'a, T: Cache<'a> + ?Sized + 'a, U: Deref<Target = T> + Send + Sync> Cache<'a> for U {
@@ -220,29 +274,42 @@ impl<'a, T: Cache<'a> + ?Sized + 'a, U: Deref<Target = T> + Send + Sync> Cache<'
     }
 
     fn clean(&self) -> Result<()> { (<Self as Deref>::deref(self) as &T).clean() }
+
+    fn enforce_quota(&self) -> Result<()> {
+        (<Self as Deref>::deref(self) as &T).enforce_quota()
+    }
 }
 
+/// The values of a cache entry, decoded as they are iterated
+pub type Values<'a> = Box<dyn Iterator<Item = CacheValue<'static>> + 'a>;
+
 pub trait CacheEntry: Send {
-    fn read_impl(&mut self) -> Vec<CacheValue<'static>>;
+    fn read_impl(&mut self) -> Values<'_>;
 
     fn append_impl(&mut self, val: &CacheValue) -> Result<()>;
 
     /// Drop all values from this cache entry
     fn truncate(&mut self) -> Result<()>;
+
+    /// Store any appended values this entry has held back.  Entries that
+    /// write values as they are appended have nothing to do.
+    fn flush(&mut self) -> Result<()> { Ok(()) }
 }
 
-impl<T: CacheEntry + ?Sized, U: Deref<Target = T> + DerefMut + Send> CacheEntry for U {
-    fn read_impl(&mut self) -> Vec<CacheValue<'static>> {
-        (<Self as DerefMut>::deref_mut(self) as &mut T).read_impl()
-    }
+// The target is named as a projection rather than a type parameter so that it
+// is known to outlive any borrow of U, which read_impl relies on
+impl<U: DerefMut + Send> CacheEntry for U
+where U::Target: CacheEntry
+{
+    fn read_impl(&mut self) -> Values<'_> { <Self as DerefMut>::deref_mut(self).read_impl() }
 
     fn append_impl(&mut self, val: &CacheValue) -> Result<()> {
-        (<Self as DerefMut>::deref_mut(self) as &mut T).append_impl(val)
+        <Self as DerefMut>::deref_mut(self).append_impl(val)
     }
 
-    fn truncate(&mut self) -> Result<()> {
-        (<Self as DerefMut>::deref_mut(self) as &mut T).truncate()
-    }
+    fn truncate(&mut self) -> Result<()> { <Self as DerefMut>::deref_mut(self).truncate() }
+
+    fn flush(&mut self) -> Result<()> { <Self as DerefMut>::deref_mut(self).flush() }
 }
 
 pub trait CacheExt<'a>: Cache<'a> {
@@ -251,7 +318,10 @@ pub trait CacheExt<'a>: Cache<'a> {
 
 impl<'a, T: Cache<'a> + ?Sized> CacheExt<'a> for T {
     fn entry<K: 'a + Into<CacheKey>>(&'a self, key: K) -> Result<Self::Entry> {
-        self.entry_impl(key.into())
+        let entry = self.entry_impl(key.into())?;
+        metrics::entry_opened();
+
+        Ok(entry)
     }
 }
 
@@ -260,6 +330,14 @@ pub trait CacheEntryExt<'a>: CacheEntry {
         &'a mut self,
     ) -> Result<Vec<V>>;
 
+    /// Decode and unpack values one at a time, rather than all at once
+    fn read_iter<
+        V: TryFrom<CacheValue<'static>, Error = E> + 'a,
+        E: 'static + StdError + Send + Sync,
+    >(
+        &'a mut self,
+    ) -> Box<dyn Iterator<Item = Result<V>> + 'a>;
+
     fn append<'v, V: Into<CacheValue<'v>>>(&'a mut self, val: V) -> Result<()>;
 }
 
@@ -267,17 +345,41 @@ impl<'a, T: CacheEntry + ?Sized + 'a> CacheEntryExt<'a> for T {
     fn read<'v, V: TryFrom<CacheValue<'v>, Error = E>, E: 'static + StdError + Send + Sync>(
         &'a mut self,
     ) -> Result<Vec<V>> {
-        self.read_impl()
-            .into_iter()
-            .map(|v| v.try_into().context("failed to unpack cache value"))
-            .collect()
+        self.read_impl().map(unpack).collect()
+    }
+
+    fn read_iter<
+        V: TryFrom<CacheValue<'static>, Error = E> + 'a,
+        E: 'static + StdError + Send + Sync,
+    >(
+        &'a mut self,
+    ) -> Box<dyn Iterator<Item = Result<V>> + 'a> {
+        Box::new(self.read_impl().map(unpack::<V, E>))
     }
 
     fn append<'v, V: Into<CacheValue<'v>>>(&'a mut self, val: V) -> Result<()> {
-        self.append_impl(&val.into())
+        let val = val.into();
+        self.append_impl(&val)?;
+        metrics::written(value_size(&val));
+
+        Ok(())
     }
 }
 
+/// Convert a value read from an entry into the type stored in it
+fn unpack<'v, V: TryFrom<CacheValue<'v>, Error = E>, E: 'static + StdError + Send + Sync>(
+    val: CacheValue<'static>,
+) -> Result<V> {
+    metrics::read(1, value_size(&val));
+
+    val.try_into().context("failed to unpack cache value")
+}
+
+/// The uncompressed size of a value as stored in a cache entry
+fn value_size(val: &CacheValue) -> u64 {
+    file::val_bin_opts().serialized_size(val).unwrap_or(0)
+}
+
 pub struct NullCache;
 
 impl<'a> Cache<'a> for NullCache {
@@ -286,10 +388,12 @@ impl<'a> Cache<'a> for NullCache {
     fn entry_impl(&'a self, _: CacheKey) -> Result<Self::Entry> { Ok(Self) }
 
     fn clean(&self) -> Result<()> { Ok(()) }
+
+    fn enforce_quota(&self) -> Result<()> { Ok(()) }
 }
 
 impl CacheEntry for NullCache {
-    fn read_impl(&mut self) -> Vec<CacheValue<'static>> { vec![] }
+    fn read_impl(&mut self) -> Values<'_> { Box::new(iter::empty()) }
 
     fn append_impl(&mut self, _: &CacheValue) -> Result<()> { Ok(()) }
 
@@ -298,11 +402,13 @@ impl CacheEntry for NullCache {
 
 pub enum DynamicCache {
     File(FileCache),
+    Http(HttpCache),
     Null(NullCache),
 }
 
 pub enum DynamicCacheEntry<'a> {
     File(FileCacheEntry<'a>),
+    Http(HttpCacheEntry<'a>),
     Null(NullCache),
 }
 
@@ -312,6 +418,7 @@ impl<'a> Cache<'a> for DynamicCache {
     fn entry_impl(&'a self, key: CacheKey) -> Result<Self::Entry> {
         Ok(match self {
             Self::File(f) => Self::Entry::File(f.entry(key)?),
+            Self::Http(h) => Self::Entry::Http(h.entry(key)?),
             Self::Null(n) => Self::Entry::Null(n.entry(key)?),
         })
     }
@@ -319,15 +426,25 @@ impl<'a> Cache<'a> for DynamicCache {
     fn clean(&self) -> Result<()> {
         match self {
             Self::File(f) => f.clean(),
+            Self::Http(h) => h.clean(),
             Self::Null(n) => n.clean(),
         }
     }
+
+    fn enforce_quota(&self) -> Result<()> {
+        match self {
+            Self::File(f) => f.enforce_quota(),
+            Self::Http(h) => h.enforce_quota(),
+            Self::Null(n) => n.enforce_quota(),
+        }
+    }
 }
 
 impl<'a> CacheEntry for DynamicCacheEntry<'a> {
-    fn read_impl(&mut self) -> Vec<CacheValue<'static>> {
+    fn read_impl(&mut self) -> Values<'_> {
         match self {
             Self::File(f) => f.read_impl(),
+            Self::Http(h) => h.read_impl(),
             Self::Null(n) => n.read_impl(),
         }
     }
@@ -335,6 +452,7 @@ impl<'a> CacheEntry for DynamicCacheEntry<'a> {
     fn append_impl(&mut self, val: &CacheValue) -> Result<()> {
         match self {
             Self::File(f) => f.append_impl(val),
+            Self::Http(h) => h.append_impl(val),
             Self::Null(n) => n.append_impl(val),
         }
     }
@@ -342,16 +460,447 @@ impl<'a> CacheEntry for DynamicCacheEntry<'a> {
     fn truncate(&mut self) -> Result<()> {
         match self {
             Self::File(f) => f.truncate(),
+            Self::Http(h) => h.truncate(),
             Self::Null(n) => n.truncate(),
         }
     }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Self::File(f) => f.flush(),
+            Self::Http(h) => h.flush(),
+            Self::Null(n) => n.flush(),
+        }
+    }
 }
 
-pub fn from_opts(mode: CacheMode) -> DynamicCache {
-    match mode {
+pub fn from_opts(opts: CacheOpts) -> AccessCache<DynamicCache> {
+    let CacheOpts {
+        mode,
+        cache_quota,
+        cache_lock_timeout,
+        cache_codec,
+        memory_entries: _,
+        refresh,
+        cache_readonly,
+    } = opts;
+
+    let access = if cache_readonly {
+        Access::ReadOnly
+    } else if refresh {
+        Access::Refresh
+    } else {
+        Access::ReadWrite
+    };
+
+    let cache = match mode {
         CacheMode::Off => DynamicCache::Null(NullCache),
-        CacheMode::File(d) => DynamicCache::File(FileCache(d)),
+        CacheMode::Http(url) => DynamicCache::Http(HttpCache::new(url)),
+        CacheMode::File(dir) => DynamicCache::File(FileCache::new(
+            dir,
+            cache_quota.map(|q| q.0),
+            Duration::from_secs(cache_lock_timeout),
+            cache_codec,
+            access == Access::ReadOnly,
+        )),
+    };
+
+    AccessCache::new(cache, access)
+}
+
+/// Find the entries matching every filter given to clean
+fn select(cache: &FileCache, opts: &CleanOpts) -> Result<Vec<EntryInfo>> {
+    let now = SystemTime::now();
+    let mut ret = vec![];
+
+    for entry in cache.entries()? {
+        if let Some(Age(age)) = opts.older_than {
+            if now.duration_since(entry.accessed).unwrap_or_default() < age {
+                continue;
+            }
+        }
+
+        if opts.not_current_version
+            && entry.version().ok() == Some(file::version())
+        {
+            continue;
+        }
+
+        if let Some(ref re) = opts.matching {
+            // Keys written by other versions can't be decoded, so never match
+            match entry.read_key() {
+                Ok(k) if re.is_match(&k.to_string()) => (),
+                _ => continue,
+            }
+        }
+
+        ret.push(entry);
+    }
+
+    Ok(ret)
+}
+
+/// Find the least-recently-used entries that would be evicted to bring the
+/// cache within its quota, assuming none of them are in use
+fn over_quota(cache: &FileCache) -> Result<Vec<EntryInfo>> {
+    let quota = cache.quota.ok_or_else(|| {
+        anyhow!("no cache quota configured, please specify one with --cache-quota")
+    })?;
+    let mut entries = cache.entries()?;
+    let mut total: u64 = entries.iter().map(|e| e.len).sum();
+
+    entries.sort_by_key(|e| e.accessed);
+
+    Ok(entries
+        .into_iter()
+        .take_while(|e| {
+            let over = total > quota;
+            total -= e.len;
+            over
+        })
+        .collect())
+}
+
+fn clean_dry_run(cache: &FileCache, opts: &CleanOpts) -> Result<()> {
+    let (files, dirs) = if opts.enforce_quota {
+        (over_quota(cache)?, vec![])
+    } else if opts.is_selective() {
+        (select(cache, opts)?, vec![])
+    } else {
+        (cache.entries()?, cache.clean_dirs()?)
+    };
+
+    for entry in &files {
+        println!(
+            "{:>10}  {}",
+            ByteSize(entry.len).to_string(),
+            entry.path.to_string_lossy()
+        );
+    }
+
+    for dir in &dirs {
+        println!("{:>10}  {}", "dir", dir.to_string_lossy());
     }
+
+    info!(
+        "Would remove {} cache file(s) and {} dir(s), freeing {}.",
+        files.len(),
+        dirs.len(),
+        ByteSize(files.iter().map(|e| e.len).sum())
+    );
+
+    Ok(())
+}
+
+/// Remove only the entries selected by the filters in `opts`
+fn clean_matching(cache: &FileCache, opts: &CleanOpts) -> Result<()> {
+    let mut removed = 0_usize;
+    let mut freed = 0;
+
+    for entry in select(cache, opts)? {
+        info!("Removing file {}...", entry.path.to_string_lossy());
+
+        entry.delete()?;
+        removed += 1;
+        freed += entry.len;
+    }
+
+    info!("Removed {} cache file(s), freeing {}.", removed, ByteSize(freed));
+
+    Ok(())
+}
+
+pub fn clean(cache_opts: CacheOpts, opts: &CleanOpts) -> Result<()> {
+    let cache = from_opts(cache_opts);
+
+    if opts.dry_run {
+        return match cache.inner {
+            DynamicCache::File(f) => clean_dry_run(&f, opts),
+            DynamicCache::Http(_) => Err(anyhow!("remote caches cannot be cleaned")),
+            DynamicCache::Null(_) => Ok(()),
+        };
+    }
+
+    cache.check_writable()?;
+
+    if opts.enforce_quota {
+        cache.enforce_quota()
+    } else if opts.is_selective() {
+        match cache.inner {
+            DynamicCache::File(f) => clean_matching(&f, opts),
+            DynamicCache::Http(_) => Err(anyhow!("remote caches cannot be cleaned")),
+            DynamicCache::Null(_) => Ok(()),
+        }
+    } else {
+        cache.clean()
+    }
+}
+
+/// Describe how long before `now` something happened, e.g. `5m`
+pub fn ago(now: SystemTime, then: SystemTime) -> String {
+    let secs = now.duration_since(then).unwrap_or_default().as_secs();
+
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// An entry of a local cache, along with a description of its key
+#[derive(Debug)]
+pub struct Listing {
+    pub entry: EntryInfo,
+    pub key: String,
+}
+
+/// List every entry of a local cache, most recently used first.  Keys are
+/// described from the index where possible, so most entries aren't opened.
+pub fn list(cache: &FileCache) -> Result<Vec<Listing>> {
+    let mut entries = cache.entries()?;
+    entries.sort_by_key(|e| Reverse(e.accessed));
+
+    let index = index::read(&cache.locate_cache()?)?.unwrap_or_default();
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            let key = match index.0.get(&entry.hash) {
+                Some(i) => i.key.clone(),
+                None => entry
+                    .read_key()
+                    .map_or_else(|e| format!("<{e}>"), |k| k.to_string()),
+            };
+
+            Listing { entry, key }
+        })
+        .collect())
+}
+
+/// Find the entry of a local cache whose hash starts with the given prefix
+pub fn find(cache: &FileCache, hash: &str) -> Result<EntryInfo> {
+    let hash = hash.to_lowercase();
+    let mut matches: Vec<_> = cache
+        .entries()?
+        .into_iter()
+        .filter(|e| e.hash.starts_with(&hash))
+        .collect();
+
+    match matches.len() {
+        0 => Err(anyhow!("no cache entry matches {:?}", hash)),
+        1 => Ok(matches.remove(0)),
+        n => Err(anyhow!("{:?} is ambiguous, matching {} entries", hash, n)),
+    }
+}
+
+fn ls(cache: &FileCache) -> Result<()> {
+    let listings = list(cache)?;
+    let now = SystemTime::now();
+
+    println!("{:<16}  {:>10}  {:>5}  KEY", "HASH", "SIZE", "USED");
+
+    for Listing { entry, key } in &listings {
+        println!(
+            "{:.16}  {:>10}  {:>5}  {}",
+            entry.hash,
+            ByteSize(entry.len).to_string(),
+            ago(now, entry.accessed),
+            key
+        );
+    }
+
+    info!(
+        "{} entries, {} total",
+        listings.len(),
+        ByteSize(listings.iter().map(|l| l.entry.len).sum())
+    );
+
+    Ok(())
+}
+
+fn show(cache: &FileCache, hash: &str) -> Result<()> {
+    let entry = find(cache, hash)?;
+    let key = entry.read_key().context("failed to read cache key")?;
+
+    println!("Hash: {}", entry.hash);
+    println!("Path: {}", entry.path.to_string_lossy());
+    println!("Size: {}", ByteSize(entry.len));
+    println!("Used: {} ago", ago(SystemTime::now(), entry.accessed));
+    println!("Key:  {key}");
+    println!("{key:#?}");
+
+    Ok(())
+}
+
+fn verify(cache: &FileCache, opts: &VerifyOpts) -> Result<()> {
+    let entries = cache.entries()?;
+    let mut good = 0_usize;
+    let mut stale = 0_usize;
+    let mut fixed = 0_usize;
+    let mut bad = 0_usize;
+
+    for entry in &entries {
+        let health = match entry.check(opts.repair) {
+            Ok(h) => h,
+            Err(e) => {
+                warn!("Couldn't check cache entry {}: {:?}", entry.hash, e);
+                continue;
+            },
+        };
+
+        let problem = match health {
+            Health::Good => {
+                good += 1;
+                continue;
+            },
+            Health::Stale(v) => {
+                stale += 1;
+                println!("{:.16}  stale, written by version {}", entry.hash, v);
+                continue;
+            },
+            Health::Repaired { values, .. } => {
+                fixed += 1;
+                println!("{:.16}  repaired, keeping {} value(s)", entry.hash, values);
+                continue;
+            },
+            Health::BadHeader(e) => format!("bad header: {e:#}"),
+            Health::Corrupt { values, offset } => {
+                format!("corrupt at byte {offset}, after {values} readable value(s)")
+            },
+        };
+
+        if opts.delete {
+            entry.delete()?;
+            fixed += 1;
+            println!("{:.16}  {}; deleted", entry.hash, problem);
+        } else {
+            bad += 1;
+            println!("{:.16}  {}", entry.hash, problem);
+        }
+    }
+
+    info!(
+        "Checked {} entries: {} good, {} stale, {} fixed, {} corrupted",
+        entries.len(),
+        good,
+        stale,
+        fixed,
+        bad
+    );
+
+    if bad > 0 {
+        return Err(anyhow!(
+            "found {} corrupted cache entries, rerun with --repair or --delete to fix them",
+            bad
+        ));
+    }
+
+    Ok(())
 }
 
-pub fn clean(cache_mode: CacheMode) -> Result<()> { from_opts(cache_mode).clean() }
+fn repair(cache: &FileCache) -> Result<()> {
+    let entries = cache.entries()?;
+    let mut repaired = 0_usize;
+    let mut salvaged_total = 0_usize;
+    let mut dropped_total = 0_u64;
+    let mut unreadable = 0_usize;
+
+    for entry in &entries {
+        let health = match entry.check(true) {
+            Ok(h) => h,
+            Err(e) => {
+                warn!("Couldn't repair cache entry {}: {:?}", entry.hash, e);
+                continue;
+            },
+        };
+
+        match health {
+            Health::Good | Health::Stale(_) => (),
+            Health::Repaired {
+                values,
+                salvaged,
+                dropped,
+            } => {
+                repaired += 1;
+                salvaged_total += salvaged;
+                dropped_total += dropped;
+                println!(
+                    "{:.16}  salvaged {} value(s) from a corrupted block, keeping {}; dropped {}",
+                    entry.hash,
+                    salvaged,
+                    values,
+                    ByteSize(dropped)
+                );
+            },
+            Health::BadHeader(e) => {
+                unreadable += 1;
+                println!("{:.16}  bad header, can't be repaired: {:#}", entry.hash, e);
+            },
+            Health::Corrupt { .. } => unreachable!("corrupted entry was not repaired"),
+        }
+    }
+
+    info!(
+        "Repaired {} of {} entries, salvaging {} value(s) and dropping {}",
+        repaired,
+        entries.len(),
+        salvaged_total,
+        ByteSize(dropped_total)
+    );
+
+    if unreadable > 0 {
+        warn!(
+            "{unreadable} entries have unreadable headers; run cache verify --delete to remove them"
+        );
+    }
+
+    Ok(())
+}
+
+/// Unwrap a cache that can be inspected on disk
+fn local(cache: AccessCache<DynamicCache>) -> Result<FileCache> {
+    match cache.inner {
+        DynamicCache::File(f) => Ok(f),
+        c => Err(not_local(&c)),
+    }
+}
+
+/// Borrow a cache that can be inspected on disk
+#[cfg(feature = "gui")]
+pub fn local_ref(cache: &DynamicCache) -> Result<&FileCache> {
+    match cache {
+        DynamicCache::File(f) => Ok(f),
+        c => Err(not_local(c)),
+    }
+}
+
+/// Explain why a cache that isn't on disk can't be inspected
+fn not_local(cache: &DynamicCache) -> Error {
+    match cache {
+        DynamicCache::Http(_) => anyhow!("only local caches can be inspected"),
+        _ => anyhow!("caching is disabled, nothing to inspect"),
+    }
+}
+
+pub fn run(cache_opts: CacheOpts, cmd: CacheCmd) -> Result<()> {
+    let cache = from_opts(cache_opts);
+
+    match cmd {
+        CacheCmd::Import(_) | CacheCmd::Repair | CacheCmd::Warm(_) => cache.check_writable()?,
+        CacheCmd::Verify(VerifyOpts { repair, delete }) if repair || delete => {
+            cache.check_writable()?;
+        },
+        _ => (),
+    }
+
+    match cmd {
+        CacheCmd::Export(e) => archive::export(&local(cache)?, &e),
+        CacheCmd::Import(i) => archive::import(&local(cache)?, &i),
+        CacheCmd::Ls => ls(&local(cache)?),
+        CacheCmd::Repair => repair(&local(cache)?),
+        CacheCmd::Show(ShowOpts { hash }) => show(&local(cache)?, &hash),
+        CacheCmd::Verify(v) => verify(&local(cache)?, &v),
+        CacheCmd::Warm(w) => disson::warm(cache, w),
+    }
+}
@@ -1,4 +1,10 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
 
 use thiserror::Error;
 
@@ -29,16 +35,117 @@ impl From<CancelError> for Result<(), crate::error::Error> {
 
 pub type CancelResult<T> = Result<T, CancelError>;
 
-pub struct CancelToken(AtomicBool);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Record that the user asked to stop the program
+pub fn interrupt() { INTERRUPTED.store(true, Ordering::SeqCst); }
+
+/// Whether the user asked to stop the program
+pub fn interrupted() -> bool { INTERRUPTED.load(Ordering::SeqCst) }
+
+type Hook = Box<dyn Fn() + Send + Sync>;
+
+/// Functions run when a token stops work, indexed by their [`StopHook`]
+#[derive(Default)]
+struct Hooks(Vec<Option<Hook>>);
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Hooks")
+            .field(&self.0.iter().filter(|h| h.is_some()).count())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct CancelToken {
+    cancelled: AtomicBool,
+    /// Set when a time limit runs out.  Work stops as if cancelled, but
+    /// whatever finished in time may still be saved.
+    expired: AtomicBool,
+    /// Set once the partial results of an expired operation are being saved,
+    /// after which only a real cancellation stops work
+    saving: AtomicBool,
+    hooks: Mutex<Hooks>,
+}
+
+/// Registration of a function to run when a [`CancelToken`] stops work,
+/// removed when dropped
+#[derive(Debug)]
+#[must_use = "the hook is removed when this is dropped"]
+pub struct StopHook<'a> {
+    token: &'a CancelToken,
+    index: usize,
+}
+
+impl Drop for StopHook<'_> {
+    fn drop(&mut self) { self.token.hooks.lock().unwrap().0[self.index] = None; }
+}
 
 impl CancelToken {
-    pub fn new() -> Self { Self(AtomicBool::new(false)) }
+    pub fn new() -> Self {
+        Self {
+            cancelled: AtomicBool::new(false),
+            expired: AtomicBool::new(false),
+            saving: AtomicBool::new(false),
+            hooks: Mutex::default(),
+        }
+    }
+
+    pub fn set(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.run_hooks();
+    }
+
+    /// Stop work because its time limit ran out
+    pub fn expire(&self) {
+        self.expired.store(true, Ordering::SeqCst);
+        self.run_hooks();
+    }
+
+    fn run_hooks(&self) {
+        for hook in self.hooks.lock().unwrap().0.iter().flatten() {
+            hook();
+        }
+    }
+
+    /// Run `f` when this token is set or expires, or right away if it already
+    /// was, to stop work that can't poll the token itself
+    #[cfg_attr(not(feature = "plugin"), allow(dead_code))]
+    pub fn on_stop(&self, f: impl Fn() + Send + Sync + 'static) -> StopHook<'_> {
+        let mut hooks = self.hooks.lock().unwrap();
+        let index = hooks.0.iter().position(Option::is_none).unwrap_or_else(|| {
+            hooks.0.push(None);
+            hooks.0.len() - 1
+        });
+
+        // Checked while holding the lock, so that set and expire either see
+        // the new hook or have already stored their flag
+        if self.try_strong().is_err() {
+            f();
+        }
+
+        hooks.0[index] = Some(Box::new(f));
+
+        StopHook { token: self, index }
+    }
 
-    pub fn set(&self) { self.0.store(true, Ordering::SeqCst); }
+    /// Whether work was stopped by its time limit rather than cancelled
+    pub fn is_expired(&self) -> bool {
+        self.expired.load(Ordering::SeqCst) && !self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// If the time limit ran out, allow the results finished before it to be
+    /// written out
+    pub fn save_expired(&self) {
+        if self.expired.load(Ordering::SeqCst) {
+            self.saving.store(true, Ordering::SeqCst);
+        }
+    }
 
     #[inline]
     fn try_impl(&self, ord: Ordering) -> CancelResult<()> {
-        if self.0.load(ord) {
+        if self.cancelled.load(ord) || (self.expired.load(ord) && !self.saving.load(ord)) {
             Err(CancelError::Cancelled)
         } else {
             Ok(())
@@ -1,13 +1,21 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{fmt, path::PathBuf, str::FromStr, sync::LazyLock, time::Duration};
 
-use lazy_static::lazy_static;
 use regex::{Regex, RegexBuilder};
 use structopt::StructOpt;
 use thiserror::Error;
 
-use crate::error::prelude::*;
+use crate::{
+    disson::{map::Axis, real::Precision},
+    error::prelude::*,
+    message,
+};
 
 #[derive(Debug, StructOpt)]
+#[structopt(after_help = "EXIT CODES:
+    0      The command finished successfully
+    1      The command failed
+    2      The command line couldn't be parsed
+    130    The command was stopped with ^C")]
 pub struct Opts {
     #[structopt(flatten)]
     pub opts: GlobalOpts,
@@ -17,9 +25,8 @@ pub struct Opts {
 
 #[derive(Debug, StructOpt)]
 pub struct GlobalOpts {
-    /// The cache directory to use, or "-" to disable caching
-    #[structopt(name = "cache-dir", short, long, default_value = "")]
-    pub cache_mode: CacheMode,
+    #[structopt(flatten)]
+    pub cache: CacheOpts,
 
     /// Only print warnings and errors to the console (enabled by default if no
     /// console is attached)
@@ -39,44 +46,474 @@ pub struct GlobalOpts {
         conflicts_with("no_quiet")
     )]
     pub verbose: usize,
+
+    /// How to print messages: human for readable logs and progress bars, or
+    /// json for one JSON record per line on stderr
+    #[structopt(long, default_value = "human")]
+    pub message_format: MessageFormat,
 }
 
 #[derive(Debug, StructOpt)]
 pub enum Subcommand {
+    /// Inspect the contents of the cache
+    Cache(CacheCmd),
     /// Empty the cache folder
-    Clean,
+    Clean(CleanOpts),
+    /// Print the pitch and overlap curves selected by the given config, along
+    /// with a sample of the dissonance they produce, without rendering a map
+    Explain(ExplainOpts),
     /// Generate a dissonance map from the given config
     Generate(GenerateOpts),
-    /// Open the GUI to interactively configure and generate maps
-    Gui,
+    /// Open the GUI to interactively configure and generate maps.  Only
+    /// available if disson was built with the gui feature.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    Gui(GuiOpts),
     /// Print the default configuration file to the console
-    PrintDefaults,
+    PrintDefaults(PrintDefaultsOpts),
+    /// Synthesize the tones at one point of a map into a WAV file, to hear
+    /// what the map describes
+    #[structopt(alias = "play")]
+    RenderAudio(RenderAudioOpts),
+    /// Synthesize one tone of a map gliding across an axis against the held
+    /// tones into a WAV file, to hear a slice of the map
+    RenderSweep(RenderSweepOpts),
+    /// Print statistics about the dissonance map for the given config
+    Stats(StatsOpts),
+    /// Rewrite a config written for an older schema version to the current
+    /// one, filling in the settings that keep its old behavior
+    UpgradeConfig(UpgradeConfigOpts),
     /// Generate a dissonance map from the given config, and watch it for
     /// changes
     Watch(GenerateOpts),
 }
 
 #[derive(Debug, StructOpt)]
-pub struct GenerateOpts {
+pub struct CacheOpts {
+    /// The cache directory to use, the http:// or https:// URL of a shared
+    /// remote cache, or "-" to disable caching
+    #[structopt(name = "cache-dir", short, long, default_value = "")]
+    pub mode: CacheMode,
+
+    /// The maximum total size of the cache directory, e.g. 512M or 4G
+    ///
+    /// When set, least-recently-used entries are evicted in the background
+    /// whenever a write leaves the cache over this size.
+    #[structopt(long)]
+    pub cache_quota: Option<ByteSize>,
+
+    /// How long to wait, in seconds, for another process to release a cache
+    /// entry before giving up on it
+    #[structopt(long, default_value = "10")]
+    pub cache_lock_timeout: u64,
+
+    /// How to compress cache blocks: auto, none, lz4, or zstd.  auto leaves
+    /// small blocks uncompressed and compresses the rest with zstd.
+    #[structopt(long, default_value = "auto")]
+    pub cache_codec: CacheCodec,
+
+    /// The number of cache entries to keep decoded in memory between passes
+    /// of watch
+    #[structopt(long, default_value = "4")]
+    pub memory_entries: usize,
+
+    /// Ignore any cached values, but still cache freshly computed ones in
+    /// their place
+    #[structopt(long)]
+    pub refresh: bool,
+
+    /// Read cached values without ever writing to the cache, e.g. to share a
+    /// cache directory mounted read-only
+    #[structopt(long, conflicts_with("refresh"))]
+    pub cache_readonly: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum CacheCmd {
+    /// Bundle cache entries into a .tar.zst archive
+    Export(ExportOpts),
+    /// Add the entries from a .tar.zst archive to the cache
+    Import(ImportOpts),
+    /// List every entry in the cache, most recently used first
+    Ls,
+    /// Rewrite corrupted entries, keeping every value that can still be read
+    Repair,
+    /// Print the full key of a single cache entry
+    Show(ShowOpts),
+    /// Fully decode every entry in the cache, reporting any that are corrupted
+    Verify(VerifyOpts),
+    /// Precompute every map in a sweep of configs into the cache
+    Warm(WarmOpts),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExportOpts {
+    /// The archive to write
+    #[structopt(parse(from_os_str))]
+    pub out: PathBuf,
+
+    /// Only export entries whose hashes start with one of the given prefixes
+    pub hashes: Vec<String>,
+
+    /// Only export entries whose key summary, as printed by cache ls, matches
+    /// the given regular expression
+    #[structopt(long)]
+    pub matching: Option<Regex>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ImportOpts {
+    /// The archive to read
+    #[structopt(parse(from_os_str))]
+    pub input: PathBuf,
+
+    /// Replace entries that already exist in the cache
+    #[structopt(long)]
+    pub overwrite: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ShowOpts {
+    /// The hash of the entry to show, or any unique prefix of it
+    pub hash: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct VerifyOpts {
+    /// Truncate corrupted entries to the values that can still be read
+    #[structopt(long, conflicts_with("delete"))]
+    pub repair: bool,
+
+    /// Delete corrupted entries
+    #[structopt(long)]
+    pub delete: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct WarmOpts {
+    /// The sweep file to read, listing a config and the values to sweep over
+    /// it: (config: "<file>", preset: <optional name>, sizes: [(<w>, <h>),
+    /// ...], base_frequencies: [...], pitch_curves: [...], overlap_curves:
+    /// [...])
+    #[allow(clippy::doc_markdown)]
+    #[structopt(parse(from_os_str))]
+    pub sweep: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CleanOpts {
+    /// Rather than emptying the cache, only evict least-recently-used entries
+    /// until it fits within --cache-quota
+    #[structopt(
+        long,
+        conflicts_with_all(&["older-than", "not-current-version", "matching"])
+    )]
+    pub enforce_quota: bool,
+
+    /// Only remove entries last used longer ago than the given age, e.g. 30d,
+    /// 12h, or 2w
+    #[structopt(long)]
+    pub older_than: Option<Age>,
+
+    /// Only remove entries written by a different version of the program
+    #[structopt(long)]
+    pub not_current_version: bool,
+
+    /// Only remove entries whose key summary, as printed by cache ls, matches
+    /// the given regular expression
+    #[structopt(long)]
+    pub matching: Option<Regex>,
+
+    /// Print what would be removed and how much space it would free, without
+    /// deleting anything
+    #[structopt(long)]
+    pub dry_run: bool,
+}
+
+impl CleanOpts {
+    /// Whether any filters were given to select which entries to remove
+    pub fn is_selective(&self) -> bool {
+        self.older_than.is_some() || self.not_current_version || self.matching.is_some()
+    }
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct ConfigOpts {
     /// The configuration file to read options from
     #[structopt(parse(from_os_str))]
     pub config: PathBuf,
 
+    /// The name of a preset from the config file to apply over the base
+    /// settings
+    #[structopt(short, long)]
+    pub preset: Option<String>,
+
     /// Override the output size
     ///
     /// Valid formats are <n>w and <n>h, which set width or height to n while
     /// keeping the configured aspect ratio; <x>%, which scales the configured
-    /// output dimensions by x%; or <w>x<h>, which sets the dimensions to
-    /// exactly w by h.
+    /// output dimensions by x%; <w>x<h>, which sets the dimensions to exactly
+    /// w by h; <x>MP, which scales the output to about x megapixels;
+    /// max:<n>, which shrinks the output so neither dimension exceeds n; or
+    /// <a>:<b>@<n>w and <a>:<b>@<n>h, which change the aspect ratio to a:b and
+    /// then set width or height to n.
     #[structopt(short, long)]
     pub size: Option<SizeOverride>,
 
+    /// Override the width of render tiles, in pixels
+    #[structopt(long)]
+    pub tile_width: Option<u32>,
+
+    /// Override the height of render tiles, in pixels
+    #[structopt(long)]
+    pub tile_height: Option<u32>,
+
+    /// Override the number of render threads, or 0 to use every available
+    /// core
+    #[structopt(short = "j", long)]
+    pub threads: Option<u32>,
+
+    /// Override the floating-point precision of the map, either single or
+    /// double
+    #[structopt(long)]
+    pub precision: Option<Precision>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct GenerateOpts {
+    #[structopt(flatten)]
+    pub cfg: ConfigOpts,
+
+    /// Additional configuration files to render in the same run.  When more
+    /// than one map is rendered, each output file name is suffixed with the
+    /// name of its config.
+    #[structopt(parse(from_os_str))]
+    pub batch: Vec<PathBuf>,
+
+    /// A file listing additional configuration files to render, one per line
+    /// and relative to the manifest.  Blank lines and lines starting with #
+    /// are ignored.
+    #[structopt(long, parse(from_os_str))]
+    pub manifest: Option<PathBuf>,
+
+    /// The number of maps of a batch to render at once
+    ///
+    /// The tiles of every map in flight share the same render threads, which
+    /// keeps every core busy when the maps are small, at the cost of holding
+    /// more maps in memory.
+    #[structopt(long, default_value = "1")]
+    pub jobs: usize,
+
     /// The format to output the result in
     #[structopt(name = "type", short, long, requires("out"))]
     pub ty: Option<MapFormat>,
 
+    /// The file to write the map to, or "-" for stdout
+    ///
+    /// Output paths may contain the placeholders {width}, {height},
+    /// {pitch_curve}, {base_hz}, and {config_stem}, which are filled in from
+    /// the resolved config.
+    ///
+    /// If the output is a directory, or ends with a path separator, the map is
+    /// written inside it to a file named after the config and a hash of the
+    /// resolved settings, e.g. results/consonance-1a2b3c4d.png.  Without
+    /// --type, maps are written as TSV.
+    #[allow(clippy::doc_markdown)]
     #[structopt(short, long, default_value = "-")]
     pub out: MapOutput,
+
+    /// Write the deepest local minima of the map as CSV to the given file, or
+    /// "-" for stdout
+    #[structopt(long)]
+    pub minima: Option<MapOutput>,
+
+    /// Write the most prominent local maxima of the map as CSV to the given
+    /// file, or "-" for stdout
+    #[structopt(long)]
+    pub maxima: Option<MapOutput>,
+
+    /// The maximum number of extrema to report
+    #[structopt(long, default_value = "12")]
+    pub extrema: usize,
+
+    /// Write contour lines of the map to the given file, as GeoJSON if its
+    /// extension is .geojson or .json and as CSV otherwise, or "-" for CSV on
+    /// stdout
+    #[allow(clippy::doc_markdown)]
+    #[structopt(long, requires("contour-levels"))]
+    pub contours: Option<MapOutput>,
+
+    /// Comma-separated map values to draw contour lines at
+    #[structopt(long, use_delimiter = true, allow_hyphen_values = true)]
+    pub contour_levels: Vec<f64>,
+
+    /// Continue a render left unfinished by an earlier run, reporting how many
+    /// of its tiles are already cached.  The earlier run's tile size is reused
+    /// so only the missing tiles are rendered.
+    #[structopt(long)]
+    pub resume: bool,
+
+    /// Print the resolved config, the cache key of each map, and an estimate
+    /// of the memory and time needed to render it, then exit without
+    /// rendering.  The cache is opened read-only.
+    #[structopt(long)]
+    pub dry_run: bool,
+
+    /// Stop rendering once this much time has passed (e.g. 90s or 10m) and
+    /// write out what was finished, with unrendered pixels left as NaN
+    #[structopt(long)]
+    pub time_limit: Option<Age>,
+
+    /// Write how long each phase of the run took, along with the cache
+    /// activity, as JSON to the given file
+    ///
+    /// The same breakdown is logged at the end of every run.
+    #[structopt(long, parse(from_os_str))]
+    pub timings: Option<PathBuf>,
+
+    /// Re-render this fraction (from 0 to 1) of the tiles loaded from the
+    /// cache, chosen at random, and fail if any differ from the cached values
+    #[structopt(long)]
+    pub verify_cache: Option<f64>,
+
+    /// A shell command to run once the run completes, or after every pass of
+    /// watch, e.g. to send a desktop notification
+    ///
+    /// The command can read DISSON_STATUS (success, failure, or interrupted),
+    /// DISSON_ELAPSED (in seconds), DISSON_OUTPUTS (the files written, one per
+    /// line), and DISSON_SUMMARY (all of the above along with timings and
+    /// cache activity, as JSON) from its environment.
+    #[allow(clippy::doc_markdown)]
+    #[structopt(long)]
+    pub on_complete: Option<String>,
+}
+
+// Parsed even without the gui feature, to explain why the command failed
+#[derive(Debug, StructOpt)]
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub struct GuiOpts {
+    /// A configuration file to open on startup
+    #[structopt(parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// The name of a preset from the config file to apply over the base
+    /// settings
+    #[structopt(short, long)]
+    pub preset: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct StatsOpts {
+    #[structopt(flatten)]
+    pub cfg: ConfigOpts,
+
+    /// Comma-separated percentiles of the map values to report
+    #[structopt(long, use_delimiter = true, default_value = "1,5,25,50,75,95,99")]
+    pub percentiles: Vec<f64>,
+
+    /// The number of histogram bins to report
+    #[structopt(long, default_value = "10")]
+    pub bins: usize,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExplainOpts {
+    #[structopt(flatten)]
+    pub cfg: ConfigOpts,
+
+    /// The widest interval to sample, as a ratio (e.g. 2/1) or in cents (e.g.
+    /// 1200c)
+    #[structopt(long, default_value = "2/1")]
+    pub range: Interval,
+
+    /// The number of steps to sample the range in
+    #[structopt(long, default_value = "24")]
+    pub steps: u32,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct UpgradeConfigOpts {
+    /// The config file to upgrade.  Files it includes are left as they are.
+    #[structopt(parse(from_os_str))]
+    pub config: PathBuf,
+
+    /// Overwrite the config file instead of printing the upgraded config
+    #[structopt(short, long)]
+    pub in_place: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct RenderAudioOpts {
+    #[structopt(flatten)]
+    pub cfg: ConfigOpts,
+
+    /// The interval of the X tone above the base frequency, as a ratio (e.g.
+    /// 3/2 or 1.5) or in cents (e.g. 702c)
+    #[structopt(short, long, conflicts_with("pixel"))]
+    pub x: Option<Interval>,
+
+    /// The interval of the Y tone above the base frequency, as a ratio (e.g.
+    /// 5/4 or 1.25) or in cents (e.g. 386c)
+    #[structopt(short, long, conflicts_with("pixel"))]
+    pub y: Option<Interval>,
+
+    /// Sound the X and Y tones at this pixel of the map, given as <x>,<y>
+    #[structopt(long, required_unless_one(&["x", "y"]))]
+    pub pixel: Option<MapPoint>,
+
+    /// The WAV file to write
+    #[structopt(short, long, parse(from_os_str))]
+    pub out: PathBuf,
+
+    /// The length of the clip, in seconds
+    #[structopt(long, default_value = "3")]
+    pub duration: f64,
+
+    /// The sample rate of the clip, in Hz
+    #[structopt(long, default_value = "44100")]
+    pub sample_rate: u32,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct RenderSweepOpts {
+    #[structopt(flatten)]
+    pub cfg: ConfigOpts,
+
+    /// The axis to sweep along, either x or y
+    #[structopt(long, default_value = "x")]
+    pub axis: Axis,
+
+    /// The interval the tone of the other axis is held at above the base
+    /// frequency, as a ratio or in cents
+    #[structopt(long, default_value = "1/1")]
+    pub at: Interval,
+
+    /// The WAV file to write
+    #[structopt(short, long, parse(from_os_str))]
+    pub out: PathBuf,
+
+    /// Write the value of the map along the sweep to a second channel, scaled
+    /// from 0 to 1, for use as a sidechain or automation curve
+    #[structopt(long)]
+    pub sidechain: bool,
+
+    /// The length of the sweep, in seconds
+    #[structopt(long, default_value = "10")]
+    pub duration: f64,
+
+    /// The sample rate of the sweep, in Hz
+    #[structopt(long, default_value = "44100")]
+    pub sample_rate: u32,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct PrintDefaultsOpts {
+    /// Only print the given section of the config (map, render, or format)
+    pub section: Option<ConfigSection>,
+
+    /// Annotate each field with a comment describing it and its accepted
+    /// values
+    #[structopt(short, long)]
+    pub comments: bool,
 }
 
 impl GenerateOpts {
@@ -122,10 +559,49 @@ pub enum FromStrErr {
     ParseFloat(String, std::num::ParseFloatError),
 }
 
+/// A musical interval in octaves, parsed from a frequency ratio or a number of
+/// cents
+#[derive(Debug, Clone, Copy)]
+pub struct Interval(pub f64);
+
+/// A position on a map in pixels, parsed from <x>,<y>
+#[derive(Debug, Clone, Copy)]
+pub struct MapPoint(pub f64, pub f64);
+
+/// A length of time, parsed from a number and a unit suffix
+#[derive(Debug, Clone, Copy)]
+pub struct Age(pub Duration);
+
+/// A size in bytes, parsed with an optional binary unit suffix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+#[derive(Debug, Clone, Copy)]
+pub enum MessageFormat {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum CacheCodec {
+    Auto,
+    None,
+    Lz4,
+    Zstd,
+}
+
 #[derive(Debug)]
 pub enum CacheMode {
     Off,
     File(Option<PathBuf>),
+    Http(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ConfigSection {
+    Map,
+    Render,
+    Format,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -140,12 +616,183 @@ pub enum MapOutput {
     File(PathBuf),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum SizeOverride {
     Width(u32),
     Height(u32),
     Exact(u32, u32),
     Percent(f64),
+    /// Scale to about this many megapixels, keeping the aspect ratio
+    Megapixels(f64),
+    /// Scale down, keeping the aspect ratio, until neither dimension is larger
+    /// than this
+    Max(u32),
+    /// Change the aspect ratio, then set the width or height
+    Aspect {
+        ratio: (u32, u32),
+        len: u32,
+        vertical: bool,
+    },
+}
+
+impl FromStr for Interval {
+    type Err = FromStrErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let float = |s: &str| {
+            s.trim()
+                .parse::<f64>()
+                .map_err(|e| FromStrErr::ParseFloat(s.into(), e))
+        };
+
+        let octaves = if let Some(cents) = s.strip_suffix('c') {
+            float(cents)? / 1200.0
+        } else {
+            let ratio = match s.find('/') {
+                Some(i) => float(&s[..i])? / float(&s[i + 1..])?,
+                None => float(s)?,
+            };
+
+            if !(ratio.is_finite() && ratio > 0.0) {
+                return Err(FromStrErr::Custom(
+                    s.into(),
+                    "expected a positive ratio, e.g. 3/2, or a number of cents, e.g. 702c",
+                ));
+            }
+
+            ratio.log2()
+        };
+
+        Ok(Self(octaves))
+    }
+}
+
+impl FromStr for MapPoint {
+    type Err = FromStrErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let float = |s: &str| {
+            s.trim()
+                .parse::<f64>()
+                .map_err(|e| FromStrErr::ParseFloat(s.into(), e))
+        };
+
+        match s.find(',') {
+            Some(i) => Ok(Self(float(&s[..i])?, float(&s[i + 1..])?)),
+            None => Err(FromStrErr::Custom(s.into(), "expected a pixel position <x>,<y>")),
+        }
+    }
+}
+
+impl FromStr for Age {
+    type Err = FromStrErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        static AGE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            RegexBuilder::new(r"^(\d+)\s*([smhdw])$")
+                .case_insensitive(true)
+                .build()
+                .unwrap()
+        });
+
+        let caps = AGE_REGEX.captures(s.trim()).ok_or_else(|| {
+            FromStrErr::Custom(s.into(), "expected a number and one of s, m, h, d, or w")
+        })?;
+        let n: u64 = caps[1]
+            .parse()
+            .map_err(|e| FromStrErr::ParseInt(caps[1].into(), e))?;
+        let unit = match caps[2].to_lowercase().as_ref() {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => unreachable!(),
+        };
+
+        n.checked_mul(unit)
+            .map(|s| Self(Duration::from_secs(s)))
+            .ok_or_else(|| FromStrErr::Custom(s.into(), "age is too large"))
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = FromStrErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        static SIZE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            RegexBuilder::new(r"^(\d+)\s*([kmgt]?)i?b?$")
+                .case_insensitive(true)
+                .build()
+                .unwrap()
+        });
+
+        let caps = SIZE_REGEX.captures(s.trim()).ok_or_else(|| {
+            FromStrErr::Custom(s.into(), "expected a number of bytes, e.g. 500M or 2G")
+        })?;
+        let n: u64 = caps[1]
+            .parse()
+            .map_err(|e| FromStrErr::ParseInt(caps[1].into(), e))?;
+        let shift = match caps[2].to_lowercase().as_ref() {
+            "" => 0,
+            "k" => 10,
+            "m" => 20,
+            "g" => 30,
+            "t" => 40,
+            _ => unreachable!(),
+        };
+
+        n.checked_mul(1 << shift)
+            .map(Self)
+            .ok_or_else(|| FromStrErr::Custom(s.into(), "size is too large"))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    #[allow(clippy::cast_precision_loss)]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+
+        let mut n = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+
+        while n >= 1024.0 && unit + 1 < UNITS.len() {
+            n /= 1024.0;
+            unit += 1;
+        }
+
+        write!(f, "{:.1} {}", n, UNITS[unit])
+    }
+}
+
+impl FromStr for MessageFormat {
+    type Err = FromStrErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_ref() {
+            "human" => Self::Human,
+            "json" => Self::Json,
+            _ => return Err(FromStrErr::OneOf(s.into(), &["human", "json"])),
+        })
+    }
+}
+
+impl FromStr for CacheCodec {
+    type Err = FromStrErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_ref() {
+            "auto" => Self::Auto,
+            "none" => Self::None,
+            "lz4" => Self::Lz4,
+            "zstd" => Self::Zstd,
+            _ => return Err(FromStrErr::OneOf(s.into(), &["auto", "none", "lz4", "zstd"])),
+        })
+    }
 }
 
 impl FromStr for CacheMode {
@@ -155,14 +802,39 @@ impl FromStr for CacheMode {
         Ok(match s {
             "" => Self::File(None),
             "-" => Self::Off,
+            s if s.starts_with("http://") || s.starts_with("https://") => {
+                Self::Http(s.trim_end_matches('/').into())
+            },
             s => Self::File(Some(s.into())),
         })
     }
 }
 
+impl FromStr for ConfigSection {
+    type Err = FromStrErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_ref() {
+            "map" => Self::Map,
+            "render" => Self::Render,
+            "format" => Self::Format,
+            _ => return Err(FromStrErr::OneOf(s.into(), &["map", "render", "format"])),
+        })
+    }
+}
+
 impl MapFormat {
     const CSV: Self = Self::Xsv(b',');
-    const TSV: Self = Self::Xsv(b'\t');
+    pub const TSV: Self = Self::Xsv(b'\t');
+
+    /// The file extension for maps written in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Xsv(b',') => "csv",
+            Self::Xsv(_) => "tsv",
+            Self::Png => "png",
+        }
+    }
 }
 
 impl FromStr for MapFormat {
@@ -193,20 +865,44 @@ impl FromStr for SizeOverride {
     type Err = FromStrErr;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        lazy_static! {
-            static ref WIDTH_HEIGHT_REGEX: Regex = RegexBuilder::new(r"^(\d+)([wh])$")
+        static WIDTH_HEIGHT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            RegexBuilder::new(r"^(\d+)([wh])$")
                 .case_insensitive(true)
                 .build()
-                .unwrap();
-            static ref PERCENT_REGEX: Regex = RegexBuilder::new(r"^(\d+(?:\.\d+))%$")
+                .unwrap()
+        });
+        static PERCENT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            RegexBuilder::new(r"^(\d+(?:\.\d+))%$")
                 .case_insensitive(true)
                 .build()
-                .unwrap();
-            static ref EXACT_REGEX: Regex = RegexBuilder::new(r"^(\d+)x(\d+)$")
+                .unwrap()
+        });
+        static EXACT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            RegexBuilder::new(r"^(\d+)x(\d+)$")
                 .case_insensitive(true)
                 .build()
-                .unwrap();
-        }
+                .unwrap()
+        });
+        static MEGAPIXEL_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            RegexBuilder::new(r"^(\d+(?:\.\d+)?)mp$")
+                .case_insensitive(true)
+                .build()
+                .unwrap()
+        });
+        static MAX_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            RegexBuilder::new(r"^max:(\d+)$")
+                .case_insensitive(true)
+                .build()
+                .unwrap()
+        });
+        static ASPECT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            RegexBuilder::new(r"^(\d+):(\d+)@(\d+)([wh])$")
+                .case_insensitive(true)
+                .build()
+                .unwrap()
+        });
+
+        let int = |s: &str| s.parse().map_err(|e| FromStrErr::ParseInt(s.into(), e));
 
         Ok(if let Some(caps) = WIDTH_HEIGHT_REGEX.captures(s) {
             let len = caps[1]
@@ -233,13 +929,54 @@ impl FromStr for SizeOverride {
                 .map_err(|e| FromStrErr::ParseInt(caps[2].into(), e))?;
 
             SizeOverride::Exact(w, h)
+        } else if let Some(caps) = MEGAPIXEL_REGEX.captures(s) {
+            let mp = caps[1]
+                .parse()
+                .map_err(|e| FromStrErr::ParseFloat(caps[1].into(), e))?;
+
+            SizeOverride::Megapixels(mp)
+        } else if let Some(caps) = MAX_REGEX.captures(s) {
+            SizeOverride::Max(int(&caps[1])?)
+        } else if let Some(caps) = ASPECT_REGEX.captures(s) {
+            SizeOverride::Aspect {
+                ratio: (int(&caps[1])?, int(&caps[2])?),
+                len: int(&caps[3])?,
+                vertical: caps[4].eq_ignore_ascii_case("h"),
+            }
         } else {
             return Err(FromStrErr::Custom(
                 s.into(),
-                "valid formats are <n>w, <n>h, <x>%, or <w>x<h>",
+                "valid formats are <n>w, <n>h, <x>%, <w>x<h>, <x>MP, max:<n>, or <a>:<b>@<n>[wh]",
             ));
         })
     }
 }
 
-pub fn parse() -> Opts { Opts::from_args() }
+pub fn parse() -> Opts {
+    Opts::from_args_safe().unwrap_or_else(|e| {
+        if !e.use_stderr() {
+            e.exit();
+        }
+
+        eprintln!("{}", e.message);
+        std::process::exit(message::exit::USAGE);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use structopt::StructOpt;
+
+    use super::{Opts, Subcommand};
+
+    #[test]
+    fn generate_flags_parse() {
+        // clap checks that no two flags share a name as it builds the parser
+        let opts = Opts::from_iter_safe(&["disson", "generate", "-j", "2", "map.ron"]).unwrap();
+
+        match opts.cmd {
+            Subcommand::Generate(g) => assert_eq!(g.cfg.threads, Some(2)),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,1588 @@
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
+    fmt::{Display, Write as _},
+    fs,
+    io::{prelude::*, stdout},
+    mem,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::LazyLock,
+};
+
+use itertools::iproduct;
+use log::warn;
+use regex::Regex;
+use ron::ser::PrettyConfig;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use thiserror::Error;
+
+pub use crate::cli::{MapFormat, MapOutput};
+pub use upgrade::upgrade_config;
+use crate::{
+    cli::{ConfigOpts, ConfigSection, FromStrErr, PrintDefaultsOpts, SizeOverride},
+    disson::{
+        algo::{AmpWeighting, CombinationTones, CurveParams, Masking, OverlapCurve, PitchCurve},
+        map::{AxisRange, Combine},
+        post::PostTransform,
+        real::Precision,
+        timbre::{Filter, Frames, Timbre},
+    },
+    error::prelude::*,
+    tile_renderer::{DEFAULT_TILE_HEIGHT, DEFAULT_TILE_WIDTH},
+};
+
+mod upgrade;
+
+/// The current config schema version.  Bump this and add a step to
+/// `GenerateConfigPatch::legacy_defaults` whenever the config layout changes.
+pub const CONFIG_VERSION: u32 = 22;
+
+/// A note name with an optional offset in cents, e.g. `A4`, `Bb2`, or
+/// `C#3+14c`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Note {
+    /// MIDI note number, where 69 is A4
+    midi: i32,
+    cents: f64,
+}
+
+/// A frequency, given either in Hz or as a note name
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Frequency {
+    Hz(f64),
+    Note(Note),
+}
+
+impl Note {
+    const NAMES: [&'static str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+
+    /// Get the frequency of this note, given the frequency of A4
+    pub fn hz(self, reference: f64) -> f64 {
+        reference * 2.0_f64.powf((f64::from(self.midi - 69) + self.cents / 100.0) / 12.0)
+    }
+}
+
+impl FromStr for Note {
+    type Err = FromStrErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        static NOTE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"^([A-Ga-g])([#b♯♭]*)(-?\d+)(?:([+-]\d+(?:\.\d*)?)c)?$").unwrap()
+        });
+
+        let caps = NOTE_REGEX.captures(s).ok_or_else(|| {
+            FromStrErr::Custom(
+                s.into(),
+                "expected a note name, octave, and optional cents offset, e.g. C#3+14c",
+            )
+        })?;
+
+        let class = match caps[1].to_ascii_uppercase().as_ref() {
+            "C" => 0,
+            "D" => 2,
+            "E" => 4,
+            "F" => 5,
+            "G" => 7,
+            "A" => 9,
+            "B" => 11,
+            _ => unreachable!(),
+        };
+
+        let accidental: i32 = caps[2]
+            .chars()
+            .map(|c| match c {
+                '#' | '♯' => 1,
+                'b' | '♭' => -1,
+                _ => unreachable!(),
+            })
+            .sum();
+
+        let octave: i32 = caps[3]
+            .parse()
+            .map_err(|e| FromStrErr::ParseInt(caps[3].into(), e))?;
+
+        let cents = caps
+            .get(4)
+            .map(|c| {
+                c.as_str()
+                    .parse()
+                    .map_err(|e| FromStrErr::ParseFloat(c.as_str().into(), e))
+            })
+            .transpose()?
+            .unwrap_or(0.0);
+
+        Ok(Self {
+            midi: (octave + 1) * 12 + class + accidental,
+            cents,
+        })
+    }
+}
+
+impl TryFrom<String> for Note {
+    type Error = FromStrErr;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> { s.parse() }
+}
+
+impl Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+        let name = Self::NAMES[self.midi.rem_euclid(12) as usize];
+
+        write!(f, "{}{}", name, self.midi.div_euclid(12) - 1)?;
+
+        if self.cents.abs() > 0.0 {
+            write!(f, "{:+}c", self.cents)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Note> for String {
+    fn from(note: Note) -> Self { note.to_string() }
+}
+
+impl Frequency {
+    /// Get this frequency in Hz, given the frequency of A4
+    pub fn hz(self, reference: f64) -> f64 {
+        match self {
+            Self::Hz(hz) => hz,
+            Self::Note(n) => n.hz(reference),
+        }
+    }
+}
+
+/// A tone held under the map alongside the base tone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeldNote {
+    pub frequency: Frequency,
+    /// Timbre of this tone, or the map's timbre if not given
+    #[serde(default)]
+    pub timbre: Option<Timbre>,
+}
+
+impl HeldNote {
+    fn validate(&self, path: &str, reference: f64, v: &mut Validator) {
+        let hz = self.frequency.hz(reference);
+
+        v.check(
+            hz.is_finite() && hz > 0.0,
+            format_args!("{path}.frequency"),
+            "must be a positive frequency",
+        );
+
+        if let Some(ref timbre) = self.timbre {
+            timbre.validate(&format!("{path}.timbre"), v);
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("invalid config:\n  {}", .0.join("\n  "))]
+pub struct ValidationError(Vec<String>);
+
+/// Collects config violations, each prefixed with the path to the offending
+/// field
+#[derive(Debug, Default)]
+pub struct Validator(Vec<String>);
+
+impl Validator {
+    pub fn check(&mut self, ok: bool, path: impl Display, msg: impl Display) {
+        if !ok {
+            self.0.push(format!("{path}: {msg}"));
+        }
+    }
+
+    pub fn finish(self) -> Result<(), ValidationError> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError(self.0))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ConfigFormat {
+    Ron,
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    fn for_path(path: &Path) -> Result<Self> {
+        Ok(
+            match path
+                .extension()
+                .map(|s| {
+                    s.to_str()
+                        .ok_or_else(|| anyhow!("couldn't read config file extension"))
+                })
+                .transpose()?
+            {
+                Some("ron") | None => Self::Ron,
+                Some("toml") => Self::Toml,
+                Some("yaml" | "yml") => Self::Yaml,
+                Some("json") => Self::Json,
+                Some(e) => {
+                    return Err(anyhow!(
+                        "couldn't guess config format from file extension {:?}",
+                        e
+                    ))
+                },
+            },
+        )
+    }
+
+    fn parse<T: DeserializeOwned>(self, s: &str) -> Result<T> {
+        Ok(match self {
+            Self::Ron => ron::de::from_str(s)?,
+            Self::Toml => {
+                // toml only reads enums written as inline tables, but writes
+                // them as [headers] like any other table, so go through JSON
+                let val: toml::Value = toml::from_str(s)?;
+
+                serde_json::from_value(serde_json::to_value(val)?)?
+            },
+            Self::Yaml => serde_yaml::from_str(s)?,
+            Self::Json => serde_json::from_str(s)?,
+        })
+    }
+}
+
+/// Deserialize a patch field, wrapping it in `Some` so config fragments don't
+/// have to spell out `Some(...)` for every value they set
+fn patch_field<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    de: D,
+) -> Result<Option<T>, D::Error> {
+    T::deserialize(de).map(Some)
+}
+
+fn pretty_config() -> PrettyConfig { PrettyConfig::new().with_decimal_floats(true) }
+
+/// Append a RON struct field to a commented config template, preceded by its
+/// doc comment
+fn write_commented_field(out: &mut String, doc: &str, name: &str, val: &str) -> fmt::Result {
+    for line in doc.lines() {
+        writeln!(out, "    //{line}")?;
+    }
+
+    writeln!(out, "    {}: {},", name, val.replace('\n', "\n    "))
+}
+
+/// A field filled in when upgrading a config file to the current schema
+#[derive(Debug)]
+struct AddedField {
+    name: &'static str,
+    ron: String,
+    json: serde_json::Value,
+}
+
+/// Declares a config section along with a patch type, where every field is
+/// optional, used to merge layered config files
+macro_rules! config_section {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident / $patch:ident {
+            $($(#[doc = $doc:expr])* pub $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Serialize, Deserialize)]
+        pub struct $name {
+            $($(#[doc = $doc])* pub $field: $ty,)*
+        }
+
+        #[derive(Debug, Default, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct $patch {
+            $(
+                #[serde(default, deserialize_with = "patch_field")]
+                $field: Option<$ty>,
+            )*
+        }
+
+        impl $name {
+            /// Serialize this section as RON, with each field preceded by its
+            /// doc comment
+            // Only some sections are Copy, so this can't take self by value
+            #[allow(clippy::wrong_self_convention)]
+            fn to_commented(&self) -> Result<String> {
+                let mut out = String::from("(\n");
+
+                $(
+                    write_commented_field(
+                        &mut out,
+                        concat!($($doc, "\n",)*),
+                        stringify!($field),
+                        &ron::ser::to_string_pretty(&self.$field, pretty_config())?,
+                    )?;
+                )*
+
+                out.push(')');
+
+                Ok(out)
+            }
+        }
+
+        impl $patch {
+            #[allow(unused_variables)]
+            fn merge(&mut self, over: Self) {
+                $(
+                    if over.$field.is_some() {
+                        self.$field = over.$field;
+                    }
+                )*
+            }
+
+            /// Every field set in `after` but not in this patch, serialized
+            /// both as RON and as JSON
+            fn added_fields(&self, after: &Self) -> Result<Vec<AddedField>> {
+                let mut ret = vec![];
+
+                $(
+                    if let (None, Some(val)) = (&self.$field, &after.$field) {
+                        ret.push(AddedField {
+                            name: stringify!($field),
+                            ron: ron::ser::to_string_pretty(val, pretty_config())?,
+                            json: serde_json::to_value(val)?,
+                        });
+                    }
+                )*
+
+                Ok(ret)
+            }
+
+            /// Fill in this section.  Optional fields left out are unset, since
+            /// formats like TOML have no way to spell out a null value.
+            #[allow(unused_variables)]
+            fn resolve(self, section: &str) -> Result<$name> {
+                Ok($name {
+                    $(
+                        $field: match self.$field {
+                            Some(val) => val,
+                            None => serde_json::from_value(serde_json::Value::Null).map_err(
+                                |_| anyhow!("missing field {}.{}", section, stringify!($field)),
+                            )?,
+                        },
+                    )*
+                })
+            }
+        }
+    };
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenerateConfig {
+    pub version: u32,
+    pub map: MapConfig,
+    pub render: RenderConfig,
+    pub format: FormatConfig,
+    /// Weights and map settings of each component of `map.combine`, resolved
+    /// from their presets
+    #[serde(skip)]
+    pub components: Vec<(f64, MapConfig)>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GenerateConfigPatch {
+    #[serde(default, deserialize_with = "patch_field")]
+    version: Option<u32>,
+    /// Other config files to merge underneath this one, relative to this file
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    #[serde(default)]
+    map: MapConfigPatch,
+    #[serde(default)]
+    render: RenderConfigPatch,
+    #[serde(default)]
+    format: FormatConfigPatch,
+    /// Named sets of overrides, selected with --preset
+    #[serde(default)]
+    presets: HashMap<String, PresetPatch>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PresetPatch {
+    #[serde(default)]
+    map: MapConfigPatch,
+    #[serde(default)]
+    render: RenderConfigPatch,
+    #[serde(default)]
+    format: FormatConfigPatch,
+}
+
+impl PresetPatch {
+    fn merge(&mut self, over: Self) {
+        self.map.merge(over.map);
+        self.render.merge(over.render);
+        self.format.merge(over.format);
+    }
+}
+
+config_section! {
+    #[derive(Clone)]
+    // Field docs are written into generated configs, so they stay plain text
+    #[allow(clippy::doc_markdown)]
+    pub struct MapConfig / MapConfigPatch {
+        /// Width of the map, in pixels
+        pub width: u32,
+        /// Height of the map, in pixels
+        pub height: u32,
+        /// Frequency of the reference tone, either in Hz or as a note name with
+        /// an optional offset in cents (e.g. "A4" or "C#3+14c")
+        pub base_frequency: Frequency,
+        /// Frequency of A4 in Hz, used to tune note names
+        pub tuning_reference: f64,
+        /// Leave the tone at base_frequency out of the map, comparing only the
+        /// X and Y tones against each other
+        pub omit_base: bool,
+        /// Additional tones held under the map, forming a chord with the base
+        /// tone: [(frequency: <Hz or note>, timbre: <optional timbre>), ...].
+        /// Held notes without a timbre use the map's timbre.
+        pub held_notes: Vec<HeldNote>,
+        /// Divide every value by the dissonance of the map's tones sounding in
+        /// unison with the base tone, so maps of different timbres can be
+        /// compared directly
+        pub normalize: bool,
+        /// Interval range covered by the X axis: Octaves(start, end),
+        /// Cents(start, end), Ratios(start, end), EdoSteps(edo: <n>, start:
+        /// <step>, end: <step>), or Hertz(min, max)
+        pub x_range: AxisRange,
+        /// Interval range covered by the Y axis: Octaves(start, end),
+        /// Cents(start, end), Ratios(start, end), EdoSteps(edo: <n>, start:
+        /// <step>, end: <step>), or Hertz(min, max)
+        pub y_range: AxisRange,
+        /// Distance between pixels along the X axis, in the units of x_range.
+        /// If given, overrides width.
+        pub x_step: Option<f64>,
+        /// Distance between pixels along the Y axis, in the units of y_range.
+        /// If given, overrides height.
+        pub y_step: Option<f64>,
+        /// Scale used to measure the distance between partials: Logarithmic,
+        /// ErbRate, or Bark
+        pub pitch_curve: PitchCurve,
+        /// Roughness curve applied to partial distances: ExponentialDissonance,
+        /// TrapezoidDissonance, TriangleConsonance, TrapezoidConsonance, or
+        /// Sethares (which ignores pitch_curve), or a weighted sum of curves:
+        /// Blend([(<weight>, <curve>), ...])
+        pub overlap_curve: OverlapCurve,
+        /// Constants of the overlap curves: distance_scale, exp_rate,
+        /// trap_rise, trap_end, and tri_end.  Omitted constants keep their
+        /// defaults.
+        pub curve_params: CurveParams,
+        /// If given, the overlap curve is sampled into a table with this many
+        /// intervals (e.g. 4096) and interpolated, trading a little accuracy
+        /// for speed.  Has no effect on a plugin's overlap curve.
+        pub lookup_table: Option<u32>,
+        /// How partial amplitudes weight their overlap: Linear, Power, or
+        /// Loudness
+        pub amp_weighting: AmpWeighting,
+        /// If given, partials within a critical band of a partial at least
+        /// threshold dB louder are attenuated, or dropped if drop is true:
+        /// (threshold: <dB>, drop: <bool>)
+        pub masking: Option<Masking>,
+        /// If given, difference and summation tones between the loudest
+        /// partials of each pair of voices are added before evaluating
+        /// roughness: (partials: <n>, difference: <level>, cubic: <level>,
+        /// summation: <level>)
+        pub combination_tones: Option<CombinationTones>,
+        /// Partials of the compared tones: Harmonic(partials: <n>, rolloff:
+        /// Flat | Inverse | InverseSquare | ExponentialDecay(<k>), stretch:
+        /// <exponent>),
+        /// Partials([(pitch: <ratio>, amp: <amp>), ...]), Waveform(shape: Sine
+        /// | Sawtooth | Square | Triangle | Clarinet, partials: <n>), or
+        /// FromFile("<file>.wav")
+        pub timbre: Timbre,
+        /// If given, a spectral tilt applied to every timbre above a cutoff:
+        /// (cutoff: <ratio of fundamental>, slope: <dB per octave>)
+        pub filter: Option<Filter>,
+        /// If given, partials above this frequency in Hz (e.g. 20000) are
+        /// ignored
+        pub max_frequency: Option<f64>,
+        /// If given, partials with a linear amplitude below this threshold are
+        /// ignored
+        pub min_amplitude: Option<f64>,
+        /// If given, partial amplitudes are replaced with the amplitude of a
+        /// 1 kHz tone of equal loudness per ISO 226, taking an amplitude of 1
+        /// to be this many dB SPL
+        pub equal_loudness: Option<f64>,
+        /// Path to a WASM module exporting pitch(hz) and/or overlap(a, b) to
+        /// replace pitch_curve and overlap_curve.  Relative paths are
+        /// resolved against the directory of the config file.
+        pub plugin: Option<PathBuf>,
+        /// If given, a FromFile timbre is analyzed in frames and one map is
+        /// generated per frame, written to the output as a directory:
+        /// (window: <seconds>, hop: <seconds>, limit: <n>)
+        pub frames: Option<Frames>,
+        /// Transformations applied in order to the finished map: any of
+        /// Consonance, Rank, ZScore, Gradient, or Laplacian
+        pub post_transforms: Vec<PostTransform>,
+        /// If given, the output is combined from the maps of several presets,
+        /// each applied over these settings and cached separately: (op: Sum |
+        /// Max | Difference, components: [(<weight>, "<preset>"), ...]).
+        /// Difference subtracts every other component from the first.
+        pub combine: Option<Combine>,
+        /// Floating-point precision to compute the map in: Single or Double
+        pub precision: Precision,
+    }
+}
+
+impl MapConfigPatch {
+    /// Resolve relative file paths in this section against the directory of
+    /// the config file it was read from
+    fn rebase(&mut self, dir: &Path) {
+        if let Some(Timbre::FromFile(ref mut file)) = self.timbre {
+            *file = dir.join(&*file);
+        }
+
+        for note in self.held_notes.iter_mut().flatten() {
+            if let Some(Timbre::FromFile(ref mut file)) = note.timbre {
+                *file = dir.join(&*file);
+            }
+        }
+
+        if let Some(Some(ref mut file)) = self.plugin {
+            *file = dir.join(&*file);
+        }
+    }
+}
+
+config_section! {
+    #[derive(Clone, Copy)]
+    pub struct RenderConfig / RenderConfigPatch {
+        /// Width of each render tile, in pixels
+        pub tile_width: u32,
+        /// Height of each render tile, in pixels
+        pub tile_height: u32,
+        /// Number of threads to render with, or 0 to use every available core
+        pub threads: u32,
+    }
+}
+
+config_section! {
+    pub struct FormatConfig / FormatConfigPatch {
+        /// If given, image outputs mark every just interval up to this odd
+        /// limit (e.g. 5 or 7)
+        pub ji_limit: Option<u32>,
+        /// If given, image outputs mark every step of this equal division of
+        /// the octave (e.g. 12)
+        pub edo_grid: Option<u32>,
+    }
+}
+
+impl GenerateConfigPatch {
+    fn merge(&mut self, over: Self) {
+        if over.version.is_some() {
+            self.version = over.version;
+        }
+
+        self.map.merge(over.map);
+        self.render.merge(over.render);
+        self.format.merge(over.format);
+
+        for (name, preset) in over.presets {
+            self.presets.entry(name).or_default().merge(preset);
+        }
+    }
+
+    /// Overlay the named preset on top of the base settings
+    fn apply_preset(&mut self, name: &str) -> Result<()> {
+        let PresetPatch {
+            map,
+            render,
+            format,
+        } = self.presets.remove(name).ok_or_else(|| {
+            let mut names: Vec<_> = self.presets.keys().map(String::as_str).collect();
+            names.sort_unstable();
+
+            anyhow!(
+                "no preset named {:?} (available presets: {})",
+                name,
+                if names.is_empty() {
+                    "none".into()
+                } else {
+                    names.join(", ")
+                }
+            )
+        })?;
+
+        self.map.merge(map);
+        self.render.merge(render);
+        self.format.merge(format);
+
+        Ok(())
+    }
+
+    fn resolve(self) -> Result<GenerateConfig> {
+        Ok(GenerateConfig {
+            version: self.version.unwrap_or(CONFIG_VERSION),
+            map: self.map.resolve("map")?,
+            render: self.render.resolve("render")?,
+            format: self.format.resolve("format")?,
+            components: vec![],
+        })
+    }
+
+    /// Get the schema version of a config file, failing if it's newer than
+    /// this program supports.  Configs without a version are assumed to
+    /// predate versioning entirely.
+    fn check_version(&self, path: &Path) -> Result<u32> {
+        let version = self.version.unwrap_or(0);
+
+        if version > CONFIG_VERSION {
+            return Err(anyhow!(
+                "config version {} is newer than the latest supported version {}",
+                version,
+                CONFIG_VERSION
+            ));
+        }
+
+        if version < CONFIG_VERSION {
+            warn!(
+                "Config file {} uses schema version {}, upgrading to version {}",
+                path.display(), version, CONFIG_VERSION
+            );
+        }
+
+        Ok(version)
+    }
+
+    /// The settings a config written against an older schema version relied
+    /// on implicitly, for every field added since.  These go underneath every
+    /// config file and its includes, so they never override a field that any
+    /// of them set.
+    fn legacy_defaults(version: u32) -> Self {
+        let mut ret = Self::default();
+
+        if version < 1 {
+            // Version 0 had no axis or timbre settings, and always rendered
+            // one octave per axis using a fixed harmonic series
+            let map = &mut ret.map;
+
+            map.x_range.get_or_insert_with(AxisRange::default);
+            map.y_range.get_or_insert_with(AxisRange::default);
+            map.timbre.get_or_insert_with(Timbre::default);
+        }
+
+        if version < 2 {
+            // Version 1 always weighted overlaps by the product of amplitudes
+            ret.map.amp_weighting.get_or_insert(AmpWeighting::Linear);
+        }
+
+        if version < 3 {
+            // Version 2 had no render settings
+            ret.render.tile_width.get_or_insert(DEFAULT_TILE_WIDTH);
+            ret.render.tile_height.get_or_insert(DEFAULT_TILE_HEIGHT);
+        }
+
+        if version < 4 {
+            // Version 3 always rendered on every available core
+            ret.render.threads.get_or_insert(0);
+        }
+
+        if version < 5 {
+            // Version 4 only accepted frequencies in Hz, so the tuning
+            // reference was irrelevant
+            ret.map.tuning_reference.get_or_insert(440.0);
+        }
+
+        if version < 6 {
+            // Version 5 always used the configured pixel dimensions
+            ret.map.x_step.get_or_insert(None);
+            ret.map.y_step.get_or_insert(None);
+        }
+
+        if version < 7 {
+            // Version 6 always computed maps in double precision
+            ret.map.precision.get_or_insert(Precision::Double);
+        }
+
+        if version < 8 {
+            // Version 7 hard-coded the overlap curve constants
+            ret.map.curve_params.get_or_insert_with(CurveParams::default);
+        }
+
+        if version < 9 {
+            // Version 8 had no plugin support
+            ret.map.plugin.get_or_insert(None);
+        }
+
+        if version < 10 {
+            // Version 9 had no masking model
+            ret.map.masking.get_or_insert(None);
+        }
+
+        if version < 11 {
+            // Version 10 had no combination tones
+            ret.map.combination_tones.get_or_insert(None);
+        }
+
+        if version < 12 {
+            // Version 11 always included the base tone
+            ret.map.omit_base.get_or_insert(false);
+        }
+
+        if version < 13 {
+            // Version 12 only held the base tone
+            ret.map.held_notes.get_or_insert_with(Vec::new);
+        }
+
+        if version < 14 {
+            // Version 13 never normalized maps
+            ret.map.normalize.get_or_insert(false);
+        }
+
+        if version < 15 {
+            // Version 14 output raw maps
+            ret.map.post_transforms.get_or_insert_with(Vec::new);
+        }
+
+        if version < 16 {
+            // Version 15 considered every partial
+            ret.map.max_frequency.get_or_insert(None);
+            ret.map.min_amplitude.get_or_insert(None);
+        }
+
+        if version < 17 {
+            // Version 16 had no equal-loudness weighting
+            ret.map.equal_loudness.get_or_insert(None);
+        }
+
+        if version < 18 {
+            // Version 17 had no timbre filter
+            ret.map.filter.get_or_insert(None);
+        }
+
+        if version < 19 {
+            // Version 18 had no time-varying timbres
+            ret.map.frames.get_or_insert(None);
+        }
+
+        if version < 20 {
+            // Version 19 always evaluated the overlap curve directly
+            ret.map.lookup_table.get_or_insert(None);
+        }
+
+        if version < 21 {
+            // Version 20 had no image overlays
+            ret.format.ji_limit.get_or_insert(None);
+            ret.format.edo_grid.get_or_insert(None);
+        }
+
+        if version < 22 {
+            // Version 21 had no map algebra
+            ret.map.combine.get_or_insert(None);
+        }
+
+        ret
+    }
+
+    /// Read a single config file as written, without its includes
+    fn parse(path: &Path) -> Result<Self> {
+        let format = ConfigFormat::for_path(path)?;
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to open config file {}", path.display()))?;
+
+        let mut cfg: Self = format
+            .parse(&text)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        cfg.map.rebase(dir);
+
+        for preset in cfg.presets.values_mut() {
+            preset.map.rebase(dir);
+        }
+
+        Ok(cfg)
+    }
+
+    /// Read a config file and everything it includes, with later includes
+    /// taking precedence over earlier ones and the file itself taking
+    /// precedence over all of its includes.  Fields added since the oldest
+    /// schema version among the files are filled in underneath all of them.
+    fn load(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Self> {
+        let (cfg, version) = Self::load_layers(path, stack)?;
+        let mut ret = Self::legacy_defaults(version);
+
+        ret.merge(cfg);
+        ret.version = Some(CONFIG_VERSION);
+
+        Ok(ret)
+    }
+
+    /// Read a config file and merge it over everything it includes, without
+    /// filling in any defaults.  Returns the merged patch along with the
+    /// oldest schema version of any of the files.
+    fn load_layers(path: &Path, stack: &mut Vec<PathBuf>) -> Result<(Self, u32)> {
+        let canon = path
+            .canonicalize()
+            .with_context(|| format!("failed to locate config file {}", path.display()))?;
+
+        if stack.contains(&canon) {
+            return Err(anyhow!("config file {:?} includes itself", path));
+        }
+
+        let mut cfg = Self::parse(path)?;
+        let mut version = cfg.check_version(path)?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        stack.push(canon);
+
+        let mut base = Self::default();
+
+        for include in mem::take(&mut cfg.include) {
+            let (layers, oldest) = Self::load_layers(&dir.join(include), stack)?;
+
+            base.merge(layers);
+            version = version.min(oldest);
+        }
+
+        stack.pop();
+
+        base.merge(cfg);
+
+        Ok((base, version))
+    }
+}
+
+impl Default for GenerateConfig {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            map: MapConfig {
+                width: 1000,
+                height: 1000,
+                base_frequency: Frequency::Hz(440.0),
+                tuning_reference: 440.0,
+                omit_base: false,
+                held_notes: vec![],
+                normalize: false,
+                x_range: AxisRange::default(),
+                y_range: AxisRange::default(),
+                x_step: None,
+                y_step: None,
+                pitch_curve: PitchCurve::Erb,
+                overlap_curve: OverlapCurve::ExpDiss,
+                curve_params: CurveParams::default(),
+                lookup_table: None,
+                amp_weighting: AmpWeighting::Linear,
+                masking: None,
+                combination_tones: None,
+                timbre: Timbre::default(),
+                filter: None,
+                max_frequency: None,
+                min_amplitude: None,
+                equal_loudness: None,
+                plugin: None,
+                frames: None,
+                post_transforms: vec![],
+                combine: None,
+                precision: Precision::Double,
+            },
+            render: RenderConfig {
+                tile_width: DEFAULT_TILE_WIDTH,
+                tile_height: DEFAULT_TILE_HEIGHT,
+                threads: 0,
+            },
+            format: FormatConfig {
+                ji_limit: None,
+                edo_grid: None,
+            },
+            components: vec![],
+        }
+    }
+}
+
+impl MapConfig {
+    /// Cover the given range of each axis, in octaves above the base
+    /// frequency, keeping the current dimensions
+    #[cfg(feature = "gui")]
+    pub fn set_view(&mut self, x: (f64, f64), y: (f64, f64)) {
+        self.x_range = AxisRange::Octaves(x.0, x.1);
+        self.y_range = AxisRange::Octaves(y.0, y.1);
+        self.x_step = None;
+        self.y_step = None;
+    }
+
+    /// Derive the map dimensions from the axis step sizes, if any were given
+    fn apply_steps(&mut self) {
+        if let Some(w) = self.x_step.and_then(|s| self.x_range.pixels(s)) {
+            self.width = w;
+        }
+
+        if let Some(h) = self.y_step.and_then(|s| self.y_range.pixels(s)) {
+            self.height = h;
+        }
+    }
+
+    fn validate(&self, path: &str, v: &mut Validator) {
+        let Self {
+            width,
+            height,
+            base_frequency,
+            tuning_reference,
+            omit_base: _,
+            held_notes,
+            normalize: _,
+            x_range,
+            y_range,
+            x_step,
+            y_step,
+            pitch_curve: _,
+            overlap_curve,
+            curve_params,
+            lookup_table,
+            amp_weighting: _,
+            masking,
+            combination_tones,
+            timbre,
+            filter,
+            max_frequency,
+            min_amplitude,
+            equal_loudness,
+            plugin,
+            frames,
+            post_transforms: _,
+            combine,
+            precision: _,
+        } = self;
+
+        v.check(*width > 0, format_args!("{path}.width"), "must be non-zero");
+        v.check(
+            *height > 0,
+            format_args!("{path}.height"),
+            "must be non-zero",
+        );
+        v.check(
+            tuning_reference.is_finite() && *tuning_reference > 0.0,
+            format_args!("{path}.tuning_reference"),
+            "must be a positive number of Hz",
+        );
+
+        let base_hz = base_frequency.hz(*tuning_reference);
+
+        v.check(
+            base_hz.is_finite() && base_hz > 0.0,
+            format_args!("{path}.base_frequency"),
+            "must be a positive frequency",
+        );
+
+        for (i, note) in held_notes.iter().enumerate() {
+            note.validate(&format!("{path}.held_notes[{i}]"), *tuning_reference, v);
+        }
+
+        x_range.validate(&format!("{path}.x_range"), v);
+        y_range.validate(&format!("{path}.y_range"), v);
+
+        if let Some(step) = x_step {
+            x_range.validate_step(*step, &format!("{path}.x_step"), v);
+        }
+
+        if let Some(step) = y_step {
+            y_range.validate_step(*step, &format!("{path}.y_step"), v);
+        }
+
+        overlap_curve.validate(&format!("{path}.overlap_curve"), v);
+        curve_params.validate(&format!("{path}.curve_params"), v);
+
+        v.check(
+            lookup_table.is_none_or(|r| r > 0),
+            format_args!("{path}.lookup_table"),
+            "must be non-zero",
+        );
+
+        if let Some(masking) = masking {
+            masking.validate(&format!("{path}.masking"), v);
+        }
+
+        if let Some(tones) = combination_tones {
+            tones.validate(&format!("{path}.combination_tones"), v);
+        }
+
+        timbre.validate(&format!("{path}.timbre"), v);
+
+        if let Some(filter) = filter {
+            filter.validate(&format!("{path}.filter"), v);
+        }
+
+        validate_cutoffs(path, *max_frequency, *min_amplitude, *equal_loudness, v);
+
+        if let Some(plugin) = plugin {
+            v.check(
+                plugin.is_file(),
+                format_args!("{path}.plugin"),
+                format_args!("{} is not a file", plugin.display()),
+            );
+        }
+
+        if let Some(frames) = frames {
+            frames.validate(&format!("{path}.frames"), v);
+
+            v.check(
+                matches!(timbre, Timbre::FromFile(_)),
+                format_args!("{path}.frames"),
+                "requires a FromFile timbre",
+            );
+        }
+
+        if let Some(combine) = combine {
+            combine.validate(&format!("{path}.combine"), v);
+
+            v.check(
+                frames.is_none(),
+                format_args!("{path}.combine"),
+                "can't be combined with frames",
+            );
+        }
+    }
+}
+
+/// Check the settings of a map that limit and weight its partials
+fn validate_cutoffs(
+    path: &str,
+    max_frequency: Option<f64>,
+    min_amplitude: Option<f64>,
+    equal_loudness: Option<f64>,
+    v: &mut Validator,
+) {
+    if let Some(max) = max_frequency {
+        v.check(
+            max.is_finite() && max > 0.0,
+            format_args!("{path}.max_frequency"),
+            "must be a positive number of Hz",
+        );
+    }
+
+    if let Some(min) = min_amplitude {
+        v.check(
+            min.is_finite() && min >= 0.0,
+            format_args!("{path}.min_amplitude"),
+            "must be non-negative",
+        );
+    }
+
+    if let Some(level) = equal_loudness {
+        v.check(
+            level.is_finite(),
+            format_args!("{path}.equal_loudness"),
+            "must be a finite number of dB",
+        );
+    }
+}
+
+impl RenderConfig {
+    fn validate(&self, path: &str, v: &mut Validator) {
+        v.check(
+            self.tile_width > 0,
+            format_args!("{path}.tile_width"),
+            "must be non-zero",
+        );
+        v.check(
+            self.tile_height > 0,
+            format_args!("{path}.tile_height"),
+            "must be non-zero",
+        );
+    }
+}
+
+impl FormatConfig {
+    fn validate(&self, path: &str, v: &mut Validator) {
+        if let Some(limit) = self.ji_limit {
+            v.check(
+                limit % 2 == 1,
+                format_args!("{path}.ji_limit"),
+                "must be an odd number",
+            );
+        }
+
+        if let Some(edo) = self.edo_grid {
+            v.check(edo > 0, format_args!("{path}.edo_grid"), "must be non-zero");
+        }
+    }
+}
+
+impl GenerateConfig {
+    fn to_commented(&self) -> Result<String> {
+        let mut out = String::from("(\n");
+
+        write_commented_field(
+            &mut out,
+            " Config schema version, used to upgrade configs from older releases",
+            "version",
+            &self.version.to_string(),
+        )?;
+        write_commented_field(&mut out, "", "map", &self.map.to_commented()?)?;
+        write_commented_field(&mut out, "", "render", &self.render.to_commented()?)?;
+        write_commented_field(&mut out, "", "format", &self.format.to_commented()?)?;
+
+        out.push(')');
+
+        Ok(out)
+    }
+
+    /// Check the config for values that would produce a nonsensical map,
+    /// reporting every violation at once
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut v = Validator::default();
+
+        self.map.validate("map", &mut v);
+        self.render.validate("render", &mut v);
+        self.format.validate("format", &mut v);
+
+        for (i, (_, map)) in self.components.iter().enumerate() {
+            let path = format!("map.combine.components[{i}]");
+
+            map.validate(&path, &mut v);
+            v.check(
+                map.width == self.map.width && map.height == self.map.height,
+                &path,
+                "must have the same size as the combined map",
+            );
+        }
+
+        v.finish()
+    }
+
+    /// Scale map dimensions by `factor`, rounding to the nearest pixel
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn scale_size(width: u32, height: u32, factor: f64) -> Result<(u32, u32)> {
+        let w = (f64::from(width) * factor).round();
+        let h = (f64::from(height) * factor).round();
+
+        if !(w.is_normal() && h.is_normal()) {
+            return Err(anyhow!("couldn't calculate new map size for override"));
+        }
+
+        Ok((w as u32, h as u32))
+    }
+
+    fn override_size(size: &SizeOverride, map: &mut MapConfig) -> Result<()> {
+        let MapConfig { width, height, .. } = map;
+
+        match size {
+            SizeOverride::Width(w) => {
+                let h = (f64::from(*w) * f64::from(*height) / f64::from(*width)).round();
+
+                if !h.is_normal() {
+                    return Err(anyhow!("couldn't calculate new map height for override"));
+                }
+
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                {
+                    *width = *w;
+                    *height = h as u32;
+                }
+            },
+            SizeOverride::Height(h) => {
+                let w = (f64::from(*h) * f64::from(*width) / f64::from(*height)).round();
+
+                if !w.is_normal() {
+                    return Err(anyhow!("couldn't calculate new map width for override"));
+                }
+
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                {
+                    *width = w as u32;
+                    *height = *h;
+                }
+            },
+            SizeOverride::Exact(w, h) => {
+                *width = *w;
+                *height = *h;
+            },
+            SizeOverride::Percent(p) => {
+                if *p < 1e-7 {
+                    return Err(anyhow!(
+                        "invalid percentage for map size override, must be non-negative"
+                    ));
+                }
+
+                let w = (f64::from(*width) * *p).round();
+                let h = (f64::from(*height) * p).round();
+
+                if !(w.is_normal() && h.is_normal()) {
+                    return Err(anyhow!("couldn't calculate new map size for override"));
+                }
+
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                {
+                    *width = w as u32;
+                    *height = h as u32;
+                }
+            },
+            SizeOverride::Megapixels(mp) => {
+                if !(mp.is_finite() && *mp > 0.0) {
+                    return Err(anyhow!(
+                        "invalid megapixel count for map size override, must be positive"
+                    ));
+                }
+
+                let pixels = f64::from(*width) * f64::from(*height);
+                let (w, h) = Self::scale_size(*width, *height, (mp * 1e6 / pixels).sqrt())?;
+
+                *width = w;
+                *height = h;
+            },
+            SizeOverride::Max(max) => {
+                let longest = (*width).max(*height);
+
+                if longest > *max {
+                    let factor = f64::from(*max) / f64::from(longest);
+                    let (w, h) = Self::scale_size(*width, *height, factor)?;
+
+                    *width = w.min(*max);
+                    *height = h.min(*max);
+                }
+            },
+            SizeOverride::Aspect {
+                ratio: (a, b),
+                len,
+                vertical,
+            } => {
+                if *a == 0 || *b == 0 {
+                    return Err(anyhow!("invalid aspect ratio for map size override"));
+                }
+
+                *width = *a;
+                *height = *b;
+
+                let size = if *vertical {
+                    SizeOverride::Height(*len)
+                } else {
+                    SizeOverride::Width(*len)
+                };
+
+                return Self::override_size(&size, map);
+            },
+        }
+
+        Ok(())
+    }
+
+    pub fn read(opts: &ConfigOpts) -> Result<Self> {
+        let mut cfg = Self::read_preset(opts, None)?;
+
+        if let Some(ref combine) = cfg.map.combine {
+            cfg.components = combine
+                .components
+                .iter()
+                .map(|(w, name)| {
+                    Self::read_preset(opts, Some(name))
+                        .map(|c| (*w, c.map))
+                        .with_context(|| format!("failed to read component preset {name:?}"))
+                })
+                .collect::<Result<_>>()?;
+        }
+
+        cfg.validate()?;
+
+        Ok(cfg)
+    }
+
+    /// Read the config, applying the preset given on the command line and
+    /// then the given component preset, if any
+    fn read_preset(opts: &ConfigOpts, component: Option<&str>) -> Result<Self> {
+        let ConfigOpts {
+            config,
+            preset,
+            size,
+            tile_width,
+            tile_height,
+            threads,
+            precision,
+        } = opts;
+
+        let mut patch = GenerateConfigPatch::load(config, &mut vec![])?;
+
+        if let Some(preset) = preset {
+            patch.apply_preset(preset)?;
+        }
+
+        if let Some(preset) = component {
+            patch.apply_preset(preset)?;
+        }
+
+        let mut cfg = patch.resolve()?;
+
+        cfg.map.apply_steps();
+
+        if let Some(size) = size {
+            Self::override_size(size, &mut cfg.map)?;
+        }
+
+        if let Some(w) = tile_width {
+            cfg.render.tile_width = *w;
+        }
+
+        if let Some(h) = tile_height {
+            cfg.render.tile_height = *h;
+        }
+
+        if let Some(t) = threads {
+            cfg.render.threads = *t;
+        }
+
+        if let Some(p) = precision {
+            cfg.map.precision = *p;
+        }
+
+        Ok(cfg)
+    }
+}
+
+/// A set of maps to precompute into the cache with `cache warm`.  Every
+/// combination of the listed values is applied over the map settings of a
+/// config; empty lists keep the configured value.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sweep {
+    /// The config file to sweep, relative to the sweep file
+    pub config: PathBuf,
+    /// A preset from the config file to apply before sweeping
+    #[serde(default)]
+    pub preset: Option<String>,
+    /// Map sizes as (width, height) pairs
+    #[serde(default)]
+    pub sizes: Vec<(u32, u32)>,
+    #[serde(default)]
+    pub base_frequencies: Vec<Frequency>,
+    #[serde(default)]
+    pub pitch_curves: Vec<PitchCurve>,
+    #[serde(default)]
+    pub overlap_curves: Vec<OverlapCurve>,
+}
+
+impl Sweep {
+    pub fn read(path: &Path) -> Result<Self> {
+        let s = fs::read_to_string(path)
+            .with_context(|| format!("failed to read sweep file {}", path.display()))?;
+        let mut sweep: Self = ConfigFormat::for_path(path)?
+            .parse(&s)
+            .with_context(|| format!("failed to parse sweep file {}", path.display()))?;
+
+        sweep.config = path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&sweep.config);
+
+        Ok(sweep)
+    }
+
+    /// Options to read the swept config with
+    pub fn config_opts(&self) -> ConfigOpts {
+        ConfigOpts {
+            config: self.config.clone(),
+            preset: self.preset.clone(),
+            size: None,
+            tile_width: None,
+            tile_height: None,
+            threads: None,
+            precision: None,
+        }
+    }
+
+    /// Apply every combination of swept values to the given map settings,
+    /// checking each result
+    pub fn apply(&self, map: &MapConfig) -> Result<Vec<MapConfig>> {
+        fn or_keep<T: Clone>(vals: &[T]) -> Vec<Option<T>> {
+            if vals.is_empty() {
+                vec![None]
+            } else {
+                vals.iter().cloned().map(Some).collect()
+            }
+        }
+
+        let mut v = Validator::default();
+        let mut ret = vec![];
+
+        for (size, base, pitch, overlap) in iproduct!(
+            or_keep(&self.sizes),
+            or_keep(&self.base_frequencies),
+            or_keep(&self.pitch_curves),
+            or_keep(&self.overlap_curves)
+        ) {
+            let mut map = map.clone();
+
+            if let Some((w, h)) = size {
+                map.width = w;
+                map.height = h;
+            }
+
+            if let Some(b) = base {
+                map.base_frequency = b;
+            }
+
+            if let Some(p) = pitch {
+                map.pitch_curve = p;
+            }
+
+            if let Some(o) = overlap {
+                map.overlap_curve = o;
+            }
+
+            map.validate(&format!("sweep[{}]", ret.len()), &mut v);
+            ret.push(map);
+        }
+
+        v.finish()?;
+
+        Ok(ret)
+    }
+}
+
+/// Serialize any part of a config as pretty-printed RON
+pub fn to_ron(val: &impl Serialize) -> Result<String> {
+    ron::ser::to_string_pretty(val, pretty_config()).context("failed to serialize config")
+}
+
+pub fn print_defaults(opts: &PrintDefaultsOpts) -> Result<()> {
+    let PrintDefaultsOpts { section, comments } = *opts;
+    let cfg = GenerateConfig::default();
+
+    let s = if comments {
+        match section {
+            None => cfg.to_commented(),
+            Some(ConfigSection::Map) => cfg.map.to_commented(),
+            Some(ConfigSection::Render) => cfg.render.to_commented(),
+            Some(ConfigSection::Format) => cfg.format.to_commented(),
+        }
+    } else {
+        match section {
+            None => ron::ser::to_string_pretty(&cfg, pretty_config()),
+            Some(ConfigSection::Map) => ron::ser::to_string_pretty(&cfg.map, pretty_config()),
+            Some(ConfigSection::Render) => ron::ser::to_string_pretty(&cfg.render, pretty_config()),
+            Some(ConfigSection::Format) => ron::ser::to_string_pretty(&cfg.format, pretty_config()),
+        }
+        .map_err(Error::from)
+    }
+    .context("failed to serialize default config")?;
+
+    let mut stream = stdout();
+
+    write!(stream, "{s}").context("failed to write default config")?;
+
+    if atty::is(atty::Stream::Stdout) {
+        writeln!(stream).context("failed to write trailing newline")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{
+        AmpWeighting, AxisRange, ConfigFormat, GenerateConfigPatch, Precision, CONFIG_VERSION,
+    };
+
+    #[test]
+    fn version_zero_migrates_to_latest() {
+        let patch: GenerateConfigPatch = ConfigFormat::Toml
+            .parse(
+                "[map]\nwidth = 8\nheight = 8\nbase_frequency = 220.0\n\
+                 pitch_curve = \"Logarithmic\"\noverlap_curve = \"ExponentialDissonance\"\n",
+            )
+            .unwrap();
+
+        let mut migrated = GenerateConfigPatch::legacy_defaults(0);
+        migrated.merge(patch);
+
+        let cfg = migrated.resolve().unwrap();
+        assert!(matches!(cfg.map.amp_weighting, AmpWeighting::Linear));
+        assert!(matches!(cfg.map.precision, Precision::Double));
+        assert_eq!(cfg.render.threads, 0);
+        assert!(!cfg.map.normalize);
+        assert!(cfg.map.post_transforms.is_empty());
+        assert!(cfg.map.combine.is_none());
+    }
+
+    #[test]
+    fn migration_keeps_set_fields() {
+        let patch: GenerateConfigPatch = ConfigFormat::Toml
+            .parse("version = 13\n[map]\nnormalize = true\n[render]\nthreads = 3\n")
+            .unwrap();
+
+        let mut migrated = GenerateConfigPatch::legacy_defaults(13);
+        migrated.merge(patch);
+
+        assert_eq!(migrated.map.normalize, Some(true));
+        assert_eq!(migrated.render.threads, Some(3));
+        // Fields from before version 13 are left for the defaults to fill in
+        assert_eq!(migrated.map.tuning_reference, None);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn unversioned_config_keeps_included_fields() {
+        let dir = env::temp_dir().join(format!("disson-unversioned-{}", process::id()));
+        let (base, top) = (dir.join("base.toml"), dir.join("top.toml"));
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            &base,
+            "[map]\nx_range = { Cents = [0.0, 700.0] }\n[render]\nthreads = 3\n",
+        )
+        .unwrap();
+        fs::write(&top, "include = [\"base.toml\"]\n[map]\nwidth = 8\n").unwrap();
+
+        let loaded = GenerateConfigPatch::load(&top, &mut vec![]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.version, Some(CONFIG_VERSION));
+        assert_eq!(loaded.map.width, Some(8));
+        assert!(
+            matches!(loaded.map.x_range, Some(AxisRange::Cents(l, h)) if l == 0.0 && h == 700.0)
+        );
+        assert_eq!(loaded.render.threads, Some(3));
+        // Fields neither file sets still get their version 0 behavior
+        assert!(
+            matches!(loaded.map.y_range, Some(AxisRange::Octaves(l, h)) if l == 0.0 && h == 1.0)
+        );
+        assert!(matches!(loaded.map.amp_weighting, Some(AmpWeighting::Linear)));
+    }
+
+    #[test]
+    fn includes_migrate_from_their_own_version() {
+        let dir = env::temp_dir().join(format!("disson-migrate-{}", process::id()));
+        let (base, top) = (dir.join("base.toml"), dir.join("top.toml"));
+
+        fs::create_dir_all(&dir).unwrap();
+        // Version 13 predates normalization, so it never normalized maps
+        fs::write(&base, "version = 13\n[map]\nwidth = 8\n").unwrap();
+        fs::write(
+            &top,
+            format!("version = {CONFIG_VERSION}\ninclude = [\"base.toml\"]\n"),
+        )
+        .unwrap();
+
+        let loaded = GenerateConfigPatch::load(&top, &mut vec![]);
+
+        fs::write(&base, format!("version = {}\n", CONFIG_VERSION + 1)).unwrap();
+        let newer = GenerateConfigPatch::load(&top, &mut vec![]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.version, Some(CONFIG_VERSION));
+        assert_eq!(loaded.map.width, Some(8));
+        assert_eq!(loaded.map.normalize, Some(false));
+
+        assert!(newer.is_err());
+    }
+}
@@ -0,0 +1,342 @@
+//! Rewriting config files written against an older schema version
+
+use std::{cmp::Reverse, fs, iter, ops::Range, path::Path};
+
+use log::{info, warn};
+
+use super::{AddedField, ConfigFormat, GenerateConfigPatch, CONFIG_VERSION};
+use crate::{cli::UpgradeConfigOpts, error::prelude::*};
+
+/// The fields `legacy_defaults` fills in for a config file, by section
+struct Upgrade {
+    from: u32,
+    sections: Vec<(&'static str, Vec<AddedField>)>,
+}
+
+impl Upgrade {
+    /// Find the defaults a config file relies on from its schema version
+    /// that neither it nor any of its includes set.  Includes themselves are
+    /// left alone, since their own versions are still honored when loading.
+    fn of(path: &Path) -> Result<Self> {
+        let own = GenerateConfigPatch::parse(path)?;
+        let from = own.check_version(path)?;
+        let (layers, _) = GenerateConfigPatch::load_layers(path, &mut vec![])?;
+        let legacy = GenerateConfigPatch::legacy_defaults(from);
+
+        Ok(Self {
+            from,
+            sections: vec![
+                ("map", layers.map.added_fields(&legacy.map)?),
+                ("render", layers.render.added_fields(&legacy.render)?),
+                ("format", layers.format.added_fields(&legacy.format)?),
+            ],
+        })
+    }
+
+    /// Lines declaring the fields added to one section, preceded by a comment
+    fn lines(&self, fields: &[AddedField]) -> Vec<String> {
+        iter::once(format!(
+            "// Filled in by upgrade-config to keep the behavior of version {}",
+            self.from
+        ))
+        .chain(fields.iter().map(|f| format!("{}: {},", f.name, f.ron)))
+        .collect()
+    }
+}
+
+/// A RON struct in a document, with the byte ranges of its fields' values
+struct RonStruct {
+    open: usize,
+    close: usize,
+    fields: Vec<(String, Range<usize>)>,
+}
+
+/// Find the brackets, commas, and colons of a RON document outside of
+/// strings, comments, and attributes, along with their byte offsets
+fn ron_tokens(text: &str) -> Vec<(usize, u8)> {
+    let bytes = text.as_bytes();
+    let mut ret = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i += 1;
+
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+
+                    i += 1;
+                }
+            },
+            b'\'' if bytes.get(i + 2) == Some(&b'\'') => i += 2,
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            },
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = text[i + 2..].find("*/").map_or(bytes.len(), |j| i + j + 3);
+            },
+            b'#' => i = text[i..].find(']').map_or(bytes.len(), |j| i + j),
+            b'(' | b')' | b'[' | b']' | b'{' | b'}' | b',' | b':' => ret.push((i, bytes[i])),
+            _ => (),
+        }
+
+        i += 1;
+    }
+
+    ret
+}
+
+/// Read the struct opened by the token at `start`
+fn ron_struct(text: &str, tokens: &[(usize, u8)], start: usize) -> Result<RonStruct> {
+    let open = tokens[start].0;
+    let mut depth = 0_usize;
+    let mut fields = vec![];
+    let mut field: Option<(String, usize)> = None;
+
+    for &(pos, tok) in &tokens[start + 1..] {
+        match tok {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' if depth > 0 => depth -= 1,
+            b')' | b',' if depth == 0 => {
+                if let Some((name, val)) = field.take() {
+                    fields.push((name, val..pos));
+                }
+
+                if tok == b')' {
+                    return Ok(RonStruct {
+                        open,
+                        close: pos,
+                        fields,
+                    });
+                }
+            },
+            b':' if depth == 0 => {
+                let name = text[..pos].trim_end();
+                let ident = name
+                    .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .map_or(name, |i| &name[i + 1..]);
+
+                field = Some((ident.to_owned(), pos + 1));
+            },
+            _ => (),
+        }
+    }
+
+    Err(anyhow!("unterminated struct in config file"))
+}
+
+/// The indentation of the line containing `pos`
+fn indent_at(text: &str, pos: usize) -> &str {
+    let line = &text[text[..pos].rfind('\n').map_or(0, |i| i + 1)..];
+
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Insert new lines at the end of a struct, after its last field
+fn ron_append(
+    text: &str,
+    st: &RonStruct,
+    lines: &[String],
+    edits: &mut Vec<(Range<usize>, String)>,
+) {
+    let indent = match st.fields.first() {
+        Some((_, val)) => indent_at(text, val.start).to_owned(),
+        None => format!("{}    ", indent_at(text, st.open)),
+    };
+    let mut insert = String::new();
+
+    for line in lines {
+        insert.push('\n');
+        insert.push_str(&indent);
+        insert.push_str(&line.replace('\n', &format!("\n{indent}")));
+    }
+
+    let pos = match st.fields.last() {
+        Some((_, val)) => {
+            let after = &text[val.end..st.close];
+
+            if after.trim_start().starts_with(',') {
+                // Keep any comment trailing the last field on its line
+                let comma = val.end + after.find(',').unwrap();
+
+                text[comma..st.close]
+                    .find('\n')
+                    .map_or(comma + 1, |i| comma + i)
+            } else {
+                insert.insert(0, ',');
+                val.start + text[val.clone()].trim_end().len()
+            }
+        },
+        None => st.open + 1,
+    };
+
+    edits.push((pos..pos, insert));
+}
+
+/// Rewrite a RON config in place, leaving its comments and formatting intact
+fn upgrade_ron(text: &str, upgrade: &Upgrade) -> Result<String> {
+    let tokens = ron_tokens(text);
+    let top_idx = tokens
+        .iter()
+        .position(|(_, t)| *t == b'(')
+        .ok_or_else(|| anyhow!("config file doesn't contain a struct"))?;
+    let top = ron_struct(text, &tokens, top_idx)?;
+    let mut edits = vec![];
+    let mut top_lines = vec![];
+
+    match top.fields.iter().find(|(n, _)| n == "version") {
+        Some((_, val)) => edits.push((val.clone(), format!(" {CONFIG_VERSION}"))),
+        None => top_lines.push(format!("version: {CONFIG_VERSION},")),
+    }
+
+    for (section, fields) in &upgrade.sections {
+        if fields.is_empty() {
+            continue;
+        }
+
+        let lines = upgrade.lines(fields);
+
+        match top.fields.iter().find(|(n, _)| n == section) {
+            Some((_, val)) => {
+                let start = val.end - text[val.clone()].trim_start().len();
+                let idx = tokens
+                    .iter()
+                    .position(|(p, t)| *p == start && *t == b'(')
+                    .ok_or_else(|| anyhow!("expected {} to be a struct", section))?;
+
+                ron_append(text, &ron_struct(text, &tokens, idx)?, &lines, &mut edits);
+            },
+            None => top_lines.push(format!(
+                "{}: (\n    {}\n),",
+                section,
+                lines.join("\n").replace('\n', "\n    ")
+            )),
+        }
+    }
+
+    if !top_lines.is_empty() {
+        ron_append(text, &top, &top_lines, &mut edits);
+    }
+
+    edits.sort_by_key(|(r, _)| Reverse(r.start));
+
+    let mut ret = text.to_owned();
+
+    for (range, s) in edits {
+        ret.replace_range(range, &s);
+    }
+
+    Ok(ret)
+}
+
+/// Rewrite a TOML, YAML, or JSON config by parsing it and serializing it
+/// again.  Comments are lost, and null values are left out of TOML files
+/// since it has no way to express them.  Optional fields left out of a config
+/// are read back as null.
+fn upgrade_value(text: &str, format: ConfigFormat, upgrade: &Upgrade) -> Result<String> {
+    let mut doc: serde_json::Value = format.parse(text)?;
+    let obj = doc
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("config file doesn't contain a table"))?;
+
+    obj.insert("version".into(), CONFIG_VERSION.into());
+
+    for (section, fields) in &upgrade.sections {
+        if fields.is_empty() {
+            continue;
+        }
+
+        let table = obj
+            .entry(*section)
+            .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()))
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("expected {} to be a table", section))?;
+
+        for field in fields {
+            if !(field.json.is_null() && matches!(format, ConfigFormat::Toml)) {
+                table.insert(field.name.into(), field.json.clone());
+            }
+        }
+    }
+
+    Ok(match format {
+        ConfigFormat::Ron => unreachable!("RON configs are upgraded as text"),
+        ConfigFormat::Toml => toml::to_string_pretty(&toml::Value::try_from(&doc)?)?,
+        ConfigFormat::Yaml => serde_yaml::to_string(&doc)?,
+        ConfigFormat::Json => serde_json::to_string_pretty(&doc)? + "\n",
+    })
+}
+
+pub fn upgrade_config(opts: UpgradeConfigOpts) -> Result<()> {
+    let UpgradeConfigOpts { config, in_place } = opts;
+
+    let format = ConfigFormat::for_path(&config)?;
+    let text = fs::read_to_string(&config)
+        .with_context(|| format!("failed to open config file {}", config.display()))?;
+    let upgrade = Upgrade::of(&config)?;
+    let added: usize = upgrade.sections.iter().map(|(_, f)| f.len()).sum();
+
+    if upgrade.from == CONFIG_VERSION {
+        info!("Config is already at the latest version {CONFIG_VERSION}");
+    } else {
+        info!(
+            "Upgrading config from version {} to {}, filling in {} field(s)",
+            upgrade.from, CONFIG_VERSION, added
+        );
+    }
+
+    let ret = match format {
+        ConfigFormat::Ron => upgrade_ron(&text, &upgrade)?,
+        format => {
+            warn!("Comments are only preserved when upgrading RON configs");
+
+            upgrade_value(&text, format, &upgrade)?
+        },
+    };
+
+    if in_place {
+        fs::write(&config, ret).with_context(|| format!("failed to write {}", config.display()))
+    } else {
+        print!("{ret}");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{upgrade_value, Upgrade};
+    use crate::config::{ConfigFormat, GenerateConfigPatch, CONFIG_VERSION};
+
+    #[test]
+    fn upgraded_toml_loads() {
+        let dir = env::temp_dir().join(format!("disson-upgrade-{}", process::id()));
+        let path = dir.join("v0.toml");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            &path,
+            "[map]\nwidth = 8\nheight = 8\nbase_frequency = 220.0\n\
+             pitch_curve = \"Logarithmic\"\noverlap_curve = \"ExponentialDissonance\"\n",
+        )
+        .unwrap();
+
+        let upgrade = Upgrade::of(&path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, upgrade_value(&text, ConfigFormat::Toml, &upgrade).unwrap()).unwrap();
+
+        let parsed = GenerateConfigPatch::parse(&path);
+        let loaded = GenerateConfigPatch::load(&path, &mut vec![]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(parsed.unwrap().version, Some(CONFIG_VERSION));
+        loaded.unwrap().resolve().unwrap();
+    }
+}
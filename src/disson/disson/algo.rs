@@ -1,8 +1,25 @@
 use std::iter::FromIterator;
 
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
-use super::wave::Partial;
+use super::{
+    real::Real,
+    simd::{Backend, Kernel},
+    wave::{Partial, Wave},
+};
+use crate::config::Validator;
+
+/// How many pairs of partials packed loops work on at once, enough to fill a
+/// 256-bit vector register with f32s
+const LANES: usize = 8;
+
+/// Constants of Sethares' curve
+const SETHARES_X_STAR: f64 = 0.24;
+const SETHARES_S1: f64 = 0.0207;
+const SETHARES_S2: f64 = 18.96;
+const SETHARES_B1: f64 = 3.5;
+const SETHARES_B2: f64 = 5.75;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum PitchCurve {
@@ -10,9 +27,11 @@ pub enum PitchCurve {
     Edo,
     #[serde(rename = "ErbRate")]
     Erb,
+    /// Critical-band rate, per Traunmüller's approximation of the Bark scale
+    Bark,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum OverlapCurve {
     #[serde(rename = "ExponentialDissonance")]
     ExpDiss,
@@ -22,98 +41,813 @@ pub enum OverlapCurve {
     TriCons,
     #[serde(rename = "TrapezoidConsonance")]
     TrapCons,
+    /// Sethares' parameterization of the Plomp-Levelt curve, scaled by the
+    /// critical bandwidth at the lower partial.  Operates on frequencies in
+    /// Hz, so the pitch curve is not applied.
+    Sethares,
+    /// A weighted sum of other curves
+    Blend(Vec<(f64, OverlapCurve)>),
+}
+
+/// How the amplitudes of two partials scale their overlap
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum AmpWeighting {
+    /// Product of the linear amplitudes
+    Linear,
+    /// Product of the partials' powers (squared amplitudes)
+    Power,
+    /// Product of the partials' loudnesses, per Stevens' power law
+    Loudness,
+}
+
+/// Approximate auditory masking between partials of the combined spectrum
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Masking {
+    /// How much louder, in dB, a partial must be than a neighbor within the
+    /// same critical band to mask it
+    pub threshold: f64,
+    /// Drop masked partials entirely instead of attenuating them
+    pub drop: bool,
+}
+
+impl Masking {
+    /// Width of the critical band around a frequency, using the ERB formula
+    /// of Glasberg and Moore
+    fn bandwidth<T: Real>(hz: T) -> T { T::of(24.7) * (T::of(4.37e-3) * hz + T::one()) }
+
+    pub fn validate(&self, path: &str, v: &mut Validator) {
+        v.check(
+            self.threshold.is_finite() && self.threshold >= 0.0,
+            format_args!("{path}.threshold"),
+            "must be a non-negative number of dB",
+        );
+    }
+
+    /// Attenuate or drop every partial lying within the critical band of a
+    /// sufficiently louder partial.  Partial pitches must be in Hz.
+    ///
+    /// Attenuation falls off linearly with distance from the masker, so a
+    /// partial in unison with its masker is silenced and one at the edge of
+    /// the band is unaffected.
+    pub fn apply<T: Real>(&self, it: impl IntoIterator<Item = Partial<T>>) -> Vec<Partial<T>> {
+        let partials: Vec<_> = it.into_iter().collect();
+        let ratio = T::of(10.0_f64.powf(self.threshold / 20.0));
+
+        partials
+            .iter()
+            .filter_map(|p| {
+                let gain = partials
+                    .iter()
+                    .filter(|m| m.amp > p.amp * ratio)
+                    .map(|m| ((p.pitch - m.pitch).abs() / Self::bandwidth(m.pitch)).min(T::one()))
+                    .fold(T::one(), T::min);
+
+                if gain >= T::one() {
+                    Some(*p)
+                } else if self.drop {
+                    None
+                } else {
+                    Some(Partial {
+                        amp: p.amp * gain,
+                        ..*p
+                    })
+                }
+            })
+            .collect()
+    }
+}
+
+/// Synthesized intermodulation products between the partials of different
+/// voices
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CombinationTones {
+    /// Number of the loudest partials of each voice to combine
+    pub partials: u32,
+    /// Level of the difference tone f2 - f1, relative to the product of the
+    /// source amplitudes, or 0 to disable it
+    pub difference: f64,
+    /// Level of the cubic difference tone 2f1 - f2, or 0 to disable it
+    pub cubic: f64,
+    /// Level of the summation tone f1 + f2, or 0 to disable it
+    pub summation: f64,
+}
+
+impl CombinationTones {
+    pub fn validate(&self, path: &str, v: &mut Validator) {
+        let Self {
+            partials,
+            difference,
+            cubic,
+            summation,
+        } = *self;
+
+        v.check(
+            partials > 0,
+            format_args!("{path}.partials"),
+            "must be non-zero",
+        );
+
+        for (name, val) in &[
+            ("difference", difference),
+            ("cubic", cubic),
+            ("summation", summation),
+        ] {
+            v.check(
+                val.is_finite() && *val >= 0.0,
+                format_args!("{path}.{name}"),
+                "must be non-negative",
+            );
+        }
+    }
+
+    /// Generate combination tones between every pair of voices.  Partial
+    /// pitches, both of the voices and of the returned partials, are in Hz.
+    pub fn generate<T: Real>(&self, voices: &[&Wave<T>]) -> Vec<Partial<T>> {
+        let loudest: Vec<Vec<_>> = voices
+            .iter()
+            .map(|w| {
+                let mut v: Vec<_> = w.iter().copied().collect();
+                v.sort_by(|a, b| b.amp.widen().total_cmp(&a.amp.widen()));
+                v.truncate(self.partials as usize);
+                v
+            })
+            .collect();
+
+        // Each tone is k_lo * f_lo + k_hi * f_hi
+        let tones = [
+            (T::of(self.difference), -T::one(), T::one()),
+            (T::of(self.cubic), T::of(2.0), -T::one()),
+            (T::of(self.summation), T::one(), T::one()),
+        ];
+
+        let mut ret = vec![];
+
+        for (i, voice_a) in loudest.iter().enumerate() {
+            for voice_b in &loudest[i + 1..] {
+                for a in voice_a {
+                    for b in voice_b {
+                        let (lo, hi) = (a.pitch.min(b.pitch), a.pitch.max(b.pitch));
+                        let amp = a.amp * b.amp;
+
+                        ret.extend(tones.iter().filter_map(|&(level, k_lo, k_hi)| {
+                            let pitch = k_lo * lo + k_hi * hi;
+
+                            if level > T::zero() && pitch > T::zero() {
+                                Some(Partial {
+                                    pitch,
+                                    amp: amp * level,
+                                })
+                            } else {
+                                None
+                            }
+                        }));
+                    }
+                }
+            }
+        }
+
+        ret
+    }
+}
+
+impl AmpWeighting {
+    /// The weight of partials with amplitudes a1 and a2, as written out for
+    /// explain
+    pub fn formula(self) -> &'static str {
+        match self {
+            Self::Linear => "a1 a2",
+            Self::Power => "(a1 a2)^2",
+            Self::Loudness => "(a1 a2)^0.6",
+        }
+    }
+
+    pub fn eval<T: Real>(self, a: T, b: T) -> T {
+        match self {
+            Self::Linear => a * b,
+            Self::Power => (a * b).powi(2),
+            Self::Loudness => (a * b).powf(T::of(0.6)),
+        }
+    }
+
+    /// The factor contributed by one partial of amplitude a, such that the
+    /// weight of a pair is the product of both partials' factors.  This holds
+    /// for every weighting, since amplitudes are non-negative.
+    pub fn gain<T: Real>(self, a: T) -> T {
+        match self {
+            Self::Linear => a,
+            Self::Power => a.powi(2),
+            Self::Loudness => a.powf(T::of(0.6)),
+        }
+    }
+}
+
+/// Partials laid out as separate arrays of pitches and amplitude weights, so
+/// that the overlap of every pair of them can be summed a vector of lanes at
+/// a time.  The arrays are padded to a whole number of lanes with silent
+/// partials.
+#[derive(Debug, Clone, Default)]
+pub struct Packed<T> {
+    pitch: Vec<T>,
+    gain: Vec<T>,
+}
+
+impl<T: Real> Packed<T> {
+    /// Replace the packed partials, reusing the storage of the arrays
+    pub fn pack<'a>(&mut self, it: impl IntoIterator<Item = &'a Partial<T>>, weight: AmpWeighting) {
+        self.pitch.clear();
+        self.gain.clear();
+
+        for p in it {
+            self.pitch.push(p.pitch);
+            self.gain.push(weight.gain(p.amp));
+        }
+
+        // Padding repeats the last pitch, so that every curve stays finite on
+        // it and the zero gain cancels it out
+        if let Some(&last) = self.pitch.last() {
+            while !self.pitch.len().is_multiple_of(LANES) {
+                self.pitch.push(last);
+                self.gain.push(T::zero());
+            }
+        }
+    }
+
+    /// Sum `f` over every ordered pair of partials, weighted by their gains.
+    /// The inner loop runs over whole chunks of lanes with no branches, which
+    /// the compiler vectorizes for curves built from arithmetic, min, and
+    /// max.
+    #[inline]
+    fn sum_pairs(&self, f: impl Fn((T, T)) -> T) -> T {
+        let mut acc = [T::zero(); LANES];
+
+        for (&pa, &ga) in self.pitch.iter().zip(&self.gain) {
+            let mut row = [T::zero(); LANES];
+
+            for (pb, gb) in self.pitch.chunks_exact(LANES).zip(self.gain.chunks_exact(LANES)) {
+                for ((r, &b), &g) in row.iter_mut().zip(pb).zip(gb) {
+                    *r = *r + f((pa, b)) * g;
+                }
+            }
+
+            for (a, r) in acc.iter_mut().zip(&row) {
+                *a = *a + *r * ga;
+            }
+        }
+
+        acc.iter().copied().sum()
+    }
+
+    /// Sum a vectorized curve over every ordered pair of partials, weighted by
+    /// their gains
+    fn sum_kernel(&self, backend: Backend, kernel: Kernel<T>) -> T {
+        T::sum_kernel(backend, kernel, &self.pitch, &self.gain)
+    }
 }
 
 impl PitchCurve {
-    fn edo(hz: f64) -> f64 { hz.log2() }
+    fn edo<T: Real>(hz: T) -> T { hz.log2() }
+
+    fn erb<T: Real>(hz: T) -> T {
+        T::of(11.17268) * (T::one() + (hz * T::of(46.06538)) / (hz + T::of(14678.49))).ln()
+    }
 
-    fn erb(hz: f64) -> f64 { 11.17268 * (1.0 + (hz * 46.06538) / (hz + 14678.49)).ln() }
+    fn bark<T: Real>(hz: T) -> T {
+        let z = T::of(26.81) * hz / (T::of(1960.0) + hz) - T::of(0.53);
+
+        // Traunmüller's corrections for the ends of the scale
+        if z < T::of(2.0) {
+            z + T::of(0.15) * (T::of(2.0) - z)
+        } else if z > T::of(20.1) {
+            z + T::of(0.22) * (z - T::of(20.1))
+        } else {
+            z
+        }
+    }
 
     #[inline]
-    fn partial(f: impl Fn(f64) -> f64) -> impl Fn(Partial) -> Partial {
+    fn partial<T: Real>(f: impl Fn(T) -> T) -> impl Fn(Partial<T>) -> Partial<T> {
         move |p| Partial {
             pitch: f(p.pitch),
             ..p
         }
     }
 
-    pub fn eval(self, hz: f64) -> f64 {
+    /// The pitch p(f) of a partial at f Hz, as written out for explain
+    pub fn formula(self) -> &'static str {
         match self {
-            Self::Edo => Self::edo(hz),
-            Self::Erb => Self::erb(hz),
+            Self::Edo => "log2(f)",
+            Self::Erb => "11.17268 ln(1 + 46.06538 f / (f + 14678.49))",
+            Self::Bark => {
+                "26.81 f / (1960 + f) - 0.53, with Traunmüller's corrections below 2 and \
+                 above 20.1"
+            },
+        }
+    }
+
+    /// The revision of this curve's formula.  Bump it whenever its output
+    /// changes, so that maps cached with the old formula are recomputed.
+    pub fn version(self) -> u32 {
+        match self {
+            Self::Edo | Self::Erb | Self::Bark => 1,
         }
     }
 
-    pub fn collect<I: IntoIterator<Item = f64>>(self, it: I) -> Vec<f64> {
+    pub fn eval<T: Real>(self, hz: T) -> T {
         match self {
-            Self::Edo => it.into_iter().map(Self::edo).collect(),
-            Self::Erb => it.into_iter().map(Self::erb).collect(),
+            Self::Edo => Self::edo(hz),
+            Self::Erb => Self::erb(hz),
+            Self::Bark => Self::bark(hz),
         }
     }
 
-    pub fn collect_partials<I: IntoIterator<Item = Partial>, F: FromIterator<Partial>>(
+    pub fn collect_partials<
+        T: Real,
+        I: IntoIterator<Item = Partial<T>>,
+        F: FromIterator<Partial<T>>,
+    >(
         self,
         it: I,
     ) -> F {
         match self {
             Self::Edo => it.into_iter().map(Self::partial(Self::edo)).collect(),
             Self::Erb => it.into_iter().map(Self::partial(Self::erb)).collect(),
+            Self::Bark => it.into_iter().map(Self::partial(Self::bark)).collect(),
+        }
+    }
+}
+
+/// Tunable constants for the overlap curves
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CurveParams {
+    /// Multiplier applied to pitch-curve distances before evaluating the curve
+    pub distance_scale: f64,
+    /// Rate of `ExponentialDissonance`; the curve peaks at a distance of
+    /// 1 / `exp_rate`
+    pub exp_rate: f64,
+    /// Slope of the rising edge of `TrapezoidDissonance`
+    pub trap_rise: f64,
+    /// Distance at which the trapezoid curves reach zero
+    pub trap_end: f64,
+    /// Distance at which `TriangleConsonance` reaches zero
+    pub tri_end: f64,
+}
+
+impl Default for CurveParams {
+    fn default() -> Self {
+        Self {
+            distance_scale: 12.0,
+            exp_rate: 1.0,
+            trap_rise: 3.0,
+            trap_end: 2.0,
+            tri_end: 1.0,
+        }
+    }
+}
+
+impl CurveParams {
+    pub fn validate(&self, path: &str, v: &mut Validator) {
+        let Self {
+            distance_scale,
+            exp_rate,
+            trap_rise,
+            trap_end,
+            tri_end,
+        } = *self;
+
+        for (name, val) in &[
+            ("distance_scale", distance_scale),
+            ("exp_rate", exp_rate),
+            ("trap_rise", trap_rise),
+            ("trap_end", trap_end),
+            ("tri_end", tri_end),
+        ] {
+            v.check(
+                val.is_finite() && *val > 0.0,
+                format_args!("{path}.{name}"),
+                "must be positive",
+            );
         }
     }
 }
 
 impl OverlapCurve {
-    fn exp_diss(x: f64) -> f64 { x * (1.0 - x).exp() }
+    fn exp_diss<T: Real>(p: &CurveParams) -> impl Fn(T) -> T {
+        let k = T::of(p.exp_rate);
+        move |x| k * x * (T::one() - k * x).exp()
+    }
+
+    fn trap_diss<T: Real>(p: &CurveParams) -> impl Fn(T) -> T {
+        let (rise, end) = (T::of(p.trap_rise), T::of(p.trap_end));
+        move |x| (rise * x).min(T::one()) * (end - x).max(T::zero()).min(T::one())
+    }
+
+    fn tri_cons<T: Real>(p: &CurveParams) -> impl Fn(T) -> T {
+        let end = T::of(p.tri_end);
+        move |x| (T::one() - x / end).max(T::zero())
+    }
+
+    fn trap_cons<T: Real>(p: &CurveParams) -> impl Fn(T) -> T {
+        let end = T::of(p.trap_end);
+        move |x| (end - x).max(T::zero()).min(T::one())
+    }
 
-    fn trap_diss(x: f64) -> f64 { (3.0 * x).min(1.0) * (2.0 - x).max(0.0).min(1.0) }
+    /// Distance between two partials in Hz, scaled by the critical bandwidth
+    /// at the lower partial
+    fn sethares_distance<T: Real>((a, b): (T, T)) -> T {
+        let s = T::of(SETHARES_X_STAR) / (T::of(SETHARES_S1) * a.min(b) + T::of(SETHARES_S2));
+        (b - a).abs() * s
+    }
 
-    fn tri_cons(x: f64) -> f64 { (1.0 - x).max(0.0) }
+    fn sethares_shape<T: Real>(d: T) -> T {
+        (-T::of(SETHARES_B1) * d).exp() - (-T::of(SETHARES_B2) * d).exp()
+    }
 
-    fn trap_cons(x: f64) -> f64 { (2.0 - x).max(0.0).min(1.0) }
+    fn sethares<T: Real>(pair: (T, T)) -> T { Self::sethares_shape(Self::sethares_distance(pair)) }
 
     #[inline]
-    fn overlap(f: impl Fn(f64) -> f64) -> impl Fn((f64, f64)) -> f64 {
-        // TODO
-        move |(a, b)| f((b - a).abs() * 12.0)
+    fn overlap<T: Real>(p: &CurveParams, f: impl Fn(T) -> T) -> impl Fn((T, T)) -> T {
+        let scale = T::of(p.distance_scale);
+        move |(a, b)| f((b - a).abs() * scale)
     }
 
     #[inline]
-    fn partial(f: impl Fn(f64) -> f64) -> impl Fn((&Partial, &Partial)) -> f64 {
-        let f = Self::overlap(f);
-        move |(a, b)| f((a.pitch, b.pitch)) * a.amp * b.amp
+    fn partial<T: Real>(
+        f: impl Fn((T, T)) -> T,
+        weight: AmpWeighting,
+    ) -> impl Fn((&Partial<T>, &Partial<T>)) -> T {
+        move |(a, b)| f((a.pitch, b.pitch)) * weight.eval(a.amp, b.amp)
+    }
+
+    fn blend<'a, T: Real>(
+        curves: &'a [(f64, OverlapCurve)],
+        params: &'a CurveParams,
+    ) -> impl Fn((T, T)) -> T + 'a {
+        move |pair| {
+            curves
+                .iter()
+                .map(|(w, c)| T::of(*w) * c.eval(params, pair))
+                .sum()
+        }
     }
 
-    pub fn eval(self, pair: (f64, f64)) -> f64 {
+    /// Evaluate the curve at an already-scaled distance between two partials
+    fn shape<T: Real>(&self, params: &CurveParams, x: T) -> T {
         match self {
-            Self::ExpDiss => Self::overlap(Self::exp_diss)(pair),
-            Self::TrapDiss => Self::overlap(Self::trap_diss)(pair),
-            Self::TriCons => Self::overlap(Self::tri_cons)(pair),
-            Self::TrapCons => Self::overlap(Self::trap_cons)(pair),
+            Self::ExpDiss => Self::exp_diss(params)(x),
+            Self::TrapDiss => Self::trap_diss(params)(x),
+            Self::TriCons => Self::tri_cons(params)(x),
+            Self::TrapCons => Self::trap_cons(params)(x),
+            Self::Sethares => Self::sethares_shape(x),
+            Self::Blend(c) => c.iter().map(|(w, c)| T::of(*w) * c.shape(params, x)).sum(),
         }
     }
 
-    pub fn collect<I: IntoIterator<Item = (f64, f64)>>(self, it: I) -> Vec<f64> {
+    /// Scaled distance beyond which the curve is negligibly close to its final
+    /// value
+    fn support(&self, params: &CurveParams) -> f64 {
         match self {
-            Self::ExpDiss => it.into_iter().map(Self::overlap(Self::exp_diss)).collect(),
-            Self::TrapDiss => it.into_iter().map(Self::overlap(Self::trap_diss)).collect(),
-            Self::TriCons => it.into_iter().map(Self::overlap(Self::tri_cons)).collect(),
-            Self::TrapCons => it.into_iter().map(Self::overlap(Self::trap_cons)).collect(),
+            Self::ExpDiss => 40.0 / params.exp_rate,
+            Self::TrapDiss | Self::TrapCons => params.trap_end,
+            Self::TriCons => params.tri_end,
+            Self::Sethares => 12.0,
+            Self::Blend(c) => c
+                .iter()
+                .map(|(_, c)| c.support(params))
+                .fold(0.0, f64::max),
         }
     }
 
-    pub fn collect_partials<
-        'a,
-        I: IntoIterator<Item = (&'a Partial, &'a Partial)>,
-        F: FromIterator<f64>,
-    >(
-        self,
-        it: I,
-    ) -> F {
+    /// Sample this curve into a lookup table with the given number of
+    /// intervals
+    pub fn table<T: Real>(&self, params: &CurveParams, resolution: u32) -> CurveTable<T> {
+        let support = self.support(params);
+        let res = f64::from(resolution);
+
+        CurveTable {
+            hz: self.uses_hz(),
+            distance_scale: T::of(params.distance_scale),
+            density: T::of(res / support),
+            values: (0..=resolution)
+                .map(|i| self.shape(params, T::of(f64::from(i) * support / res)))
+                .collect(),
+        }
+    }
+
+    /// The overlap d(x) of two partials a scaled distance x apart, with the
+    /// given constants filled in, as written out for explain
+    pub fn formula(&self, params: &CurveParams) -> String {
+        let CurveParams {
+            exp_rate,
+            trap_rise,
+            trap_end,
+            tri_end,
+            ..
+        } = *params;
+
         match self {
-            Self::ExpDiss => it.into_iter().map(Self::partial(Self::exp_diss)).collect(),
-            Self::TrapDiss => it.into_iter().map(Self::partial(Self::trap_diss)).collect(),
-            Self::TriCons => it.into_iter().map(Self::partial(Self::tri_cons)).collect(),
-            Self::TrapCons => it.into_iter().map(Self::partial(Self::trap_cons)).collect(),
+            Self::ExpDiss => format!("{exp_rate} x exp(1 - {exp_rate} x)"),
+            Self::TrapDiss => format!("min({trap_rise} x, 1) clamp({trap_end} - x, 0, 1)"),
+            Self::TriCons => format!("max(1 - x / {tri_end}, 0)"),
+            Self::TrapCons => format!("clamp({trap_end} - x, 0, 1)"),
+            Self::Sethares => "exp(-3.5 x) - exp(-5.75 x), where x = |f2 - f1| 0.24 / (0.0207 \
+                               min(f1, f2) + 18.96)"
+                .into(),
+            Self::Blend(c) => c
+                .iter()
+                .map(|(w, c)| format!("{} [{}]", w, c.formula(params)))
+                .join(" + "),
+        }
+    }
+
+    /// The revision of this curve's formula.  Bump it whenever its output
+    /// changes, so that maps cached with the old formula are recomputed.
+    /// Blends change whenever any of their components do.
+    pub fn version(&self) -> u32 {
+        match self {
+            Self::ExpDiss | Self::TrapDiss | Self::TriCons | Self::TrapCons | Self::Sethares => 1,
+            Self::Blend(c) => 1 + c.iter().map(|(_, c)| c.version()).sum::<u32>(),
+        }
+    }
+
+    /// Whether this curve expects partial pitches in Hz rather than the
+    /// output of a pitch curve
+    pub fn uses_hz(&self) -> bool {
+        match self {
+            Self::Sethares => true,
+            Self::Blend(c) => c.iter().any(|(_, c)| c.uses_hz()),
+            _ => false,
+        }
+    }
+
+    pub fn validate(&self, path: &str, v: &mut Validator) {
+        if let Self::Blend(curves) = self {
+            v.check(!curves.is_empty(), path, "must blend at least one curve");
+
+            let hz = self.uses_hz();
+
+            for (i, (w, c)) in curves.iter().enumerate() {
+                v.check(
+                    w.is_finite(),
+                    format_args!("{path}[{i}].0"),
+                    "weight must be finite",
+                );
+                v.check(
+                    c.uses_hz() == hz,
+                    format_args!("{path}[{i}].1"),
+                    "Sethares can't be blended with curves that use the pitch curve",
+                );
+
+                c.validate(&format!("{path}[{i}].1"), v);
+            }
+        }
+    }
+
+    pub fn eval<T: Real>(&self, params: &CurveParams, pair: (T, T)) -> T {
+        match self {
+            Self::ExpDiss => Self::overlap(params, Self::exp_diss(params))(pair),
+            Self::TrapDiss => Self::overlap(params, Self::trap_diss(params))(pair),
+            Self::TriCons => Self::overlap(params, Self::tri_cons(params))(pair),
+            Self::TrapCons => Self::overlap(params, Self::trap_cons(params))(pair),
+            Self::Sethares => Self::sethares(pair),
+            Self::Blend(c) => Self::blend(c, params)(pair),
+        }
+    }
+
+    /// This curve in the form the vectorized kernels evaluate, if it has one
+    fn kernel<T: Real>(&self, params: &CurveParams) -> Option<Kernel<T>> {
+        let scale = T::of(params.distance_scale);
+
+        Some(match self {
+            Self::ExpDiss => Kernel::ExpDiss {
+                scale: T::of(params.distance_scale * params.exp_rate),
+            },
+            Self::TrapDiss => Kernel::TrapDiss {
+                scale,
+                rise: T::of(params.trap_rise),
+                end: T::of(params.trap_end),
+            },
+            Self::TriCons => Kernel::TriCons {
+                scale: T::of(params.distance_scale / params.tri_end),
+            },
+            Self::TrapCons => Kernel::TrapCons {
+                scale,
+                end: T::of(params.trap_end),
+            },
+            Self::Sethares => Kernel::Sethares {
+                x_star: T::of(SETHARES_X_STAR),
+                s1: T::of(SETHARES_S1),
+                s2: T::of(SETHARES_S2),
+                b1: T::of(SETHARES_B1),
+                b2: T::of(SETHARES_B2),
+            },
+            Self::Blend(_) => return None,
+        })
+    }
+
+    /// Sum the weighted overlap of each pair of partials
+    pub fn sum_partials<'a, T: Real>(
+        &self,
+        params: &CurveParams,
+        weight: AmpWeighting,
+        it: impl IntoIterator<Item = (&'a Partial<T>, &'a Partial<T>)>,
+    ) -> T {
+        let it = it.into_iter();
+        let p = params;
+
+        match self {
+            Self::ExpDiss => it
+                .map(Self::partial(Self::overlap(p, Self::exp_diss(p)), weight))
+                .sum(),
+            Self::TrapDiss => it
+                .map(Self::partial(Self::overlap(p, Self::trap_diss(p)), weight))
+                .sum(),
+            Self::TriCons => it
+                .map(Self::partial(Self::overlap(p, Self::tri_cons(p)), weight))
+                .sum(),
+            Self::TrapCons => it
+                .map(Self::partial(Self::overlap(p, Self::trap_cons(p)), weight))
+                .sum(),
+            Self::Sethares => it.map(Self::partial(Self::sethares, weight)).sum(),
+            Self::Blend(c) => it.map(Self::partial(Self::blend(c, params), weight)).sum(),
+        }
+    }
+
+    /// Sum the weighted overlap of every ordered pair of packed partials.
+    /// Curves with a vectorized kernel run it on the given backend; the rest
+    /// get their own copy of the generic packed loop with the curve inlined
+    /// into it.
+    pub fn sum_packed<T: Real>(
+        &self,
+        params: &CurveParams,
+        packed: &Packed<T>,
+        backend: Backend,
+    ) -> T {
+        let p = params;
+
+        if let Some(kernel) = self.kernel(params) {
+            return packed.sum_kernel(backend, kernel);
+        }
+
+        match self {
+            Self::ExpDiss => packed.sum_pairs(Self::overlap(p, Self::exp_diss(p))),
+            Self::TrapDiss => packed.sum_pairs(Self::overlap(p, Self::trap_diss(p))),
+            Self::TriCons => packed.sum_pairs(Self::overlap(p, Self::tri_cons(p))),
+            Self::TrapCons => packed.sum_pairs(Self::overlap(p, Self::trap_cons(p))),
+            Self::Sethares => packed.sum_pairs(Self::sethares),
+            Self::Blend(c) => packed.sum_pairs(Self::blend(c, params)),
+        }
+    }
+}
+
+/// An overlap curve sampled at evenly spaced distances and linearly
+/// interpolated, avoiding a transcendental function call for every pair
+#[derive(Debug, Clone)]
+pub struct CurveTable<T> {
+    /// Whether distances are measured as in Sethares, rather than scaled by
+    /// `distance_scale`
+    hz: bool,
+    distance_scale: T,
+    /// Samples per unit of scaled distance
+    density: T,
+    values: Vec<T>,
+}
+
+impl<T: Real> CurveTable<T> {
+    pub fn eval(&self, (a, b): (T, T)) -> T {
+        let x = if self.hz {
+            OverlapCurve::sethares_distance((a, b))
+        } else {
+            (b - a).abs() * self.distance_scale
+        };
+        let pos = x * self.density;
+        let last = self.values.len() - 1;
+
+        match pos.to_usize() {
+            Some(i) if i < last => {
+                let (lo, hi) = (self.values[i], self.values[i + 1]);
+                lo + (hi - lo) * pos.fract()
+            },
+            Some(_) => self.values[last],
+            None => T::nan(),
+        }
+    }
+
+    /// Sum the weighted overlap of every ordered pair of packed partials
+    pub fn sum_packed(&self, packed: &Packed<T>) -> T { packed.sum_pairs(|pair| self.eval(pair)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::{AmpWeighting, CurveParams, OverlapCurve, Packed, Partial, LANES};
+    use crate::disson::{real::Real, simd::Backend};
+
+    const CURVES: [OverlapCurve; 5] = [
+        OverlapCurve::ExpDiss,
+        OverlapCurve::TrapDiss,
+        OverlapCurve::TriCons,
+        OverlapCurve::TrapCons,
+        OverlapCurve::Sethares,
+    ];
+
+    /// A few partials spread over the interesting part of each curve, in Hz
+    /// for Sethares and in pitch space otherwise
+    #[allow(clippy::cast_precision_loss)]
+    fn partials<T: Real>(curve: &OverlapCurve, n: usize) -> Vec<Partial<T>> {
+        let (base, step) = if curve.uses_hz() { (220.0, 37.0) } else { (10.0, 0.037) };
+
+        (0..n)
+            .map(|i| Partial {
+                pitch: T::of(base + step * (i * i) as f64),
+                amp: T::of(1.0 / (i + 1) as f64),
+            })
+            .collect()
+    }
+
+    fn assert_close<T: Real>(actual: T, expected: T, tol: f64, what: &str) {
+        let (a, e) = (actual.widen(), expected.widen());
+
+        assert!(
+            (a.is_nan() && e.is_nan()) || (a - e).abs() <= tol * e.abs().max(1.0),
+            "{}: expected {}, got {}",
+            what,
+            e,
+            a
+        );
+    }
+
+    /// Check every kernel on every supported backend against the generic
+    /// packed loop, for waves that do and don't fill a whole number of lanes
+    fn check_kernels<T: Real>(tol: f64, nan: bool) {
+        let params = CurveParams::default();
+        let mut packed = Packed::default();
+
+        for curve in &CURVES {
+            for n in 1..=2 * LANES + 1 {
+                let mut wave = partials::<T>(curve, n);
+
+                if nan {
+                    wave[n / 2].pitch = T::nan();
+                }
+
+                packed.pack(&wave, AmpWeighting::Linear);
+                assert_eq!(packed.pitch.len() % LANES, 0);
+
+                let expected = packed.sum_pairs(|pair| curve.eval(&params, pair));
+                let kernel = curve.kernel(&params).unwrap();
+                let portable = packed.sum_kernel(Backend::Portable, kernel);
+
+                assert_close(portable, expected, tol, &format!("{curve:?} x{n}"));
+
+                if Backend::Avx2.is_supported() {
+                    let avx2 = packed.sum_kernel(Backend::Avx2, kernel);
+
+                    // Both backends must agree exactly, so cached tiles can be
+                    // shared between machines
+                    assert_eq!(
+                        avx2.widen().to_bits(),
+                        portable.widen().to_bits(),
+                        "{curve:?} x{n}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn kernels_match_packed_loop_f32() { check_kernels::<f32>(1e-5, false); }
+
+    #[test]
+    fn kernels_match_packed_loop_f64() { check_kernels::<f64>(1e-12, false); }
+
+    #[test]
+    fn kernels_match_packed_loop_with_nan() {
+        check_kernels::<f32>(1e-5, true);
+        check_kernels::<f64>(1e-12, true);
+    }
+
+    #[test]
+    fn padding_adds_nothing() {
+        let params = CurveParams::default();
+        let mut packed = Packed::default();
+
+        for curve in &CURVES {
+            for n in 1..=LANES + 1 {
+                let wave = partials::<f64>(curve, n);
+                let pairs = wave.iter().cartesian_product(&wave);
+                let expected: f64 = curve.sum_partials(&params, AmpWeighting::Linear, pairs);
+
+                packed.pack(&wave, AmpWeighting::Linear);
+
+                assert_close(
+                    curve.sum_packed(&params, &packed, Backend::Portable),
+                    expected,
+                    1e-12,
+                    &format!("{curve:?} x{n}"),
+                );
+            }
         }
     }
 }
@@ -0,0 +1,170 @@
+//! Additive synthesis of the tones behind a point of the map, so it can be
+//! heard as well as seen
+
+use std::{f64::consts::PI, path::Path};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use log::trace;
+
+use super::wave::Wave;
+use crate::error::prelude::*;
+
+/// Length of the fade at either end of a rendered clip, in seconds
+const FADE: f64 = 0.01;
+/// Peak level of a rendered clip, leaving some headroom below full scale
+const PEAK: f64 = 0.8;
+
+/// A tone gliding evenly in pitch from one frequency to another
+#[derive(Debug, Clone, Copy)]
+pub struct Glide<'a> {
+    /// The partials of the tone, with pitches relative to its fundamental
+    pub wave: &'a Wave,
+    pub from_hz: f64,
+    pub to_hz: f64,
+    /// Partials are silenced while they're above this frequency
+    pub max_hz: f64,
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn samples(rate: f64, duration: f64) -> Vec<f64> {
+    let len = (duration * rate).max(0.0) as usize;
+
+    trace!("Synthesizing {len} samples...");
+
+    vec![0.0; len]
+}
+
+/// Add the partials of every voice, given in Hz, to a buffer.  Partials at or
+/// above the Nyquist frequency are dropped.
+#[allow(clippy::cast_precision_loss)]
+fn add_voices(buf: &mut [f64], voices: &[Wave], rate: f64) {
+    for partial in voices
+        .iter()
+        .flat_map(Wave::iter)
+        .filter(|p| p.pitch > 0.0 && p.pitch < rate / 2.0)
+    {
+        let step = 2.0 * PI * partial.pitch / rate;
+
+        for (i, sample) in buf.iter_mut().enumerate() {
+            *sample += partial.amp * (step * i as f64).sin();
+        }
+    }
+}
+
+/// Sum the partials of every voice, given in Hz, into `duration` seconds of
+/// mono samples normalized to a safe peak level
+pub fn synthesize(voices: &[Wave], rate: u32, duration: f64) -> Vec<f32> {
+    let rate = f64::from(rate);
+    let mut buf = samples(rate, duration);
+
+    add_voices(&mut buf, voices, rate);
+
+    finish(buf, rate)
+}
+
+/// Like [`synthesize`], with a gliding tone sounding over the fixed voices
+#[allow(clippy::cast_precision_loss)]
+pub fn synthesize_glide(voices: &[Wave], glide: Glide, rate: u32, duration: f64) -> Vec<f32> {
+    let rate = f64::from(rate);
+    let mut buf = samples(rate, duration);
+    let len = buf.len().saturating_sub(1).max(1) as f64;
+    let ratio = glide.to_hz / glide.from_hz;
+
+    add_voices(&mut buf, voices, rate);
+
+    for partial in glide.wave.iter() {
+        let mut phase = 0.0_f64;
+
+        for (i, sample) in buf.iter_mut().enumerate() {
+            let hz = glide.from_hz * ratio.powf(i as f64 / len) * partial.pitch;
+
+            if hz < rate / 2.0 && hz <= glide.max_hz {
+                *sample += partial.amp * phase.sin();
+            }
+
+            phase = (phase + 2.0 * PI * hz / rate) % (2.0 * PI);
+        }
+    }
+
+    finish(buf, rate)
+}
+
+/// Fade out the ends of a clip and scale it to a safe peak level
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn finish(mut buf: Vec<f64>, rate: f64) -> Vec<f32> {
+    let len = buf.len();
+    let fade = ((FADE * rate) as usize).min(len / 2).max(1);
+
+    for i in 0..fade.min(len) {
+        let gain = i as f64 / fade as f64;
+
+        buf[i] *= gain;
+        buf[len - 1 - i] *= gain;
+    }
+
+    let peak = buf.iter().fold(0.0_f64, |m, s| m.max(s.abs()));
+    let scale = if peak > 0.0 { PEAK / peak } else { 0.0 };
+
+    buf.into_iter().map(|s| (s * scale) as f32).collect()
+}
+
+/// Stretch a curve of map values across `len` samples, scaled to the range 0
+/// to 1 and interpolated linearly.  NaNs become 0.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+pub fn sidechain(values: &[f64], len: usize) -> Vec<f32> {
+    let (min, max) = values
+        .iter()
+        .filter(|v| v.is_finite())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(a, b), &v| {
+            (a.min(v), b.max(v))
+        });
+    let range = if max > min { max - min } else { 1.0 };
+    let value = |i: usize| match values.get(i) {
+        Some(v) if v.is_finite() => (v - min) / range,
+        _ => 0.0,
+    };
+
+    let last = values.len().saturating_sub(1) as f64;
+    let denom = len.saturating_sub(1).max(1) as f64;
+
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 / denom * last;
+            let (idx, frac) = (pos.floor() as usize, pos.fract());
+
+            (value(idx) * (1.0 - frac) + value(idx + 1) * frac) as f32
+        })
+        .collect()
+}
+
+/// Write channels of equal length to a 32-bit float WAV file
+#[allow(clippy::cast_possible_truncation)]
+pub fn write_wav(path: impl AsRef<Path>, channels: &[&[f32]], rate: u32) -> Result<()> {
+    let spec = WavSpec {
+        channels: channels.len() as u16,
+        sample_rate: rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+
+    let mut writer = WavWriter::create(path, spec).context("failed to create WAV file")?;
+    let len = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+
+    for i in 0..len {
+        for channel in channels {
+            writer
+                .write_sample(channel[i])
+                .context("failed to write WAV samples")?;
+        }
+    }
+
+    writer.finalize().context("failed to finish WAV file")
+}
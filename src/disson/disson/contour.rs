@@ -0,0 +1,137 @@
+//! Iso-dissonance contour extraction using marching squares
+
+use std::collections::HashMap;
+
+use nalgebra::Point2;
+
+use super::{map::DissonMap, real::Real};
+
+/// A contour line at a single level, as a list of points in pixel coordinates.
+/// Closed contours end with their first point.
+#[derive(Debug, Clone)]
+pub struct Contour {
+    pub level: f64,
+    pub points: Vec<Point2<f64>>,
+}
+
+/// Grid edges are identified by the index of their top or left corner, times
+/// two, plus one for vertical edges
+fn edge(width: usize, c: usize, r: usize, vertical: bool) -> usize {
+    (r * width + c) * 2 + usize::from(vertical)
+}
+
+/// Extract the contour lines of the map at each of the given levels.  Cells
+/// touching a NaN are skipped.
+pub(super) fn contours<T: Real>(map: &DissonMap<T>, levels: &[f64]) -> Vec<Contour> {
+    levels
+        .iter()
+        .flat_map(|&l| trace(map, l).into_iter())
+        .collect()
+}
+
+#[allow(clippy::cast_precision_loss, clippy::many_single_char_names)]
+fn trace<T: Real>(map: &DissonMap<T>, level: f64) -> Vec<Contour> {
+    let (width, height) = (map.size.x as usize, map.size.y as usize);
+    let at = |c: usize, r: usize| map.data[r * width + c].widen();
+
+    let mut points = HashMap::new();
+    let mut links: HashMap<usize, Vec<usize>> = HashMap::new();
+
+    // Interpolate the crossing on the edge between two corners
+    let mut cross = |(c0, r0): (usize, usize), (c1, r1): (usize, usize)| {
+        let id = edge(width, c0, r0, c0 == c1);
+        let (a, b) = (at(c0, r0), at(c1, r1));
+        let t = (level - a) / (b - a);
+
+        points.entry(id).or_insert_with(|| {
+            Point2::new(
+                c0 as f64 + (c1 as f64 - c0 as f64) * t,
+                r0 as f64 + (r1 as f64 - r0 as f64) * t,
+            )
+        });
+
+        id
+    };
+
+    for r in 0..height.saturating_sub(1) {
+        for c in 0..width.saturating_sub(1) {
+            let corners = [(c, r), (c + 1, r), (c + 1, r + 1), (c, r + 1)];
+            let vals = [at(c, r), at(c + 1, r), at(c + 1, r + 1), at(c, r + 1)];
+
+            if vals.iter().any(|v| v.is_nan()) {
+                continue;
+            }
+
+            let case = vals
+                .iter()
+                .enumerate()
+                .fold(0, |n, (i, &v)| n | (usize::from(v >= level) << i));
+
+            // Edges in clockwise order from the top
+            let [t, rt, b, l] = [
+                (corners[0], corners[1]),
+                (corners[1], corners[2]),
+                (corners[3], corners[2]),
+                (corners[0], corners[3]),
+            ];
+
+            let segs = match case {
+                0 | 15 => vec![],
+                1 | 14 => vec![(l, t)],
+                2 | 13 => vec![(t, rt)],
+                3 | 12 => vec![(l, rt)],
+                4 | 11 => vec![(rt, b)],
+                6 | 9 => vec![(t, b)],
+                7 | 8 => vec![(l, b)],
+                5 | 10 => {
+                    // Saddle; resolve it using the average of the corners
+                    let center = vals.iter().sum::<f64>() / 4.0;
+
+                    if (center >= level) == (case == 5) {
+                        vec![(l, b), (t, rt)]
+                    } else {
+                        vec![(l, t), (rt, b)]
+                    }
+                },
+                _ => unreachable!(),
+            };
+
+            for ((a0, a1), (b0, b1)) in segs {
+                let (a, b) = (cross(a0, a1), cross(b0, b1));
+
+                links.entry(a).or_default().push(b);
+                links.entry(b).or_default().push(a);
+            }
+        }
+    }
+
+    // Walk open contours from their ends first, then whatever cycles remain
+    let mut starts: Vec<_> = links.keys().copied().collect();
+    starts.sort_by_key(|k| links[k].len() != 1);
+
+    let mut ret = vec![];
+
+    for start in starts {
+        if links[&start].is_empty() {
+            continue;
+        }
+
+        let mut line = vec![points[&start]];
+        let mut cur = start;
+
+        while let Some(next) = links.get_mut(&cur).and_then(Vec::pop) {
+            let back = links.get_mut(&next).unwrap();
+            back.remove(back.iter().position(|&n| n == cur).unwrap());
+
+            line.push(points[&next]);
+            cur = next;
+        }
+
+        ret.push(Contour {
+            level,
+            points: line,
+        });
+    }
+
+    ret
+}
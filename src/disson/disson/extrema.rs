@@ -0,0 +1,107 @@
+//! Detection of local extrema in finished maps
+
+use std::cmp::Ordering;
+
+use super::{map::DissonMap, real::Real};
+
+/// A local extremum of a map
+#[derive(Debug, Clone, Copy)]
+pub struct Extremum<T> {
+    pub col: u32,
+    pub row: u32,
+    pub value: T,
+    /// How far the map must be flooded past this extremum before its basin
+    /// merges with that of a more extreme one.  The most extreme point spans
+    /// the whole range of the map.
+    pub depth: T,
+}
+
+/// Find the local minima of the map, ranked from deepest to shallowest
+pub fn minima<T: Real>(map: &DissonMap<T>) -> Vec<Extremum<T>> {
+    basins(map.size.x, &map.data)
+}
+
+/// Find the local maxima of the map, ranked from most to least prominent
+pub(super) fn maxima<T: Real>(map: &DissonMap<T>) -> Vec<Extremum<T>> {
+    let negated: Vec<_> = map.data.iter().map(|&x| -x).collect();
+
+    basins(map.size.x, &negated)
+        .into_iter()
+        .map(|e| Extremum {
+            value: -e.value,
+            ..e
+        })
+        .collect()
+}
+
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+
+    i
+}
+
+/// Flood a row-major grid from its lowest value upwards, recording the depth
+/// of each basin as it merges into a lower one.  NaNs are never flooded.
+#[allow(clippy::cast_possible_truncation)]
+fn basins<T: Real>(width: u32, data: &[T]) -> Vec<Extremum<T>> {
+    let width = width as usize;
+    let mut order: Vec<_> = (0..data.len()).filter(|&i| !data[i].is_nan()).collect();
+    order.sort_by(|&a, &b| data[a].partial_cmp(&data[b]).unwrap_or(Ordering::Equal));
+
+    let top = match order.last() {
+        Some(&i) => data[i],
+        None => return vec![],
+    };
+
+    // Basins are represented by their lowest point, and ties between basins
+    // go to the one flooded first
+    let mut rank = vec![usize::MAX; data.len()];
+    let mut parent: Vec<_> = (0..data.len()).collect();
+    let mut ret = vec![];
+
+    let extremum = |i: usize, depth| Extremum {
+        col: (i % width) as u32,
+        row: (i / width) as u32,
+        value: data[i],
+        depth,
+    };
+
+    for (n, &i) in order.iter().enumerate() {
+        rank[i] = n;
+
+        let (c, r) = (i % width, i / width);
+        let rows = r.saturating_sub(1)..=(r + 1);
+        let cols = c.saturating_sub(1)..=(c + 1).min(width - 1);
+
+        for j in rows
+            .flat_map(|r| cols.clone().map(move |c| r * width + c))
+            .filter(|&j| j < data.len() && rank[j] != usize::MAX)
+        {
+            let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+
+            if a == b {
+                continue;
+            }
+
+            let (lo, hi) = if rank[a] < rank[b] { (a, b) } else { (b, a) };
+            parent[hi] = lo;
+
+            // A newly flooded point with a lower neighbor is not a basin
+            if hi != i && data[i] > data[hi] {
+                ret.push(extremum(hi, data[i] - data[hi]));
+            }
+        }
+    }
+
+    for &i in &order {
+        if parent[i] == i {
+            ret.push(extremum(i, top - data[i]));
+        }
+    }
+
+    ret.sort_by(|a, b| b.depth.partial_cmp(&a.depth).unwrap_or(Ordering::Equal));
+    ret
+}
@@ -0,0 +1,109 @@
+//! Equal-loudness contours from ISO 226:2003
+
+use super::{real::Real, wave::Partial};
+
+/// Frequencies at which the contour parameters are tabulated, in Hz
+const FREQS: [f64; 29] = [
+    20.0, 25.0, 31.5, 40.0, 50.0, 63.0, 80.0, 100.0, 125.0, 160.0, 200.0, 250.0, 315.0, 400.0,
+    500.0, 630.0, 800.0, 1000.0, 1250.0, 1600.0, 2000.0, 2500.0, 3150.0, 4000.0, 5000.0, 6300.0,
+    8000.0, 10000.0, 12500.0,
+];
+
+/// Exponent for loudness perception
+const AF: [f64; 29] = [
+    0.532, 0.506, 0.480, 0.455, 0.432, 0.409, 0.387, 0.367, 0.349, 0.330, 0.315, 0.301, 0.288,
+    0.276, 0.267, 0.259, 0.253, 0.250, 0.246, 0.244, 0.243, 0.243, 0.243, 0.242, 0.242, 0.245,
+    0.254, 0.271, 0.301,
+];
+
+/// Magnitude of the linear transfer function normalized at 1 kHz, in dB
+const LU: [f64; 29] = [
+    -31.6, -27.2, -23.0, -19.1, -15.9, -13.0, -10.3, -8.1, -6.2, -4.5, -3.1, -2.0, -1.1, -0.4,
+    0.0, 0.3, 0.5, 0.0, -2.7, -4.1, -1.0, 1.7, 2.5, 1.2, -2.1, -7.1, -11.2, -10.7, -3.1,
+];
+
+/// Threshold of hearing, in dB SPL
+const TF: [f64; 29] = [
+    78.5, 68.7, 59.5, 51.1, 44.0, 37.5, 31.5, 26.5, 22.1, 17.9, 14.4, 11.4, 8.6, 6.2, 4.4, 3.0,
+    2.2, 2.4, 3.5, 1.7, -1.3, -4.2, -6.0, -5.4, -1.5, 6.0, 12.6, 13.9, 12.3,
+];
+
+/// Interpolate the contour parameters (af, Lu, Tf) at the given frequency,
+/// linearly in log-frequency and clamped to the ends of the table
+fn params(hz: f64) -> (f64, f64, f64) {
+    let i = FREQS.iter().position(|&f| f > hz).unwrap_or(FREQS.len());
+
+    if i == 0 {
+        return (AF[0], LU[0], TF[0]);
+    }
+
+    if i == FREQS.len() {
+        let j = FREQS.len() - 1;
+        return (AF[j], LU[j], TF[j]);
+    }
+
+    let t = (hz / FREQS[i - 1]).ln() / (FREQS[i] / FREQS[i - 1]).ln();
+    let lerp = |table: &[f64; 29]| table[i - 1] + (table[i] - table[i - 1]) * t;
+
+    (lerp(&AF), lerp(&LU), lerp(&TF))
+}
+
+/// Get the loudness level, in phons, of a tone at the given frequency and
+/// sound pressure level.  Returns `None` for inaudible tones.
+fn phons(hz: f64, spl: f64) -> Option<f64> {
+    let (af, lu, tf) = params(hz);
+    let pressure = |l: f64| (0.4 * 10.0_f64.powf((l + lu) / 10.0 - 9.0)).powf(af);
+    let b = pressure(spl) - pressure(tf) + 0.005_135;
+
+    if b > 0.0 {
+        Some(40.0 * b.log10() + 94.0)
+    } else {
+        None
+    }
+}
+
+/// Replace the amplitude of a partial, given in Hz, with the amplitude of a
+/// 1 kHz tone of equal loudness.  `reference` is the sound pressure level, in
+/// dB, of a partial with an amplitude of 1.
+pub fn weight<T: Real>(p: Partial<T>, reference: f64) -> Partial<T> {
+    let amp = p.amp.widen();
+    let spl = reference + 20.0 * amp.log10();
+
+    let amp = match phons(p.pitch.widen(), spl) {
+        Some(phons) if amp > 0.0 => 10.0_f64.powf((phons - reference) / 20.0),
+        _ => 0.0,
+    };
+
+    Partial {
+        amp: T::of(amp),
+        ..p
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{phons, weight, Partial};
+
+    fn weighted(pitch: f64, amp: f64) -> f64 { weight(Partial { pitch, amp }, 60.0).amp }
+
+    #[test]
+    fn phons_match_spl_at_1_khz() {
+        for &spl in &[20.0, 40.0, 60.0, 80.0] {
+            assert!((phons(1000.0, spl).unwrap() - spl).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn bass_is_quieter_than_midrange() {
+        assert!((weighted(1000.0, 1.0) - 1.0).abs() < 0.01);
+        assert!(weighted(50.0, 1.0) < weighted(1000.0, 1.0));
+        assert!(weighted(3150.0, 1.0) > weighted(1000.0, 1.0));
+    }
+
+    #[test]
+    fn quiet_bass_nearly_vanishes() {
+        // 20 dB SPL is below the threshold of hearing at 20 Hz
+        assert!(weighted(20.0, 0.01) < 1e-3);
+        assert!(weighted(1000.0, 0.0).abs() < f64::EPSILON);
+    }
+}
@@ -1,211 +1,1992 @@
-use std::{borrow::Cow, collections::HashMap, sync::Mutex};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    fmt, iter, mem,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
 
+use indicatif::{ProgressBar, ProgressStyle};
 use itertools::Itertools;
-use log::{trace, warn};
-use nalgebra::{Point2, Transform2, Vector2};
+use log::{info, log_enabled, trace, warn, Level};
+use nalgebra::{Matrix3, Point2, Transform2, Vector2};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use super::{
-    algo::{OverlapCurve, PitchCurve},
+    algo::{
+        AmpWeighting, CombinationTones, CurveParams, CurveTable, Masking, OverlapCurve, Packed,
+        PitchCurve,
+    },
+    loudness,
+    plugin::{self, Plugin, PluginDigest},
+    post::PostTransform,
+    real::{Precision, Real},
+    simd::Backend,
+    timbre::{Frames, Timbre},
+    timing::{self, Phase},
     wave::{Partial, Wave},
 };
 use crate::{
-    cache::prelude::*,
+    cache,
+    cache::{prelude::*, ConvertError, NullCache},
     cancel::prelude::*,
-    config::MapConfig,
+    cli::FromStrErr,
+    config::{MapConfig, RenderConfig, Validator},
     error::prelude::*,
-    tile_renderer::{DefaultTileRenderer, Tile, TileRange, TileRenderFunction},
+    message,
+    tile_renderer::{self, Tile, TileEvent, TileRange, TileRenderFunction, TileRenderer},
 };
 
-#[derive(Debug, Clone, Copy, Serialize)]
-pub(super) struct Config {
+/// The interval range covered by one axis of the map, relative to the base
+/// frequency
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum AxisRange {
+    Octaves(f64, f64),
+    Cents(f64, f64),
+    /// Frequency ratios relative to the base frequency
+    Ratios(f64, f64),
+    /// Steps of an equal division of the octave
+    EdoSteps { edo: u32, start: f64, end: f64 },
+    #[serde(rename = "Hertz")]
+    Hz(f64, f64),
+}
+
+/// One axis of a map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+}
+
+impl FromStr for Axis {
+    type Err = FromStrErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_ref() {
+            "x" => Self::X,
+            "y" => Self::Y,
+            _ => return Err(FromStrErr::OneOf(s.into(), &["x", "y"])),
+        })
+    }
+}
+
+/// How the maps of several components are combined into one
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum CombineOp {
+    /// The weighted sum of every component
+    Sum,
+    /// The largest weighted value of any component
+    Max,
+    /// The first weighted component minus every other weighted component
+    Difference,
+}
+
+/// A map combined from the maps of several presets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Combine {
+    pub op: CombineOp,
+    /// Pairs of weights and the names of the presets to compute each
+    /// component from
+    pub components: Vec<(f64, String)>,
+}
+
+impl Combine {
+    pub fn validate(&self, path: &str, v: &mut Validator) {
+        v.check(
+            !self.components.is_empty(),
+            format_args!("{path}.components"),
+            "must contain at least one component",
+        );
+
+        for (i, (w, _)) in self.components.iter().enumerate() {
+            v.check(
+                w.is_finite(),
+                format_args!("{path}.components[{i}].0"),
+                "weight must be finite",
+            );
+        }
+    }
+}
+
+/// The most pixels a step size may divide an axis into
+const MAX_STEP_PIXELS: u32 = 1 << 16;
+
+impl AxisRange {
+    fn bounds(self) -> (f64, f64) {
+        match self {
+            Self::Octaves(a, b) | Self::Cents(a, b) | Self::Ratios(a, b) | Self::Hz(a, b) => {
+                (a, b)
+            },
+            Self::EdoSteps { start, end, .. } => (start, end),
+        }
+    }
+
+    /// Get the (start, end) of this range in octaves above the base frequency
+    pub fn octaves(self, base_hz: f64) -> (f64, f64) {
+        match self {
+            Self::Octaves(a, b) => (a, b),
+            Self::Cents(a, b) => (a / 1200.0, b / 1200.0),
+            Self::Ratios(a, b) => (a.log2(), b.log2()),
+            Self::EdoSteps { edo, start, end } => {
+                let edo = f64::from(edo);
+                (start / edo, end / edo)
+            },
+            Self::Hz(a, b) => ((a / base_hz).log2(), (b / base_hz).log2()),
+        }
+    }
+
+    /// Whether this range is measured in units linear in pitch, i.e. whether
+    /// it makes sense to sample it at a fixed step size
+    fn is_steppable(self) -> bool {
+        matches!(self, Self::Octaves(..) | Self::Cents(..) | Self::EdoSteps { .. })
+    }
+
+    /// Get the number of pixels needed to sample this range every `step`
+    /// units, including both endpoints
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn pixels(self, step: f64) -> Option<u32> {
+        if !self.is_steppable() {
+            return None;
+        }
+
+        let (a, b) = self.bounds();
+        let n = ((b - a).abs() / step).round() + 1.0;
+
+        if n.is_finite() && n <= f64::from(u32::MAX) {
+            Some(n as u32)
+        } else {
+            None
+        }
+    }
+
+    pub fn validate(self, path: &str, v: &mut Validator) {
+        let (a, b) = self.bounds();
+
+        v.check(
+            a.is_finite() && b.is_finite(),
+            path,
+            "range bounds must be finite",
+        );
+        v.check((a - b).abs() > 0.0, path, "range must not be empty");
+
+        match self {
+            Self::Ratios(..) => v.check(a > 0.0 && b > 0.0, path, "ratios must be positive"),
+            Self::Hz(..) => v.check(a > 0.0 && b > 0.0, path, "frequencies must be positive"),
+            Self::EdoSteps { edo, .. } => v.check(
+                edo > 0,
+                path,
+                "octave must be divided into at least one step",
+            ),
+            Self::Octaves(..) | Self::Cents(..) => (),
+        }
+    }
+
+    pub fn validate_step(self, step: f64, path: &str, v: &mut Validator) {
+        v.check(
+            step.is_finite() && step > 0.0,
+            path,
+            "step size must be positive",
+        );
+        v.check(
+            self.is_steppable(),
+            path,
+            "step sizes are only supported for Octaves, Cents, and EdoSteps ranges",
+        );
+
+        if step.is_finite() && step > 0.0 && self.is_steppable() {
+            v.check(
+                self.pixels(step).is_some_and(|n| n <= MAX_STEP_PIXELS),
+                path,
+                format_args!("step size divides the range into more than {MAX_STEP_PIXELS} pixels"),
+            );
+        }
+    }
+}
+
+impl Default for AxisRange {
+    fn default() -> Self { Self::Octaves(0.0, 1.0) }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct Config<P = Plugin> {
     size: Vector2<u32>,
     view: Transform2<f64>,
     base_hz: f64,
+    normalize: bool,
+    /// Frequencies and waves of every tone held under the map
+    held: Vec<(f64, Wave)>,
     pitch: PitchCurve,
     overlap: OverlapCurve,
+    curve_params: CurveParams,
+    lookup_table: Option<u32>,
+    amp_weighting: AmpWeighting,
+    masking: Option<Masking>,
+    combination_tones: Option<CombinationTones>,
+    wave: Wave,
+    max_frequency: Option<f64>,
+    min_amplitude: Option<f64>,
+    equal_loudness: Option<f64>,
+    plugin: Option<P>,
+    precision: Precision,
 }
 
 impl Config {
-    pub fn for_generate(cfg: &MapConfig) -> Self {
+    pub fn for_generate(cfg: &MapConfig) -> Result<Self> {
+        let wave = cfg.timbre.wave()?;
+
+        Ok(Self::new(cfg, wave, &Self::held_timbres(cfg)?, Self::plugin(cfg)?))
+    }
+
+    /// Prepare one config per frame of a time-varying timbre.  Fails if the
+    /// timbre cannot be analyzed in frames.
+    pub fn for_frames(cfg: &MapConfig, frames: &Frames) -> Result<Vec<Self>> {
+        let waves = match cfg.timbre.frames(frames) {
+            Some(w) => w?,
+            None => return Err(anyhow!("only timbres loaded from a file have frames")),
+        };
+        let held = Self::held_timbres(cfg)?;
+        let plugin = Self::plugin(cfg)?;
+
+        Ok(waves
+            .into_iter()
+            .map(|w| Self::new(cfg, w, &held, plugin.clone()))
+            .collect())
+    }
+
+    fn held_timbres(cfg: &MapConfig) -> Result<Vec<Option<Wave>>> {
+        cfg.held_notes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| {
+                n.timbre
+                    .as_ref()
+                    .map(Timbre::wave)
+                    .transpose()
+                    .with_context(|| format!("failed to get timbre of held note {i}"))
+            })
+            .collect()
+    }
+
+    fn plugin(cfg: &MapConfig) -> Result<Option<Plugin>> {
+        cfg.plugin
+            .as_ref()
+            .map(Plugin::load)
+            .transpose()
+            .context("failed to load plugin")
+    }
+
+    /// Assemble a config from the map settings, the unfiltered main wave, the
+    /// unfiltered timbre of each held note, if it has its own, and the plugin
+    fn new(
+        cfg: &MapConfig,
+        wave: Wave,
+        held_timbres: &[Option<Wave>],
+        plugin: Option<Plugin>,
+    ) -> Self {
         let MapConfig {
             width,
             height,
             base_frequency,
+            tuning_reference,
+            omit_base,
+            ref held_notes,
+            normalize,
+            x_range,
+            y_range,
+            x_step: _,
+            y_step: _,
             pitch_curve,
-            overlap_curve,
+            ref overlap_curve,
+            curve_params,
+            lookup_table,
+            amp_weighting,
+            masking,
+            combination_tones,
+            timbre: _,
+            filter,
+            max_frequency,
+            min_amplitude,
+            equal_loudness,
+            plugin: _,
+            frames: _,
+            post_transforms: _,
+            combine: _,
+            precision,
         } = *cfg;
 
+        let base_hz = base_frequency.hz(tuning_reference);
+        let (x0, x1) = x_range.octaves(base_hz);
+        let (y0, y1) = y_range.octaves(base_hz);
+
+        let apply_filter = |w: Wave| match filter {
+            Some(ref f) => f.apply(&w),
+            None => w,
+        };
+
+        let wave = apply_filter(wave);
+        let mut held = vec![];
+
+        if !omit_base {
+            held.push((base_hz, wave.clone()));
+        }
+
+        for (note, timbre) in held_notes.iter().zip(held_timbres) {
+            let wave = match timbre {
+                Some(t) => apply_filter(t.clone()),
+                None => wave.clone(),
+            };
+
+            held.push((note.frequency.hz(tuning_reference), wave));
+        }
+
         Self {
             size: Vector2::new(width, height),
-            view: Transform2::identity(), // TODO
-            base_hz: base_frequency,
+            view: Transform2::from_matrix_unchecked(Matrix3::new(
+                x1 - x0,
+                0.0,
+                x0,
+                0.0,
+                y1 - y0,
+                y0,
+                0.0,
+                0.0,
+                1.0,
+            )),
+            base_hz,
+            normalize,
+            held,
             pitch: pitch_curve,
-            overlap: overlap_curve,
+            overlap: overlap_curve.clone(),
+            curve_params,
+            lookup_table,
+            amp_weighting,
+            masking,
+            combination_tones,
+            wave,
+            max_frequency,
+            min_amplitude,
+            equal_loudness,
+            plugin,
+            precision,
+        }
+    }
+
+    pub fn precision(&self) -> Precision { self.precision }
+
+    /// Find the position of a pixel in octaves above the base frequency
+    pub fn pixel_octaves(&self, px: Point2<f64>) -> Point2<f64> {
+        pixel_octaves(self.view, self.size, px)
+    }
+
+    /// The partials of every tone sounding at one point of the map, with
+    /// pitches in Hz: each held tone, then the X and Y tones at the given
+    /// number of octaves above the base frequency, if any.  Partials the map
+    /// would ignore are left out.
+    pub fn voices(&self, x: Option<f64>, y: Option<f64>) -> Vec<Wave> {
+        let max_hz = self.max_frequency.unwrap_or(f64::INFINITY);
+        let min_amp = self.min_amplitude.unwrap_or(0.0);
+        let moving = x.into_iter().chain(y).map(|o| (self.base_hz * o.exp2(), &self.wave));
+
+        self.held
+            .iter()
+            .map(|(hz, w)| (*hz, w))
+            .chain(moving)
+            .map(|(hz, w)| {
+                w.iter()
+                    .map(|p| Partial {
+                        pitch: hz * p.pitch,
+                        amp: p.amp,
+                    })
+                    .filter(|p| p.pitch <= max_hz && p.amp >= min_amp)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The partials of the X and Y tones, relative to their fundamental and
+    /// without any the map would ignore
+    pub fn tone(&self) -> Wave {
+        let min_amp = self.min_amplitude.unwrap_or(0.0);
+
+        self.wave.iter().copied().filter(|p| p.amp >= min_amp).collect()
+    }
+
+    pub fn max_frequency(&self) -> Option<f64> { self.max_frequency }
+
+    pub fn base_hz(&self) -> f64 { self.base_hz }
+
+    /// The range covered by one axis, in octaves above the base frequency
+    pub fn axis_octaves(&self, axis: Axis) -> (f64, f64) {
+        let m = self.view.matrix();
+        let i = match axis {
+            Axis::X => 0,
+            Axis::Y => 1,
+        };
+
+        (m[(i, 2)], m[(i, 2)] + m[(i, i)])
+    }
+
+    /// A one-pixel-thick slice of the map along `axis`, with the tone of the
+    /// other axis held `at` octaves above the base frequency
+    pub fn slice(&self, axis: Axis, at: f64) -> Self {
+        let (a0, a1) = self.axis_octaves(axis);
+        let (size, view) = match axis {
+            Axis::X => (
+                Vector2::new(self.size.x, 1),
+                Matrix3::new(a1 - a0, 0.0, a0, 0.0, 0.0, at, 0.0, 0.0, 1.0),
+            ),
+            Axis::Y => (
+                Vector2::new(1, self.size.y),
+                Matrix3::new(0.0, 0.0, at, 0.0, a1 - a0, a0, 0.0, 0.0, 1.0),
+            ),
+        };
+
+        self.with_layout(Layout {
+            size,
+            view: Transform2::from_matrix_unchecked(view),
+        })
+    }
+}
+
+/// The revision of the render kernel, covering everything [`compute`] does
+/// with a config besides evaluating the pitch and overlap curves.  Bump it
+/// whenever the output of a map changes, so that cached maps are recomputed.
+const RENDER_VERSION: u32 = 2;
+
+/// Revisions of each algorithm used to compute a map
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct AlgoVersions {
+    render: u32,
+    pitch: u32,
+    overlap: u32,
+}
+
+impl AlgoVersions {
+    fn of<P>(cfg: &Config<P>) -> Self {
+        Self {
+            render: RENDER_VERSION,
+            pitch: cfg.pitch.version(),
+            overlap: cfg.overlap.version(),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub struct CacheKey(Config);
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheKey<P = Plugin>(AlgoVersions, Config<P>);
+
+/// A cache key decoded from a cache file
+pub type KeyInfo = CacheKey<PluginDigest>;
 
-pub(super) struct DissonMap {
+impl KeyInfo {
+    /// Recover the config the key was made from, so its map can be loaded.
+    /// Fails if the map was computed with older algorithms, or with a plugin,
+    /// which the key only identifies by its digest.
+    #[cfg(feature = "gui")]
+    pub(super) fn config(&self) -> Result<Config> {
+        let Self(versions, ref cfg) = *self;
+
+        if versions != AlgoVersions::of(cfg) {
+            return Err(anyhow!("map was computed with older algorithms"));
+        }
+
+        if cfg.plugin.is_some() {
+            return Err(anyhow!("map was computed with a plugin, which can't be loaded"));
+        }
+
+        let Config {
+            size,
+            view,
+            base_hz,
+            normalize,
+            ref held,
+            pitch,
+            ref overlap,
+            curve_params,
+            lookup_table,
+            amp_weighting,
+            masking,
+            combination_tones,
+            ref wave,
+            max_frequency,
+            min_amplitude,
+            equal_loudness,
+            plugin: _,
+            precision,
+        } = *cfg;
+
+        Ok(Config {
+            size,
+            view,
+            base_hz,
+            normalize,
+            held: held.clone(),
+            pitch,
+            overlap: overlap.clone(),
+            curve_params,
+            lookup_table,
+            amp_weighting,
+            masking,
+            combination_tones,
+            wave: wave.clone(),
+            max_frequency,
+            min_amplitude,
+            equal_loudness,
+            plugin: None,
+            precision,
+        })
+    }
+}
+
+impl fmt::Display for KeyInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Config {
+            size,
+            view,
+            base_hz,
+            ref held,
+            pitch,
+            ref overlap,
+            ref plugin,
+            precision,
+            ..
+        } = self.1;
+        let m = view.matrix();
+
+        write!(
+            f,
+            "{}x{} {:?}, {:.2} Hz, x {:+.0}..{:+.0}c, y {:+.0}..{:+.0}c, {} tone(s), {:?} pitch, \
+             {:?} overlap",
+            size.x,
+            size.y,
+            precision,
+            base_hz,
+            m[(0, 2)] * 1200.0,
+            (m[(0, 2)] + m[(0, 0)]) * 1200.0,
+            m[(1, 2)] * 1200.0,
+            (m[(1, 2)] + m[(1, 1)]) * 1200.0,
+            held.len(),
+            pitch,
+            overlap,
+        )?;
+
+        if let Some(p) = plugin {
+            write!(f, ", plugin {p:.12}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The size and view of a map.  Maps differing only in layout share the value
+/// of any pixel landing on the same interval.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    size: Vector2<u32>,
+    view: Transform2<f64>,
+}
+
+impl<P> Config<P> {
+    fn layout(&self) -> Layout {
+        Layout {
+            size: self.size,
+            view: self.view,
+        }
+    }
+}
+
+impl<P: Clone> Config<P> {
+    fn with_layout(&self, layout: Layout) -> Self {
+        Self {
+            size: layout.size,
+            view: layout.view,
+            ..self.clone()
+        }
+    }
+}
+
+/// Key of the entry listing every layout a map has been cached with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayoutsKey<P = Plugin>(AlgoVersions, Config<P>);
+
+/// A layouts key decoded from a cache file
+pub type LayoutsKeyInfo = LayoutsKey<PluginDigest>;
+
+impl LayoutsKey {
+    fn of(cfg: &Config) -> Self {
+        Self(
+            AlgoVersions::of(cfg),
+            cfg.with_layout(Layout {
+                size: Vector2::zeros(),
+                view: Transform2::identity(),
+            }),
+        )
+    }
+}
+
+impl fmt::Display for LayoutsKeyInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Config {
+            base_hz,
+            ref held,
+            pitch,
+            ref overlap,
+            precision,
+            ..
+        } = self.1;
+
+        write!(
+            f,
+            "{:?}, {:.2} Hz, {} tone(s), {:?} pitch, {:?} overlap",
+            precision,
+            base_hz,
+            held.len(),
+            pitch,
+            overlap,
+        )
+    }
+}
+
+pub struct DissonMap<T = f64> {
     pub size: Vector2<u32>,
-    pub data: Box<[f64]>,
+    /// Transform from normalized pixel coordinates to octaves above the base
+    /// frequency
+    pub view: Transform2<f64>,
+    pub data: Box<[T]>,
+}
+
+// Pixel data is left out, since it can run to millions of values
+impl<T> fmt::Debug for DissonMap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DissonMap")
+            .field("size", &self.size)
+            .field("view", &self.view)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> DissonMap<T> {
+    /// Get the interval of each axis at the given (possibly fractional) pixel,
+    /// in octaves above the base frequency
+    pub fn octaves(&self, px: Point2<f64>) -> Point2<f64> {
+        pixel_octaves(self.view, self.size, px)
+    }
+
+    /// Get the (possibly fractional) pixel at the given interval of each axis,
+    /// or `None` if an axis covers no range
+    pub fn pixel(&self, octaves: Point2<f64>) -> Option<Point2<f64>> {
+        self.view
+            .try_inverse()
+            .map(|inv| Point2::from((inv * octaves).coords.component_mul(&pixel_denom(self.size))))
+    }
+}
+
+impl<T: Real> DissonMap<T> {
+    pub fn widen(&self) -> DissonMap<f64> {
+        DissonMap {
+            size: self.size,
+            view: self.view,
+            data: self.data.iter().map(|x| x.widen()).collect(),
+        }
+    }
+}
+
+/// Combine the given weighted maps, which must all have the same size.  The
+/// view of the first map is kept.
+pub(super) fn combine(op: CombineOp, maps: Vec<(f64, DissonMap)>) -> DissonMap {
+    let mut it = maps.into_iter();
+    let (w0, mut ret) = it.next().expect("no maps to combine");
+
+    ret.data.iter_mut().for_each(|x| *x *= w0);
+
+    for (w, map) in it {
+        assert_eq!(map.size, ret.size, "combined maps differ in size");
+
+        for (x, y) in ret.data.iter_mut().zip(map.data.iter()) {
+            *x = match op {
+                // Max would skip over NaN, but a pixel missing from any map is
+                // missing from the combination
+                _ if x.is_nan() || y.is_nan() => f64::NAN,
+                CombineOp::Sum => *x + w * y,
+                CombineOp::Max => x.max(w * y),
+                CombineOp::Difference => *x - w * y,
+            };
+        }
+    }
+
+    ret
+}
+
+/// A map of the given config with every pixel left unrendered, as NaN
+pub(super) fn unrendered(cfg: &Config) -> DissonMap {
+    let len = cfg.size.x as usize * cfg.size.y as usize;
+
+    DissonMap {
+        size: cfg.size,
+        view: cfg.view,
+        data: vec![f64::NAN; len].into_boxed_slice(),
+    }
+}
+
+// Maps one pixel wide or tall are 1D curves along the other axis
+fn pixel_denom(size: Vector2<u32>) -> Vector2<f64> {
+    size.map(|s| s.saturating_sub(1).max(1)).cast()
+}
+
+fn pixel_octaves(view: Transform2<f64>, size: Vector2<u32>, px: Point2<f64>) -> Point2<f64> {
+    view * Point2::from(px.coords.component_div(&pixel_denom(size)))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum CacheValue<'a> {
-    Block(TileRange, Cow<'a, [f64]>),
+pub enum CacheValue<'a, T: Clone = f64> {
+    Block(TileRange, Cow<'a, [T]>),
     Histogram(()),
 }
 
-struct RenderFunction<'a, E: CacheEntry> {
+impl<T: Clone> CacheValue<'_, T> {
+    pub fn into_owned(self) -> CacheValue<'static, T> {
+        match self {
+            Self::Block(k, v) => CacheValue::Block(k, Cow::Owned(v.into_owned())),
+            Self::Histogram(h) => CacheValue::Histogram(h),
+        }
+    }
+}
+
+/// Apply equal-loudness weighting to a partial in Hz, if enabled
+fn equal_loudness<T: Real>(p: Partial<T>, reference: Option<f64>) -> Partial<T> {
+    match reference {
+        Some(r) => loudness::weight(p, r),
+        None => p,
+    }
+}
+
+/// Convert partials with pitches in Hz into the pitch space expected by the
+/// overlap curve, appending them to `out`
+fn extend_pitch_space<T: Real>(
+    out: &mut Vec<Partial<T>>,
+    it: impl Iterator<Item = Partial<T>>,
+    pitch: PitchCurve,
+    overlap: &OverlapCurve,
+    plugin: Option<&plugin::Instance>,
+) {
+    let curve = |q: Partial<T>| Partial {
+        pitch: pitch.eval(q.pitch),
+        ..q
+    };
+
+    match plugin {
+        Some(p) if p.has_pitch() => out.extend(it.map(|q| Partial {
+            pitch: p.pitch(q.pitch),
+            ..q
+        })),
+        Some(p) if p.has_overlap() => out.extend(it.map(curve)),
+        _ if overlap.uses_hz() => out.extend(it),
+        _ => out.extend(it.map(curve)),
+    }
+}
+
+/// Convert partials with pitches in Hz into the pitch space expected by the
+/// overlap curve
+fn to_pitch_space<T: Real>(
+    it: impl Iterator<Item = Partial<T>>,
+    pitch: PitchCurve,
+    overlap: &OverlapCurve,
+    plugin: Option<&plugin::Instance>,
+) -> Wave<T> {
+    let mut ret = vec![];
+
+    extend_pitch_space(&mut ret, it, pitch, overlap, plugin);
+
+    Wave::new(ret)
+}
+
+/// How far a reprojected pixel may be from the pixel it lands on
+const REPROJECT_EPSILON: f64 = 1e-6;
+/// The most other layouts tried when reprojecting a map, starting from the
+/// most recently recorded
+const MAX_REPROJECT_LAYOUTS: usize = 8;
+
+/// Fill in tiles missing from `preload` with blocks cached for the same map
+/// under other layouts, wherever their pixels land on pixels of this one, and
+/// record the layout of this map for later runs.  Layouts whose cached maps
+/// have since been removed are forgotten.  Returns the ranges of the tiles
+/// that were filled in.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn reproject<C: for<'a> Cache<'a>, T: Real>(
+    cache: &C,
+    cfg: &Config,
+    tile_size: Vector2<u32>,
+    preload: &mut HashMap<TileRange, Cow<'static, [T]>>,
+) -> Result<Vec<TileRange>>
+where
+    CacheValue<'static, T>: TryFrom<cache::CacheValue<'static>, Error = ConvertError>,
+{
+    let layout = cfg.layout();
+    let mut layouts_entry = cache
+        .entry(LayoutsKey::of(cfg))
+        .context("couldn't open map layouts entry")?;
+    let layouts: Vec<Layout> = layouts_entry
+        .read()
+        .context("couldn't read map layouts")?;
+
+    if !layouts.contains(&layout) {
+        layouts_entry
+            .append(layout)
+            .context("failed to cache map layout")?;
+        layouts_entry
+            .flush()
+            .context("failed to save map layouts")?;
+    }
+
+    mem::drop(layouts_entry);
+
+    let others: Vec<_> = layouts
+        .into_iter()
+        .rev()
+        .filter(|l| *l != layout)
+        .take(MAX_REPROJECT_LAYOUTS)
+        .collect();
+    let missing: Vec<_> = tile_renderer::tile_ranges(cfg.size, tile_size)
+        .into_iter()
+        .filter(|r| !preload.contains_key(r))
+        .collect();
+
+    let inv = match cfg.view.try_inverse() {
+        Some(i) if !others.is_empty() && !missing.is_empty() => i,
+        _ => return Ok(vec![]),
+    };
+
+    let (size, denom) = (cfg.size, pixel_denom(cfg.size));
+    let stride = size.x as usize;
+    let mut grid = vec![None; stride * size.y as usize];
+    let mut gone = vec![];
+
+    for other in others {
+        let mut entry = match cache.entry(CacheKey(AlgoVersions::of(cfg), cfg.with_layout(other))) {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("Failed to open map cached with another layout: {e:?}");
+                continue;
+            },
+        };
+        let mut empty = true;
+
+        for val in entry.read_iter::<CacheValue<T>, _>() {
+            empty = false;
+
+            let (range, data) = match val {
+                Ok(CacheValue::Block(r, d)) => (r, d),
+                Ok(CacheValue::Histogram(())) => continue,
+                Err(e) => {
+                    warn!("Failed to read map cached with another layout: {e:?}");
+                    break;
+                },
+            };
+
+            for r in 0..range.size.y {
+                for c in 0..range.size.x {
+                    let val = match data.get((r * range.size.x + c) as usize) {
+                        Some(v) => *v,
+                        None => continue,
+                    };
+
+                    let old = Point2::from(range.pos + Vector2::new(c, r)).cast();
+                    let px = (inv * pixel_octaves(other.view, other.size, old))
+                        .coords
+                        .component_mul(&denom);
+                    let near = px.map(f64::round);
+
+                    if (px - near).amax() > REPROJECT_EPSILON
+                        || near.x < 0.0
+                        || near.y < 0.0
+                        || near.x >= f64::from(size.x)
+                        || near.y >= f64::from(size.y)
+                    {
+                        continue;
+                    }
+
+                    grid[near.y as usize * stride + near.x as usize] = Some(val);
+                }
+            }
+        }
+
+        if empty {
+            gone.push(other);
+        }
+    }
+
+    if !gone.is_empty() {
+        trace!("Forgetting {} layout(s) no longer cached", gone.len());
+
+        if let Err(e) = forget_layouts(cache, cfg, &gone) {
+            warn!("Failed to forget stale map layouts: {e:?}");
+        }
+    }
+
+    Ok(fill_tiles(&grid, stride, missing, preload))
+}
+
+/// Remove `gone` from the layouts recorded for the map of `cfg`
+fn forget_layouts<C: for<'a> Cache<'a>>(cache: &C, cfg: &Config, gone: &[Layout]) -> Result<()> {
+    let mut entry = cache
+        .entry(LayoutsKey::of(cfg))
+        .context("couldn't open map layouts entry")?;
+    // Read the list again, since other processes may have added to it
+    let mut layouts: Vec<Layout> = entry.read().context("couldn't read map layouts")?;
+
+    layouts.retain(|l| !gone.contains(l));
+
+    entry.truncate().context("failed to clear map layouts")?;
+
+    for layout in layouts {
+        entry.append(layout).context("failed to cache map layout")?;
+    }
+
+    entry.flush().context("failed to save map layouts")
+}
+
+/// Fill in each of `missing` whose pixels are all set in `grid`, returning the
+/// ranges of the tiles that were filled in
+fn fill_tiles<T: Real>(
+    grid: &[Option<T>],
+    stride: usize,
+    missing: impl IntoIterator<Item = TileRange>,
+    preload: &mut HashMap<TileRange, Cow<'static, [T]>>,
+) -> Vec<TileRange> {
+    let mut ret = vec![];
+
+    for range in missing {
+        let vals: Option<Vec<T>> = (range.pos.y..range.pos.y + range.size.y)
+            .flat_map(|r| (range.pos.x..range.pos.x + range.size.x).map(move |c| (r, c)))
+            .map(|(r, c)| grid[r as usize * stride + c as usize])
+            .collect();
+
+        if let Some(vals) = vals {
+            preload.insert(range, Cow::Owned(vals));
+            ret.push(range);
+        }
+    }
+
+    ret
+}
+
+/// Reassemble blocks cached with another tile size into the tiles of this
+/// one.  Blocks that don't line up with the current tiles are dropped from
+/// `preload`, since they'd never be used.  Returns the ranges of the tiles
+/// that were reassembled.
+fn retile<T: Real>(
+    size: Vector2<u32>,
+    tile_size: Vector2<u32>,
+    preload: &mut HashMap<TileRange, Cow<'static, [T]>>,
+) -> Vec<TileRange> {
+    let tiles = tile_renderer::tile_ranges(size, tile_size);
+    let aligned: HashSet<_> = tiles.iter().copied().collect();
+    let stray: Vec<_> = preload
+        .keys()
+        .filter(|r| !aligned.contains(r))
+        .copied()
+        .collect();
+
+    if stray.is_empty() {
+        return vec![];
+    }
+
+    let stride = size.x as usize;
+    let mut grid = vec![None; stride * size.y as usize];
+
+    for range in stray {
+        let data = preload.remove(&range).unwrap();
+
+        for r in 0..range.size.y {
+            for c in 0..range.size.x {
+                let (x, y) = (range.pos.x + c, range.pos.y + r);
+
+                match data.get((r * range.size.x + c) as usize) {
+                    Some(v) if x < size.x && y < size.y => {
+                        grid[y as usize * stride + x as usize] = Some(*v);
+                    },
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    let missing = tiles.into_iter().filter(|r| !preload.contains_key(r));
+    let missing: Vec<_> = missing.collect();
+
+    fill_tiles(&grid, stride, missing, preload)
+}
+
+/// The partials of the map's wave transposed to one frequency, in the pitch
+/// space of the overlap curve
+#[derive(Debug, Default)]
+struct Placement<T> {
+    /// The frequency the partials were placed at, if any
+    hz: Option<T>,
+    partials: Vec<Partial<T>>,
+}
+
+/// Buffers reused for every pixel a render thread evaluates
+#[derive(Debug, Default)]
+struct Scratch<T> {
+    /// The partials sounding at a pixel, in the pitch space of the overlap
+    /// curve
+    partials: Vec<Partial<T>>,
+    packed: Packed<T>,
+    /// The X tone of each column of the tile.  X frequencies only change
+    /// from column to column, so each column's tone is placed once per tile.
+    cols: Vec<Placement<T>>,
+    /// The Y tone of the row being rendered
+    row: Placement<T>,
+}
+
+struct RenderFunction<'a, E: CacheEntry, T: Real> {
     cache_entry: &'a Mutex<E>,
+    /// Interrupts plugin calls that would otherwise never return
+    cancel: &'a CancelToken,
     pitch: PitchCurve,
     overlap: OverlapCurve,
-    wave: Wave,
-    base_wave: &'a Wave,
+    curve_params: CurveParams,
+    /// Sampled overlap curve, used in place of `overlap` if present
+    table: Option<CurveTable<T>>,
+    amp_weighting: AmpWeighting,
+    masking: Option<Masking>,
+    combination_tones: Option<CombinationTones>,
+    wave: Wave<T>,
+    /// Partials above this frequency are ignored
+    max_hz: T,
+    /// Reference level for equal-loudness weighting, if enabled
+    equal_loudness: Option<f64>,
+    /// Partials of each held tone, in Hz
+    held: Vec<Wave<T>>,
+    /// Partials of every held tone, in the pitch space of the overlap curve
+    base_wave: &'a Wave<T>,
+    plugin: Option<Plugin>,
+    /// Factor applied to every output value
+    scale: T,
+    /// Implementation of the packed overlap sums, detected once per map
+    backend: Backend,
 }
 
-impl<'a, E: CacheEntry + Send> TileRenderFunction for RenderFunction<'a, E> {
-    type Input = Point2<f64>;
-    type Output = f64;
+impl<E: CacheEntry, T: Real> RenderFunction<'_, E, T> {
+    /// Transpose the map's wave to the given frequency, dropping any partials
+    /// above the frequency cutoff and applying loudness weighting
+    fn transpose(&self, hz: T) -> impl Iterator<Item = Partial<T>> + '_ {
+        let max_hz = self.max_hz;
+        let reference = self.equal_loudness;
 
-    fn process(&self, mut tile: Tile<Self::Input, Self::Output>) {
-        for r in 0..tile.range().size.y {
-            let (row_in, row_out) = tile.row_mut(r);
+        self.wave
+            .map_pitch(move |p| p * hz)
+            .filter(move |p| p.pitch <= max_hz)
+            .map(move |p| equal_loudness(p, reference))
+    }
+
+    /// Place the map's wave at the given frequency, unless it's already there
+    fn place(&self, plugin: Option<&plugin::Instance>, placement: &mut Placement<T>, hz: T) {
+        if placement.hz == Some(hz) {
+            return;
+        }
+
+        placement.partials.clear();
+        extend_pitch_space(
+            &mut placement.partials,
+            self.transpose(hz),
+            self.pitch,
+            &self.overlap,
+            plugin,
+        );
+        placement.hz = Some(hz);
+    }
+
+    /// Combine the held tones with the waves at the given frequencies, adding
+    /// combination tones and applying masking.  This works on absolute
+    /// frequencies, so it can't reuse the precomputed base wave.
+    fn combine_hz(&self, x: T, y: T) -> Vec<Partial<T>> {
+        let wave_x: Wave<T> = self.transpose(x).collect();
+        let wave_y: Wave<T> = self.transpose(y).collect();
+
+        let voices: Vec<_> = self
+            .held
+            .iter()
+            .chain(iter::once(&wave_x))
+            .chain(iter::once(&wave_y))
+            .collect();
+
+        let mut partials: Vec<_> = voices.iter().flat_map(|w| w.iter().copied()).collect();
+
+        if let Some(ref tones) = self.combination_tones {
+            partials.extend(tones.generate(&voices));
+        }
+
+        if let Some(ref masking) = self.masking {
+            partials = masking.apply(partials);
+        }
+
+        partials
+    }
+
+    /// Scale every value so the map's tones sounding in unison with the base
+    /// tone have a dissonance of 1
+    fn normalize(&mut self, plugin: Option<&plugin::Instance>, base_hz: T) {
+        let unison = self.eval(plugin, &mut Scratch::default(), 0, base_hz, base_hz);
+
+        if unison.is_finite() && unison > T::zero() {
+            self.scale = unison.recip();
+        } else {
+            warn!("Timbre has no dissonance at unison; not normalizing map");
+        }
+    }
+
+    /// Compute the unscaled dissonance with the X and Y tones at the given
+    /// frequencies, for a pixel in column `col` of its tile.  The partials
+    /// sounding are gathered into `scratch`, so that nothing is allocated
+    /// unless masking or combination tones are enabled, and the X and Y tones
+    /// are reused from earlier pixels of the same column or row.
+    fn eval(
+        &self,
+        plugin: Option<&plugin::Instance>,
+        scratch: &mut Scratch<T>,
+        col: usize,
+        x: T,
+        y: T,
+    ) -> T {
+        let Scratch {
+            partials,
+            packed,
+            cols,
+            row,
+        } = scratch;
+        let overlap = &self.overlap;
 
-            for (ins, out) in row_in.iter().zip(row_out.iter_mut()) {
-                let wave_x: Wave<_> = self
-                    .pitch
-                    .collect_partials(self.wave.map_pitch(|p| p * ins.x));
+        partials.clear();
 
-                let wave_y: Wave<_> = self
-                    .pitch
-                    .collect_partials(self.wave.map_pitch(|p| p * ins.y));
+        if self.masking.is_some() || self.combination_tones.is_some() {
+            let combined = self.combine_hz(x, y).into_iter();
 
-                let it = self
-                    .base_wave
+            extend_pitch_space(partials, combined, self.pitch, overlap, plugin);
+        } else {
+            if cols.len() <= col {
+                cols.resize_with(col + 1, Placement::default);
+            }
+
+            let col = &mut cols[col];
+
+            self.place(plugin, col, x);
+            self.place(plugin, row, y);
+
+            partials.extend(
+                self.base_wave
                     .iter()
-                    .chain(wave_x.iter())
-                    .chain(wave_y.iter());
-
-                *out = self
-                    .overlap
-                    .collect_partials::<_, Vec<_>>(it.clone().cartesian_product(it))
-                    .into_iter()
-                    .sum::<f64>();
+                    .chain(&col.partials)
+                    .chain(&row.partials)
+                    .copied(),
+            );
+        }
+
+        let weight = self.amp_weighting;
+
+        match (plugin, &self.table) {
+            (Some(p), _) if p.has_overlap() => partials
+                .iter()
+                .cartesian_product(partials.iter())
+                .map(|(a, b)| p.overlap(a.pitch, b.pitch) * weight.eval(a.amp, b.amp))
+                .sum(),
+            (_, Some(t)) => {
+                packed.pack(partials.iter(), weight);
+                t.sum_packed(packed)
+            },
+            _ => {
+                packed.pack(partials.iter(), weight);
+                overlap.sum_packed(&self.curve_params, packed, self.backend)
+            },
+        }
+    }
+}
+
+impl<E: CacheEntry + Send, T: Real> TileRenderFunction for RenderFunction<'_, E, T>
+where for<'b> CacheValue<'b, T>: Into<cache::CacheValue<'b>>
+{
+    type Input = Point2<T>;
+    type Output = T;
+
+    fn process(&self, mut tile: Tile<Self::Input, Self::Output>) -> CancelResult<()> {
+        let plugin = self
+            .plugin
+            .as_ref()
+            .map(Plugin::instantiate)
+            .transpose()
+            .context("failed to instantiate plugin for render thread")?;
+        let plugin = plugin.as_ref();
+        let _hook = plugin.map(|p| p.stop_on(self.cancel));
+        let mut scratch = Scratch::default();
+
+        for r in 0..tile.range().size.y {
+            let (row_in, row_out) = tile.row_mut(r);
+
+            for (c, (ins, out)) in row_in.iter().zip(row_out.iter_mut()).enumerate() {
+                *out = self.eval(plugin, &mut scratch, c, ins.x, ins.y) * self.scale;
             }
         }
 
+        // Interrupted plugin calls return NaN, which mustn't be cached
+        if plugin.is_some_and(plugin::Instance::interrupted) {
+            return Err(Cancelled);
+        }
+
         match self
             .cache_entry
             .lock()
             .unwrap()
             .append(CacheValue::Block(*tile.range(), Cow::Borrowed(tile.out())))
         {
-            Ok(()) => (),
+            Ok(()) => cache::metrics::block_rendered(),
             Err(e) => {
                 warn!("Error caching tile {}: {:?}", tile.range().pos, e);
             },
         }
+
+        Ok(())
     }
 }
 
-pub(super) fn compute<C: for<'a> Cache<'a>>(
-    cache: C,
-    cfg: Config,
+/// Largest difference between a cached and a freshly rendered pixel, relative
+/// to the larger of the two (or 1), that --verify-cache tolerates
+const VERIFY_TOLERANCE: f64 = 1e-4;
+
+/// Re-render a random `fraction` of the tiles preloaded from the cache and
+/// check them against the cached values
+#[allow(clippy::cast_possible_truncation)]
+fn verify_cached<E: CacheEntry, T: Real>(
+    f: &RenderFunction<E, T>,
+    plugin: Option<&plugin::Instance>,
+    pitches: &[Point2<T>],
+    size: Vector2<u32>,
+    preload: &HashMap<TileRange, Cow<'static, [T]>>,
+    fraction: f64,
+) -> Result<()> {
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(anyhow!("cache verification fraction must be between 0 and 1"));
+    }
+
+    let mut rng = rand::thread_rng();
+    let tiles: Vec<_> = preload
+        .iter()
+        .filter(|(r, _)| r.pos.x + r.size.x <= size.x && r.pos.y + r.size.y <= size.y)
+        .filter(|_| rng.gen_bool(fraction))
+        .collect();
+    let tol = T::of(VERIFY_TOLERANCE);
+    let mut scratch = Scratch::default();
+
+    trace!("Verifying {} of {} cached tile(s)...", tiles.len(), preload.len());
+
+    for (range, cached) in &tiles {
+        if cached.len() != (range.size.x * range.size.y) as usize {
+            return Err(anyhow!("cached tile at {} has the wrong size", range.pos));
+        }
+
+        for (i, old) in cached.iter().enumerate() {
+            let (col, row) = (i as u32 % range.size.x, i as u32 / range.size.x);
+            let (x, y) = (range.pos.x + col, range.pos.y + row);
+            let px = pitches[(y * size.x + x) as usize];
+            let new = f.eval(plugin, &mut scratch, col as usize, px.x, px.y) * f.scale;
+
+            let same = (new.is_nan() && old.is_nan())
+                || (new - *old).abs() <= tol * new.abs().max(old.abs()).max(T::one());
+
+            if !same {
+                return Err(anyhow!(
+                    "cached pixel ({}, {}) is {} but renders as {}; the cache may be stale or \
+                     corrupted, try again with --refresh",
+                    x,
+                    y,
+                    old.widen(),
+                    new.widen()
+                ));
+            }
+        }
+    }
+
+    info!("Verified {} cached tile(s) against a fresh render", tiles.len());
+
+    Ok(())
+}
+
+/// Side length of the map sampled to estimate render time
+const ESTIMATE_SAMPLE: u32 = 32;
+
+/// A rough forecast of the cost of rendering a map
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    pub pixels: u64,
+    /// Bytes needed for the input pitches and the rendered map
+    pub memory: u64,
+    /// Time to render every pixel, extrapolated from a small sample
+    pub time: Duration,
+}
+
+/// The hash naming the cache file of a map
+pub(super) fn key_hash(cfg: &Config) -> Result<String> {
+    cache::file::key_hash(&cache::CacheKey::from(CacheKey(AlgoVersions::of(cfg), cfg.clone())))
+}
+
+/// Estimate the cost of rendering a map by timing a low-resolution render of
+/// the same view on one thread
+#[allow(clippy::cast_precision_loss)]
+pub(super) fn estimate(
+    cfg: &Config,
+    render: &RenderConfig,
     cancel: &CancelToken,
-) -> CancelResult<DissonMap> {
-    let mut cache_entry = cache
-        .entry(CacheKey(cfg))
-        .context("couldn't open cache entry")?;
+) -> CancelResult<Estimate> {
+    let pixels = u64::from(cfg.size.x) * u64::from(cfg.size.y);
+    let elem = match cfg.precision {
+        Precision::Single => mem::size_of::<f32>(),
+        Precision::Double => mem::size_of::<f64>(),
+    } as u64;
 
-    let Config {
+    let size = cfg.size.map(|s| s.min(ESTIMATE_SAMPLE));
+    let sample = cfg.with_layout(Layout {
         size,
-        view,
-        base_hz,
-        pitch,
-        overlap,
-    } = cfg;
+        view: cfg.view,
+    });
+    let sample_render = RenderConfig {
+        tile_width: size.x,
+        tile_height: size.y,
+        threads: 1,
+    };
+
+    let start = Instant::now();
+
+    match cfg.precision {
+        Precision::Single => {
+            compute::<_, f32>(NullCache, sample, &[], &sample_render, None, cancel)?;
+        },
+        Precision::Double => {
+            compute::<_, f64>(NullCache, sample, &[], &sample_render, None, cancel)?;
+        },
+    }
+
+    let per_pixel = start.elapsed().as_secs_f64() / f64::from(size.x * size.y);
+    let threads = match render.threads {
+        0 => rayon::current_num_threads(),
+        n => n as usize,
+    };
+
+    Ok(Estimate {
+        pixels,
+        // Two coordinates in, one value out
+        memory: pixels * elem * 3,
+        time: Duration::from_secs_f64(per_pixel * pixels as f64 / threads as f64),
+    })
+}
+
+/// How much of a map an earlier run left in the cache
+#[derive(Debug, Clone, Copy)]
+pub struct Resume {
+    /// The tile size to render the rest of the map with
+    pub tile_size: Vector2<u32>,
+    /// The number of tiles already cached
+    pub cached: usize,
+    /// The number of tiles in the whole map
+    pub total: usize,
+    /// Whether the earlier run finished the map
+    pub complete: bool,
+}
+
+fn resume_impl<C: for<'a> Cache<'a>, T: Real>(
+    cache: &C,
+    cfg: &Config,
+    tile_size: Vector2<u32>,
+) -> Result<Resume>
+where
+    CacheValue<'static, T>: TryFrom<cache::CacheValue<'static>, Error = ConvertError>,
+{
+    let mut entry = cache
+        .entry(CacheKey(AlgoVersions::of(cfg), cfg.clone()))
+        .context("couldn't open cache entry")?;
+
+    let mut ranges = vec![];
+    let mut complete = false;
+
+    for val in entry.read_iter::<CacheValue<T>, _>() {
+        match val.context("couldn't read cache blocks")? {
+            CacheValue::Block(r, _) => ranges.push(r),
+            CacheValue::Histogram(()) => complete = true,
+        }
+    }
+
+    // Edge tiles may be clipped, so the largest cached tile is the best guess
+    // at the tile size of the earlier run
+    let cached_size = ranges
+        .iter()
+        .map(|r| r.size)
+        .fold(None, |m: Option<Vector2<u32>>, s| {
+            Some(m.map_or(s, |m| Vector2::new(m.x.max(s.x), m.y.max(s.y))))
+        });
+
+    let tile_size = match cached_size {
+        Some(s) if s != tile_size => {
+            let tiles = tile_renderer::tile_ranges(cfg.size, s);
+
+            if ranges.iter().all(|r| tiles.contains(r)) {
+                s
+            } else {
+                warn!("Couldn't determine the tile size of the cached map");
+                tile_size
+            }
+        },
+        _ => tile_size,
+    };
+
+    let tiles = tile_renderer::tile_ranges(cfg.size, tile_size);
+    let cached = tiles.iter().filter(|t| ranges.contains(t)).count();
+
+    Ok(Resume {
+        tile_size,
+        cached,
+        total: tiles.len(),
+        complete,
+    })
+}
+
+/// Inspect the cache entry for a map to see how much of it is already
+/// rendered, and with which tile size
+pub(super) fn resume<C: for<'a> Cache<'a>>(
+    cache: &C,
+    cfg: &Config,
+    tile_size: Vector2<u32>,
+) -> Result<Resume> {
+    match cfg.precision {
+        Precision::Single => resume_impl::<_, f32>(cache, cfg, tile_size),
+        Precision::Double => resume_impl::<_, f64>(cache, cfg, tile_size),
+    }
+}
+
+/// Whether a progress bar is on screen, so maps rendered at the same time
+/// don't draw over each other
+static BAR_SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Create a progress bar for rendering a map, hidden unless stderr is a
+/// terminal, info logs are enabled, messages aren't printed as JSON, and no
+/// other map is showing its progress
+fn progress_bar() -> ProgressBar {
+    if !(log_enabled!(Level::Info) && atty::is(atty::Stream::Stderr))
+        || message::is_json()
+        || BAR_SHOWN.swap(true, Ordering::AcqRel)
+    {
+        return ProgressBar::hidden();
+    }
+
+    let bar = ProgressBar::new(0);
+
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template(
+                "[{elapsed_precise}] [{bar:40}] {pos}/{len} tiles ({per_sec}, ETA {eta}) \
+                 {msg}",
+            )
+            .progress_chars("=> "),
+    );
+
+    bar
+}
+
+/// Remove a finished progress bar, letting the next map show its progress
+fn clear_progress_bar(bar: &ProgressBar) {
+    bar.finish_and_clear();
+
+    if !bar.is_hidden() {
+        BAR_SHOWN.store(false, Ordering::Release);
+    }
+}
+
+fn update_progress(bar: ProgressBar) -> impl Fn(TileEvent) + Send + Sync + 'static {
+    let (done, total) = (AtomicU64::new(0), AtomicU64::new(0));
+
+    move |evt| match evt {
+        TileEvent::Started {
+            total: tiles,
+            preloaded,
+        } => {
+            let (tiles, preloaded) = (tiles as u64, preloaded as u64);
+
+            done.store(preloaded, Ordering::Relaxed);
+            total.store(tiles, Ordering::Relaxed);
+            message::progress(preloaded, tiles);
+
+            bar.reset();
+            // Only rendered tiles are counted so preloading doesn't skew the ETA
+            bar.set_length(tiles - preloaded);
 
-    let mut blk_preload = HashMap::new();
-    let mut hist_preload = None;
+            if preloaded > 0 {
+                bar.set_message(&format!("{preloaded} tiles preloaded"));
+            }
+        },
+        TileEvent::Rendered => {
+            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+
+            message::progress(done, total.load(Ordering::Relaxed));
+            bar.inc(1);
+        },
+    }
+}
 
-    for val in cache_entry.read().context("couldn't read cache blocks")? {
-        match val {
+/// Tiles of a map read from the cache, by the range they cover
+type Tiles<T> = HashMap<TileRange, Cow<'static, [T]>>;
+
+/// Read the tiles stored in a map's cache entry
+fn read_tiles<E: CacheEntry, T: Real>(entry: &mut E) -> Result<Tiles<T>>
+where
+    CacheValue<'static, T>: TryFrom<cache::CacheValue<'static>, Error = ConvertError>,
+{
+    let mut tiles = HashMap::new();
+    let mut histogram = false;
+
+    for val in entry.read_iter::<CacheValue<T>, _>() {
+        match val.context("couldn't read cache blocks")? {
             CacheValue::Block(k, v) => {
-                if blk_preload.insert(k, v).is_some() {
+                if tiles.insert(k, v).is_some() {
                     warn!(
                         "Multiple blocks at {} stored in map cache; taking latest",
                         k.pos
                     );
                 }
             },
-            CacheValue::Histogram(h) => {
-                if hist_preload.is_some() {
+            CacheValue::Histogram(()) => {
+                if mem::replace(&mut histogram, true) {
                     warn!("Multiple histograms stored in map cache; taking latest");
                 }
-
-                hist_preload = Some(h);
             },
         }
     }
 
-    trace!("Computing map inputs...");
+    Ok(tiles)
+}
 
-    let pitches: Vec<_> = {
-        let denom = (size - Vector2::new(1, 1)).cast::<f64>();
+/// Fill in tiles missing from `preload` from cached tiles of other sizes and
+/// other layouts, returning the ranges of the tiles added
+fn reuse_tiles<C: for<'a> Cache<'a>, T: Real>(
+    cache: &C,
+    cfg: &Config,
+    tile_size: Vector2<u32>,
+    preload: &mut Tiles<T>,
+) -> Vec<TileRange>
+where
+    CacheValue<'static, T>: TryFrom<cache::CacheValue<'static>, Error = ConvertError>,
+{
+    let mut reused = retile(cfg.size, tile_size, preload);
 
-        let coords = (0..size.x).into_iter().flat_map(move |r| {
-            (0..size.y).into_iter().map(move |c| {
-                view * Point2::from(Vector2::new(c, r).cast::<f64>().component_div(&denom))
-            })
-        });
+    if !reused.is_empty() {
+        trace!("Reassembled {} tile(s) from other tile sizes", reused.len());
+    }
 
-        coords
-            .map(|mut c| {
-                c.x = base_hz * 2.0_f64.powf(c.x);
-                c.y = base_hz * 2.0_f64.powf(c.y);
-                c
-            })
-            .take_while(|_| cancel.try_weak().is_ok())
-            .collect()
-    };
+    match reproject(cache, cfg, tile_size, preload) {
+        Ok(tiles) => {
+            if !tiles.is_empty() {
+                trace!("Reusing {} tile(s) from other layouts", tiles.len());
+            }
+
+            reused.extend(tiles);
+        },
+        Err(e) => warn!("Failed to reuse tiles from other layouts: {e:?}"),
+    }
+
+    reused
+}
+
+/// Open a map's cache entry and read the tiles it holds, adding any that can
+/// be reused from other tile sizes and layouts to the entry
+fn preload<'c, C: for<'a> Cache<'a>, T: Real>(
+    cache: &'c C,
+    cfg: &Config,
+    tile_size: Vector2<u32>,
+) -> Result<(<C as Cache<'c>>::Entry, Tiles<T>)>
+where
+    for<'a> CacheValue<'a, T>: Into<cache::CacheValue<'a>>,
+    CacheValue<'static, T>: TryFrom<cache::CacheValue<'static>, Error = ConvertError>,
+{
+    let mut entry = cache
+        .entry(CacheKey(AlgoVersions::of(cfg), cfg.clone()))
+        .context("couldn't open cache entry")?;
+
+    let mut tiles = read_tiles(&mut entry)?;
+
+    for range in reuse_tiles(cache, cfg, tile_size, &mut tiles) {
+        entry
+            .append(CacheValue::Block(range, Cow::Borrowed(&*tiles[&range])))
+            .context("failed to cache reused tile")?;
+    }
+
+    Ok((entry, tiles))
+}
+
+/// Get the frequencies of the X and Y tones at every pixel of a map, in
+/// row-major order
+fn pixel_pitches<T: Real>(
+    size: Vector2<u32>,
+    view: Transform2<f64>,
+    base_hz: T,
+    cancel: &CancelToken,
+) -> CancelResult<Vec<Point2<T>>> {
+    let coords = (0..size.y).flat_map(move |r| {
+        (0..size.x).map(move |c| pixel_octaves(view, size, Point2::new(c, r).cast()))
+    });
+
+    let pitches = coords
+        .map(|c| {
+            Point2::new(
+                base_hz * T::of(2.0_f64.powf(c.x)),
+                base_hz * T::of(2.0_f64.powf(c.y)),
+            )
+        })
+        .take_while(|_| cancel.try_weak().is_ok())
+        .collect();
 
     cancel.try_weak()?;
 
-    trace!("Rendering map...");
+    Ok(pitches)
+}
+
+/// Compute a map, loading whatever tiles are cached and rendering the rest,
+/// then apply `post_transforms` to it.  The cache only holds untransformed
+/// values, so the transforms aren't part of the config.  If `verify` is given,
+/// that fraction of the cached tiles is rendered again and checked against the
+/// cache.
+pub(super) fn compute<C: for<'a> Cache<'a>, T: Real>(
+    cache: C,
+    cfg: Config,
+    post_transforms: &[PostTransform],
+    render: &RenderConfig,
+    verify: Option<f64>,
+    cancel: &CancelToken,
+) -> CancelResult<DissonMap<T>>
+where
+    for<'a> CacheValue<'a, T>: Into<cache::CacheValue<'a>>,
+    CacheValue<'static, T>: TryFrom<cache::CacheValue<'static>, Error = ConvertError>,
+{
+    let preload_timer = timing::start(Phase::Preload);
+    let tile_size = Vector2::new(render.tile_width, render.tile_height);
+    let (cache_entry, blk_preload) = preload(&cache, &cfg, tile_size)?;
+
+    mem::drop(preload_timer);
 
-    // TODO
-    let wave: Wave = (1..=32)
+    let Config { size, view, .. } = cfg;
+    let base_hz = T::of(cfg.base_hz);
+    let max_hz = T::of(cfg.max_frequency.unwrap_or(f64::INFINITY));
+    let min_amp = T::of(cfg.min_amplitude.unwrap_or(0.0));
+
+    let wave: Wave<T> = cfg
+        .wave
+        .cast::<T>()
         .into_iter()
-        .map(|i| Partial {
-            pitch: i.into(),
-            amp: 1.0 / f64::from(i),
-        })
+        .filter(|p| p.amp >= min_amp)
         .collect();
 
+    let table = cfg
+        .lookup_table
+        .map(|r| cfg.overlap.table(&cfg.curve_params, r));
+    let held = held_waves(cfg.held, max_hz, min_amp, cfg.equal_loudness);
+
+    cache::metrics::blocks_preloaded(blk_preload.len() as u64);
+
+    trace!("Computing map inputs...");
+
+    let grid_timer = timing::start(Phase::Grid);
+    let pitches = pixel_pitches(size, view, base_hz, cancel)?;
+
+    mem::drop(grid_timer);
+
+    trace!("Rendering map...");
+
     let cache_mutex = Mutex::new(cache_entry);
-    let base_wave = &pitch.collect_partials(wave.map_pitch(|p| p * base_hz));
+    let plugin_inst = cfg.plugin.as_ref().map(Plugin::instantiate).transpose()?;
+    let plugin_hook = plugin_inst.as_ref().map(|p| p.stop_on(cancel));
 
-    let data = DefaultTileRenderer::new(RenderFunction {
+    let base_wave = &to_pitch_space(
+        held.iter().flat_map(|w| w.iter().copied()),
+        cfg.pitch,
+        &cfg.overlap,
+        plugin_inst.as_ref(),
+    );
+
+    let mut f = RenderFunction {
         cache_entry: &cache_mutex,
-        pitch,
-        overlap,
+        cancel,
+        pitch: cfg.pitch,
+        overlap: cfg.overlap,
+        curve_params: cfg.curve_params,
+        table,
+        amp_weighting: cfg.amp_weighting,
+        masking: cfg.masking,
+        combination_tones: cfg.combination_tones,
         wave,
+        max_hz,
+        equal_loudness: cfg.equal_loudness,
+        held,
         base_wave,
-    })
-    .run(size, pitches, &blk_preload, cancel)?;
+        plugin: cfg.plugin,
+        scale: T::one(),
+        backend: Backend::detect(),
+    };
+
+    if cfg.normalize {
+        f.normalize(plugin_inst.as_ref(), base_hz);
+    }
+
+    if let Some(fraction) = verify {
+        verify_cached(&f, plugin_inst.as_ref(), &pitches, size, &blk_preload, fraction)?;
+    }
+
+    if plugin_inst
+        .as_ref()
+        .is_some_and(plugin::Instance::interrupted)
+    {
+        return Err(Cancelled);
+    }
+
+    mem::drop(plugin_hook);
+    // Without plugin support there's never an instance to free
+    #[cfg_attr(not(feature = "plugin"), allow(clippy::drop_non_drop))]
+    mem::drop(plugin_inst);
+
+    let bar = progress_bar();
+    let render_timer = timing::start(Phase::Render);
+    let data = TileRenderer::new(f, tile_size, render.threads as usize)
+        .on_event(update_progress(bar.clone()))
+        .run_partial(size, pitches, &blk_preload, cancel, T::nan());
 
-    cancel.try_strong()?;
+    clear_progress_bar(&bar);
+    mem::drop(render_timer);
 
     let mut cache_entry = cache_mutex.into_inner().unwrap();
+    let (mut data, left) = match data {
+        Ok(d) => d,
+        Err(e) => {
+            save_entry(&mut cache_entry);
+            return Err(e.into());
+        },
+    };
+
+    // A map cut short by its time limit is still returned, with the missing
+    // tiles left as NaN
+    if let Err(e) = cancel.try_strong() {
+        if !cancel.is_expired() {
+            save_entry(&mut cache_entry);
+            return Err(e);
+        }
+    }
+
+    let _timer = timing::start(Phase::Histogram);
+
+    for transform in post_transforms {
+        transform.apply(&mut data, size.x as usize);
+    }
+
+    finish_entry::<T>(&mut cache_entry, left);
 
-    cache_entry
-        .append(CacheValue::Histogram(()))
-        .context("failed to cache map histogram")?;
+    Ok(DissonMap { size, view, data })
+}
+
+/// Transpose each held tone's wave to its frequency, dropping any partials
+/// outside the frequency and amplitude cutoffs and applying loudness
+/// weighting
+fn held_waves<T: Real>(
+    held: Vec<(f64, Wave)>,
+    max_hz: T,
+    min_amp: T,
+    equal_loudness_ref: Option<f64>,
+) -> Vec<Wave<T>> {
+    held.into_iter()
+        .map(|(hz, wave)| {
+            let hz = T::of(hz);
+
+            wave.cast::<T>()
+                .map_pitch(|p| p * hz)
+                .filter(|p| p.pitch <= max_hz && p.amp >= min_amp)
+                .map(|p| equal_loudness(p, equal_loudness_ref))
+                .collect()
+        })
+        .collect()
+}
+
+/// Mark a map's cache entry complete unless tiles were left unrendered, and
+/// save it
+fn finish_entry<T: Real>(entry: &mut impl CacheEntry, left: usize)
+where for<'a> CacheValue<'a, T>: Into<cache::CacheValue<'a>> {
+    if left == 0 {
+        if let Err(e) = entry.append(CacheValue::<T>::Histogram(())) {
+            warn!("Failed to cache map histogram: {e:?}");
+        }
+    } else {
+        warn!("Time limit reached with {left} map tile(s) left unrendered");
+    }
 
-    Ok(DissonMap { size, data })
+    save_entry(entry);
+}
+
+/// Store the tiles appended to a map's cache entry, even if rendering stopped
+/// early.  A map that couldn't be cached is still usable, so failures are
+/// only logged.
+fn save_entry(entry: &mut impl CacheEntry) {
+    if let Err(e) = entry.flush() {
+        warn!("Failed to save cached map tiles: {e:?}");
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{borrow::Cow, collections::HashMap, convert::TryFrom};
+
+    use nalgebra::{Transform2, Vector2};
+
+    use super::{
+        combine, reproject, AlgoVersions, CacheKey, CacheValue, Combine, CombineOp, Config,
+        DissonMap, Layout, LayoutsKey,
+    };
+    use crate::{
+        cache::{memory::MemoryCache, prelude::*, NullCache},
+        config::Validator,
+        disson::{
+            algo::{AmpWeighting, CurveParams, OverlapCurve, PitchCurve},
+            real::Precision,
+            timbre::Timbre,
+        },
+        tile_renderer::TileRange,
+    };
+
+    /// A square map of the default view, with the default timbre
+    fn config(size: u32) -> Config {
+        Config {
+            size: Vector2::new(size, size),
+            view: Transform2::identity(),
+            base_hz: 220.0,
+            normalize: false,
+            held: vec![],
+            pitch: PitchCurve::Edo,
+            overlap: OverlapCurve::ExpDiss,
+            curve_params: CurveParams::default(),
+            lookup_table: None,
+            amp_weighting: AmpWeighting::Linear,
+            masking: None,
+            combination_tones: None,
+            wave: Timbre::default().wave().unwrap(),
+            max_frequency: None,
+            min_amplitude: None,
+            equal_loudness: None,
+            plugin: None,
+            precision: Precision::Double,
+        }
+    }
+
+    /// The cache key of a square map, for tests of the caches storing it
+    pub(crate) fn key(size: u32) -> CacheKey {
+        let cfg = config(size);
+
+        CacheKey(AlgoVersions::of(&cfg), cfg)
+    }
+
+    /// Reproject a square map of `size` pixels, as one tile, from the layouts
+    /// cached so far
+    fn reproject_square(
+        cache: &MemoryCache<NullCache>,
+        size: u32,
+    ) -> (Vec<TileRange>, HashMap<TileRange, Cow<'static, [f64]>>) {
+        let mut preload = HashMap::new();
+        let tile_size = Vector2::new(size, size);
+        let filled = reproject(cache, &config(size), tile_size, &mut preload).unwrap();
+
+        (filled, preload)
+    }
+
+    #[test]
+    fn reprojects_pixels_shared_with_other_layouts() {
+        let cache = MemoryCache::new(NullCache, 16);
+        let range = TileRange {
+            pos: Vector2::zeros(),
+            size: Vector2::new(5, 5),
+        };
+
+        // Records the layout of the finer map
+        reproject_square(&cache, 5);
+
+        let mut entry = cache.entry(key(5)).unwrap();
+        let data: Vec<_> = (0..25).map(f64::from).collect();
+        entry.read::<CacheValue, _>().unwrap();
+        entry.append(CacheValue::Block(range, Cow::Owned(data))).unwrap();
+        drop(entry);
+
+        // Every pixel of the 3x3 map lands on an even pixel of the 5x5 one
+        let (filled, preload) = reproject_square(&cache, 3);
+        let range = TileRange {
+            pos: Vector2::zeros(),
+            size: Vector2::new(3, 3),
+        };
+
+        assert_eq!(filled, [range]);
+        let expected = [0.0, 2.0, 4.0, 10.0, 12.0, 14.0, 20.0, 22.0, 24.0];
+        assert_eq!(&*preload[&range], &expected);
+    }
+
+    #[test]
+    fn forgets_layouts_no_longer_cached() {
+        let cache = MemoryCache::new(NullCache, 16);
+
+        reproject_square(&cache, 5);
+        let (filled, _) = reproject_square(&cache, 3);
+
+        let layouts: Vec<Layout> = cache
+            .entry(LayoutsKey::of(&config(3)))
+            .and_then(|mut e| e.read())
+            .unwrap();
+
+        assert!(filled.is_empty());
+        assert_eq!(layouts, [config(3).layout()]);
+    }
+
+    /// A map one pixel tall holding the given values
+    fn row(data: &[f64]) -> DissonMap {
+        DissonMap {
+            size: Vector2::new(u32::try_from(data.len()).unwrap(), 1),
+            view: Transform2::identity(),
+            data: data.into(),
+        }
+    }
+
+    fn combined(op: CombineOp) -> Box<[f64]> {
+        let maps = vec![(2.0, row(&[1.0, 3.0, f64::NAN])), (0.5, row(&[4.0, 8.0, 1.0]))];
+
+        combine(op, maps).data
+    }
+
+    #[test]
+    fn combine_weights_each_component() {
+        assert_eq!(combined(CombineOp::Sum)[..2], [4.0, 10.0]);
+        assert_eq!(combined(CombineOp::Max)[..2], [2.0, 6.0]);
+        assert_eq!(combined(CombineOp::Difference)[..2], [0.0, 2.0]);
+    }
+
+    #[test]
+    fn combine_keeps_missing_pixels_missing() {
+        for &op in &[CombineOp::Sum, CombineOp::Max, CombineOp::Difference] {
+            assert!(combined(op)[2].is_nan());
+        }
+    }
+
+    #[test]
+    fn combine_rejects_bad_components() {
+        for components in [vec![], vec![(f64::INFINITY, "a".into())]] {
+            let mut v = Validator::default();
+            Combine {
+                op: CombineOp::Sum,
+                components,
+            }
+            .validate("map.combine", &mut v);
+
+            assert!(v.finish().is_err());
+        }
+    }
 }
@@ -1,28 +1,71 @@
-use std::{borrow::Borrow, fs::File, future::Future, io, sync::Arc};
+use std::{
+    borrow::{Borrow, Cow},
+    ffi::OsStr,
+    fs::{self, File},
+    future::Future,
+    io, iter, mem,
+    path::{self, Path, PathBuf},
+    sync::{Arc, LazyLock},
+    thread,
+    time::Duration,
+};
 
 use anyhow::anyhow;
 use dispose::defer;
 use futures::prelude::*;
-use log::{debug, info, trace, warn};
-use map::DissonMap;
+use itertools::Itertools;
+use log::{debug, error, info, trace, warn};
+use contour::Contour;
+use extrema::Extremum;
+use map::{Axis, CombineOp, DissonMap};
+use nalgebra::{Point2, Vector2};
 use notify::{event::ModifyKind, EventKind, RecursiveMode, Watcher};
+use post::PostTransform;
+use real::{Precision, Real};
+use regex::{Captures, Regex};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use stats::Stats;
+use summary::Status;
+use timing::Phase;
 use tokio::{runtime, select, signal, sync::mpsc};
+use wave::Partial;
 
 use crate::{
     cache,
-    cache::prelude::*,
-    cancel::prelude::*,
-    cli::{CacheMode, GenerateOpts},
-    config::{GenerateConfig, MapFormat, MapOutput},
+    cache::{prelude::*, MemoryCache},
+    cancel::{self, prelude::*, CancelError},
+    cli::{
+        Age, ByteSize, CacheOpts, ConfigOpts, ExplainOpts, GenerateOpts, Interval, MapPoint,
+        RenderAudioOpts, RenderSweepOpts, StatsOpts, WarmOpts,
+    },
+    config::{
+        self, FormatConfig, GenerateConfig, MapConfig, MapFormat, MapOutput, RenderConfig, Sweep,
+    },
     error::prelude::*,
 };
 
 pub mod algo;
+mod audio;
+mod contour;
+pub mod extrema;
+mod loudness;
 pub mod map;
-mod wave;
+mod plugin;
+mod png;
+pub mod post;
+pub mod real;
+mod simd;
+pub mod spectrum;
+pub mod stats;
+mod summary;
+pub mod timbre;
+mod timing;
+pub mod wave;
 
-fn write_xsv<W: io::Write>(
-    map: &DissonMap,
+fn write_xsv<W: io::Write, T: Real>(
+    map: &DissonMap<T>,
     delim: u8,
     out: W,
     cancel: &CancelToken,
@@ -54,7 +97,619 @@ fn write_xsv<W: io::Write>(
     Ok(())
 }
 
-fn generate_impl<C: for<'a> Cache<'a>>(
+fn write_map<T: Real>(
+    map: &DissonMap<T>,
+    ty: MapFormat,
+    out: &MapOutput,
+    format: &FormatConfig,
+    cancel: &CancelToken,
+) -> CancelResult<()> {
+    match ty {
+        MapFormat::Xsv(ref d) => match *out {
+            MapOutput::Stdout => write_xsv(map, *d, io::stdout(), cancel)?,
+            MapOutput::File(ref p) => write_xsv(
+                map,
+                *d,
+                File::create(p).context("failed to open output file")?,
+                cancel,
+            )?,
+        },
+        MapFormat::Png => match *out {
+            MapOutput::Stdout => png::write(map, format, io::stdout())?,
+            MapOutput::File(ref p) => png::write(
+                map,
+                format,
+                File::create(p).context("failed to open output file")?,
+            )?,
+        },
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct ExtremumRow<T> {
+    col: u32,
+    row: u32,
+    x_cents: f64,
+    y_cents: f64,
+    x_ratio: f64,
+    y_ratio: f64,
+    value: T,
+    depth: T,
+}
+
+fn open_output(out: &MapOutput) -> Result<Box<dyn io::Write>> {
+    Ok(match *out {
+        MapOutput::Stdout => Box::new(io::stdout()),
+        MapOutput::File(ref p) => Box::new(File::create(p).context("failed to open output file")?),
+    })
+}
+
+fn write_extrema<T: Real>(
+    map: &DissonMap<T>,
+    extrema: &[Extremum<T>],
+    out: &MapOutput,
+) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(open_output(out)?);
+
+    for e in extrema {
+        let oct = map.octaves(Point2::new(e.col, e.row).cast());
+
+        writer
+            .serialize(ExtremumRow {
+                col: e.col,
+                row: e.row,
+                x_cents: oct.x * 1200.0,
+                y_cents: oct.y * 1200.0,
+                x_ratio: oct.x.exp2(),
+                y_ratio: oct.y.exp2(),
+                value: e.value,
+                depth: e.depth,
+            })
+            .context("failed to write extremum")?;
+    }
+
+    writer.flush().context("failed to flush extrema")?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct ContourRow {
+    level: f64,
+    contour: usize,
+    x_cents: f64,
+    y_cents: f64,
+}
+
+fn write_contours<T: Real>(
+    map: &DissonMap<T>,
+    contours: &[Contour],
+    out: &MapOutput,
+) -> Result<()> {
+    let cents = |p| map.octaves(p).coords * 1200.0;
+    let geojson = match *out {
+        MapOutput::Stdout => false,
+        MapOutput::File(ref p) => matches!(
+            p.extension().and_then(OsStr::to_str),
+            Some("geojson" | "json")
+        ),
+    };
+    let out = open_output(out)?;
+
+    if geojson {
+        let features: Vec<_> = contours
+            .iter()
+            .map(|c| {
+                let coords: Vec<_> = c
+                    .points
+                    .iter()
+                    .map(|&p| {
+                        let p = cents(p);
+                        [p.x, p.y]
+                    })
+                    .collect();
+
+                json!({
+                    "type": "Feature",
+                    "properties": { "level": c.level },
+                    "geometry": { "type": "LineString", "coordinates": coords },
+                })
+            })
+            .collect();
+
+        serde_json::to_writer(out, &json!({ "type": "FeatureCollection", "features": features }))
+            .context("failed to write GeoJSON contours")?;
+    } else {
+        let mut writer = csv::Writer::from_writer(out);
+
+        for (i, c) in contours.iter().enumerate() {
+            for &p in &c.points {
+                let p = cents(p);
+
+                writer
+                    .serialize(ContourRow {
+                        level: c.level,
+                        contour: i,
+                        x_cents: p.x,
+                        y_cents: p.y,
+                    })
+                    .context("failed to write contour point")?;
+            }
+        }
+
+        writer.flush().context("failed to flush contours")?;
+    }
+
+    Ok(())
+}
+
+/// Insert a frame number before the extension of an output file
+fn numbered(out: &MapOutput, frame: Option<usize>) -> MapOutput {
+    match frame {
+        Some(i) => suffixed(out, &format!("{i:05}")),
+        None => out.clone(),
+    }
+}
+
+/// Insert `suffix` between the stem and extension of an output file name
+fn suffixed(out: &MapOutput, suffix: &str) -> MapOutput {
+    match out {
+        MapOutput::File(p) => {
+            let mut name = p.file_stem().unwrap_or_default().to_owned();
+            name.push(".");
+            name.push(suffix);
+
+            if let Some(ext) = p.extension() {
+                name.push(".");
+                name.push(ext);
+            }
+
+            MapOutput::File(p.with_file_name(name))
+        },
+        MapOutput::Stdout => MapOutput::Stdout,
+    }
+}
+
+fn is_templated(out: &MapOutput) -> bool {
+    matches!(out, MapOutput::File(p) if p.to_string_lossy().contains('{'))
+}
+
+/// Whether an output names a directory to write an automatically named file
+/// into, rather than the file itself
+fn is_directory(out: &MapOutput) -> bool {
+    matches!(out, MapOutput::File(p)
+        if p.is_dir() || p.to_string_lossy().chars().last().is_some_and(path::is_separator))
+}
+
+/// Name the file for a map written into `dir` after its config and a hash of
+/// the resolved settings, so that repeating an experiment overwrites its
+/// earlier output and changing it doesn't
+fn auto_name(dir: &Path, opts: &GenerateOpts, cfg: &GenerateConfig) -> Result<MapOutput> {
+    let hash = Sha256::digest(config::to_ron(&(&cfg.map, &cfg.format))?.as_bytes());
+    let hash = hash[..4]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .fold(String::new(), |mut s, h| {
+            s.push_str(&h);
+            s
+        });
+    let stem = opts.cfg.config.file_stem().unwrap_or_default();
+
+    if !opts.dry_run {
+        fs::create_dir_all(dir).context("failed to create output directory")?;
+    }
+
+    Ok(MapOutput::File(dir.join(format!(
+        "{}-{}.{}",
+        stem.to_string_lossy(),
+        hash,
+        opts.ty.unwrap_or(MapFormat::TSV).extension()
+    ))))
+}
+
+/// Format a frequency for a file name, without trailing zeros
+fn short_hz(hz: f64) -> String {
+    let s = format!("{hz:.2}");
+
+    s.trim_end_matches('0').trim_end_matches('.').to_owned()
+}
+
+/// Replace placeholders such as `{width}` in an output path with values from
+/// the resolved config.  `{{` and `}}` stand for literal braces.
+fn expand_template(out: &MapOutput, cfg: &GenerateConfig, config: &Path) -> Result<MapOutput> {
+    static PLACEHOLDER: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"\{\{|\}\}|\{([^{}]*)\}").unwrap());
+
+    let path = match out {
+        MapOutput::File(p) if is_templated(out) => p.to_string_lossy(),
+        out => return Ok(out.clone()),
+    };
+
+    let mut err = None;
+    let expanded = PLACEHOLDER.replace_all(&path, |caps: &Captures| {
+        let name = match caps.get(1) {
+            Some(n) => n.as_str(),
+            None => return caps[0][..1].to_owned(),
+        };
+
+        match name {
+            "width" => cfg.map.width.to_string(),
+            "height" => cfg.map.height.to_string(),
+            "pitch_curve" => ron::ser::to_string(&cfg.map.pitch_curve).unwrap_or_default(),
+            "base_hz" => short_hz(cfg.map.base_frequency.hz(cfg.map.tuning_reference)),
+            "config_stem" => config
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            _ => {
+                err.get_or_insert_with(|| {
+                    anyhow!(
+                        "unknown placeholder {{{}}} in output path (expected one of width, \
+                         height, pitch_curve, base_hz, or config_stem)",
+                        name
+                    )
+                });
+                String::new()
+            },
+        }
+    });
+
+    match err {
+        Some(e) => Err(e),
+        None => Ok(MapOutput::File(expanded.into_owned().into())),
+    }
+}
+
+/// Expand the placeholders in every output path of `opts`, and name the map
+/// file if the output is a directory
+fn expand_outputs<'a>(
+    opts: &'a GenerateOpts,
+    cfg: &GenerateConfig,
+) -> Result<Cow<'a, GenerateOpts>> {
+    let extra = [&opts.minima, &opts.maxima, &opts.contours];
+
+    if !(is_templated(&opts.out)
+        || is_directory(&opts.out)
+        || extra.iter().any(|o| o.as_ref().is_some_and(is_templated)))
+    {
+        return Ok(Cow::Borrowed(opts));
+    }
+
+    let config = &opts.cfg.config;
+    let expand = |o: &Option<MapOutput>| {
+        o.as_ref()
+            .map(|o| expand_template(o, cfg, config))
+            .transpose()
+    };
+
+    // Frames of a time-varying map are already written inside a directory
+    let out = expand_template(&opts.out, cfg, config)?;
+    let out = match out {
+        MapOutput::File(ref dir) if cfg.map.frames.is_none() && is_directory(&out) => {
+            auto_name(dir, opts, cfg)?
+        },
+        out => out,
+    };
+
+    Ok(Cow::Owned(GenerateOpts {
+        out,
+        minima: expand(&opts.minima)?,
+        maxima: expand(&opts.maxima)?,
+        contours: expand(&opts.contours)?,
+        ..opts.clone()
+    }))
+}
+
+/// Write a finished map and any analyses of it requested on the command line.
+/// Frames of a time-varying map are identified by `frame`.
+fn write_outputs<T: Real>(
+    map: &DissonMap<T>,
+    format: &FormatConfig,
+    opts: &GenerateOpts,
+    frame: Option<usize>,
+    cancel: &CancelToken,
+) -> CancelResult<()> {
+    let _timer = timing::start(Phase::Output);
+    let ty = opts.ty()?;
+
+    // Frames are written as numbered files inside the output directory, or
+    // one after another to stdout
+    let out = match (frame, &opts.out) {
+        (Some(i), MapOutput::File(dir)) => {
+            MapOutput::File(dir.join(format!("{:05}.{}", i, ty.extension())))
+        },
+        (_, out) => out.clone(),
+    };
+
+    write_map(map, ty, &out, format, cancel)?;
+    summary::output_written(&out);
+
+    if let Some(ref out) = opts.minima {
+        let minima = extrema::minima(map);
+        let n = minima.len().min(opts.extrema);
+        let out = numbered(out, frame);
+
+        write_extrema(map, &minima[..n], &out).context("failed to output local minima")?;
+        summary::output_written(&out);
+    }
+
+    if let Some(ref out) = opts.maxima {
+        let maxima = extrema::maxima(map);
+        let n = maxima.len().min(opts.extrema);
+        let out = numbered(out, frame);
+
+        write_extrema(map, &maxima[..n], &out).context("failed to output local maxima")?;
+        summary::output_written(&out);
+    }
+
+    if let Some(ref out) = opts.contours {
+        let out = numbered(out, frame);
+
+        write_contours(map, &contour::contours(map, &opts.contour_levels), &out)
+            .context("failed to output contours")?;
+        summary::output_written(&out);
+    }
+
+    Ok(())
+}
+
+/// Report how much of a map is already cached, and switch to the tile size it
+/// was rendered with so its tiles can be reused
+fn resume<C: for<'a> Cache<'a>>(
+    cache: &C,
+    map_cfg: &map::Config,
+    render: &mut RenderConfig,
+) -> Result<()> {
+    let tile_size = Vector2::new(render.tile_width, render.tile_height);
+    let state = map::resume(cache, map_cfg, tile_size)?;
+
+    if state.complete {
+        info!("Map is already complete, nothing to resume");
+    } else if state.cached == 0 {
+        info!("No cached tiles found, rendering from scratch");
+    } else {
+        if state.tile_size != tile_size {
+            info!(
+                "Using tile size {}x{} from the earlier run",
+                state.tile_size.x, state.tile_size.y
+            );
+
+            render.tile_width = state.tile_size.x;
+            render.tile_height = state.tile_size.y;
+        }
+
+        info!(
+            "Resuming map: {} of {} tiles already rendered, {} left",
+            state.cached,
+            state.total,
+            state.total - state.cached
+        );
+    }
+
+    Ok(())
+}
+
+fn render_map<C: for<'a> Cache<'a>>(
+    cache: C,
+    map_cfg: map::Config,
+    cfg: &GenerateConfig,
+    opts: &GenerateOpts,
+    frame: Option<usize>,
+    cancel: &CancelToken,
+) -> CancelResult<()> {
+    let mut render = cfg.render;
+    let (post, verify) = (&cfg.map.post_transforms, opts.verify_cache);
+
+    if opts.resume {
+        resume(&cache, &map_cfg, &mut render).context("failed to inspect cached map")?;
+    }
+
+    match map_cfg.precision() {
+        Precision::Single => {
+            let map = map::compute::<_, f32>(cache, map_cfg, post, &render, verify, cancel)
+                .context("failed to generate dissonance map")?;
+
+            cancel.save_expired();
+            write_outputs(&map, &cfg.format, opts, frame, cancel)
+        },
+        Precision::Double => {
+            let map = map::compute::<_, f64>(cache, map_cfg, post, &render, verify, cancel)
+                .context("failed to generate dissonance map")?;
+
+            cancel.save_expired();
+            write_outputs(&map, &cfg.format, opts, frame, cancel)
+        },
+    }
+}
+
+/// Compute a map and apply `post` to it, widening it to double precision if
+/// necessary
+fn compute_f64<C: for<'a> Cache<'a>>(
+    cache: C,
+    map_cfg: map::Config,
+    post: &[PostTransform],
+    render: &RenderConfig,
+    verify: Option<f64>,
+    cancel: &CancelToken,
+) -> CancelResult<DissonMap> {
+    Ok(match map_cfg.precision() {
+        Precision::Single => map::compute::<_, f32>(cache, map_cfg, post, render, verify, cancel)
+            .context("failed to generate dissonance map")?
+            .widen(),
+        Precision::Double => map::compute::<_, f64>(cache, map_cfg, post, render, verify, cancel)
+            .context("failed to generate dissonance map")?,
+    })
+}
+
+/// Compute the map of every component of `map.combine` and combine them.  If
+/// the time limit runs out, the components it cut short keep their finished
+/// tiles and the rest are left as NaN.
+fn compute_combined<C: for<'a> Cache<'a> + 'static>(
+    cache: &C,
+    cfg: &GenerateConfig,
+    op: CombineOp,
+    verify: Option<f64>,
+    cancel: &CancelToken,
+) -> CancelResult<DissonMap> {
+    let len = cfg.components.len();
+    let mut maps = Vec::with_capacity(len);
+
+    for (i, (w, map)) in cfg.components.iter().enumerate() {
+        let map_cfg = map::Config::for_generate(map)
+            .with_context(|| format!("failed to prepare config of component {i}"))?;
+
+        if cancel.is_expired() {
+            warn!("Time limit reached, skipping component {}/{}", i + 1, len);
+            maps.push((*w, map::unrendered(&map_cfg)));
+            continue;
+        }
+
+        cancel.try_weak()?;
+
+        info!("Computing component {}/{}...", i + 1, len);
+
+        let post = &map.post_transforms;
+
+        maps.push((*w, compute_f64(cache, map_cfg, post, &cfg.render, verify, cancel)?));
+    }
+
+    Ok(map::combine(op, maps))
+}
+
+/// Compute the map of a config for display, combining its components if it has
+/// any.  Only the first frame of a time-varying timbre is computed.
+#[cfg(feature = "gui")]
+pub fn compute_view<C: for<'a> Cache<'a> + 'static>(
+    cache: &C,
+    cfg: &GenerateConfig,
+    cancel: &CancelToken,
+) -> CancelResult<DissonMap> {
+    if let Some(ref combine) = cfg.map.combine {
+        return compute_combined(cache, cfg, combine.op, None, cancel);
+    }
+
+    let map_cfg = map_configs(&cfg.map)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("config has no frames to display"))?;
+
+    compute_f64(cache, map_cfg, &cfg.map.post_transforms, &cfg.render, None, cancel)
+}
+
+/// Load the map a cache key was made for, rendering whatever tiles of it
+/// aren't cached with the tile size it was cached with.  Returns the map
+/// along with its base frequency.
+#[cfg(feature = "gui")]
+pub fn load_cached<C: for<'a> Cache<'a>>(
+    cache: C,
+    key: &map::KeyInfo,
+    cancel: &CancelToken,
+) -> CancelResult<(DissonMap, f64)> {
+    use crate::tile_renderer::{DEFAULT_TILE_HEIGHT, DEFAULT_TILE_WIDTH};
+
+    let map_cfg = key.config()?;
+    let base_hz = map_cfg.base_hz();
+    let state = map::resume(
+        &cache,
+        &map_cfg,
+        Vector2::new(DEFAULT_TILE_WIDTH, DEFAULT_TILE_HEIGHT),
+    )
+    .context("failed to inspect cached map")?;
+    let render = RenderConfig {
+        tile_width: state.tile_size.x,
+        tile_height: state.tile_size.y,
+        threads: 0,
+    };
+
+    // Cached maps are stored without their post-transforms
+    Ok((compute_f64(cache, map_cfg, &[], &render, None, cancel)?, base_hz))
+}
+
+/// Prepare the map configs for a config file, one per frame if the timbre is
+/// time-varying
+fn map_configs(map: &MapConfig) -> Result<Vec<map::Config>> {
+    match map.frames {
+        Some(ref f) => {
+            trace!("Analyzing frames...");
+
+            map::Config::for_frames(map, f).context("failed to prepare frame configs")
+        },
+        None => Ok(vec![
+            map::Config::for_generate(map).context("failed to prepare map config")?,
+        ]),
+    }
+}
+
+/// Print the resolved config and what rendering each of its maps would take.
+/// Everything after the config is a RON comment, so the output can be read
+/// back as a config.
+fn dry_run<C: for<'a> Cache<'a>>(
+    cache: &C,
+    cfg: &GenerateConfig,
+    cancel: &CancelToken,
+) -> CancelResult<()> {
+    println!("{}", config::to_ron(cfg)?);
+
+    for (i, (weight, map)) in cfg.components.iter().enumerate() {
+        println!("\n// Component {i} (weight {weight}):");
+
+        for line in config::to_ron(map)?.lines() {
+            println!("// {line}");
+        }
+    }
+
+    let maps: Vec<_> = if cfg.map.combine.is_some() {
+        cfg.components.iter().map(|(_, m)| m).collect()
+    } else {
+        vec![&cfg.map]
+    };
+
+    let tile_size = Vector2::new(cfg.render.tile_width, cfg.render.tile_height);
+    let (mut count, mut memory, mut time) = (0, 0, Duration::default());
+
+    println!();
+
+    for map in maps {
+        for map_cfg in map_configs(map)? {
+            cancel.try_weak()?;
+
+            let hash = map::key_hash(&map_cfg)?;
+            let state = map::resume(cache, &map_cfg, tile_size)?;
+            let est = map::estimate(&map_cfg, &cfg.render, cancel)?;
+
+            #[allow(clippy::cast_precision_loss)]
+            let left = if state.complete {
+                Duration::default()
+            } else {
+                est.time
+                    .mul_f64((state.total - state.cached) as f64 / state.total.max(1) as f64)
+            };
+
+            println!(
+                "// Map {}: {} pixels, cache key {}, {} of {} tiles cached",
+                count, est.pixels, hash, state.cached, state.total
+            );
+
+            count += 1;
+            memory = memory.max(est.memory);
+            time += left;
+        }
+    }
+
+    println!(
+        "// Estimated {} of memory per map and {:.1?} to render {} map(s)",
+        ByteSize(memory),
+        time,
+        count
+    );
+
+    Ok(())
+}
+
+fn generate_impl<C: for<'a> Cache<'a> + 'static>(
     cache: C,
     opts: impl Borrow<GenerateOpts>,
     cancel: impl Borrow<CancelToken>,
@@ -64,24 +719,99 @@ fn generate_impl<C: for<'a> Cache<'a>>(
 
     trace!("Reading config...");
 
-    let cfg = GenerateConfig::read(opts).context("failed to get config")?;
+    let config_timer = timing::start(Phase::Config);
+    let cfg = GenerateConfig::read(&opts.cfg).context("failed to get config")?;
+    let opts = &expand_outputs(opts, &cfg)?;
 
-    trace!("Computing map...");
+    mem::drop(config_timer);
 
-    let map_cfg = map::Config::for_generate(&cfg.map);
-    let map = map::compute(cache, map_cfg, cancel).context("failed to generate dissonance map")?;
+    if opts.dry_run {
+        return dry_run(&cache, &cfg, cancel);
+    }
 
-    match opts.ty()? {
-        MapFormat::Xsv(ref d) => match opts.out {
-            MapOutput::Stdout => write_xsv(&map, *d, io::stderr(), cancel)?,
-            MapOutput::File(ref p) => write_xsv(
-                &map,
-                *d,
-                File::create(p).context("failed to open output file")?,
-                cancel,
-            )?,
-        },
-        MapFormat::Png => todo!(),
+    if let Some(ref combine) = cfg.map.combine {
+        let map = compute_combined(&cache, &cfg, combine.op, opts.verify_cache, cancel)?;
+
+        cancel.save_expired();
+        return write_outputs(&map, &cfg.format, opts, None, cancel);
+    }
+
+    let frames = cfg.map.frames.is_some();
+    let map_cfgs = map_configs(&cfg.map)?;
+
+    if let (true, MapOutput::File(dir)) = (frames, &opts.out) {
+        fs::create_dir_all(dir).context("failed to create output directory")?;
+    }
+
+    let len = map_cfgs.len();
+
+    for (i, map_cfg) in map_cfgs.into_iter().enumerate() {
+        cancel.try_weak()?;
+
+        if cancel.is_expired() {
+            warn!("Time limit reached, skipping the last {} frame(s)", len - i);
+            break;
+        }
+
+        if frames {
+            info!("Computing frame {}/{}...", i + 1, len);
+        } else {
+            trace!("Computing map...");
+        }
+
+        render_map(
+            &cache,
+            map_cfg,
+            &cfg,
+            opts,
+            if frames { Some(i) } else { None },
+            cancel,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn stats_impl<C: for<'a> Cache<'a> + 'static>(
+    cache: C,
+    opts: &StatsOpts,
+    cancel: &CancelToken,
+) -> CancelResult<()> {
+    trace!("Reading config...");
+
+    let cfg = GenerateConfig::read(&opts.cfg).context("failed to get config")?;
+    let print = |map: &DissonMap| match Stats::of(map, &opts.percentiles, opts.bins) {
+        Some(s) => print!("{s}"),
+        None => println!("Map contains only NaNs"),
+    };
+
+    if let Some(ref combine) = cfg.map.combine {
+        print(&compute_combined(&cache, &cfg, combine.op, None, cancel)?);
+
+        return Ok(());
+    }
+
+    let frames = cfg.map.frames.is_some();
+
+    for (i, map_cfg) in map_configs(&cfg.map)?.into_iter().enumerate() {
+        cancel.try_weak()?;
+
+        trace!("Computing map...");
+
+        let map = compute_f64(
+            &cache,
+            map_cfg,
+            &cfg.map.post_transforms,
+            &cfg.render,
+            None,
+            cancel,
+        )?;
+
+        if frames {
+            println!("Frame {i}:");
+        }
+
+        print(&map);
     }
 
     Ok(())
@@ -124,6 +854,7 @@ fn run_cancelable<
                 }
 
                 info!("^C received, stopping...");
+                cancel::interrupt();
 
                 Err(Cancelled)
             },
@@ -142,23 +873,513 @@ fn run_cancelable<
     }
 }
 
-pub fn generate(cache_mode: CacheMode, opts: GenerateOpts) -> Result<()> {
-    let cache = cache::from_opts(cache_mode);
+/// Read the config paths listed in a batch manifest
+fn read_manifest(path: &Path) -> Result<Vec<PathBuf>> {
+    let text = fs::read_to_string(path).context("failed to read batch manifest")?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !(l.is_empty() || l.starts_with('#')))
+        .map(|l| dir.join(l))
+        .collect())
+}
+
+/// Split the options for a batch run into the options for each map,
+/// suffixing every output with the name of the map's config
+fn batch_jobs(opts: GenerateOpts) -> Result<Vec<GenerateOpts>> {
+    let mut configs = opts.batch.clone();
+
+    if let Some(ref manifest) = opts.manifest {
+        configs.extend(read_manifest(manifest)?);
+    }
+
+    if configs.is_empty() {
+        return Ok(vec![opts]);
+    }
+
+    configs.insert(0, opts.cfg.config.clone());
+
+    let stems: Vec<_> = configs
+        .iter()
+        .map(|c| c.file_stem().unwrap_or_default().to_string_lossy().into_owned())
+        .collect();
+
+    Ok(configs
+        .into_iter()
+        .enumerate()
+        .map(|(i, config)| {
+            // Disambiguate configs with the same name in different directories
+            let suffix = if stems.iter().filter(|s| **s == stems[i]).count() > 1 {
+                format!("{}-{}", i, stems[i])
+            } else {
+                stems[i].clone()
+            };
+            // Templated and directory outputs are already named per config
+            let label = |o: &MapOutput| {
+                if is_templated(o) || is_directory(o) {
+                    o.clone()
+                } else {
+                    suffixed(o, &suffix)
+                }
+            };
+
+            GenerateOpts {
+                cfg: ConfigOpts {
+                    config,
+                    ..opts.cfg.clone()
+                },
+                batch: vec![],
+                manifest: None,
+                out: label(&opts.out),
+                minima: opts.minima.as_ref().map(label),
+                maxima: opts.maxima.as_ref().map(label),
+                contours: opts.contours.as_ref().map(label),
+                ..opts.clone()
+            }
+        })
+        .collect())
+}
+
+async fn generate_batch<C: for<'a> Cache<'a> + 'static>(
+    cache: Arc<C>,
+    jobs: Vec<GenerateOpts>,
+    cancel: Arc<CancelToken>,
+) -> CancelResult<()> {
+    let len = jobs.len();
+    let parallel = jobs[0].jobs.max(1);
+
+    // Up to `parallel` maps run at once, each on its own blocking thread, and
+    // rayon interleaves their tiles on the shared render pool
+    let outcomes: Vec<_> = stream::iter(jobs.into_iter().enumerate())
+        .map(|(i, job)| {
+            let (cache, cancel) = (cache.clone(), cancel.clone());
+
+            async move {
+                if cancel.is_expired() {
+                    return None;
+                }
+
+                let name = job.cfg.config.display().to_string();
+
+                info!("Generating map {}/{} ({})...", i + 1, len, name);
+
+                let ret = generate_async(cache, job, cancel).await;
+
+                if let Err(CancelError::Failed(ref e)) = ret {
+                    error!("Failed to generate {name}: {e:?}");
+                }
+
+                Some((name, ret))
+            }
+        })
+        .buffered(parallel)
+        .collect()
+        .await;
+
+    let mut results = vec![];
+
+    for outcome in outcomes {
+        match outcome {
+            Some((name, Ok(()))) => results.push((name, None)),
+            Some((_, Err(Cancelled))) => return Err(Cancelled),
+            Some((name, Err(CancelError::Failed(e)))) => results.push((name, Some(e))),
+            None => (),
+        }
+    }
+
+    if results.len() < len {
+        warn!("Time limit reached, skipped the last {} map(s)", len - results.len());
+    }
+
+    let failed = results.iter().filter(|(_, e)| e.is_some()).count();
+
+    info!("Generated {} of {} maps:", results.len() - failed, len);
+
+    for (name, err) in results {
+        match err {
+            None => info!("  ok:     {name}"),
+            Some(e) => error!("  failed: {name} ({e:#})"),
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} of {} maps failed", failed, len).into());
+    }
+
+    Ok(())
+}
+
+/// Expire `cancel` once `limit` has passed, unless it's dropped first
+fn start_time_limit(cancel: &Arc<CancelToken>, limit: Duration) {
+    let cancel = Arc::downgrade(cancel);
+
+    thread::spawn(move || {
+        thread::sleep(limit);
+
+        if let Some(cancel) = cancel.upgrade() {
+            info!("Time limit reached, saving partial output...");
+            cancel.expire();
+        }
+    });
+}
+
+pub fn generate(mut cache_opts: CacheOpts, opts: GenerateOpts) -> Result<()> {
+    // Reading a cache file can repair or touch it, which a dry run mustn't do
+    if opts.dry_run {
+        cache_opts.cache_readonly = true;
+    }
+
+    let cache = Arc::new(cache::from_opts(cache_opts));
+    let time_limit = opts.time_limit;
+    let timings_out = opts.timings.clone();
+    let on_complete = opts.on_complete.clone();
+    let mut jobs = batch_jobs(opts)?;
+    let start = summary::start();
+
+    let ret = run_cancelable(move |cancel| {
+        if let Some(Age(limit)) = time_limit {
+            start_time_limit(&cancel, limit);
+        }
+
+        if jobs.len() == 1 {
+            generate_async(cache, jobs.remove(0), cancel).left_future()
+        } else {
+            generate_batch(cache, jobs, cancel).right_future()
+        }
+    })
+    .map(|s| s.map_or_else(|| (), |()| ()));
+
+    let summary = start.finish(match ret {
+        Ok(()) if cancel::interrupted() => Status::Interrupted,
+        Ok(()) => Status::Success,
+        Err(_) => Status::Failure,
+    });
+
+    info!("{}", summary.cache);
+    info!("{}", summary.timings);
+    info!("{summary}");
+
+    if let Some(cmd) = on_complete {
+        summary::run_hook(&cmd, &summary);
+    }
+
+    if let Some(path) = timings_out {
+        let json = json!({ "timings": summary.timings, "cache": summary.cache });
+
+        fs::write(&path, format!("{json:#}\n"))
+            .with_context(|| format!("failed to write timings to {}", path.display()))?;
+    }
+
+    ret
+}
+
+/// Run one pass of watch, summarizing it once it's done
+async fn watch_pass<C: for<'a> Cache<'a> + 'static>(
+    cache: C,
+    opts: Arc<GenerateOpts>,
+    cancel: Arc<CancelToken>,
+) -> CancelResult<()> {
+    let start = summary::start();
+    let ret = generate_async(cache, opts.clone(), cancel).await;
+
+    let summary = start.finish(match ret {
+        Ok(()) => Status::Success,
+        Err(Cancelled) => Status::Interrupted,
+        Err(CancelError::Failed(_)) => Status::Failure,
+    });
+
+    info!("{summary}");
 
-    run_cancelable(move |cancel| generate_async(cache, opts, cancel))
-        .map(|s| s.map_or_else(|| (), |()| ()))
+    if let Some(ref cmd) = opts.on_complete {
+        summary::run_hook(cmd, &summary);
+    }
+
+    ret
 }
 
-pub fn watch(cache_mode: CacheMode, opts: GenerateOpts) -> Result<()> {
+pub fn stats(cache_opts: CacheOpts, opts: StatsOpts) -> Result<()> {
+    let cache = cache::from_opts(cache_opts);
+
+    run_cancelable(move |cancel| {
+        tokio::task::spawn_blocking(move || stats_impl(cache, &opts, &cancel)).map(Result::unwrap)
+    })
+    .map(|s| s.map_or_else(|| (), |()| ()))
+}
+
+fn warm_impl<C: for<'a> Cache<'a> + 'static>(
+    cache: C,
+    opts: &WarmOpts,
+    cancel: &CancelToken,
+) -> CancelResult<()> {
+    trace!("Reading sweep...");
+
+    let sweep = Sweep::read(&opts.sweep)?;
+    let cfg = GenerateConfig::read(&sweep.config_opts()).context("failed to get config")?;
+
+    // Combined maps are cached per component, so sweep each of those instead
+    let bases: Vec<_> = if cfg.map.combine.is_some() {
+        cfg.components.iter().map(|(_, m)| m).collect()
+    } else {
+        vec![&cfg.map]
+    };
+
+    let mut maps = vec![];
+
+    for base in bases {
+        maps.extend(sweep.apply(base).context("invalid sweep")?);
+    }
+
+    let len = maps.len();
+
+    for (i, map) in maps.iter().enumerate() {
+        cancel.try_weak()?;
+
+        info!("Warming map {}/{}...", i + 1, len);
+
+        for map_cfg in map_configs(map)? {
+            // Only the untransformed values are cached
+            compute_f64(&cache, map_cfg, &[], &cfg.render, None, cancel)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn warm(cache: impl for<'a> Cache<'a> + 'static, opts: WarmOpts) -> Result<()> {
+    let start = cache::metrics::snapshot();
+
+    let ret = run_cancelable(move |cancel| {
+        tokio::task::spawn_blocking(move || warm_impl(cache, &opts, &cancel)).map(Result::unwrap)
+    })
+    .map(|s| s.map_or_else(|| (), |()| ()));
+
+    info!("{}", cache::metrics::snapshot() - start);
+
+    ret
+}
+
+/// Dissonance between two tones of a map's timbre `octaves` apart, the lower
+/// one at the base frequency.  Held tones, masking, combination tones,
+/// loudness weighting, and plugins are left out.
+fn dyad(cfg: &MapConfig, map_cfg: &map::Config, octaves: f64) -> f64 {
+    let tone = map_cfg.tone();
+    let max_hz = map_cfg.max_frequency().unwrap_or(f64::INFINITY);
+    let tones = [0.0, octaves];
+    let partials = tones
+        .iter()
+        .flat_map(|o| {
+            let hz = map_cfg.base_hz() * o.exp2();
+
+            tone.iter().map(move |p| Partial {
+                pitch: hz * p.pitch,
+                amp: p.amp,
+            })
+        })
+        .filter(|p| p.pitch <= max_hz);
+
+    let partials: Vec<_> = if cfg.overlap_curve.uses_hz() {
+        partials.collect()
+    } else {
+        cfg.pitch_curve.collect_partials(partials)
+    };
+
+    cfg.overlap_curve.sum_partials(
+        &cfg.curve_params,
+        cfg.amp_weighting,
+        partials.iter().cartesian_product(&partials),
+    )
+}
+
+/// The name of a config value, as written in a config file
+fn ron_name(val: &impl Serialize) -> String { ron::ser::to_string(val).unwrap_or_default() }
+
+/// Width of the bars drawn next to the sampled dissonance
+const EXPLAIN_BAR: f64 = 40.0;
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn explain(opts: &ExplainOpts) -> Result<()> {
+    trace!("Reading config...");
+
+    let cfg = GenerateConfig::read(&opts.cfg).context("failed to get config")?;
+    let map_cfg = map::Config::for_generate(&cfg.map).context("failed to prepare map config")?;
+    let map = &cfg.map;
+
+    if map.plugin.is_some() {
+        warn!("This map uses a plugin, which may replace the curves below");
+    }
+
+    if map.overlap_curve.uses_hz() {
+        println!("Pitch curve: none, {} uses frequencies in Hz", ron_name(&map.overlap_curve));
+    } else {
+        println!("Pitch curve: {}", ron_name(&map.pitch_curve));
+        println!("  p(f) = {}", map.pitch_curve.formula());
+        println!("  x = {} |p(f2) - p(f1)|", map.curve_params.distance_scale);
+    }
+
+    println!("Overlap curve: {}", ron_name(&map.overlap_curve));
+    println!("  d(x) = {}", map.overlap_curve.formula(&map.curve_params));
+    println!(
+        "Amplitude weighting: {}\n  w(a1, a2) = {}",
+        ron_name(&map.amp_weighting),
+        map.amp_weighting.formula()
+    );
+
+    println!(
+        "\nDissonance of two tones of the map's timbre, the lower at {} Hz:",
+        short_hz(map_cfg.base_hz())
+    );
+
+    let Interval(range) = opts.range;
+    let steps = opts.steps.max(1);
+    let rows: Vec<_> = (0..=steps)
+        .map(|i| {
+            let octaves = range * f64::from(i) / f64::from(steps);
+
+            (octaves, dyad(map, &map_cfg, octaves))
+        })
+        .collect();
+    let max = rows.iter().fold(0.0_f64, |m, (_, d)| m.max(d.abs()));
+
+    println!("{:>8}  {:>10}", "CENTS", "DISSONANCE");
+
+    for (octaves, diss) in rows {
+        let bar = if max > 0.0 {
+            (diss.abs() / max * EXPLAIN_BAR).round() as usize
+        } else {
+            0
+        };
+
+        println!("{:>8.1}  {:>10.4}  {}", octaves * 1200.0, diss, "#".repeat(bar));
+    }
+
+    Ok(())
+}
+
+pub fn render_audio(opts: &RenderAudioOpts) -> Result<()> {
+    trace!("Reading config...");
+
+    let cfg = GenerateConfig::read(&opts.cfg).context("failed to get config")?;
+    let map_cfg = map::Config::for_generate(&cfg.map).context("failed to prepare map config")?;
+
+    let (x, y) = match opts.pixel {
+        Some(MapPoint(x, y)) => {
+            let pos = map_cfg.pixel_octaves(Point2::new(x, y));
+
+            (Some(pos.x), Some(pos.y))
+        },
+        None => (opts.x.map(|Interval(o)| o), opts.y.map(|Interval(o)| o)),
+    };
+
+    let voices = map_cfg.voices(x, y);
+
+    if voices.iter().all(|v| v.iter().next().is_none()) {
+        return Err(anyhow!("no partials left to synthesize"));
+    }
+
+    for (axis, octaves) in &[("X", x), ("Y", y)] {
+        if let Some(o) = octaves {
+            info!("{} tone: {:+.1} cents", axis, o * 1200.0);
+        }
+    }
+
+    let samples = audio::synthesize(&voices, opts.sample_rate, opts.duration);
+
+    audio::write_wav(&opts.out, &[&samples], opts.sample_rate)
+        .with_context(|| format!("failed to write {}", opts.out.display()))
+}
+
+fn render_sweep_impl<C: for<'a> Cache<'a>>(
+    cache: C,
+    opts: &RenderSweepOpts,
+    cancel: &CancelToken,
+) -> CancelResult<()> {
+    trace!("Reading config...");
+
+    let cfg = GenerateConfig::read(&opts.cfg).context("failed to get config")?;
+    let map_cfg = map::Config::for_generate(&cfg.map).context("failed to prepare map config")?;
+    let Interval(at) = opts.at;
+
+    let voices = match opts.axis {
+        Axis::X => map_cfg.voices(None, Some(at)),
+        Axis::Y => map_cfg.voices(Some(at), None),
+    };
+    let tone = map_cfg.tone();
+    let (from, to) = map_cfg.axis_octaves(opts.axis);
+
+    info!(
+        "Sweeping from {:+.1} to {:+.1} cents, other tone at {:+.1} cents",
+        from * 1200.0,
+        to * 1200.0,
+        at * 1200.0
+    );
+
+    let glide = audio::Glide {
+        wave: &tone,
+        from_hz: map_cfg.base_hz() * from.exp2(),
+        to_hz: map_cfg.base_hz() * to.exp2(),
+        max_hz: map_cfg.max_frequency().unwrap_or(f64::INFINITY),
+    };
+
+    let samples = audio::synthesize_glide(&voices, glide, opts.sample_rate, opts.duration);
+
+    cancel.try_weak()?;
+
+    let curve = if opts.sidechain {
+        trace!("Computing map slice...");
+
+        let slice = compute_f64(
+            cache,
+            map_cfg.slice(opts.axis, at),
+            &cfg.map.post_transforms,
+            &cfg.render,
+            None,
+            cancel,
+        )?;
+
+        Some(audio::sidechain(&slice.data, samples.len()))
+    } else {
+        None
+    };
+
+    let channels: Vec<&[f32]> = iter::once(&samples[..]).chain(curve.as_deref()).collect();
+
+    audio::write_wav(&opts.out, &channels, opts.sample_rate)
+        .with_context(|| format!("failed to write {}", opts.out.display()))?;
+
+    Ok(())
+}
+
+pub fn render_sweep(cache_opts: CacheOpts, opts: RenderSweepOpts) -> Result<()> {
+    let cache = cache::from_opts(cache_opts);
+
+    run_cancelable(move |cancel| {
+        tokio::task::spawn_blocking(move || render_sweep_impl(cache, &opts, &cancel))
+            .map(Result::unwrap)
+    })
+    .map(|s| s.map_or_else(|| (), |()| ()))
+}
+
+pub fn watch(cache_opts: CacheOpts, opts: GenerateOpts) -> Result<()> {
+    let memory_entries = cache_opts.memory_entries;
     // TODO: can this be scoped to drop the Arc?
-    let cache = Arc::new(cache::from_opts(cache_mode));
+    let cache = Arc::new(MemoryCache::new(
+        cache::from_opts(cache_opts),
+        memory_entries,
+    ));
+
+    if !opts.batch.is_empty() || opts.manifest.is_some() {
+        return Err(anyhow!("watch only supports a single config"));
+    }
+
     let opts = Arc::new(opts);
 
     run_cancelable(move |cancel| async move {
-        if opts.config.exists() {
+        if opts.cfg.config.exists() {
             info!("Running initial pass...");
 
-            generate_async(cache.clone(), opts.clone(), cancel.clone()).await?;
+            watch_pass(cache.clone(), opts.clone(), cancel.clone()).await?;
         } else {
             warn!("Config file doesn't exist yet, waiting for a new one...");
         }
@@ -174,12 +1395,13 @@ pub fn watch(cache_mode: CacheMode, opts: GenerateOpts) -> Result<()> {
 
         watcher
             .watch(
-                opts.config
+                opts.cfg
+                    .config
                     .parent()
-                    .ok_or_else(|| anyhow!("invalid config path {:?}", opts.config))?,
+                    .ok_or_else(|| anyhow!("invalid config path {:?}", opts.cfg.config))?,
                 RecursiveMode::NonRecursive,
             )
-            .with_context(|| format!("failed to watch file {:?}", opts.config))?;
+            .with_context(|| format!("failed to watch file {}", opts.cfg.config.display()))?;
 
         while let Some(evt) = rx.recv().await {
             let evt = evt.context(
@@ -190,7 +1412,7 @@ pub fn watch(cache_mode: CacheMode, opts: GenerateOpts) -> Result<()> {
             if let EventKind::Modify(ModifyKind::Data(_)) = evt.kind {
                 info!("Config change detected; rerunning...");
 
-                generate_async(cache.clone(), opts.clone(), cancel.clone()).await?;
+                watch_pass(cache.clone(), opts.clone(), cancel.clone()).await?;
             }
         }
 
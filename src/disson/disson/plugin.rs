@@ -0,0 +1,262 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(feature = "plugin"))]
+pub use disabled::{Instance, Plugin};
+#[cfg(feature = "plugin")]
+pub use wasm::{Instance, Plugin};
+
+/// The identity of a plugin as recorded in a cache key, which is all that can
+/// be recovered when reading the key back
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PluginDigest(pub [u8; 32]);
+
+impl fmt::Display for PluginDigest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = self
+            .0
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .fold(String::new(), |mut s, h| {
+                s.push_str(&h);
+                s
+            });
+
+        f.pad(&hex)
+    }
+}
+
+#[cfg(feature = "plugin")]
+mod wasm {
+    use std::{
+        cell::Cell,
+        fmt, fs,
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use log::{trace, warn};
+    use serde::{Serialize, Serializer};
+    use sha2::{Digest, Sha256};
+    use wasmtime::{
+        Config, Engine, Instance as WasmInstance, InterruptHandle, Module, Store, Trap, TypedFunc,
+        WasmParams, WasmResults,
+    };
+
+    use crate::{
+        cancel::{CancelToken, StopHook},
+        disson::real::Real,
+        error::prelude::*,
+    };
+
+    /// A compiled WASM module providing custom pitch and/or overlap curves.
+    ///
+    /// Plugins may export either or both of the following functions:
+    ///  - `pitch(hz: f64) -> f64`, which replaces the configured pitch curve
+    ///  - `overlap(a: f64, b: f64) -> f64`, which replaces the configured
+    ///    overlap curve and receives the pitches of two partials after the
+    ///    pitch curve
+    #[derive(Clone)]
+    pub struct Plugin {
+        engine: Engine,
+        module: Module,
+        digest: [u8; 32],
+    }
+
+    impl fmt::Debug for Plugin {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("Plugin")
+                .field("digest", &self.digest)
+                .finish_non_exhaustive()
+        }
+    }
+
+    // Plugins are identified in cache keys by the hash of their bytecode
+    impl Serialize for Plugin {
+        fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+            self.digest.serialize(ser)
+        }
+    }
+
+    impl Plugin {
+        pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+            let path = path.as_ref();
+
+            trace!("Loading plugin {}...", path.display());
+
+            let bytes = fs::read(path).context("failed to read plugin file")?;
+            // Plugins run arbitrary code, so every call must be able to be
+            // stopped if the render is cancelled
+            let engine = Engine::new(Config::new().interruptable(true))
+                .context("failed to create plugin engine")?;
+            let module = Module::new(&engine, &bytes).context("failed to compile plugin")?;
+
+            let mut digest = [0_u8; 32];
+            digest.copy_from_slice(&Sha256::digest(&bytes));
+
+            let ret = Self {
+                engine,
+                module,
+                digest,
+            };
+
+            let inst = ret.instantiate()?;
+
+            if inst.pitch.is_none() && inst.overlap.is_none() {
+                return Err(anyhow!("plugin exports neither pitch nor overlap"));
+            }
+
+            Ok(ret)
+        }
+
+        /// Create a new instance of this plugin.  Instances are not
+        /// thread-safe, so each render thread must create its own.
+        pub fn instantiate(&self) -> Result<Instance> {
+            let store = Store::new(&self.engine);
+            let interrupt = store
+                .interrupt_handle()
+                .context("failed to get plugin interrupt handle")?;
+            let inst = WasmInstance::new(&store, &self.module, &[])
+                .context("failed to instantiate plugin")?;
+
+            Ok(Instance {
+                pitch: export(&inst, "pitch")?,
+                overlap: export(&inst, "overlap")?,
+                interrupt: Arc::new(interrupt),
+                interrupted: Arc::new(AtomicBool::new(false)),
+                trapped: Cell::new(false),
+            })
+        }
+    }
+
+    fn export<P: WasmParams, R: WasmResults>(
+        inst: &WasmInstance,
+        name: &str,
+    ) -> Result<Option<TypedFunc<P, R>>> {
+        if inst.get_export(name).is_none() {
+            return Ok(None);
+        }
+
+        inst.get_typed_func(name)
+            .map(Some)
+            .with_context(|| format!("plugin export {name:?} has the wrong signature"))
+    }
+
+    pub struct Instance {
+        pitch: Option<TypedFunc<f64, f64>>,
+        overlap: Option<TypedFunc<(f64, f64), f64>>,
+        interrupt: Arc<InterruptHandle>,
+        /// Set once a [`CancelToken`] has interrupted this instance
+        interrupted: Arc<AtomicBool>,
+        trapped: Cell<bool>,
+    }
+
+    impl Instance {
+        pub fn has_pitch(&self) -> bool { self.pitch.is_some() }
+
+        pub fn has_overlap(&self) -> bool { self.overlap.is_some() }
+
+        /// Interrupt any call to this instance, and every later one, once
+        /// `cancel` stops work
+        pub fn stop_on<'a>(&self, cancel: &'a CancelToken) -> StopHook<'a> {
+            let interrupt = Arc::clone(&self.interrupt);
+            let interrupted = Arc::clone(&self.interrupted);
+
+            cancel.on_stop(move || {
+                interrupted.store(true, Ordering::SeqCst);
+                interrupt.interrupt();
+            })
+        }
+
+        /// Whether calls were interrupted by [`stop_on`](Self::stop_on), in
+        /// which case their results are meaningless
+        pub fn interrupted(&self) -> bool { self.interrupted.load(Ordering::SeqCst) }
+
+        /// Make a plugin call unless this instance was interrupted, reporting
+        /// the first trap and producing NaN for every failed call.
+        ///
+        /// An interrupt only stops the call it lands in, so later calls are
+        /// skipped here rather than left to run to completion.
+        fn call<T: Real>(&self, f: impl FnOnce() -> Result<f64, Trap>) -> T {
+            if self.interrupted() {
+                return T::nan();
+            }
+
+            match f() {
+                Ok(x) => T::of(x),
+                Err(e) => {
+                    if !self.interrupted() && !self.trapped.replace(true) {
+                        warn!("Plugin trapped: {e}");
+                    }
+
+                    T::nan()
+                },
+            }
+        }
+
+        /// Evaluate the plugin's pitch curve.  Panics if `pitch` is not
+        /// exported.
+        pub fn pitch<T: Real>(&self, hz: T) -> T {
+            self.call(|| self.pitch.as_ref().unwrap().call(hz.widen()))
+        }
+
+        /// Evaluate the plugin's overlap curve.  Panics if `overlap` is not
+        /// exported.
+        pub fn overlap<T: Real>(&self, a: T, b: T) -> T {
+            self.call(|| self.overlap.as_ref().unwrap().call((a.widen(), b.widen())))
+        }
+    }
+}
+
+/// Stand-ins for builds without WASM support, where no plugin can be loaded
+#[cfg(not(feature = "plugin"))]
+mod disabled {
+    use std::path::Path;
+
+    use serde::{Serialize, Serializer};
+
+    use crate::{
+        cancel::{CancelToken, StopHook},
+        disson::real::Real,
+        error::prelude::*,
+    };
+
+    #[derive(Debug, Clone)]
+    pub enum Plugin {}
+
+    impl Serialize for Plugin {
+        fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> { match *self {} }
+    }
+
+    impl Plugin {
+        pub fn load(_: impl AsRef<Path>) -> Result<Self> {
+            Err(anyhow!(
+                "disson was built without plugin support; rebuild it with the plugin feature \
+                 enabled"
+            ))
+        }
+
+        pub fn instantiate(&self) -> Result<Instance> { match *self {} }
+    }
+
+    #[derive(Debug)]
+    pub enum Instance {}
+
+    impl Instance {
+        pub fn has_pitch(&self) -> bool { match *self {} }
+
+        pub fn has_overlap(&self) -> bool { match *self {} }
+
+        pub fn stop_on<'a>(&self, _: &'a CancelToken) -> StopHook<'a> { match *self {} }
+
+        pub fn interrupted(&self) -> bool { match *self {} }
+
+        pub fn pitch<T: Real>(&self, _: T) -> T { match *self {} }
+
+        pub fn overlap<T: Real>(&self, _: T, _: T) -> T { match *self {} }
+    }
+}
@@ -0,0 +1,129 @@
+//! PNG output of finished maps
+
+use std::io;
+
+use image::{codecs::png::PngEncoder, ColorType, Rgb, RgbImage};
+use nalgebra::Point2;
+
+use super::{map::DissonMap, real::Real};
+use crate::{config::FormatConfig, error::prelude::*};
+
+const EDO_COLOR: Rgb<u8> = Rgb([64, 160, 255]);
+const JI_COLOR: Rgb<u8> = Rgb([255, 96, 64]);
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Every step of `n`-EDO between `lo` and `hi` octaves
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn edo_steps(n: u32, lo: f64, hi: f64) -> impl Iterator<Item = f64> {
+    let n = f64::from(n);
+
+    ((lo * n).ceil() as i64..=(hi * n).floor() as i64).map(move |k| k as f64 / n)
+}
+
+/// Every just interval within the odd limit between `lo` and `hi` octaves
+#[allow(clippy::cast_possible_truncation)]
+fn ji_intervals(limit: u32, lo: f64, hi: f64) -> impl Iterator<Item = f64> {
+    let odd: Vec<_> = (1..=limit).step_by(2).collect();
+
+    odd.iter()
+        .flat_map(|&a| odd.iter().map(move |&b| (a, b)))
+        .filter(|&(a, b)| gcd(a, b) == 1)
+        .map(|(a, b)| (f64::from(a) / f64::from(b)).log2())
+        .collect::<Vec<_>>()
+        .into_iter()
+        .flat_map(move |base| {
+            ((lo - base).ceil() as i32..=(hi - base).floor() as i32)
+                .map(move |k| base + f64::from(k))
+        })
+}
+
+/// Draw a line across the image at each of the given intervals, blended
+/// halfway into the map underneath
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn draw_lines<T>(
+    img: &mut RgbImage,
+    map: &DissonMap<T>,
+    intervals: impl Iterator<Item = f64>,
+    color: Rgb<u8>,
+) {
+    let (w, h) = img.dimensions();
+    let mut blend = |c: u32, r: u32| {
+        let px = img.get_pixel_mut(c, r);
+
+        for (p, k) in px.0.iter_mut().zip(&color.0) {
+            *p = u16::midpoint(u16::from(*p), u16::from(*k)) as u8;
+        }
+    };
+
+    for oct in intervals {
+        let px = match map.pixel(Point2::new(oct, oct)) {
+            Some(p) => p.map(f64::round),
+            None => continue,
+        };
+
+        if px.x >= 0.0 && px.x < f64::from(w) {
+            (0..h).for_each(|r| blend(px.x as u32, r));
+        }
+
+        if px.y >= 0.0 && px.y < f64::from(h) {
+            (0..w).for_each(|c| blend(c, px.y as u32));
+        }
+    }
+}
+
+/// Render the map in grayscale, from black at its minimum to white at its
+/// maximum, with any reference lines configured in `format` drawn over it.
+/// NaNs are drawn in black.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub(super) fn write<T: Real, W: io::Write>(
+    map: &DissonMap<T>,
+    format: &FormatConfig,
+    out: W,
+) -> Result<()> {
+    let (min, max) = map
+        .data
+        .iter()
+        .map(|x| x.widen())
+        .filter(|x| !x.is_nan())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), x| {
+            (lo.min(x), hi.max(x))
+        });
+    let range = max - min;
+
+    let mut img = RgbImage::from_fn(map.size.x, map.size.y, |c, r| {
+        let x = map.data[(r * map.size.x + c) as usize].widen();
+        let v = if range > 0.0 && !x.is_nan() {
+            ((x - min) / range * 255.0).round() as u8
+        } else {
+            0
+        };
+
+        Rgb([v, v, v])
+    });
+
+    let corners = [
+        map.octaves(Point2::origin()),
+        map.octaves(map.size.map(|s| s.saturating_sub(1)).cast::<f64>().into()),
+    ];
+    let lo = corners.iter().map(|p| p.x.min(p.y)).fold(f64::INFINITY, f64::min);
+    let hi = corners.iter().map(|p| p.x.max(p.y)).fold(f64::NEG_INFINITY, f64::max);
+
+    if let Some(n) = format.edo_grid {
+        draw_lines(&mut img, map, edo_steps(n, lo, hi), EDO_COLOR);
+    }
+
+    if let Some(limit) = format.ji_limit {
+        draw_lines(&mut img, map, ji_intervals(limit, lo, hi), JI_COLOR);
+    }
+
+    PngEncoder::new(out)
+        .encode(&img, map.size.x, map.size.y, ColorType::Rgb8)
+        .context("failed to encode PNG")
+}
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+
+use super::real::Real;
+
+/// A transformation applied to a finished map before it is output
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum PostTransform {
+    /// Subtract every value from the maximum, turning dissonance into
+    /// consonance
+    #[serde(rename = "Consonance")]
+    Invert,
+    /// Replace every value with its rank, scaled to [0, 1].  Tied values
+    /// share their average rank.
+    Rank,
+    /// Subtract the mean and divide by the standard deviation
+    ZScore,
+    /// Replace every value with the magnitude of the map's gradient, per
+    /// pixel
+    Gradient,
+    /// Replace every value with the map's Laplacian, per pixel squared.
+    /// Dissonance valleys appear as positive peaks.
+    Laplacian,
+}
+
+impl PostTransform {
+    /// Apply this transform to a row-major map with the given width.  NaN
+    /// pixels, such as tiles left unrendered by a time limit, are left as NaN
+    /// and ignored by Rank and `ZScore`.
+    pub fn apply<T: Real>(self, data: &mut [T], width: usize) {
+        match self {
+            Self::Invert => invert(data),
+            Self::Rank => rank(data),
+            Self::ZScore => z_score(data),
+            Self::Gradient => gradient(data, width),
+            Self::Laplacian => laplacian(data, width),
+        }
+    }
+}
+
+/// Get the neighbors of a pixel along each axis, clamped to the edges of the
+/// map, as (left, right, up, down)
+fn neighbors<T: Real>(data: &[T], width: usize, i: usize) -> (T, T, T, T) {
+    let (c, r) = (i % width, i / width);
+    let height = data.len() / width;
+
+    (
+        data[r * width + c.saturating_sub(1)],
+        data[r * width + (c + 1).min(width - 1)],
+        data[r.saturating_sub(1) * width + c],
+        data[(r + 1).min(height - 1) * width + c],
+    )
+}
+
+fn gradient<T: Real>(data: &mut [T], width: usize) {
+    let src = data.to_vec();
+    let half = T::of(0.5);
+
+    for (i, x) in data.iter_mut().enumerate() {
+        let (l, r, u, d) = neighbors(&src, width, i);
+        let (dx, dy) = ((r - l) * half, (d - u) * half);
+
+        *x = dx.hypot(dy);
+    }
+}
+
+fn laplacian<T: Real>(data: &mut [T], width: usize) {
+    let src = data.to_vec();
+
+    for (i, x) in data.iter_mut().enumerate() {
+        let (l, r, u, d) = neighbors(&src, width, i);
+
+        *x = l + r + u + d - T::of(4.0) * src[i];
+    }
+}
+
+fn invert<T: Real>(data: &mut [T]) {
+    let max = data.iter().copied().fold(T::neg_infinity(), T::max);
+
+    for x in data {
+        *x = max - *x;
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn rank<T: Real>(data: &mut [T]) {
+    let mut idx: Vec<_> = (0..data.len()).filter(|&i| !data[i].is_nan()).collect();
+
+    if idx.len() < 2 {
+        for &i in &idx {
+            data[i] = T::zero();
+        }
+
+        return;
+    }
+
+    idx.sort_by(|&a, &b| data[a].widen().total_cmp(&data[b].widen()));
+
+    let denom = (idx.len() - 1) as f64;
+    let mut ranks = data.to_vec();
+    let mut start = 0;
+
+    while start < idx.len() {
+        let val = data[idx[start]];
+        let len = idx[start..].iter().take_while(|&&i| data[i] == val).count();
+        let end = start + len;
+        let avg = T::of((start + end - 1) as f64 / 2.0 / denom);
+
+        for &i in &idx[start..end] {
+            ranks[i] = avg;
+        }
+
+        start = end;
+    }
+
+    data.copy_from_slice(&ranks);
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn z_score<T: Real>(data: &mut [T]) {
+    let vals = || data.iter().copied().filter(|x| !x.is_nan());
+    let count = vals().count();
+
+    if count == 0 {
+        return;
+    }
+
+    let n = T::of(count as f64);
+    let mean = vals().sum::<T>() / n;
+    let var = vals().map(|x| (x - mean) * (x - mean)).sum::<T>() / n;
+    let dev = var.sqrt();
+
+    for x in data.iter_mut().filter(|x| !x.is_nan()) {
+        *x = if dev > T::zero() {
+            (*x - mean) / dev
+        } else {
+            T::zero()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostTransform;
+
+    fn apply(transform: PostTransform, data: &[f64], width: usize) -> Vec<f64> {
+        let mut data = data.to_vec();
+        transform.apply(&mut data, width);
+        data
+    }
+
+    fn assert_all_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a - e).abs() < 1e-9,
+                "expected {:?}, got {:?}",
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn consonance_subtracts_from_max() {
+        assert_all_close(
+            &apply(PostTransform::Invert, &[1.0, 3.0, 2.5], 3),
+            &[2.0, 0.0, 0.5],
+        );
+    }
+
+    #[test]
+    fn rank_averages_ties() {
+        assert_all_close(
+            &apply(PostTransform::Rank, &[3.0, 1.0, 2.0, 1.0], 4),
+            &[1.0, 1.0 / 6.0, 2.0 / 3.0, 1.0 / 6.0],
+        );
+    }
+
+    #[test]
+    fn rank_and_z_score_skip_nan() {
+        let ranked = apply(PostTransform::Rank, &[f64::NAN, 1.0, 3.0], 3);
+        let scored = apply(PostTransform::ZScore, &[f64::NAN, 1.0, 3.0], 3);
+
+        assert!(ranked[0].is_nan() && scored[0].is_nan());
+        assert_all_close(&ranked[1..], &[0.0, 1.0]);
+        assert_all_close(&scored[1..], &[-1.0, 1.0]);
+    }
+
+    #[test]
+    fn z_score_of_flat_map_is_zero() {
+        assert_all_close(&apply(PostTransform::ZScore, &[2.0; 4], 2), &[0.0; 4]);
+    }
+
+    #[test]
+    fn gradient_of_ramp_is_its_slope() {
+        // Edge pixels are differenced against themselves, halving the slope
+        assert_all_close(
+            &apply(PostTransform::Gradient, &[0.0, 1.0, 2.0, 0.0, 1.0, 2.0], 3),
+            &[0.5, 1.0, 0.5, 0.5, 1.0, 0.5],
+        );
+    }
+
+    #[test]
+    fn laplacian_peaks_at_valleys() {
+        let mut data = [1.0; 9];
+        data[4] = 0.0;
+
+        let out = apply(PostTransform::Laplacian, &data, 3);
+
+        assert!((out[4] - 4.0).abs() < 1e-9);
+        assert!(out.iter().enumerate().all(|(i, &x)| i == 4 || x <= 0.0));
+    }
+}
@@ -0,0 +1,65 @@
+use std::{fmt::Debug, iter::Sum, str::FromStr};
+
+use nalgebra::Scalar;
+use num_traits::Float;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::simd::{self, Backend, Kernel};
+use crate::cli::FromStrErr;
+
+/// A floating-point type maps can be computed in
+pub trait Real:
+    Float + Scalar + Sum + Default + Debug + Send + Sync + Serialize + DeserializeOwned + 'static
+{
+    /// Convert a constant or config value into this type
+    fn of(x: f64) -> Self;
+
+    /// Widen this value back into an f64
+    fn widen(self) -> f64;
+
+    /// Sum a curve over every ordered pair of packed partials, using the
+    /// vectorized kernel for this type
+    fn sum_kernel(backend: Backend, kernel: Kernel<Self>, pitch: &[Self], gain: &[Self]) -> Self;
+}
+
+impl Real for f32 {
+    #[allow(clippy::cast_possible_truncation)]
+    fn of(x: f64) -> Self { x as f32 }
+
+    fn widen(self) -> f64 { self.into() }
+
+    fn sum_kernel(backend: Backend, kernel: Kernel<Self>, pitch: &[Self], gain: &[Self]) -> Self {
+        simd::sum_f32(backend, kernel, pitch, gain)
+    }
+}
+
+impl Real for f64 {
+    fn of(x: f64) -> Self { x }
+
+    fn widen(self) -> f64 { self }
+
+    fn sum_kernel(backend: Backend, kernel: Kernel<Self>, pitch: &[Self], gain: &[Self]) -> Self {
+        simd::sum_f64(backend, kernel, pitch, gain)
+    }
+}
+
+/// The floating-point type to compute a map in
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Precision {
+    /// 32-bit floats; faster and smaller, but less accurate
+    Single,
+    /// 64-bit floats
+    Double,
+}
+
+impl FromStr for Precision {
+    type Err = FromStrErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_ref() {
+            "single" | "f32" => Self::Single,
+            "double" | "f64" => Self::Double,
+            _ => return Err(FromStrErr::OneOf(s.into(), &["single", "double"])),
+        })
+    }
+}
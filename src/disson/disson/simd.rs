@@ -0,0 +1,543 @@
+//! Explicitly vectorized sums of overlap curves over packed partials
+//!
+//! Each curve is written once against [`Lanes`], a vector of floats, and run
+//! either with AVX2 registers or with plain arrays of the same width.  Both
+//! perform the same operations in the same order, including the polynomial
+//! approximation of exp, so a map comes out bit-for-bit the same on every
+//! machine and its cached tiles can be shared between them.
+
+// Every helper has to be inlined into the target_feature entry points to be
+// compiled with AVX2 enabled
+#![allow(clippy::inline_always)]
+
+use std::ops::{Add, Div, Mul, Sub};
+
+use num_traits::{Float, One, Zero};
+
+/// An overlap curve, with its constants filled in, in the form the vectorized
+/// kernels evaluate.  Pitch distances are multiplied by `scale` before the
+/// curve is applied.
+#[derive(Debug, Clone, Copy)]
+pub enum Kernel<T> {
+    /// `x exp(1 - x)`, with the rate folded into `scale`
+    ExpDiss { scale: T },
+    TrapDiss { scale: T, rise: T, end: T },
+    /// `max(1 - x, 0)`, with the end folded into `scale`
+    TriCons { scale: T },
+    TrapCons { scale: T, end: T },
+    /// Sethares' curve, with the constants of its distance and its two
+    /// exponentials
+    Sethares {
+        x_star: T,
+        s1: T,
+        s2: T,
+        b1: T,
+        b2: T,
+    },
+}
+
+/// An implementation of the vectorized kernels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Kernels run on arrays, for CPUs without a supported vector extension
+    Portable,
+    /// Kernels run on 256-bit AVX2 registers
+    Avx2,
+}
+
+impl Backend {
+    /// The fastest backend this CPU supports
+    pub fn detect() -> Self {
+        if Self::Avx2.is_supported() {
+            Self::Avx2
+        } else {
+            Self::Portable
+        }
+    }
+
+    pub fn is_supported(self) -> bool {
+        match self {
+            Self::Portable => true,
+            #[cfg(target_arch = "x86_64")]
+            Self::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(not(target_arch = "x86_64"))]
+            Self::Avx2 => false,
+        }
+    }
+}
+
+/// A vector of floats, operated on one lane at a time
+trait Lanes:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self>
+{
+    type Elem: Float;
+
+    const LEN: usize;
+
+    fn splat(x: Self::Elem) -> Self;
+
+    /// Read the first `LEN` elements of a slice
+    fn load(s: &[Self::Elem]) -> Self;
+
+    /// Write every lane to the first `LEN` elements of a slice
+    fn store(self, s: &mut [Self::Elem]);
+
+    fn abs(self) -> Self;
+
+    /// The lesser of each pair of lanes, or `other` if they're unordered
+    fn min(self, other: Self) -> Self;
+
+    /// The greater of each pair of lanes, or `other` if they're unordered
+    fn max(self, other: Self) -> Self;
+
+    fn floor(self) -> Self;
+
+    /// Zero every lane where `x` is less than `bound`, keeping lanes where
+    /// `x` is NaN
+    fn zero_below(self, x: Self, bound: Self) -> Self;
+
+    /// Two to the power of each lane, which must hold an integer in the
+    /// range of normal exponents
+    fn exp2i(self) -> Self;
+
+    fn exp(self) -> Self;
+
+    /// The sum of every lane, added up in order
+    #[inline(always)]
+    fn sum(self) -> Self::Elem {
+        let mut buf = [Self::Elem::zero(); 8];
+        self.store(&mut buf);
+
+        buf[..Self::LEN]
+            .iter()
+            .fold(Self::Elem::zero(), |s, &x| s + x)
+    }
+}
+
+/// Evaluate a polynomial at each lane by Horner's method, highest coefficient
+/// first.  This is a plain loop rather than a fold, since a closure handed to
+/// the standard library may not be inlined into the AVX2 entry points.
+#[inline(always)]
+fn horner<V: Lanes>(x: V, coeffs: &[V::Elem]) -> V {
+    let mut acc = V::splat(coeffs[0]);
+
+    for &k in &coeffs[1..] {
+        acc = acc * x + V::splat(k);
+    }
+
+    acc
+}
+
+/// Cephes' single-precision exp, accurate to about one ulp.  Lanes below
+/// `LO` are flushed to zero a little before they would underflow, since the
+/// subnormal products near the limit are many times slower to compute.
+#[inline(always)]
+#[allow(clippy::excessive_precision, clippy::many_single_char_names)]
+fn exp_f32<V: Lanes<Elem = f32>>(x: V) -> V {
+    const LO: f32 = -86.0;
+    const HI: f32 = 88.0;
+    const C1: f32 = 0.693_359_375;
+    const C2: f32 = -2.121_944_4e-4;
+    const P: [f32; 6] = [
+        1.987_569_1e-4,
+        1.398_199_9e-3,
+        8.333_452e-3,
+        4.166_579_6e-2,
+        1.666_666_5e-1,
+        5.000_000_1e-1,
+    ];
+
+    let c = V::splat;
+    // NaNs pass through the clamp, since min and max return their argument
+    // when unordered, and so propagate to the result
+    let xc = c(HI).min(c(LO).max(x));
+    let n = (xc * c(std::f32::consts::LOG2_E) + c(0.5)).floor();
+    let r = xc - n * c(C1) - n * c(C2);
+    let p = horner(r, &P);
+    let y = p * r * r + r + c(1.0);
+
+    (y * n.exp2i()).zero_below(x, c(LO))
+}
+
+/// Cephes' double-precision exp, accurate to about one ulp.  Lanes that would
+/// underflow are flushed to zero.
+#[inline(always)]
+#[allow(clippy::excessive_precision, clippy::many_single_char_names)]
+fn exp_f64<V: Lanes<Elem = f64>>(x: V) -> V {
+    const LO: f64 = -708.0;
+    const HI: f64 = 709.0;
+    const C1: f64 = 6.931_457_519_531_25e-1;
+    const C2: f64 = 1.428_606_820_309_417_232_12e-6;
+    const P: [f64; 3] = [
+        1.261_771_930_748_105_908_78e-4,
+        3.029_944_077_074_419_613e-2,
+        9.999_999_999_999_999_999_1e-1,
+    ];
+    const Q: [f64; 4] = [
+        3.001_985_051_386_644_550_42e-6,
+        2.524_483_403_496_841_041_92e-3,
+        2.272_655_482_081_550_287_66e-1,
+        2.0,
+    ];
+
+    let c = V::splat;
+    let xc = c(HI).min(c(LO).max(x));
+    let n = (xc * c(std::f64::consts::LOG2_E) + c(0.5)).floor();
+    let r = xc - n * c(C1) - n * c(C2);
+    let rr = r * r;
+    let p = r * horner(rr, &P);
+    let q = horner(rr, &Q);
+    let y = c(1.0) + c(2.0) * (p / (q - p));
+
+    (y * n.exp2i()).zero_below(x, c(LO))
+}
+
+/// Sum `f` over every ordered pair of packed partials, weighted by their
+/// gains.  Both arrays must be padded to a whole number of vectors.
+#[inline(always)]
+fn sum_pairs<V: Lanes>(pitch: &[V::Elem], gain: &[V::Elem], f: impl Fn(V, V) -> V) -> V::Elem {
+    let zero = V::splat(V::Elem::zero());
+    let mut acc = zero;
+
+    for (&pa, &ga) in pitch.iter().zip(gain) {
+        let a = V::splat(pa);
+        let mut row = zero;
+
+        for (pb, gb) in pitch.chunks_exact(V::LEN).zip(gain.chunks_exact(V::LEN)) {
+            row = row + f(a, V::load(pb)) * V::load(gb);
+        }
+
+        acc = acc + row * V::splat(ga);
+    }
+
+    acc.sum()
+}
+
+#[inline(always)]
+fn sum_kernel<V: Lanes>(kernel: Kernel<V::Elem>, pitch: &[V::Elem], gain: &[V::Elem]) -> V::Elem {
+    let c = V::splat;
+    let (zero, one) = (c(V::Elem::zero()), c(V::Elem::one()));
+
+    match kernel {
+        Kernel::ExpDiss { scale } => {
+            let scale = c(scale);
+
+            sum_pairs(pitch, gain, |a: V, b: V| {
+                let x = (b - a).abs() * scale;
+                x * (one - x).exp()
+            })
+        },
+        Kernel::TrapDiss { scale, rise, end } => {
+            let (scale, rise, end) = (c(scale), c(rise), c(end));
+
+            sum_pairs(pitch, gain, |a: V, b: V| {
+                let x = (b - a).abs() * scale;
+                (rise * x).min(one) * (end - x).max(zero).min(one)
+            })
+        },
+        Kernel::TriCons { scale } => {
+            let scale = c(scale);
+
+            sum_pairs(pitch, gain, |a: V, b: V| (one - (b - a).abs() * scale).max(zero))
+        },
+        Kernel::TrapCons { scale, end } => {
+            let (scale, end) = (c(scale), c(end));
+
+            sum_pairs(pitch, gain, |a: V, b: V| {
+                (end - (b - a).abs() * scale).max(zero).min(one)
+            })
+        },
+        Kernel::Sethares {
+            x_star,
+            s1,
+            s2,
+            b1,
+            b2,
+        } => {
+            let (x_star, s1, s2) = (c(x_star), c(s1), c(s2));
+            let (b1, b2) = (zero - c(b1), zero - c(b2));
+
+            sum_pairs(pitch, gain, |a: V, b: V| {
+                let d = (b - a).abs() * (x_star / (s1 * a.min(b) + s2));
+                (b1 * d).exp() - (b2 * d).exp()
+            })
+        },
+    }
+}
+
+/// Sum a curve over every ordered pair of packed single-precision partials
+pub fn sum_f32(backend: Backend, kernel: Kernel<f32>, pitch: &[f32], gain: &[f32]) -> f32 {
+    match backend {
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx2 => {
+            assert!(backend.is_supported(), "AVX2 isn't supported on this CPU");
+
+            // Safety: the CPU was just checked for AVX2
+            unsafe { avx2::sum_f32(kernel, pitch, gain) }
+        },
+        _ => sum_kernel::<portable::F32x8>(kernel, pitch, gain),
+    }
+}
+
+/// Sum a curve over every ordered pair of packed double-precision partials
+pub fn sum_f64(backend: Backend, kernel: Kernel<f64>, pitch: &[f64], gain: &[f64]) -> f64 {
+    match backend {
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx2 => {
+            assert!(backend.is_supported(), "AVX2 isn't supported on this CPU");
+
+            // Safety: the CPU was just checked for AVX2
+            unsafe { avx2::sum_f64(kernel, pitch, gain) }
+        },
+        _ => sum_kernel::<portable::F64x4>(kernel, pitch, gain),
+    }
+}
+
+/// Implement the arithmetic operators of a vector type lane by lane
+macro_rules! lane_ops {
+    ($ty:ident, |$a:ident, $b:ident| { $($op:ident::$fn:ident => $body:expr),* $(,)? }) => {
+        $(
+            impl $op for $ty {
+                type Output = Self;
+
+                #[inline(always)]
+                fn $fn(self, other: Self) -> Self {
+                    let ($a, $b) = (self.0, other.0);
+                    Self($body)
+                }
+            }
+        )*
+    };
+}
+
+mod portable {
+    use std::ops::{Add, Div, Mul, Sub};
+
+    use super::Lanes;
+
+    /// Apply an operation to every lane of one or two arrays
+    macro_rules! map {
+        (|$x:ident| $body:expr, $a:expr) => {{
+            let mut ret = $a;
+            for $x in &mut ret {
+                *$x = { let $x = *$x; $body };
+            }
+            ret
+        }};
+        (|$x:ident, $y:ident| $body:expr, $a:expr, $b:expr) => {{
+            let mut ret = $a;
+            for (r, &$y) in ret.iter_mut().zip(&$b) {
+                *r = { let $x = *r; $body };
+            }
+            ret
+        }};
+    }
+
+    macro_rules! portable {
+        ($ty:ident, $elem:ty, $len:literal, $bias:expr, $shift:literal, $exp:ident) => {
+            #[derive(Debug, Clone, Copy)]
+            pub struct $ty([$elem; $len]);
+
+            lane_ops!($ty, |a, b| {
+                Add::add => map!(|x, y| x + y, a, b),
+                Sub::sub => map!(|x, y| x - y, a, b),
+                Mul::mul => map!(|x, y| x * y, a, b),
+                Div::div => map!(|x, y| x / y, a, b),
+            });
+
+            impl Lanes for $ty {
+                type Elem = $elem;
+
+                const LEN: usize = $len;
+
+                #[inline(always)]
+                fn splat(x: $elem) -> Self { Self([x; $len]) }
+
+                #[inline(always)]
+                fn load(s: &[$elem]) -> Self {
+                    let mut ret = [0.0; $len];
+                    ret.copy_from_slice(&s[..$len]);
+                    Self(ret)
+                }
+
+                #[inline(always)]
+                fn store(self, s: &mut [$elem]) { s[..$len].copy_from_slice(&self.0); }
+
+                #[inline(always)]
+                fn abs(self) -> Self { Self(map!(|x| x.abs(), self.0)) }
+
+                #[inline(always)]
+                fn min(self, other: Self) -> Self {
+                    Self(map!(|x, y| if x < y { x } else { y }, self.0, other.0))
+                }
+
+                #[inline(always)]
+                fn max(self, other: Self) -> Self {
+                    Self(map!(|x, y| if x > y { x } else { y }, self.0, other.0))
+                }
+
+                #[inline(always)]
+                fn floor(self) -> Self { Self(map!(|x| x.floor(), self.0)) }
+
+                #[inline(always)]
+                fn zero_below(self, x: Self, bound: Self) -> Self {
+                    let mut ret = self.0;
+
+                    for ((r, &x), &b) in ret.iter_mut().zip(&x.0).zip(&bound.0) {
+                        if x < b {
+                            *r = 0.0;
+                        }
+                    }
+
+                    Self(ret)
+                }
+
+                #[inline(always)]
+                fn exp2i(self) -> Self {
+                    // Adding the bias leaves the biased exponent in the low
+                    // bits of the mantissa, which are then shifted into place
+                    Self(map!(|x| <$elem>::from_bits((x + $bias).to_bits() << $shift), self.0))
+                }
+
+                #[inline(always)]
+                fn exp(self) -> Self { super::$exp(self) }
+            }
+        };
+    }
+
+    portable!(F32x8, f32, 8, 8_388_735.0, 23, exp_f32);
+    portable!(F64x4, f64, 4, 4_503_599_627_371_519.0, 52, exp_f64);
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    // Every intrinsic below is only reached from the target_feature functions
+    // at the bottom of this module, which are only called once AVX2 has been
+    // detected, so the unsafe blocks are sound.
+    #![allow(clippy::wildcard_imports)]
+
+    use std::{
+        arch::x86_64::*,
+        ops::{Add, Div, Mul, Sub},
+    };
+
+    use super::{Kernel, Lanes};
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct F32x8(__m256);
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct F64x4(__m256d);
+
+    lane_ops!(F32x8, |a, b| {
+        Add::add => unsafe { _mm256_add_ps(a, b) },
+        Sub::sub => unsafe { _mm256_sub_ps(a, b) },
+        Mul::mul => unsafe { _mm256_mul_ps(a, b) },
+        Div::div => unsafe { _mm256_div_ps(a, b) },
+    });
+
+    lane_ops!(F64x4, |a, b| {
+        Add::add => unsafe { _mm256_add_pd(a, b) },
+        Sub::sub => unsafe { _mm256_sub_pd(a, b) },
+        Mul::mul => unsafe { _mm256_mul_pd(a, b) },
+        Div::div => unsafe { _mm256_div_pd(a, b) },
+    });
+
+    impl Lanes for F32x8 {
+        type Elem = f32;
+
+        const LEN: usize = 8;
+
+        #[inline(always)]
+        fn splat(x: f32) -> Self { Self(unsafe { _mm256_set1_ps(x) }) }
+
+        #[inline(always)]
+        fn load(s: &[f32]) -> Self { Self(unsafe { _mm256_loadu_ps(s[..8].as_ptr()) }) }
+
+        #[inline(always)]
+        fn store(self, s: &mut [f32]) { unsafe { _mm256_storeu_ps(s[..8].as_mut_ptr(), self.0) } }
+
+        #[inline(always)]
+        fn abs(self) -> Self { Self(unsafe { _mm256_andnot_ps(_mm256_set1_ps(-0.0), self.0) }) }
+
+        #[inline(always)]
+        fn min(self, other: Self) -> Self { Self(unsafe { _mm256_min_ps(self.0, other.0) }) }
+
+        #[inline(always)]
+        fn max(self, other: Self) -> Self { Self(unsafe { _mm256_max_ps(self.0, other.0) }) }
+
+        #[inline(always)]
+        fn floor(self) -> Self { Self(unsafe { _mm256_floor_ps(self.0) }) }
+
+        #[inline(always)]
+        fn zero_below(self, x: Self, bound: Self) -> Self {
+            Self(unsafe { _mm256_andnot_ps(_mm256_cmp_ps(x.0, bound.0, _CMP_LT_OQ), self.0) })
+        }
+
+        #[inline(always)]
+        fn exp2i(self) -> Self {
+            // See the portable version
+            Self(unsafe {
+                let biased = _mm256_add_ps(self.0, _mm256_set1_ps(8_388_735.0));
+                _mm256_castsi256_ps(_mm256_slli_epi32(_mm256_castps_si256(biased), 23))
+            })
+        }
+
+        #[inline(always)]
+        fn exp(self) -> Self { super::exp_f32(self) }
+    }
+
+    impl Lanes for F64x4 {
+        type Elem = f64;
+
+        const LEN: usize = 4;
+
+        #[inline(always)]
+        fn splat(x: f64) -> Self { Self(unsafe { _mm256_set1_pd(x) }) }
+
+        #[inline(always)]
+        fn load(s: &[f64]) -> Self { Self(unsafe { _mm256_loadu_pd(s[..4].as_ptr()) }) }
+
+        #[inline(always)]
+        fn store(self, s: &mut [f64]) { unsafe { _mm256_storeu_pd(s[..4].as_mut_ptr(), self.0) } }
+
+        #[inline(always)]
+        fn abs(self) -> Self { Self(unsafe { _mm256_andnot_pd(_mm256_set1_pd(-0.0), self.0) }) }
+
+        #[inline(always)]
+        fn min(self, other: Self) -> Self { Self(unsafe { _mm256_min_pd(self.0, other.0) }) }
+
+        #[inline(always)]
+        fn max(self, other: Self) -> Self { Self(unsafe { _mm256_max_pd(self.0, other.0) }) }
+
+        #[inline(always)]
+        fn floor(self) -> Self { Self(unsafe { _mm256_floor_pd(self.0) }) }
+
+        #[inline(always)]
+        fn zero_below(self, x: Self, bound: Self) -> Self {
+            Self(unsafe { _mm256_andnot_pd(_mm256_cmp_pd(x.0, bound.0, _CMP_LT_OQ), self.0) })
+        }
+
+        #[inline(always)]
+        fn exp2i(self) -> Self {
+            // See the portable version
+            Self(unsafe {
+                let biased = _mm256_add_pd(self.0, _mm256_set1_pd(4_503_599_627_371_519.0));
+                _mm256_castsi256_pd(_mm256_slli_epi64(_mm256_castpd_si256(biased), 52))
+            })
+        }
+
+        #[inline(always)]
+        fn exp(self) -> Self { super::exp_f64(self) }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn sum_f32(kernel: Kernel<f32>, pitch: &[f32], gain: &[f32]) -> f32 {
+        super::sum_kernel::<F32x8>(kernel, pitch, gain)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn sum_f64(kernel: Kernel<f64>, pitch: &[f64], gain: &[f64]) -> f64 {
+        super::sum_kernel::<F64x4>(kernel, pitch, gain)
+    }
+}
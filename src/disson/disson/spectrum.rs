@@ -0,0 +1,278 @@
+use std::{f64::consts::PI, path::Path};
+
+use hound::{SampleFormat, WavReader};
+use log::{debug, trace};
+use rustfft::{num_complex::Complex, FftPlanner};
+
+use super::wave::{Partial, Wave};
+use crate::error::prelude::*;
+
+/// Maximum number of samples analyzed from the start of the file
+const MAX_WINDOW: usize = 1 << 16;
+/// Maximum number of spectral peaks kept as partials
+const MAX_PEAKS: usize = 32;
+/// Peaks quieter than this (relative to the loudest peak) are discarded
+const PEAK_THRESHOLD: f64 = 1e-3;
+/// Peaks below this frequency, in Hz, are discarded as DC offset or rumble
+const MIN_PEAK_HZ: f64 = 20.0;
+/// Only peaks at least this loud (relative to the loudest peak) are
+/// considered as the fundamental
+const FUNDAMENTAL_THRESHOLD: f64 = 0.1;
+/// How far a peak may be from a harmonic of the fundamental, in multiples of
+/// the fundamental, and still count towards it
+const HARMONIC_TOLERANCE: f64 = 0.03;
+
+/// The spectrum of a sound, along with the peaks picked from it as partials.
+/// Only the GUI plots anything besides the wave.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub struct Spectrum {
+    /// Natural log of the magnitude of each frequency bin
+    pub mags: Vec<f64>,
+    /// Width of each bin, in Hz
+    pub bin_hz: f64,
+    /// Frequency of the peak picked as the fundamental, in Hz, which the
+    /// pitches of the partials are relative to
+    pub fundamental: f64,
+    pub wave: Wave,
+}
+
+fn read_mono(path: &Path) -> Result<(Vec<f64>, u32)> {
+    let mut reader = WavReader::open(path).context("failed to open WAV file")?;
+    let spec = reader.spec();
+
+    let samples: Vec<f64> = match spec.sample_format {
+        SampleFormat::Float => reader
+            .samples::<f32>()
+            .map(|s| s.map(f64::from))
+            .collect::<Result<_, _>>(),
+        SampleFormat::Int => {
+            let scale = f64::from(1_u32 << (spec.bits_per_sample - 1)).recip();
+
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| f64::from(s) * scale))
+                .collect::<Result<_, _>>()
+        },
+    }
+    .context("failed to read WAV samples")?;
+
+    let mono = samples
+        .chunks(spec.channels.into())
+        .map(|c| c.iter().sum::<f64>() / f64::from(spec.channels))
+        .collect();
+
+    Ok((mono, spec.sample_rate))
+}
+
+/// Load a WAV file and convert the strongest peaks of its spectrum into a
+/// wave, with pitches relative to its fundamental
+pub fn analyze(path: impl AsRef<Path>) -> Result<Wave> { Ok(analyze_spectrum(path)?.wave) }
+
+/// Load a WAV file and find its spectrum, converting the strongest peaks into
+/// a wave as [`analyze`] does
+pub fn analyze_spectrum(path: impl AsRef<Path>) -> Result<Spectrum> {
+    let path = path.as_ref();
+
+    trace!("Analyzing spectrum of {}...", path.display());
+
+    let (samples, rate) = read_mono(path)?;
+    let n = samples.len().min(MAX_WINDOW);
+
+    if n < 3 {
+        return Err(anyhow!("WAV file is too short to analyze"));
+    }
+
+    let spectrum = spectrum(&samples[..n], rate);
+
+    if spectrum.wave.iter().next().is_none() {
+        return Err(anyhow!("no spectral peaks found in WAV file"));
+    }
+
+    debug!("Found {} peaks in {}", spectrum.wave.iter().count(), path.display());
+
+    Ok(spectrum)
+}
+
+/// Load a WAV file and analyze it in overlapping frames, `window` seconds long
+/// and starting every `hop` seconds.  Silent frames produce empty waves.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+pub fn analyze_frames(
+    path: impl AsRef<Path>,
+    window: f64,
+    hop: f64,
+    limit: Option<u32>,
+) -> Result<Vec<Wave>> {
+    let path = path.as_ref();
+
+    trace!("Analyzing spectrogram of {}...", path.display());
+
+    let (samples, rate) = read_mono(path)?;
+    let window = ((window * f64::from(rate)) as usize).clamp(3, MAX_WINDOW);
+    let hop = ((hop * f64::from(rate)) as usize).max(1);
+    let limit = limit.map_or(usize::MAX, |l| l as usize);
+
+    let frames: Vec<_> = (0..samples.len().saturating_sub(2))
+        .step_by(hop)
+        .take(limit)
+        .map(|start| {
+            let end = (start + window).min(samples.len());
+            spectrum(&samples[start..end], rate).wave
+        })
+        .collect();
+
+    if frames.is_empty() {
+        return Err(anyhow!("WAV file is too short to analyze"));
+    }
+
+    debug!("Analyzed {} frames of {}", frames.len(), path.display());
+
+    Ok(frames)
+}
+
+/// Pick the fundamental from `peaks`, sorted loudest first, as the loud peak
+/// whose harmonics account for the most amplitude.  Ties go to the lower peak.
+fn fundamental(peaks: &[Partial]) -> f64 {
+    let floor = peaks[0].amp * FUNDAMENTAL_THRESHOLD;
+    let fit = |f0: f64| -> f64 {
+        peaks
+            .iter()
+            .filter(|p| {
+                let h = p.pitch / f0;
+                h >= 0.5 && (h - h.round()).abs() <= HARMONIC_TOLERANCE
+            })
+            .map(|p| p.amp)
+            .sum()
+    };
+
+    peaks
+        .iter()
+        .filter(|p| p.amp >= floor)
+        .map(|p| (p.pitch, fit(p.pitch)))
+        .max_by(|(a_hz, a), (b_hz, b)| a.total_cmp(b).then_with(|| b_hz.total_cmp(a_hz)))
+        .map_or(peaks[0].pitch, |(hz, _)| hz)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn spectrum(samples: &[f64], rate: u32) -> Spectrum {
+    let n = samples.len();
+
+    let len = n.next_power_of_two();
+    let denom = (n - 1) as f64;
+
+    let mut buf: Vec<_> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            let hann = 0.5 - 0.5 * (2.0 * PI * i as f64 / denom).cos();
+            Complex::new(s * hann, 0.0)
+        })
+        .chain(std::iter::repeat(Complex::new(0.0, 0.0)))
+        .take(len)
+        .collect();
+
+    FftPlanner::new().plan_fft_forward(len).process(&mut buf);
+
+    let mags: Vec<_> = buf[..len / 2]
+        .iter()
+        .map(|c| c.norm().max(f64::MIN_POSITIVE).ln())
+        .collect();
+    let bin_hz = f64::from(rate) / len as f64;
+
+    let loudest = mags.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let floor = loudest + PEAK_THRESHOLD.ln();
+
+    let mut peaks: Vec<_> = mags
+        .windows(3)
+        .enumerate()
+        .filter(|(_, w)| w[1] > w[0] && w[1] >= w[2] && w[1] >= floor)
+        .map(|(i, w)| {
+            // Parabolic interpolation on the log-magnitude spectrum
+            let (a, b, c) = (w[0], w[1], w[2]);
+            let shift = 0.5 * (a - c) / (a - 2.0 * b + c);
+            let bin = (i + 1) as f64 + shift;
+
+            Partial {
+                pitch: bin * bin_hz,
+                amp: (b - 0.25 * (a - c) * shift).exp(),
+            }
+        })
+        .filter(|p| p.pitch.is_finite() && p.amp.is_finite() && p.pitch >= MIN_PEAK_HZ)
+        .collect();
+
+    if peaks.is_empty() {
+        return Spectrum {
+            mags,
+            bin_hz,
+            fundamental: 0.0,
+            wave: Wave::new(vec![]),
+        };
+    }
+
+    peaks.sort_by(|a, b| b.amp.total_cmp(&a.amp));
+    peaks.truncate(MAX_PEAKS);
+
+    let fundamental = fundamental(&peaks);
+    let max_amp = peaks[0].amp;
+
+    trace!(
+        "Found {} peaks, fundamental at {:.2} Hz",
+        peaks.len(),
+        fundamental
+    );
+
+    peaks.sort_by(|a, b| a.pitch.total_cmp(&b.pitch));
+
+    let wave = peaks
+        .into_iter()
+        .map(|p| Partial {
+            pitch: p.pitch / fundamental,
+            amp: p.amp / max_amp,
+        })
+        .collect();
+
+    Spectrum {
+        mags,
+        bin_hz,
+        fundamental,
+        wave,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fundamental, Partial};
+
+    fn peak(pitch: f64, amp: f64) -> Partial { Partial { pitch, amp } }
+
+    #[test]
+    fn fundamental_ignores_quiet_low_peaks() {
+        // Loudest first, with a faint hum well below the harmonic series
+        let peaks = [
+            peak(200.0, 1.0),
+            peak(100.0, 0.8),
+            peak(300.0, 0.5),
+            peak(400.0, 0.3),
+            peak(37.0, 0.05),
+        ];
+
+        assert!((fundamental(&peaks) - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fundamental_prefers_harmonic_fit() {
+        // 150 Hz is louder alone, but 100 Hz explains more of the spectrum
+        let peaks = [
+            peak(150.0, 1.0),
+            peak(100.0, 0.6),
+            peak(200.0, 0.6),
+            peak(500.0, 0.5),
+        ];
+
+        assert!((fundamental(&peaks) - 100.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,153 @@
+//! Summary statistics of finished maps
+
+use std::{cmp::Ordering, fmt};
+
+use nalgebra::Point2;
+
+use super::map::DissonMap;
+
+/// A map value and the interval at which it occurs
+#[derive(Debug, Clone, Copy)]
+pub struct Location {
+    pub value: f64,
+    /// Interval of each axis, in octaves above the base frequency
+    pub octaves: Point2<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub count: usize,
+    pub nans: usize,
+    pub min: Location,
+    pub max: Location,
+    pub mean: f64,
+    pub std_dev: f64,
+    /// Pairs of percentiles and the values at them
+    pub percentiles: Vec<(f64, f64)>,
+    /// Counts of values in equal-width bins from the minimum to the maximum
+    pub histogram: Vec<usize>,
+}
+
+impl Stats {
+    /// Compute statistics over every value of the map other than NaN.
+    /// Returns `None` if the map contains only NaNs.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn of(map: &DissonMap, percentiles: &[f64], bins: usize) -> Option<Self> {
+        let width = map.size.x as usize;
+        let locate = |i: usize| Location {
+            value: map.data[i],
+            octaves: map.octaves(Point2::new(i % width, i / width).cast()),
+        };
+
+        let mut idx: Vec<_> = (0..map.data.len())
+            .filter(|&i| !map.data[i].is_nan())
+            .collect();
+        idx.sort_by(|&a, &b| {
+            map.data[a]
+                .partial_cmp(&map.data[b])
+                .unwrap_or(Ordering::Equal)
+        });
+
+        let (min, max) = (locate(*idx.first()?), locate(*idx.last()?));
+        let sorted: Vec<_> = idx.iter().map(|&i| map.data[i]).collect();
+        let n = sorted.len() as f64;
+
+        let mean = sorted.iter().sum::<f64>() / n;
+        let var = sorted.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n;
+
+        let percentiles = percentiles
+            .iter()
+            .map(|&p| {
+                let pos = (p / 100.0).clamp(0.0, 1.0) * (n - 1.0);
+                let (lo, hi) = (sorted[pos.floor() as usize], sorted[pos.ceil() as usize]);
+
+                (p, lo + (hi - lo) * pos.fract())
+            })
+            .collect();
+
+        let mut histogram = vec![0; bins];
+        let range = max.value - min.value;
+
+        if bins > 0 {
+            for x in &sorted {
+                let bin = if range > 0.0 {
+                    ((x - min.value) / range * bins as f64) as usize
+                } else {
+                    0
+                };
+
+                histogram[bin.min(bins - 1)] += 1;
+            }
+        }
+
+        Some(Self {
+            count: sorted.len(),
+            nans: map.data.len() - sorted.len(),
+            min,
+            max,
+            mean,
+            std_dev: var.sqrt(),
+            percentiles,
+            histogram,
+        })
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at x = {:+.2}c ({:.4}), y = {:+.2}c ({:.4})",
+            self.value,
+            self.octaves.x * 1200.0,
+            self.octaves.x.exp2(),
+            self.octaves.y * 1200.0,
+            self.octaves.y.exp2(),
+        )
+    }
+}
+
+impl fmt::Display for Stats {
+    #[allow(clippy::cast_precision_loss)]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Values:    {} ({} NaN)", self.count, self.nans)?;
+        writeln!(f, "Minimum:   {}", self.min)?;
+        writeln!(f, "Maximum:   {}", self.max)?;
+        writeln!(f, "Mean:      {}", self.mean)?;
+        writeln!(f, "Std. dev.: {}", self.std_dev)?;
+
+        if !self.percentiles.is_empty() {
+            writeln!(f, "Percentiles:")?;
+
+            for (p, x) in &self.percentiles {
+                writeln!(f, "  {p:>6}%: {x}")?;
+            }
+        }
+
+        if !self.histogram.is_empty() {
+            writeln!(f, "Histogram:")?;
+
+            let width = (self.max.value - self.min.value) / self.histogram.len() as f64;
+            let peak = self.histogram.iter().copied().max().unwrap_or(0).max(1);
+
+            for (i, count) in self.histogram.iter().enumerate() {
+                let lo = self.min.value + width * i as f64;
+
+                writeln!(
+                    f,
+                    "  [{:>12.6}, {:>12.6}): {:>8} {}",
+                    lo,
+                    lo + width,
+                    count,
+                    "#".repeat(count * 40 / peak)
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
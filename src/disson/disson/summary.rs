@@ -0,0 +1,162 @@
+//! A report of what a run of generate or watch did, and the hook that's run
+//! with it once the run completes
+
+use std::{
+    fmt,
+    path::PathBuf,
+    process::Command,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::{trace, warn};
+use serde::{Serialize, Serializer};
+
+use super::timing::{self, Timings};
+use crate::{
+    cache::metrics::{self, Metrics},
+    config::MapOutput,
+    error::prelude::*,
+};
+
+/// Every file written so far, in order
+static OUTPUTS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Record that an output was written, if it went to a file
+pub fn output_written(out: &MapOutput) {
+    if let MapOutput::File(path) = out {
+        OUTPUTS.lock().unwrap().push(path.clone());
+    }
+}
+
+/// How a run ended
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Success,
+    Failure,
+    Interrupted,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+            Self::Interrupted => "interrupted",
+        }
+    }
+}
+
+/// The state of the process when a run started
+#[derive(Debug)]
+pub struct Start {
+    time: Instant,
+    outputs: usize,
+    cache: Metrics,
+    timings: Timings,
+}
+
+pub fn start() -> Start {
+    Start {
+        time: Instant::now(),
+        outputs: OUTPUTS.lock().unwrap().len(),
+        cache: metrics::snapshot(),
+        timings: timing::snapshot(),
+    }
+}
+
+fn secs<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64())
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub status: Status,
+    /// Files written during the run
+    pub outputs: Vec<PathBuf>,
+    #[serde(serialize_with = "secs")]
+    pub elapsed: Duration,
+    pub timings: Timings,
+    pub cache: Metrics,
+}
+
+impl Start {
+    /// Summarize everything that happened since this run started
+    pub fn finish(self, status: Status) -> Summary {
+        Summary {
+            status,
+            outputs: OUTPUTS.lock().unwrap()[self.outputs..].to_vec(),
+            elapsed: self.time.elapsed(),
+            timings: timing::snapshot() - self.timings,
+            cache: metrics::snapshot() - self.cache,
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Run finished ({}) in {:.2}s, writing {} file(s)",
+            self.status.as_str(),
+            self.elapsed.as_secs_f64(),
+            self.outputs.len()
+        )?;
+
+        if let Some(rate) = self.cache.hit_rate() {
+            write!(f, ", {:.1}% of tiles served from the cache", rate * 100.0)?;
+        }
+
+        for path in &self.outputs {
+            write!(f, "\n  {}", path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Run a command through the shell to report a finished run.  The summary is
+/// passed in environment variables: `DISSON_STATUS`, `DISSON_ELAPSED` (in
+/// seconds), `DISSON_OUTPUTS` (one path per line), and `DISSON_SUMMARY` (all of
+/// it, as JSON).  A failing hook is reported but doesn't fail the run.
+pub fn run_hook(cmd: &str, summary: &Summary) {
+    let run = || -> Result<()> {
+        let mut shell = if cfg!(windows) {
+            let mut c = Command::new("cmd");
+            c.arg("/C");
+            c
+        } else {
+            let mut c = Command::new("sh");
+            c.arg("-c");
+            c
+        };
+
+        let outputs: Vec<_> = summary
+            .outputs
+            .iter()
+            .map(|p| p.to_string_lossy())
+            .collect();
+
+        trace!("Running completion hook {cmd:?}...");
+
+        let status = shell
+            .arg(cmd)
+            .env("DISSON_STATUS", summary.status.as_str())
+            .env("DISSON_ELAPSED", summary.elapsed.as_secs_f64().to_string())
+            .env("DISSON_OUTPUTS", outputs.join("\n"))
+            .env("DISSON_SUMMARY", serde_json::to_string(summary)?)
+            .status()
+            .context("failed to start completion hook")?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(anyhow!("completion hook exited with {}", status))
+        }
+    };
+
+    if let Err(e) = run() {
+        warn!("{e:?}");
+    }
+}
@@ -0,0 +1,302 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    spectrum,
+    wave::{Partial, Wave},
+};
+use crate::{config::Validator, error::prelude::*};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Rolloff {
+    Flat,
+    #[serde(rename = "Inverse")]
+    Inv,
+    #[serde(rename = "InverseSquare")]
+    InvSq,
+    #[serde(rename = "ExponentialDecay")]
+    Exp(f64),
+}
+
+/// A classic waveform, approximated by its harmonic series
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Shape {
+    Sine,
+    Sawtooth,
+    Square,
+    Triangle,
+    /// Strong odd harmonics with weak even harmonics, like a clarinet in its
+    /// low register
+    Clarinet,
+}
+
+/// A spectral tilt applied to every partial above a cutoff
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Filter {
+    /// Pitch, as a ratio of the fundamental, above which the filter applies
+    pub cutoff: f64,
+    /// Gain per octave above the cutoff, in dB.  Negative values darken the
+    /// timbre and positive values brighten it.
+    pub slope: f64,
+}
+
+/// Settings for analyzing a `FromFile` timbre as a sequence of frames, each of
+/// which produces its own map
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Frames {
+    /// Length of each analysis window, in seconds
+    pub window: f64,
+    /// Time between the starts of consecutive windows, in seconds
+    pub hop: f64,
+    /// Maximum number of frames to generate
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Timbre {
+    Harmonic {
+        partials: u32,
+        rolloff: Rolloff,
+        /// Exponent applied to each partial's ratio, stretching (> 1) or
+        /// compressing (< 1) the spectrum.  The nth partial is placed at
+        /// `n^stretch` times the fundamental.
+        #[serde(default = "default_stretch")]
+        stretch: f64,
+    },
+    /// An explicit list of partials, with pitches given as ratios of the
+    /// fundamental
+    Partials(Vec<Partial>),
+    /// The first harmonics of a classic waveform.  Partials that are absent
+    /// from the waveform (e.g. the even harmonics of a square wave) count
+    /// towards the limit.
+    Waveform { shape: Shape, partials: u32 },
+    /// Partials extracted from the spectrum of a WAV file.  Relative paths are
+    /// resolved against the directory of the config file.
+    FromFile(PathBuf),
+}
+
+fn default_stretch() -> f64 { 1.0 }
+
+impl Rolloff {
+    /// Amplitude of the nth partial (1-indexed) relative to the fundamental
+    pub fn amp(self, n: u32) -> f64 {
+        let n = f64::from(n);
+
+        match self {
+            Self::Flat => 1.0,
+            Self::Inv => 1.0 / n,
+            Self::InvSq => 1.0 / (n * n),
+            Self::Exp(k) => (-k * (n - 1.0)).exp(),
+        }
+    }
+}
+
+impl Shape {
+    /// Amplitude of the nth harmonic (1-indexed) relative to the fundamental
+    pub fn amp(self, n: u32) -> f64 {
+        let (fund, odd) = (n == 1, n % 2 == 1);
+        let n = f64::from(n);
+        let only_odd = |a: f64| if odd { a } else { 0.0 };
+
+        match self {
+            Self::Sine => {
+                if fund {
+                    1.0
+                } else {
+                    0.0
+                }
+            },
+            Self::Sawtooth => 1.0 / n,
+            Self::Square => only_odd(1.0 / n),
+            Self::Triangle => only_odd(1.0 / (n * n)),
+            Self::Clarinet => (if odd { 1.0 } else { 0.1 }) / n,
+        }
+    }
+}
+
+impl Filter {
+    pub fn validate(&self, path: &str, v: &mut Validator) {
+        v.check(
+            self.cutoff.is_finite() && self.cutoff > 0.0,
+            format_args!("{path}.cutoff"),
+            "must be a positive ratio",
+        );
+        v.check(
+            self.slope.is_finite(),
+            format_args!("{path}.slope"),
+            "must be a finite number of dB",
+        );
+    }
+
+    pub fn apply(&self, wave: &Wave) -> Wave {
+        wave.iter()
+            .map(|p| {
+                let octaves = (p.pitch / self.cutoff).log2().max(0.0);
+
+                Partial {
+                    amp: p.amp * 10.0_f64.powf(self.slope * octaves / 20.0),
+                    ..*p
+                }
+            })
+            .collect()
+    }
+}
+
+impl Frames {
+    pub fn validate(&self, path: &str, v: &mut Validator) {
+        v.check(
+            self.window.is_finite() && self.window > 0.0,
+            format_args!("{path}.window"),
+            "must be a positive number of seconds",
+        );
+        v.check(
+            self.hop.is_finite() && self.hop > 0.0,
+            format_args!("{path}.hop"),
+            "must be a positive number of seconds",
+        );
+
+        if let Some(limit) = self.limit {
+            v.check(limit > 0, format_args!("{path}.limit"), "must be non-zero");
+        }
+    }
+}
+
+impl Default for Timbre {
+    fn default() -> Self {
+        Self::Harmonic {
+            partials: 32,
+            rolloff: Rolloff::Inv,
+            stretch: default_stretch(),
+        }
+    }
+}
+
+impl Timbre {
+    pub fn validate(&self, path: &str, v: &mut Validator) {
+        match self {
+            Self::Harmonic {
+                partials,
+                rolloff,
+                stretch,
+            } => {
+                v.check(
+                    *partials > 0,
+                    format_args!("{path}.partials"),
+                    "must be non-zero",
+                );
+
+                if let Rolloff::Exp(k) = rolloff {
+                    v.check(
+                        k.is_finite(),
+                        format_args!("{path}.rolloff"),
+                        "decay rate must be finite",
+                    );
+                }
+
+                v.check(
+                    stretch.is_finite() && *stretch > 0.0,
+                    format_args!("{path}.stretch"),
+                    "must be positive",
+                );
+            },
+            Self::Partials(p) => {
+                v.check(!p.is_empty(), path, "must contain at least one partial");
+
+                for (i, Partial { pitch, amp }) in p.iter().enumerate() {
+                    v.check(
+                        pitch.is_finite() && *pitch > 0.0,
+                        format_args!("{path}[{i}].pitch"),
+                        "must be a positive ratio",
+                    );
+                    v.check(
+                        amp.is_finite() && *amp >= 0.0,
+                        format_args!("{path}[{i}].amp"),
+                        "must be non-negative",
+                    );
+                }
+            },
+            Self::Waveform { partials, .. } => v.check(
+                *partials > 0,
+                format_args!("{path}.partials"),
+                "must be non-zero",
+            ),
+            Self::FromFile(f) => v.check(
+                f.is_file(),
+                path,
+                format_args!("{} is not a file", f.display()),
+            ),
+        }
+    }
+
+    pub fn wave(&self) -> Result<Wave> {
+        Ok(match self {
+            Self::Harmonic {
+                partials,
+                rolloff,
+                stretch,
+            } => (1..=*partials)
+                .map(|i| Partial {
+                    pitch: f64::from(i).powf(*stretch),
+                    amp: rolloff.amp(i),
+                })
+                .collect(),
+            Self::Partials(p) => Wave::new(p.clone()),
+            Self::Waveform { shape, partials } => (1..=*partials)
+                .map(|i| Partial {
+                    pitch: f64::from(i),
+                    amp: shape.amp(i),
+                })
+                .filter(|p| p.amp > 0.0)
+                .collect(),
+            Self::FromFile(p) => spectrum::analyze(p)
+                .with_context(|| format!("failed to analyze timbre file {}", p.display()))?,
+        })
+    }
+
+    /// Analyze a `FromFile` timbre frame-by-frame.  Returns `None` for all
+    /// other timbres.
+    pub fn frames(&self, frames: &Frames) -> Option<Result<Vec<Wave>>> {
+        match self {
+            Self::FromFile(p) => Some(
+                spectrum::analyze_frames(p, frames.window, frames.hop, frames.limit)
+                    .with_context(|| format!("failed to analyze timbre file {}", p.display())),
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, Partial, Wave};
+    use crate::config::Validator;
+
+    #[test]
+    fn filter_tilts_partials_above_cutoff() {
+        let filter = Filter {
+            cutoff: 2.0,
+            slope: -6.0,
+        };
+        let wave: Wave = [1.0, 2.0, 4.0, 8.0]
+            .iter()
+            .map(|&pitch| Partial { pitch, amp: 1.0 })
+            .collect();
+        let expected = [0.0, 0.0, -6.0, -12.0];
+
+        for (p, db) in filter.apply(&wave).iter().zip(&expected) {
+            assert!((p.amp - 10.0_f64.powf(db / 20.0)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn filter_rejects_bad_cutoff() {
+        for &cutoff in &[0.0, -1.0, f64::NAN] {
+            let mut v = Validator::default();
+            Filter { cutoff, slope: 0.0 }.validate("map.filter", &mut v);
+
+            assert!(v.finish().is_err());
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Process-wide totals of the time spent in each phase of generating a map
+
+use std::{
+    fmt,
+    ops::Sub,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use serde::{Serialize, Serializer};
+
+/// A phase of generating a map
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    /// Reading and resolving the config
+    Config,
+    /// Computing the frequencies at every pixel of the map
+    Grid,
+    /// Reading cached tiles, including tiles reused from other layouts
+    Preload,
+    /// Rendering the tiles missing from the cache
+    Render,
+    /// Post-processing the rendered map and recording it as complete
+    Histogram,
+    /// Writing the map and any analyses of it
+    Output,
+}
+
+/// Nanoseconds spent in each phase, indexed by [`Phase`]
+static TOTALS: [AtomicU64; 6] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Adds the time until it's dropped to the total for its phase
+#[derive(Debug)]
+pub struct Timer(Phase, Instant);
+
+impl Drop for Timer {
+    #[allow(clippy::cast_possible_truncation)]
+    fn drop(&mut self) {
+        TOTALS[self.0 as usize].fetch_add(self.1.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Start timing a phase, until the returned timer is dropped
+pub fn start(phase: Phase) -> Timer { Timer(phase, Instant::now()) }
+
+fn secs<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64())
+}
+
+/// A snapshot of the time spent in each phase.  Subtract two snapshots to get
+/// the time spent between them.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Timings {
+    #[serde(serialize_with = "secs")]
+    pub config: Duration,
+    #[serde(serialize_with = "secs")]
+    pub grid: Duration,
+    #[serde(serialize_with = "secs")]
+    pub preload: Duration,
+    #[serde(serialize_with = "secs")]
+    pub render: Duration,
+    #[serde(serialize_with = "secs")]
+    pub histogram: Duration,
+    #[serde(serialize_with = "secs")]
+    pub output: Duration,
+}
+
+impl Timings {
+    fn phases(&self) -> [(&'static str, Duration); 6] {
+        [
+            ("config", self.config),
+            ("input grid", self.grid),
+            ("cache preload", self.preload),
+            ("rendering", self.render),
+            ("histogram", self.histogram),
+            ("output", self.output),
+        ]
+    }
+
+    pub fn total(&self) -> Duration { self.phases().iter().map(|(_, d)| *d).sum() }
+}
+
+// Snapshots subtracted out of order produce zero rather than panicking
+impl Sub for Timings {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self {
+            config: self.config.saturating_sub(rhs.config),
+            grid: self.grid.saturating_sub(rhs.grid),
+            preload: self.preload.saturating_sub(rhs.preload),
+            render: self.render.saturating_sub(rhs.render),
+            histogram: self.histogram.saturating_sub(rhs.histogram),
+            output: self.output.saturating_sub(rhs.output),
+        }
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total();
+
+        write!(f, "Time: {:.2}s total", total.as_secs_f64())?;
+
+        for (name, time) in &self.phases() {
+            write!(f, ", {} {:.2}s", name, time.as_secs_f64())?;
+
+            if total > Duration::default() {
+                write!(f, " ({:.0}%)", time.as_secs_f64() / total.as_secs_f64() * 100.0)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the time spent in every phase so far
+pub fn snapshot() -> Timings {
+    let total = |p: Phase| Duration::from_nanos(TOTALS[p as usize].load(Ordering::Relaxed));
+
+    Timings {
+        config: total(Phase::Config),
+        grid: total(Phase::Grid),
+        preload: total(Phase::Preload),
+        render: total(Phase::Render),
+        histogram: total(Phase::Histogram),
+        output: total(Phase::Output),
+    }
+}
@@ -1,24 +1,41 @@
-use std::iter::FromIterator;
+use std::{iter::FromIterator, marker::PhantomData};
 
-#[derive(Copy, Clone)]
-pub struct Partial {
+use serde::{Deserialize, Serialize};
+
+use super::real::Real;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Partial<T = f64> {
     /// Partial pitch.  May or may not be linear frequency.
-    pub pitch: f64,
+    pub pitch: T,
     /// Partial amplitude.  Should always be linear peak displacement.
-    pub amp: f64,
+    pub amp: T,
+}
+
+impl Partial {
+    pub fn cast<T: Real>(self) -> Partial<T> {
+        Partial {
+            pitch: T::of(self.pitch),
+            amp: T::of(self.amp),
+        }
+    }
 }
 
-pub struct Wave<S: AsRef<[Partial]> = Vec<Partial>>(S);
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Wave<T = f64, S: AsRef<[Partial<T>]> = Vec<Partial<T>>>(S, PhantomData<T>);
 
-impl<S: AsRef<[Partial]>> Wave<S> {
-    pub fn new(storage: S) -> Self { Self(storage) }
+impl<T: Copy, S: AsRef<[Partial<T>]>> Wave<T, S> {
+    pub fn new(storage: S) -> Self { Self(storage, PhantomData) }
 
-    pub fn iter(&self) -> impl Iterator<Item = &Partial> + Clone { self.0.as_ref().iter() }
+    pub fn iter(&self) -> impl Iterator<Item = &Partial<T>> + Clone { self.0.as_ref().iter() }
 
     pub fn map_pitch<'a>(
         &'a self,
-        f: impl Fn(f64) -> f64 + 'a,
-    ) -> impl Iterator<Item = Partial> + 'a {
+        f: impl Fn(T) -> T + 'a,
+    ) -> impl Iterator<Item = Partial<T>> + 'a
+    where
+        T: 'a,
+    {
         self.0.as_ref().iter().map(move |p| Partial {
             pitch: f(p.pitch),
             ..*p
@@ -26,14 +43,20 @@ impl<S: AsRef<[Partial]>> Wave<S> {
     }
 }
 
-impl<S: AsRef<[Partial]>> From<S> for Wave<S> {
-    fn from(s: S) -> Self { Self(s) }
+impl<S: AsRef<[Partial]>> Wave<f64, S> {
+    pub fn cast<T: Real>(&self) -> Wave<T> { self.iter().map(|p| p.cast()).collect() }
+}
+
+impl<T, S: AsRef<[Partial<T>]>> From<S> for Wave<T, S> {
+    fn from(s: S) -> Self { Self(s, PhantomData) }
 }
 
-impl<S: AsRef<[Partial]> + IntoIterator<Item = Partial>> Wave<S> {
+impl<T, S: AsRef<[Partial<T>]> + IntoIterator<Item = Partial<T>>> Wave<T, S> {
     pub fn into_iter(self) -> S::IntoIter { self.0.into_iter() }
 }
 
-impl FromIterator<Partial> for Wave<Vec<Partial>> {
-    fn from_iter<I: IntoIterator<Item = Partial>>(it: I) -> Self { Self(it.into_iter().collect()) }
+impl<T> FromIterator<Partial<T>> for Wave<T, Vec<Partial<T>>> {
+    fn from_iter<I: IntoIterator<Item = Partial<T>>>(it: I) -> Self {
+        Self(it.into_iter().collect(), PhantomData)
+    }
 }
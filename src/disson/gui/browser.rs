@@ -0,0 +1,161 @@
+//! A list of the entries of the cache, from which maps can be opened in tabs
+//! or deleted
+
+use std::{sync::Arc, time::SystemTime};
+
+use iced::{
+    button, scrollable, Align, Button, Column, Command, Element, Length, Row, Scrollable, Text,
+};
+
+use super::render::{self, GuiCache};
+use crate::{
+    cache::{self, Listing},
+    cli::ByteSize,
+    error::prelude::*,
+};
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Refresh,
+    Listed(Result<Arc<Vec<Listing>>, Arc<Error>>),
+    /// Open the map of an entry in a new tab, which is up to the GUI
+    Load(String),
+    Delete(String),
+    Deleted(String, Result<(), Arc<Error>>),
+}
+
+/// One entry in the list, along with the state of its buttons
+#[derive(Debug)]
+struct EntryRow {
+    hash: String,
+    key: String,
+    len: u64,
+    accessed: SystemTime,
+    load_button: button::State,
+    delete_button: button::State,
+}
+
+impl EntryRow {
+    fn new(listing: &Listing) -> Self {
+        Self {
+            hash: listing.entry.hash.clone(),
+            key: listing.key.clone(),
+            len: listing.entry.len,
+            accessed: listing.entry.accessed,
+            load_button: button::State::new(),
+            delete_button: button::State::new(),
+        }
+    }
+
+    fn view(&mut self, now: SystemTime) -> Element<'_, Message> {
+        let details = format!(
+            "{:.16}  {}  used {} ago",
+            self.hash,
+            ByteSize(self.len),
+            cache::ago(now, self.accessed)
+        );
+
+        Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(
+                Column::new()
+                    .width(Length::Fill)
+                    .push(Text::new(self.key.as_str()).size(14))
+                    .push(Text::new(details).size(12)),
+            )
+            .push(
+                Button::new(&mut self.load_button, Text::new("Open").size(14))
+                    .on_press(Message::Load(self.hash.clone())),
+            )
+            .push(
+                Button::new(&mut self.delete_button, Text::new("Delete").size(14))
+                    .on_press(Message::Delete(self.hash.clone())),
+            )
+            .into()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Browser {
+    rows: Vec<EntryRow>,
+    status: String,
+    /// Whether the cache is being listed
+    listing: bool,
+    refresh_button: button::State,
+    scroll: scrollable::State,
+}
+
+impl Browser {
+    /// List the entries of the cache again
+    pub fn refresh(&mut self, cache: &GuiCache) -> Command<Message> {
+        self.listing = true;
+        self.status = "Reading the cache...".into();
+
+        Command::perform(render::list(cache.clone()), Message::Listed)
+    }
+
+    pub fn update(&mut self, cache: &GuiCache, msg: Message) -> Command<Message> {
+        match msg {
+            Message::Refresh => return self.refresh(cache),
+            Message::Listed(Ok(listings)) => {
+                self.listing = false;
+                self.rows = listings.iter().map(EntryRow::new).collect();
+                self.status = format!(
+                    "{} entries, {} total",
+                    listings.len(),
+                    ByteSize(listings.iter().map(|l| l.entry.len).sum())
+                );
+            },
+            Message::Listed(Err(e)) => {
+                self.listing = false;
+                self.status = format!("{e:#}");
+            },
+            Message::Load(_) => (),
+            Message::Delete(hash) => {
+                self.status = format!("Deleting {hash:.16}...");
+
+                return Command::perform(
+                    render::delete(cache.clone(), hash.clone()),
+                    move |r| Message::Deleted(hash.clone(), r),
+                );
+            },
+            Message::Deleted(hash, Ok(())) => {
+                self.rows.retain(|r| r.hash != hash);
+                self.status = format!("Deleted {hash:.16}");
+            },
+            Message::Deleted(_, Err(e)) => self.status = format!("Delete failed: {e:#}"),
+        }
+
+        Command::none()
+    }
+
+    pub fn view(&mut self) -> Element<'_, Message> {
+        let now = SystemTime::now();
+        let mut refresh = Button::new(&mut self.refresh_button, Text::new("Refresh"));
+
+        if !self.listing {
+            refresh = refresh.on_press(Message::Refresh);
+        }
+
+        let list = self.rows.iter_mut().fold(
+            Scrollable::new(&mut self.scroll)
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .spacing(5),
+            |s, r| s.push(r.view(now)),
+        );
+
+        Column::new()
+            .spacing(10)
+            .push(
+                Row::new()
+                    .spacing(10)
+                    .align_items(Align::Center)
+                    .push(refresh)
+                    .push(Text::new(self.status.as_str()).size(16)),
+            )
+            .push(list)
+            .into()
+    }
+}
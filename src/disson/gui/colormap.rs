@@ -0,0 +1,158 @@
+//! Coloring of maps for display
+
+use std::fmt;
+
+use iced::image;
+use serde::Serialize;
+
+use crate::disson::map::DissonMap;
+
+/// Evenly spaced stops of each colormap, from least to most dissonant, in RGB
+const INFERNO: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [31, 12, 72],
+    [85, 15, 109],
+    [136, 34, 106],
+    [186, 54, 85],
+    [227, 89, 51],
+    [249, 140, 10],
+    [249, 201, 50],
+    [252, 255, 164],
+];
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+const GRAYSCALE: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Colormap {
+    Inferno,
+    Viridis,
+    Grayscale,
+}
+
+impl Colormap {
+    pub const ALL: [Self; 3] = [Self::Inferno, Self::Viridis, Self::Grayscale];
+
+    fn stops(self) -> &'static [[u8; 3]] {
+        match self {
+            Self::Inferno => &INFERNO,
+            Self::Viridis => &VIRIDIS,
+            Self::Grayscale => &GRAYSCALE,
+        }
+    }
+
+    /// Look up the color of a value between 0 and 1
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn color(self, t: f64) -> [u8; 3] {
+        let stops = self.stops();
+        let pos = t.clamp(0.0, 1.0) * (stops.len() - 1) as f64;
+        let i = (pos.floor() as usize).min(stops.len() - 2);
+        let frac = pos - i as f64;
+        let (lo, hi) = (stops[i], stops[i + 1]);
+        let mut ret = [0; 3];
+
+        for (c, (a, b)) in ret.iter_mut().zip(lo.iter().zip(&hi)) {
+            *c = (f64::from(*a) + (f64::from(*b) - f64::from(*a)) * frac).round() as u8;
+        }
+
+        ret
+    }
+}
+
+impl fmt::Display for Colormap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt::Debug::fmt(self, f) }
+}
+
+/// How the values of a map are turned into colors
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Style {
+    pub colormap: Colormap,
+    /// The fraction of the map's range drawn with the first color of the
+    /// colormap.  Anything below it is clamped.
+    pub low: f32,
+    /// The fraction of the map's range drawn with the last color of the
+    /// colormap.  Anything above it is clamped.
+    pub high: f32,
+    /// Exponent applied to values after clamping, where values above 1
+    /// darken the map and values below 1 brighten it
+    pub gamma: f32,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            colormap: Colormap::Inferno,
+            low: 0.0,
+            high: 1.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl Style {
+    /// Find where a value of the map lands in the colormap, between 0 and 1
+    fn position(&self, t: f64) -> f64 {
+        let (low, high) = (f64::from(self.low), f64::from(self.high));
+        let t = ((t - low) / (high - low).max(f64::EPSILON)).clamp(0.0, 1.0);
+
+        t.powf(f64::from(self.gamma))
+    }
+}
+
+/// The least and greatest values of a map, ignoring NaNs
+pub fn range(map: &DissonMap) -> (f64, f64) {
+    map.data
+        .iter()
+        .filter(|x| !x.is_nan())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &x| {
+            (lo.min(x), hi.max(x))
+        })
+}
+
+/// Color a map with values in the given range, as BGRA pixels for an iced
+/// image handle.  NaNs are left transparent.
+pub fn bgra(map: &DissonMap, (min, max): (f64, f64), style: &Style) -> Vec<u8> {
+    let range = max - min;
+    let mut ret = Vec::with_capacity(map.data.len() * 4);
+
+    for &x in &map.data {
+        if x.is_nan() {
+            ret.extend_from_slice(&[0; 4]);
+            continue;
+        }
+
+        let t = if range > 0.0 { (x - min) / range } else { 0.0 };
+        let [r, g, b] = style.colormap.color(style.position(t));
+
+        ret.extend_from_slice(&[b, g, r, 255]);
+    }
+
+    ret
+}
+
+/// Color a map into an image to display
+pub fn image(map: &DissonMap, range: (f64, f64), style: &Style) -> image::Handle {
+    image::Handle::from_pixels(map.size.x, map.size.y, bgra(map, range, style))
+}
+
+/// Color a map into an image to save.  NaNs are left transparent.
+pub fn rgba(map: &DissonMap, range: (f64, f64), style: &Style) -> ::image::RgbaImage {
+    let mut px = bgra(map, range, style);
+
+    px.chunks_mut(4).for_each(|p| p.swap(0, 2));
+
+    ::image::RgbaImage::from_raw(map.size.x, map.size.y, px).expect("pixels don't cover the map")
+}
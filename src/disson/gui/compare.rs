@@ -0,0 +1,313 @@
+//! Side-by-side comparison of the maps of two tabs, within one image
+
+use std::{fmt, sync::Arc};
+
+use iced::{
+    image, pick_list, slider, Align, Column, Container, Element, Length, PickList, Row, Slider,
+    Text,
+};
+
+use super::{
+    colormap::{self, Style},
+    render::Rendered,
+    viewer::{self, View, Viewer},
+};
+use crate::disson::map::DissonMap;
+
+/// How two maps are combined for comparison
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Show the first map left of a split and the second right of it
+    Split,
+    /// Fade from the first map to the second
+    Blend,
+    /// Show how much the second map differs from the first, in red where it's
+    /// more dissonant and blue where it's less
+    Difference,
+}
+
+impl Mode {
+    const ALL: [Self; 3] = [Self::Split, Self::Blend, Self::Difference];
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt::Debug::fmt(self, f) }
+}
+
+/// A tab that can be compared
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Choice {
+    pub id: u64,
+    pub title: String,
+}
+
+impl fmt::Display for Choice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str(&self.title) }
+}
+
+/// A map to compare, as shown in its tab
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub rendered: Arc<Rendered>,
+    pub style: Style,
+}
+
+impl PartialEq for Source {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.rendered, &other.rendered) && self.style == other.style
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    A(Choice),
+    B(Choice),
+    Mode(Mode),
+    Amount(f32),
+    ViewChanged(View),
+}
+
+/// The latest comparison image, along with what it was made from
+#[derive(Debug)]
+struct Composed {
+    a: Source,
+    b: Source,
+    mode: Mode,
+    amount: f32,
+    image: Result<image::Handle, String>,
+}
+
+#[derive(Debug)]
+pub struct Compare {
+    /// The ID of the first tab compared
+    pub a: Option<u64>,
+    /// The ID of the second tab compared
+    pub b: Option<u64>,
+    mode: Mode,
+    /// Where the split lies, or how far to fade to the second map, from 0 to 1
+    amount: f32,
+    /// The view panned and zoomed to, if it differs from the first map's
+    view: Option<View>,
+    composed: Option<Composed>,
+    viewer: viewer::State,
+    a_list: pick_list::State<Choice>,
+    b_list: pick_list::State<Choice>,
+    mode_list: pick_list::State<Mode>,
+    amount_slider: slider::State,
+}
+
+impl Default for Compare {
+    fn default() -> Self {
+        Self {
+            a: None,
+            b: None,
+            mode: Mode::Split,
+            amount: 0.5,
+            view: None,
+            composed: None,
+            viewer: viewer::State::default(),
+            a_list: pick_list::State::default(),
+            b_list: pick_list::State::default(),
+            mode_list: pick_list::State::default(),
+            amount_slider: slider::State::new(),
+        }
+    }
+}
+
+impl Compare {
+    pub fn update(&mut self, msg: Message) {
+        match msg {
+            Message::A(c) => self.a = Some(c.id),
+            Message::B(c) => self.b = Some(c.id),
+            Message::Mode(m) => self.mode = m,
+            Message::Amount(a) => self.amount = a,
+            Message::ViewChanged(v) => self.view = Some(v),
+        }
+    }
+
+    /// Combine the maps compared again if they or the comparison changed
+    pub fn refresh(&mut self, a: Option<Source>, b: Option<Source>) {
+        let (Some(a), Some(b)) = (a, b) else {
+            self.composed = None;
+            return;
+        };
+
+        #[allow(clippy::float_cmp)]
+        let fresh = self.composed.as_ref().is_some_and(|c| {
+            c.a == a && c.b == b && c.mode == self.mode && c.amount == self.amount
+        });
+
+        if fresh {
+            return;
+        }
+
+        let image = compose(&a, &b, self.mode, self.amount);
+
+        // Keep the view panned to unless the first map covers other intervals
+        if self
+            .composed
+            .as_ref()
+            .is_none_or(|c| View::of(&c.a.rendered.map) != View::of(&a.rendered.map))
+        {
+            self.view = None;
+        }
+
+        self.composed = Some(Composed {
+            a,
+            b,
+            mode: self.mode,
+            amount: self.amount,
+            image,
+        });
+    }
+
+    pub fn view(&mut self, choices: Vec<Choice>) -> Element<'_, Message> {
+        let find = |id: Option<u64>| choices.iter().find(|c| Some(c.id) == id).cloned();
+        let (a, b) = (find(self.a), find(self.b));
+        let amount_label = match self.mode {
+            Mode::Split => format!("Split at {:.0}%", self.amount * 100.0),
+            Mode::Blend => format!("{:.0}% B", self.amount * 100.0),
+            Mode::Difference => String::new(),
+        };
+
+        let mut controls = Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(Text::new("A"))
+            .push(PickList::new(
+                &mut self.a_list,
+                choices.clone(),
+                a,
+                Message::A,
+            ))
+            .push(Text::new("B"))
+            .push(PickList::new(&mut self.b_list, choices, b, Message::B))
+            .push(PickList::new(
+                &mut self.mode_list,
+                &Mode::ALL[..],
+                Some(self.mode),
+                Message::Mode,
+            ));
+
+        if self.mode != Mode::Difference {
+            controls = controls
+                .push(
+                    Slider::new(
+                        &mut self.amount_slider,
+                        0.0..=1.0,
+                        self.amount,
+                        Message::Amount,
+                    )
+                    .step(0.01)
+                    .width(Length::Units(300)),
+                )
+                .push(Text::new(amount_label).size(16));
+        }
+
+        let body: Element<_> = match self.composed {
+            Some(Composed {
+                ref a,
+                image: Ok(ref image),
+                ..
+            }) => {
+                let map = &a.rendered.map;
+                let view = self.view.unwrap_or_else(|| View::of(map));
+
+                Viewer::new(&mut self.viewer, view, map.size, Message::ViewChanged)
+                    .layer(image.clone(), View::of(map))
+                    .into()
+            },
+            Some(Composed {
+                image: Err(ref e), ..
+            }) => Text::new(e.as_str()).into(),
+            None => Text::new("Pick two rendered maps to compare").into(),
+        };
+
+        Column::new()
+            .spacing(10)
+            .push(controls)
+            .push(
+                Container::new(body)
+                    .width(Length::Fill)
+                    .height(Length::Fill)
+                    .center_x()
+                    .center_y(),
+            )
+            .into()
+    }
+}
+
+/// Combine two maps into one image, if they're the same size
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn compose(a: &Source, b: &Source, mode: Mode, amount: f32) -> Result<image::Handle, String> {
+    let (ma, mb) = (&a.rendered.map, &b.rendered.map);
+
+    if ma.size != mb.size {
+        return Err(format!(
+            "Can't compare a {}x{} map to a {}x{} map",
+            ma.size.x, ma.size.y, mb.size.x, mb.size.y
+        ));
+    }
+
+    let pixels = match mode {
+        Mode::Split => {
+            let pa = colormap::bgra(ma, a.rendered.range, &a.style);
+            let pb = colormap::bgra(mb, b.rendered.range, &b.style);
+            let split = (amount * ma.size.x as f32).round() as usize * 4;
+
+            pa.chunks(ma.size.x as usize * 4)
+                .zip(pb.chunks(mb.size.x as usize * 4))
+                .flat_map(|(ra, rb)| ra[..split].iter().chain(&rb[split..]).copied())
+                .collect()
+        },
+        Mode::Blend => {
+            let pa = colormap::bgra(ma, a.rendered.range, &a.style);
+            let pb = colormap::bgra(mb, b.rendered.range, &b.style);
+
+            pa.iter()
+                .zip(&pb)
+                .map(|(&x, &y)| {
+                    (f32::from(x) + (f32::from(y) - f32::from(x)) * amount).round() as u8
+                })
+                .collect()
+        },
+        Mode::Difference => difference(ma, mb),
+    };
+
+    Ok(image::Handle::from_pixels(ma.size.x, ma.size.y, pixels))
+}
+
+/// Color the difference of two maps of the same size as BGRA pixels, from
+/// blue where the second is least dissonant relative to the first, through
+/// white, to red where it's most.  NaNs in either map are left transparent.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn difference(a: &DissonMap, b: &DissonMap) -> Vec<u8> {
+    let diffs: Vec<_> = a.data.iter().zip(&b.data).map(|(x, y)| y - x).collect();
+    let scale = diffs
+        .iter()
+        .filter(|d| !d.is_nan())
+        .fold(0.0_f64, |m, d| m.max(d.abs()));
+    let mut ret = Vec::with_capacity(diffs.len() * 4);
+
+    for d in diffs {
+        if d.is_nan() {
+            ret.extend_from_slice(&[0; 4]);
+            continue;
+        }
+
+        let t = if scale > 0.0 { d / scale } else { 0.0 };
+        let fade = ((1.0 - t.abs()) * 255.0).round() as u8;
+
+        if t < 0.0 {
+            ret.extend_from_slice(&[255, fade, fade, 255]);
+        } else {
+            ret.extend_from_slice(&[fade, fade, 255, 255]);
+        }
+    }
+
+    ret
+}
@@ -0,0 +1,272 @@
+//! A plot of one row or column of a map, as a classic dissonance curve
+
+use iced::{
+    button,
+    canvas::{self, Cursor, Frame, Geometry, Path, Program, Stroke},
+    Align, Button, Canvas, Color, Column, Element, Length, Point, Radio, Rectangle, Row, Size,
+    Text,
+};
+use nalgebra::Point2;
+
+use super::{readout::Probe, render::Rendered};
+use crate::disson::map::Axis;
+
+const CURVE_COLOR: Color = Color {
+    r: 0.9,
+    g: 0.55,
+    b: 0.1,
+    a: 1.0,
+};
+const MARKER_COLOR: Color = Color {
+    r: 0.5,
+    g: 0.5,
+    b: 0.5,
+    a: 1.0,
+};
+const LABEL_SIZE: f32 = 14.0;
+const PANEL_HEIGHT: u16 = 180;
+
+/// The values of one row or column of a map
+#[derive(Debug)]
+pub struct Curve {
+    values: Vec<f64>,
+    /// The least and greatest values of the whole map, so curves of different
+    /// rows share a scale
+    range: (f64, f64),
+    /// The interval range of the axis the curve runs along, in octaves
+    along: (f64, f64),
+    /// Where to mark the curve, as a fractional index into `values`
+    marker: Option<f64>,
+}
+
+impl Curve {
+    /// Take the row (for `Axis::X`) or column (for `Axis::Y`) of a map through
+    /// the given point, marking `marker` along it.  Returns `None` if the
+    /// point isn't on the map.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn slice(
+        rendered: &Rendered,
+        axis: Axis,
+        at: Point2<f64>,
+        marker: Option<Point2<f64>>,
+    ) -> Option<Self> {
+        let map = &rendered.map;
+        let (w, h) = (map.size.x as usize, map.size.y as usize);
+        let px = map.pixel(at)?.map(f64::round);
+
+        let (values, along, marker) = match axis {
+            Axis::X => {
+                if px.y < 0.0 || px.y as usize >= h {
+                    return None;
+                }
+
+                let row = px.y as usize;
+                let ends = (
+                    map.octaves(Point2::new(0.0, px.y)).x,
+                    map.octaves(Point2::new(w.saturating_sub(1) as f64, px.y)).x,
+                );
+
+                (
+                    map.data[row * w..(row + 1) * w].to_vec(),
+                    ends,
+                    marker.and_then(|m| map.pixel(m)).map(|p| p.x),
+                )
+            },
+            Axis::Y => {
+                if px.x < 0.0 || px.x as usize >= w {
+                    return None;
+                }
+
+                let col = px.x as usize;
+                let ends = (
+                    map.octaves(Point2::new(px.x, 0.0)).y,
+                    map.octaves(Point2::new(px.x, h.saturating_sub(1) as f64)).y,
+                );
+
+                (
+                    map.data.iter().skip(col).step_by(w).copied().collect(),
+                    ends,
+                    marker.and_then(|m| map.pixel(m)).map(|p| p.y),
+                )
+            },
+        };
+
+        Some(Self {
+            values,
+            range: rendered.range,
+            along,
+            marker,
+        })
+    }
+}
+
+impl<Message> Program<Message> for Curve {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn draw(&self, bounds: Rectangle, _cursor: Cursor) -> Vec<Geometry> {
+        let mut frame = Frame::new(bounds.size());
+        let plot = Size::new(bounds.width, bounds.height - LABEL_SIZE * 1.5);
+        let last = self.values.len().saturating_sub(1).max(1) as f64;
+        let (min, max) = self.range;
+        let span = (max - min).max(f64::EPSILON);
+
+        let x = |i: f64| (i / last) as f32 * plot.width;
+        let y = |v: f64| (1.0 - (v - min) / span) as f32 * plot.height;
+
+        let curve = Path::new(|b| {
+            let mut drawing = false;
+
+            for (i, v) in self.values.iter().enumerate() {
+                if v.is_nan() {
+                    drawing = false;
+                    continue;
+                }
+
+                let p = Point::new(x(i as f64), y(*v));
+
+                if drawing {
+                    b.line_to(p);
+                } else {
+                    b.move_to(p);
+                    drawing = true;
+                }
+            }
+        });
+
+        if let Some(m) = self.marker.filter(|m| *m >= 0.0 && *m <= last) {
+            frame.stroke(
+                &Path::line(Point::new(x(m), 0.0), Point::new(x(m), plot.height)),
+                Stroke {
+                    color: MARKER_COLOR,
+                    width: 1.0,
+                    ..Stroke::default()
+                },
+            );
+        }
+
+        frame.stroke(
+            &curve,
+            Stroke {
+                color: CURVE_COLOR,
+                width: 1.5,
+                ..Stroke::default()
+            },
+        );
+
+        for (pos, oct) in &[(0.0, self.along.0), (1.0, self.along.1)] {
+            frame.fill_text(canvas::Text {
+                content: format!("{:+.0}¢", oct * 1200.0),
+                position: Point::new(*pos * (plot.width - LABEL_SIZE * 4.0), plot.height),
+                color: MARKER_COLOR,
+                size: LABEL_SIZE,
+                ..canvas::Text::default()
+            });
+        }
+
+        vec![frame.into_geometry()]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Message {
+    Axis(Axis),
+    TogglePin,
+}
+
+/// The panel plotting the curve through the point under the cursor, or
+/// through a pinned point
+#[derive(Debug)]
+pub struct Panel {
+    /// The axis the curve runs along
+    axis: Axis,
+    /// A point whose row or column is plotted instead of the one under the
+    /// cursor, in octaves above the base frequency
+    pin: Option<Point2<f64>>,
+    pin_button: button::State,
+}
+
+impl Default for Panel {
+    fn default() -> Self {
+        Self {
+            axis: Axis::X,
+            pin: None,
+            pin_button: button::State::new(),
+        }
+    }
+}
+
+impl Panel {
+    pub fn update(&mut self, msg: Message, hover: Option<Point2<f64>>) {
+        match msg {
+            Message::Axis(a) => self.axis = a,
+            Message::TogglePin => self.pin = if self.pin.is_some() { None } else { hover },
+        }
+    }
+
+    pub fn view(
+        &mut self,
+        rendered: Option<&Rendered>,
+        hover: Option<Point2<f64>>,
+    ) -> Element<'_, Message> {
+        let curve = rendered.and_then(|r| {
+            let at = self.pin.or(hover)?;
+
+            Curve::slice(r, self.axis, at, hover).map(|c| (c, Probe::at(r, at)))
+        });
+
+        let mut controls = Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(Radio::new(
+                Axis::X,
+                "Row (along X)",
+                Some(self.axis),
+                Message::Axis,
+            ))
+            .push(Radio::new(
+                Axis::Y,
+                "Column (along Y)",
+                Some(self.axis),
+                Message::Axis,
+            ))
+            .push(
+                Button::new(
+                    &mut self.pin_button,
+                    Text::new(if self.pin.is_some() { "Unpin" } else { "Pin" }),
+                )
+                .on_press(Message::TogglePin),
+            );
+
+        let plot: Element<_> = match curve {
+            Some((curve, probe)) => {
+                let label = match self.axis {
+                    Axis::X => format!("Y held at {:.2} Hz", probe.hz.y),
+                    Axis::Y => format!("X held at {:.2} Hz", probe.hz.x),
+                };
+
+                controls = controls.push(Text::new(label).size(16));
+
+                Canvas::new(curve)
+                    .width(Length::Fill)
+                    .height(Length::Fill)
+                    .into()
+            },
+            None if self.pin.is_some() => Text::new("The pinned point is outside the map")
+                .size(16)
+                .into(),
+            None => Text::new("Hover over the map to plot its dissonance curve")
+                .size(16)
+                .into(),
+        };
+
+        Column::new()
+            .height(Length::Units(PANEL_HEIGHT))
+            .spacing(5)
+            .push(controls)
+            .push(plot)
+            .into()
+    }
+}
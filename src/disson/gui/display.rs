@@ -0,0 +1,86 @@
+//! Controls for how maps are colored, which apply without rendering the map
+//! again
+
+use iced::{pick_list, slider, Canvas, Column, Element, Length, PickList, Slider, Text};
+
+use super::{
+    colormap::{Colormap, Style},
+    histogram::Histogram,
+    PANEL_WIDTH,
+};
+
+/// Height of the histogram of the map
+const HISTOGRAM_HEIGHT: u16 = 100;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Message {
+    Colormap(Colormap),
+    Low(f32),
+    High(f32),
+    Gamma(f32),
+}
+
+/// Apply a change made with the controls
+pub fn update(style: &mut Style, msg: Message) {
+    match msg {
+        Message::Colormap(c) => style.colormap = c,
+        Message::Low(l) => {
+            style.low = l;
+            style.high = style.high.max(l);
+        },
+        Message::High(h) => {
+            style.high = h;
+            style.low = style.low.min(h);
+        },
+        Message::Gamma(g) => style.gamma = g,
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Controls {
+    histogram: Histogram,
+    colormap_list: pick_list::State<Colormap>,
+    low_slider: slider::State,
+    high_slider: slider::State,
+    gamma_slider: slider::State,
+}
+
+impl Controls {
+    /// Show the controls, along with a histogram of the given bins whose
+    /// handles drag the clamps
+    pub fn view(&mut self, style: &Style, bins: &[usize]) -> Element<'_, Message> {
+        let label = |s: String| Text::new(s).size(16);
+
+        self.histogram.bins = bins.to_vec();
+        self.histogram.set_style(style);
+
+        Column::new()
+            .width(Length::Units(PANEL_WIDTH))
+            .spacing(5)
+            .push(label("Colormap".into()))
+            .push(PickList::new(
+                &mut self.colormap_list,
+                &Colormap::ALL[..],
+                Some(style.colormap),
+                Message::Colormap,
+            ))
+            .push(
+                Canvas::new(&mut self.histogram)
+                    .width(Length::Units(PANEL_WIDTH))
+                    .height(Length::Units(HISTOGRAM_HEIGHT)),
+            )
+            .push(label(format!("Low clamp: {:.0}%", style.low * 100.0)))
+            .push(Slider::new(&mut self.low_slider, 0.0..=1.0, style.low, Message::Low).step(0.01))
+            .push(label(format!("High clamp: {:.0}%", style.high * 100.0)))
+            .push(
+                Slider::new(&mut self.high_slider, 0.0..=1.0, style.high, Message::High)
+                    .step(0.01),
+            )
+            .push(label(format!("Gamma: {:.2}", style.gamma)))
+            .push(
+                Slider::new(&mut self.gamma_slider, 0.2..=5.0, style.gamma, Message::Gamma)
+                    .step(0.05),
+            )
+            .into()
+    }
+}
@@ -0,0 +1,660 @@
+//! One map open in the GUI, with its own config, view, and settings
+
+use std::{
+    mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use iced::{
+    button, image, scrollable, text_input, Align, Button, Column, Command, Container, Element,
+    Length, ProgressBar, Row, Scrollable, Text, TextInput,
+};
+use nalgebra::{Point2, Vector2};
+
+use super::{
+    colormap::{self, Style},
+    compare::Source,
+    curve, display,
+    export::{self, Annotation, Metadata},
+    minima,
+    params::{self, Edits, Panel},
+    pins,
+    queue::Request,
+    readout::Probe,
+    render::{self, GuiCache, Outcome, Progress, Rendered},
+    timbre::{self, Editor},
+    viewer::{self, View, Viewer},
+    PROGRESS_INTERVAL, SETTLE_TIME,
+};
+use crate::{
+    cancel::CancelToken,
+    disson::spectrum::Spectrum,
+    error::prelude::*,
+};
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    ConfigChanged(String),
+    Load,
+    Rendered(u64, Outcome),
+    Tick(u64),
+    Cancel,
+    Restart,
+    /// Queue a render of the map as it is now, which is up to the GUI
+    Enqueue,
+    ViewChanged(View),
+    /// Shift the view by a fraction of its width and height
+    Pan(f64, f64),
+    /// Scale the view around its center
+    Zoom(f64),
+    /// Go back to the view and size of the config
+    ResetView,
+    /// A region was selected to be rendered at the given size
+    Selected(View, Vector2<u32>),
+    Hover(Option<Point2<f64>>),
+    Param(params::Message),
+    Timbre(timbre::Message),
+    /// A file was dropped onto the window: either a sound to import as the
+    /// timbre or a config to load
+    Dropped(PathBuf),
+    Imported(Result<Arc<Spectrum>, Arc<Error>>),
+    Display(display::Message),
+    Curve(curve::Message),
+    Minima(minima::Message),
+    Pins(pins::Message),
+    /// Pin the probe under the cursor
+    Pin,
+    Export,
+    Exported(Result<PathBuf, Arc<Error>>),
+    Settled(u64),
+}
+
+pub struct Document {
+    cache: GuiCache,
+    /// The path typed into the config field
+    config: String,
+    preset: Option<String>,
+    /// The hash of the cache entry the map is loaded from, if it isn't
+    /// rendered from a config
+    cached: Option<String>,
+    /// Identifies the latest render, so that older renders finishing late
+    /// don't replace it
+    render_id: u64,
+    /// Stops the latest render once it's superseded or cancelled
+    cancel: Arc<CancelToken>,
+    /// Whether the latest render is still running
+    rendering: bool,
+    /// Shared by every document, so while several render at once it shows
+    /// whichever reported last
+    progress: Arc<Progress>,
+    /// Whether the latest render is of a freshly loaded config, whose settings
+    /// should fill in the panel
+    fresh: bool,
+    map: Option<Arc<Rendered>>,
+    /// The latest map, colored with the current style
+    image: Option<image::Handle>,
+    style: Style,
+    /// The view panned and zoomed to, if it differs from the config's
+    view: Option<View>,
+    /// The size to render at, if a region was selected to be rendered in
+    /// full instead of at the config's size
+    size: Option<Vector2<u32>>,
+    /// Changes made in the panel to the loaded config
+    edits: Edits,
+    /// The point under the cursor, in octaves above the base frequency
+    hover: Option<Point2<f64>>,
+    /// Identifies the latest change to the view or settings, so the map is
+    /// only rendered again once they settle
+    settle_id: u64,
+    status: String,
+    config_input: text_input::State,
+    load_button: button::State,
+    /// Cancels the render in progress, or starts another once it ends
+    render_button: button::State,
+    queue_button: button::State,
+    export_button: button::State,
+    viewer: viewer::State,
+    side_scroll: scrollable::State,
+    params: Panel,
+    timbre: Editor,
+    display: display::Controls,
+    curve: curve::Panel,
+    minima: minima::Panel,
+    pins: pins::Panel,
+}
+
+impl Document {
+    /// Open a document, rendering the given config if there is one
+    pub fn new(
+        cache: GuiCache,
+        progress: Arc<Progress>,
+        config: String,
+        preset: Option<String>,
+    ) -> (Self, Command<Message>) {
+        let mut doc = Self {
+            cache,
+            config,
+            preset,
+            cached: None,
+            render_id: 0,
+            cancel: Arc::new(CancelToken::new()),
+            rendering: false,
+            progress,
+            fresh: false,
+            map: None,
+            image: None,
+            style: Style::default(),
+            view: None,
+            size: None,
+            edits: Edits::default(),
+            hover: None,
+            settle_id: 0,
+            status: "Open a config file to view its map".into(),
+            config_input: text_input::State::new(),
+            load_button: button::State::new(),
+            render_button: button::State::new(),
+            queue_button: button::State::new(),
+            export_button: button::State::new(),
+            viewer: viewer::State::default(),
+            side_scroll: scrollable::State::new(),
+            params: Panel::default(),
+            timbre: Editor::default(),
+            display: display::Controls::default(),
+            curve: curve::Panel::default(),
+            minima: minima::Panel::default(),
+            pins: pins::Panel::default(),
+        };
+        let cmd = doc.load();
+
+        (doc, cmd)
+    }
+
+    /// Open a document showing the map of a cache entry
+    pub fn cached(
+        cache: GuiCache,
+        progress: Arc<Progress>,
+        hash: String,
+    ) -> (Self, Command<Message>) {
+        let (mut doc, _) = Self::new(cache, progress, String::new(), None);
+
+        doc.cached = Some(hash);
+
+        let cmd = doc.render(None);
+
+        (doc, cmd)
+    }
+
+    /// Open a document showing the map of a queued render, which can be
+    /// rendered again like any other
+    pub fn from_job(
+        cache: GuiCache,
+        progress: Arc<Progress>,
+        request: Request,
+        rendered: Arc<Rendered>,
+    ) -> Self {
+        let (mut doc, _) = Self::new(cache, progress, String::new(), None);
+
+        doc.config = request.config;
+        doc.preset = request.preset;
+        doc.view = request.view;
+        doc.size = request.size;
+        doc.fresh = false;
+
+        // The config was rendered with the edits applied, so the panels show
+        // them already
+        if let Some(ref cfg) = rendered.cfg {
+            doc.params = Panel::new(&cfg.map);
+            doc.timbre = Editor::new(&cfg.map);
+
+            if request.edits.replaces_timbre() {
+                doc.timbre.keep();
+            }
+        }
+
+        doc.edits = request.edits;
+        doc.status = "Opened from the render queue".into();
+        doc.map = Some(rendered);
+        doc.recolor();
+
+        doc
+    }
+
+    /// Everything needed to render the map as it is now in the background,
+    /// unless there's no config to render
+    pub fn request(&self) -> Option<Request> {
+        if self.config.is_empty() || self.cached.is_some() {
+            return None;
+        }
+
+        Some(Request {
+            title: self.title(),
+            config: self.config.clone(),
+            preset: self.preset.clone(),
+            view: self.view,
+            size: self.size,
+            edits: self.edits.clone(),
+        })
+    }
+
+    /// A short name for the document, from its config file or cache entry
+    pub fn title(&self) -> String {
+        if let Some(ref hash) = self.cached {
+            return format!("Cached {hash:.8}");
+        }
+
+        match Path::new(&self.config).file_stem() {
+            Some(s) if self.map.is_some() => s.to_string_lossy().into_owned(),
+            _ => "Untitled".into(),
+        }
+    }
+
+    /// The latest map and how it's colored, for comparing with other
+    /// documents
+    pub fn source(&self) -> Option<Source> {
+        self.map.as_ref().map(|r| Source {
+            rendered: r.clone(),
+            style: self.style,
+        })
+    }
+
+    /// Start rendering the config in the config field from scratch
+    fn load(&mut self) -> Command<Message> {
+        self.cached = None;
+        self.view = None;
+        self.size = None;
+        self.edits = Edits::default();
+        self.fresh = true;
+        self.render(None)
+    }
+
+    /// Gather the changes made in the panels, and render the map again once
+    /// they stop changing
+    fn edit(&mut self) -> Command<Message> {
+        self.edits = self.params.edits().with_timbre(self.timbre.timbre());
+        self.settle()
+    }
+
+    /// Render the map again once the view and settings stop changing
+    fn settle(&mut self) -> Command<Message> {
+        // Without a config, there's nothing to render with other settings
+        if self.cached.is_some() {
+            return Command::none();
+        }
+
+        self.settle_id += 1;
+
+        let id = self.settle_id;
+
+        Command::perform(render::delay(SETTLE_TIME), move |()| Message::Settled(id))
+    }
+
+    /// Start rendering the config in the config field, or loading the cache
+    /// entry the document shows, replacing any render in progress
+    fn render(&mut self, view: Option<View>) -> Command<Message> {
+        if self.config.is_empty() && self.cached.is_none() {
+            return Command::none();
+        }
+
+        self.cancel.set();
+        self.cancel = Arc::new(CancelToken::new());
+        self.render_id += 1;
+        self.rendering = true;
+        self.progress.reset();
+
+        let id = self.render_id;
+
+        let cmd = if let Some(ref hash) = self.cached {
+            self.status = format!("Loading {hash:.16}...");
+
+            Command::perform(
+                render::load(self.cache.clone(), hash.clone(), self.cancel.clone()),
+                move |o| Message::Rendered(id, o),
+            )
+        } else {
+            let opts = render::config_opts(&self.config, self.preset.clone(), self.size);
+
+            self.status = format!("Rendering {}...", self.config);
+
+            Command::perform(
+                render::render(
+                    self.cache.clone(),
+                    opts,
+                    view,
+                    self.edits.clone(),
+                    self.cancel.clone(),
+                ),
+                move |o| Message::Rendered(id, o),
+            )
+        };
+
+        Command::batch(vec![cmd, Self::tick(id)])
+    }
+
+    /// Check the progress of a render again after a short wait
+    fn tick(id: u64) -> Command<Message> {
+        Command::perform(render::delay(PROGRESS_INTERVAL), move |()| Message::Tick(id))
+    }
+
+    /// Show the outcome of the latest render
+    fn rendered(&mut self, outcome: Outcome) {
+        self.rendering = false;
+
+        match outcome {
+            Outcome::Done(rendered) => {
+                self.status = if self.cached.is_some() {
+                    format!(
+                        "Loaded a {}x{} map from the cache; open its config to change it",
+                        rendered.map.size.x, rendered.map.size.y
+                    )
+                } else {
+                    format!(
+                        "Rendered a {}x{} map",
+                        rendered.map.size.x, rendered.map.size.y
+                    )
+                };
+
+                match rendered.cfg {
+                    Some(ref cfg) if mem::take(&mut self.fresh) => {
+                        self.params = Panel::new(&cfg.map);
+                        self.timbre = Editor::new(&cfg.map);
+                    },
+                    _ => (),
+                }
+
+                self.map = Some(rendered);
+                self.recolor();
+            },
+            Outcome::Cancelled => self.status = "Render cancelled".into(),
+            Outcome::Failed(e) => self.status = format!("Render failed: {e:#}"),
+        }
+    }
+
+    /// Open a config file dropped onto the window, or import the timbre of a
+    /// WAV file
+    fn dropped(&mut self, path: PathBuf) -> Command<Message> {
+        let is_wav = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("wav"));
+
+        if !is_wav {
+            self.config = path.to_string_lossy().into_owned();
+
+            return self.load();
+        }
+
+        self.status = format!("Analyzing {}...", path.display());
+
+        Command::perform(render::analyze(path), Message::Imported)
+    }
+
+    /// Save the latest map as an image, with its pins and a sidecar describing
+    /// how it was made
+    fn export(&mut self) -> Command<Message> {
+        let Some(ref r) = self.map else {
+            return Command::none();
+        };
+
+        let path = export::path_for(&self.config, self.cached.as_deref());
+        let meta = Metadata {
+            config: self.cached.is_none().then(|| self.config.clone()),
+            preset: self.preset.clone(),
+            cached: self.cached.clone(),
+            size: r.map.size,
+            view: View::of(&r.map),
+            base_hz: r.base_hz,
+            style: self.style,
+            pins: self
+                .pins
+                .labels()
+                .map(|(label, p)| Annotation {
+                    label,
+                    probe: Probe::at(r, p),
+                })
+                .collect(),
+        };
+
+        self.status = format!("Exporting {}...", path.display());
+
+        Command::perform(export::export(r.clone(), path, meta), Message::Exported)
+    }
+
+    /// Color the latest map with the current style
+    fn recolor(&mut self) {
+        self.image = self
+            .map
+            .as_ref()
+            .map(|r| colormap::image(&r.map, r.range, &self.style));
+    }
+
+    /// The view shown, which is the view of the latest map until it's moved
+    fn current_view(&self) -> Option<View> {
+        self.view.or_else(|| self.map.as_ref().map(|r| View::of(&r.map)))
+    }
+
+    pub fn update(&mut self, msg: Message) -> Command<Message> {
+        match msg {
+            Message::ConfigChanged(config) => self.config = config,
+            Message::Load => return self.load(),
+            Message::Rendered(id, outcome) if id == self.render_id => self.rendered(outcome),
+            // Ticking only continues while the render is running, and the view
+            // is redrawn after each tick to show its progress
+            Message::Tick(id) if id == self.render_id && self.rendering => return Self::tick(id),
+            Message::Cancel if !self.rendering => (),
+            Message::Cancel => {
+                self.cancel.set();
+                self.status = "Cancelling render...".into();
+            },
+            Message::Restart => return self.render(self.view),
+            Message::ViewChanged(view) => {
+                self.view = Some(view);
+
+                return self.settle();
+            },
+            Message::Pan(dx, dy) => {
+                if let Some(view) = self.current_view() {
+                    return self.update(Message::ViewChanged(view.pan(dx, dy)));
+                }
+            },
+            Message::Zoom(factor) => {
+                if let Some(view) = self.current_view() {
+                    return self.update(Message::ViewChanged(view.zoom((0.5, 0.5), factor)));
+                }
+            },
+            Message::ResetView if self.view.is_none() && self.size.is_none() => (),
+            Message::ResetView => {
+                self.view = None;
+                self.size = None;
+
+                return self.settle();
+            },
+            // A selection is rendered right away, as its own map, and isn't
+            // snapped to the pixels of the map it was selected from
+            Message::Selected(..) if self.cached.is_some() => (),
+            Message::Selected(view, size) => {
+                self.view = Some(view);
+                self.size = Some(size);
+                self.settle_id += 1;
+
+                return self.render(Some(view));
+            },
+            Message::Hover(hover) => self.hover = hover,
+            Message::Param(msg) => {
+                self.params.update(msg);
+
+                return self.edit();
+            },
+            Message::Timbre(msg) => {
+                self.timbre.update(msg);
+
+                return self.edit();
+            },
+            Message::Dropped(path) => return self.dropped(path),
+            Message::Imported(Ok(spectrum)) => {
+                self.status = format!("Imported {} partials", spectrum.wave.iter().count());
+                self.timbre.import(spectrum);
+
+                return self.edit();
+            },
+            Message::Imported(Err(e)) => self.status = format!("Import failed: {e:#}"),
+            Message::Display(msg) => {
+                display::update(&mut self.style, msg);
+                self.recolor();
+            },
+            Message::Curve(msg) => self.curve.update(msg, self.hover),
+            Message::Minima(msg) => self.minima.update(msg),
+            Message::Pins(msg) => self.pins.update(msg),
+            Message::Pin => {
+                if let Some(p) = self.hover {
+                    self.pins.update(pins::Message::Add(p));
+                }
+            },
+            Message::Export => return self.export(),
+            Message::Exported(Ok(path)) => self.status = format!("Exported {}", path.display()),
+            Message::Exported(Err(e)) => self.status = format!("Export failed: {e:#}"),
+            Message::Settled(id) if id == self.settle_id => {
+                // Stay on the pixel grid of the latest map, so its tiles can be
+                // reused
+                let view = match (self.view, &self.map) {
+                    (Some(view), Some(r)) => Some(view.snap(View::of(&r.map), r.map.size)),
+                    (view, _) => view,
+                };
+
+                self.view = view;
+
+                return self.render(view);
+            },
+            // Stale messages, and queueing, which is handled outside the
+            // document
+            Message::Rendered(..) | Message::Tick(_) | Message::Settled(_) | Message::Enqueue => (),
+        }
+
+        Command::none()
+    }
+
+    /// Lay out the document.  A compact document shows only its map and
+    /// readouts, for viewing several side by side.
+    pub fn view(&mut self, compact: bool) -> Element<'_, Message> {
+        let view = self.current_view();
+        let toolbar = Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(
+                TextInput::new(
+                    &mut self.config_input,
+                    "Config file",
+                    &self.config,
+                    Message::ConfigChanged,
+                )
+                .on_submit(Message::Load)
+                .padding(5),
+            )
+            .push(Button::new(&mut self.load_button, Text::new("Load")).on_press(Message::Load));
+
+        let toolbar = if self.rendering {
+            let fraction = self.progress.fraction().unwrap_or(0.0);
+
+            toolbar
+                .push(ProgressBar::new(0.0..=1.0, fraction).width(Length::Units(200)))
+                .push(
+                    Button::new(&mut self.render_button, Text::new("Cancel"))
+                        .on_press(Message::Cancel),
+                )
+        } else {
+            toolbar.push(
+                Button::new(&mut self.render_button, Text::new("Render"))
+                    .on_press(Message::Restart),
+            )
+        };
+
+        let mut queue = Button::new(&mut self.queue_button, Text::new("Queue"));
+
+        if !self.config.is_empty() && self.cached.is_none() {
+            queue = queue.on_press(Message::Enqueue);
+        }
+
+        let mut export = Button::new(&mut self.export_button, Text::new("Export"));
+
+        if self.map.is_some() {
+            export = export.on_press(Message::Export);
+        }
+
+        let toolbar = toolbar.push(queue).push(export);
+
+        let map: Element<_> = match (&self.map, &self.image, view) {
+            (Some(r), Some(image), Some(view)) => {
+                let mut viewer =
+                    Viewer::new(&mut self.viewer, view, r.map.size, Message::ViewChanged)
+                        .layer(image.clone(), View::of(&r.map))
+                        .on_hover(Message::Hover)
+                        .on_select(Message::Selected)
+                        .on_pin(|p| Message::Pins(pins::Message::Add(p)));
+
+                if self.minima.show {
+                    for m in &r.minima {
+                        viewer = viewer.marker(m.octaves, m.label());
+                    }
+                }
+
+                for (label, p) in self.pins.labels() {
+                    viewer = viewer.marker(p, label);
+                }
+
+                viewer.into()
+            },
+            _ => Text::new("No map loaded").into(),
+        };
+
+        let mut body = Row::new().spacing(10);
+
+        match self.map {
+            Some(ref r) if !compact => {
+                body = body.push(
+                    Scrollable::new(&mut self.side_scroll)
+                        .spacing(20)
+                        .push(self.params.view().map(Message::Param))
+                        .push(self.timbre.view().map(Message::Timbre))
+                        .push(self.display.view(&self.style, &r.histogram).map(Message::Display))
+                        .push(self.minima.view(&r.minima).map(Message::Minima))
+                        .push(self.pins.view(r).map(Message::Pins)),
+                );
+            },
+            _ => (),
+        }
+
+        let mut main = Column::new().spacing(10).push(
+            Container::new(map)
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .center_x()
+                .center_y(),
+        );
+
+        if self.map.is_some() && !compact {
+            main = main.push(
+                self.curve
+                    .view(self.map.as_deref(), self.hover)
+                    .map(Message::Curve),
+            );
+        }
+
+        body = body.push(main);
+
+        let col = Column::new()
+            .spacing(10)
+            .push(toolbar)
+            .push(body)
+            .push(Text::new(&self.status).size(16));
+
+        match (&self.map, self.hover) {
+            (Some(r), Some(p)) => col.push(Text::new(Probe::at(r, p).to_string()).size(16)),
+            _ => col,
+        }
+        .into()
+    }
+}
+
+impl Drop for Document {
+    /// Stop rendering a closed document's map
+    fn drop(&mut self) { self.cancel.set(); }
+}
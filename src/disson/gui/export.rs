@@ -0,0 +1,127 @@
+//! Saving maps from the GUI as images, colored as they're shown, alongside a
+//! sidecar describing them
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use futures::prelude::*;
+use image::{Rgba, RgbaImage};
+use nalgebra::Vector2;
+use serde::Serialize;
+
+use super::{
+    colormap::{self, Style},
+    readout::Probe,
+    render::{self, Rendered},
+    viewer::View,
+};
+use crate::error::prelude::*;
+
+/// How far the arms of the cross marking a pinned probe reach, in pixels
+const MARK_RADIUS: i64 = 4;
+const MARK_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const MARK_OUTLINE: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// A probe pinned to the map, as recorded in the sidecar
+#[derive(Debug, Clone, Serialize)]
+pub struct Annotation {
+    pub label: String,
+    #[serde(flatten)]
+    pub probe: Probe,
+}
+
+/// Everything the sidecar of an exported map records about it
+#[derive(Debug, Clone, Serialize)]
+pub struct Metadata {
+    /// The config the map was rendered from, unless it was loaded from the
+    /// cache
+    pub config: Option<String>,
+    pub preset: Option<String>,
+    /// The hash of the cache entry the map was loaded from, if any
+    pub cached: Option<String>,
+    pub size: Vector2<u32>,
+    pub view: View,
+    pub base_hz: f64,
+    pub style: Style,
+    pub pins: Vec<Annotation>,
+}
+
+/// Where to save the map of a document: beside its config, or in the working
+/// directory for a map loaded from the cache
+pub fn path_for(config: &str, cached: Option<&str>) -> PathBuf {
+    match cached {
+        Some(hash) => PathBuf::from(format!("disson-{hash:.8}.png")),
+        None => Path::new(config).with_extension("png"),
+    }
+}
+
+/// Mark each pinned probe on the image with an outlined cross
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn annotate(img: &mut RgbaImage, rendered: &Rendered, pins: &[Annotation]) {
+    let (w, h) = (i64::from(img.width()), i64::from(img.height()));
+    let mut put = |x: i64, y: i64, color| {
+        if (0..w).contains(&x) && (0..h).contains(&y) {
+            img.put_pixel(x as u32, y as u32, color);
+        }
+    };
+
+    for pin in pins {
+        let px = match rendered.map.pixel(pin.probe.octaves) {
+            Some(p) => p.map(|c| c.round() as i64),
+            None => continue,
+        };
+
+        // The outline is a pixel wider than the cross on every side
+        for d in -(MARK_RADIUS + 1)..=MARK_RADIUS + 1 {
+            for side in -1..=1 {
+                put(px.x + d, px.y + side, MARK_OUTLINE);
+                put(px.x + side, px.y + d, MARK_OUTLINE);
+            }
+        }
+
+        for d in -MARK_RADIUS..=MARK_RADIUS {
+            put(px.x + d, px.y, MARK_COLOR);
+            put(px.x, px.y + d, MARK_COLOR);
+        }
+    }
+}
+
+/// Save a map as a PNG on a separate thread, colored with the given style and
+/// marked with its pinned probes, and write its metadata to a JSON sidecar
+/// named after it
+pub fn export(
+    rendered: Arc<Rendered>,
+    path: PathBuf,
+    meta: Metadata,
+) -> impl Future<Output = Result<PathBuf, Arc<Error>>> {
+    render::background(move || -> Result<PathBuf> {
+        let mut img = colormap::rgba(&rendered.map, rendered.range, &meta.style);
+
+        annotate(&mut img, &rendered, &meta.pins);
+
+        img.save(&path)
+            .with_context(|| format!("failed to save {}", path.display()))?;
+
+        // The image's whole name is kept, so the sidecar of a map exported
+        // beside a JSON config can't replace it
+        let mut sidecar = path.clone().into_os_string();
+        sidecar.push(".json");
+        let sidecar = PathBuf::from(sidecar);
+        let out = File::create(&sidecar)
+            .map(BufWriter::new)
+            .with_context(|| format!("failed to create {}", sidecar.display()))?;
+
+        serde_json::to_writer_pretty(out, &meta)
+            .with_context(|| format!("failed to write {}", sidecar.display()))?;
+
+        Ok(path)
+    })
+    .map(|r| {
+        r.unwrap_or_else(|| Err(anyhow!("export thread panicked")))
+            .map_err(Arc::new)
+    })
+}
@@ -0,0 +1,172 @@
+//! A histogram of the values of a map, with handles for the range of values
+//! spread over the colormap
+
+use iced::{
+    canvas::{event, Cursor, Event, Frame, Geometry, Path, Program, Stroke},
+    mouse, Color, Point, Rectangle, Size,
+};
+
+use super::{colormap::Style, display::Message};
+
+const BAR_COLOR: Color = Color {
+    r: 0.6,
+    g: 0.6,
+    b: 0.6,
+    a: 1.0,
+};
+const HANDLE_COLOR: Color = Color {
+    r: 0.9,
+    g: 0.55,
+    b: 0.1,
+    a: 1.0,
+};
+/// Shades the values clamped to either end of the colormap
+const CLAMPED_COLOR: Color = Color {
+    r: 0.0,
+    g: 0.0,
+    b: 0.0,
+    a: 0.4,
+};
+/// How close, in pixels, the cursor must be to a handle to grab it
+const GRAB_DISTANCE: f32 = 6.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Handle {
+    Low,
+    High,
+}
+
+/// The histogram and the state of its handles
+#[derive(Debug, Default)]
+pub struct Histogram {
+    /// Counts of values in equal-width bins from the least value of the map to
+    /// the greatest
+    pub bins: Vec<usize>,
+    pub low: f32,
+    pub high: f32,
+    drag: Option<Handle>,
+}
+
+impl Histogram {
+    /// Point the handles at the clamping range of a style
+    pub fn set_style(&mut self, style: &Style) {
+        self.low = style.low;
+        self.high = style.high;
+    }
+
+    /// Find the handle close enough to a point on the histogram to grab.  If
+    /// the handles overlap, the one on the side of the point is grabbed.
+    #[allow(clippy::float_cmp)]
+    fn handle_at(&self, bounds: Rectangle, p: Point) -> Option<Handle> {
+        let dist = |t: f32| (t * bounds.width - p.x).abs();
+        let (low, high) = (dist(self.low), dist(self.high));
+
+        if low.min(high) > GRAB_DISTANCE {
+            None
+        } else if low < high || (low == high && p.x < self.low * bounds.width) {
+            Some(Handle::Low)
+        } else {
+            Some(Handle::High)
+        }
+    }
+}
+
+impl Program<Message> for Histogram {
+    fn update(
+        &mut self,
+        event: Event,
+        bounds: Rectangle,
+        cursor: Cursor,
+    ) -> (event::Status, Option<Message>) {
+        let Event::Mouse(evt) = event else {
+            return (event::Status::Ignored, None);
+        };
+        let pos = cursor.position_in(&bounds);
+
+        match evt {
+            mouse::Event::ButtonPressed(mouse::Button::Left) => {
+                self.drag = pos.and_then(|p| self.handle_at(bounds, p));
+
+                if self.drag.is_some() {
+                    return (event::Status::Captured, None);
+                }
+            },
+            mouse::Event::ButtonReleased(mouse::Button::Left) if self.drag.is_some() => {
+                self.drag = None;
+
+                return (event::Status::Captured, None);
+            },
+            mouse::Event::CursorMoved { .. } => {
+                if let (Some(handle), Some(p)) = (self.drag, cursor.position()) {
+                    let t = ((p.x - bounds.x) / bounds.width).clamp(0.0, 1.0);
+
+                    return (
+                        event::Status::Captured,
+                        Some(match handle {
+                            Handle::Low => Message::Low(t),
+                            Handle::High => Message::High(t),
+                        }),
+                    );
+                }
+            },
+            _ => (),
+        }
+
+        (event::Status::Ignored, None)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn draw(&self, bounds: Rectangle, _cursor: Cursor) -> Vec<Geometry> {
+        let mut frame = Frame::new(bounds.size());
+        let (w, h) = (bounds.width, bounds.height);
+
+        // Counts are drawn on a log scale, so sparse tails stay visible
+        let peak = (self.bins.iter().copied().max().unwrap_or(0) as f32).ln_1p().max(1.0);
+        let bar = w / self.bins.len().max(1) as f32;
+
+        for (i, &count) in self.bins.iter().enumerate() {
+            let height = (count as f32).ln_1p() / peak * h;
+
+            frame.fill_rectangle(
+                Point::new(i as f32 * bar, h - height),
+                Size::new(bar, height),
+                BAR_COLOR,
+            );
+        }
+
+        frame.fill_rectangle(Point::ORIGIN, Size::new(self.low * w, h), CLAMPED_COLOR);
+        frame.fill_rectangle(
+            Point::new(self.high * w, 0.0),
+            Size::new((1.0 - self.high) * w, h),
+            CLAMPED_COLOR,
+        );
+
+        for &t in &[self.low, self.high] {
+            frame.stroke(
+                &Path::line(Point::new(t * w, 0.0), Point::new(t * w, h)),
+                Stroke {
+                    color: HANDLE_COLOR,
+                    width: 2.0,
+                    ..Stroke::default()
+                },
+            );
+        }
+
+        vec![frame.into_geometry()]
+    }
+
+    fn mouse_interaction(&self, bounds: Rectangle, cursor: Cursor) -> mouse::Interaction {
+        let grabbable = cursor
+            .position_in(&bounds)
+            .and_then(|p| self.handle_at(bounds, p))
+            .is_some();
+
+        if self.drag.is_some() {
+            mouse::Interaction::Grabbing
+        } else if grabbable {
+            mouse::Interaction::Grab
+        } else {
+            mouse::Interaction::default()
+        }
+    }
+}
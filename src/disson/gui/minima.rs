@@ -0,0 +1,163 @@
+//! Markers and a list of the local minima of a map, i.e. its most consonant
+//! chords
+
+use std::{cmp::Ordering, fmt};
+
+use iced::{
+    pick_list, scrollable, Checkbox, Column, Element, Length, PickList, Scrollable, Text,
+};
+use nalgebra::Point2;
+
+use super::{readout::Ratio, PANEL_WIDTH};
+use crate::disson::{extrema, map::DissonMap};
+
+/// How many of the deepest minima are kept
+const MAX_MINIMA: usize = 32;
+/// Height of the list of minima
+const LIST_HEIGHT: u16 = 240;
+
+/// A local minimum of a map
+#[derive(Debug, Clone, Copy)]
+pub struct Minimum {
+    /// Where the minimum lies, in octaves above the base frequency
+    pub octaves: Point2<f64>,
+    pub value: f64,
+    /// How much higher the map must be flooded before the minimum's basin
+    /// merges with a deeper one
+    pub depth: f64,
+}
+
+impl Minimum {
+    /// A short label to draw beside the minimum's marker
+    pub fn label(&self) -> String {
+        let (x, y) = (
+            Ratio::nearest(self.octaves.x),
+            Ratio::nearest(self.octaves.y),
+        );
+
+        format!("{}/{} : {}/{}", x.num, x.den, y.num, y.den)
+    }
+}
+
+impl fmt::Display for Minimum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "X {:+.0}¢ {}, Y {:+.0}¢ {}, depth {:.4}",
+            self.octaves.x * 1200.0,
+            Ratio::nearest(self.octaves.x),
+            self.octaves.y * 1200.0,
+            Ratio::nearest(self.octaves.y),
+            self.depth
+        )
+    }
+}
+
+/// Find the deepest local minima of a map
+pub fn find(map: &DissonMap) -> Vec<Minimum> {
+    extrema::minima(map)
+        .into_iter()
+        .take(MAX_MINIMA)
+        .map(|e| Minimum {
+            octaves: map.octaves(Point2::new(e.col, e.row).cast()),
+            value: e.value,
+            depth: e.depth,
+        })
+        .collect()
+}
+
+/// Orders the list of minima can be sorted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    Depth,
+    Value,
+    XInterval,
+    YInterval,
+}
+
+impl Sort {
+    const ALL: [Self; 4] = [Self::Depth, Self::Value, Self::XInterval, Self::YInterval];
+
+    fn cmp(self, a: &Minimum, b: &Minimum) -> Ordering {
+        let ord = match self {
+            Self::Depth => b.depth.partial_cmp(&a.depth),
+            Self::Value => a.value.partial_cmp(&b.value),
+            Self::XInterval => a.octaves.x.partial_cmp(&b.octaves.x),
+            Self::YInterval => a.octaves.y.partial_cmp(&b.octaves.y),
+        };
+
+        ord.unwrap_or(Ordering::Equal)
+    }
+}
+
+impl fmt::Display for Sort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Depth => "Deepest first",
+            Self::Value => "Most consonant first",
+            Self::XInterval => "By X interval",
+            Self::YInterval => "By Y interval",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Message {
+    Show(bool),
+    Sort(Sort),
+}
+
+/// The toggle for the minima markers and the list of minima
+#[derive(Debug)]
+pub struct Panel {
+    /// Whether markers are drawn over the map
+    pub show: bool,
+    sort: Sort,
+    sort_list: pick_list::State<Sort>,
+    scroll: scrollable::State,
+}
+
+impl Default for Panel {
+    fn default() -> Self {
+        Self {
+            show: true,
+            sort: Sort::Depth,
+            sort_list: pick_list::State::default(),
+            scroll: scrollable::State::new(),
+        }
+    }
+}
+
+impl Panel {
+    pub fn update(&mut self, msg: Message) {
+        match msg {
+            Message::Show(s) => self.show = s,
+            Message::Sort(s) => self.sort = s,
+        }
+    }
+
+    pub fn view(&mut self, minima: &[Minimum]) -> Element<'_, Message> {
+        let mut sorted = minima.to_vec();
+        sorted.sort_by(|a, b| self.sort.cmp(a, b));
+
+        let list = sorted.iter().fold(
+            Scrollable::new(&mut self.scroll)
+                .height(Length::Units(LIST_HEIGHT))
+                .spacing(5),
+            |s, m| s.push(Text::new(m.to_string()).size(14)),
+        );
+
+        Column::new()
+            .width(Length::Units(PANEL_WIDTH))
+            .spacing(5)
+            .push(Checkbox::new(self.show, "Show minima", Message::Show))
+            .push(PickList::new(
+                &mut self.sort_list,
+                &Sort::ALL[..],
+                Some(self.sort),
+                Message::Sort,
+            ))
+            .push(list)
+            .into()
+    }
+}
@@ -1,30 +1,437 @@
-use iced::{executor, Application, Command, Element, Settings};
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
-use crate::{cli::CacheMode, error::prelude::*};
+use iced::{
+    button, executor, Align, Application, Button, Checkbox, Column, Command, Element, Row,
+    Settings, Subscription, Text,
+};
+use iced_native::{
+    event,
+    keyboard::{self, KeyCode, Modifiers},
+    subscription, window, Event,
+};
 
-struct Gui;
+use crate::{
+    cache::{self, MemoryCache},
+    cli::{CacheOpts, GuiOpts},
+    error::prelude::*,
+};
+use browser::Browser;
+use compare::{Choice, Compare};
+use document::Document;
+use palette::{Action, Palette};
+use queue::Queue;
+use render::{GuiCache, Progress};
 
-#[derive(Debug)]
-enum Message {}
+mod browser;
+mod colormap;
+mod compare;
+mod curve;
+mod display;
+mod document;
+mod export;
+mod histogram;
+mod minima;
+mod palette;
+mod params;
+mod pins;
+mod queue;
+mod readout;
+mod render;
+mod spectrum;
+mod timbre;
+mod viewer;
+
+/// How long the view and settings must stay unchanged before the map is
+/// rendered again
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+/// How often the progress of a render is checked
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+/// Width of the controls beside the map
+const PANEL_WIDTH: u16 = 260;
+/// How far the arrow keys pan the view, as a fraction of its size
+const KEY_PAN: f64 = 0.1;
+/// How much the view is scaled by each press of the zoom keys
+const KEY_ZOOM: f64 = 1.25;
+
+/// A document open in a tab
+struct Tab {
+    /// Identifies the tab, so messages for it still find it after tabs before
+    /// it are closed
+    id: u64,
+    doc: Document,
+    select_button: button::State,
+    close_button: button::State,
+}
+
+// Each flag is a separate toggle in the toolbar
+#[allow(clippy::struct_excessive_bools)]
+struct Gui {
+    cache: GuiCache,
+    progress: Arc<Progress>,
+    tabs: Vec<Tab>,
+    /// The ID of the tab shown
+    active: u64,
+    next_id: u64,
+    /// Whether every tab is shown side by side instead of only the active one
+    split: bool,
+    /// Whether the point under the cursor in any tab is probed in all of them
+    linked: bool,
+    /// Whether the maps of two tabs are shown compared instead of the tabs
+    comparing: bool,
+    compare: Compare,
+    /// Whether the entries of the cache are listed instead of the tabs
+    browsing: bool,
+    browser: Browser,
+    /// Whether the render queue is shown instead of the tabs
+    queueing: bool,
+    queue: Queue,
+    palette: Palette,
+    new_button: button::State,
+}
+
+#[derive(Debug, Clone)]
+enum Message {
+    Doc(u64, document::Message),
+    NewTab,
+    SelectTab(u64),
+    CloseTab(u64),
+    Split(bool),
+    Link(bool),
+    Comparing(bool),
+    Compare(compare::Message),
+    Browsing(bool),
+    Browser(browser::Message),
+    Queueing(bool),
+    Queue(queue::Message),
+    Palette(palette::Message),
+    Key(KeyCode, Modifiers),
+    FileDropped(PathBuf),
+}
+
+impl Gui {
+    /// Open a document in a new tab and show it
+    fn open(&mut self, config: String, preset: Option<String>) -> Command<Message> {
+        let (doc, cmd) = Document::new(self.cache.clone(), self.progress.clone(), config, preset);
+
+        self.add(doc, cmd)
+    }
+
+    /// Show a newly opened document in a new tab
+    fn add(&mut self, doc: Document, cmd: Command<document::Message>) -> Command<Message> {
+        let id = self.next_id;
+
+        self.next_id += 1;
+        self.active = id;
+        self.tabs.push(Tab {
+            id,
+            doc,
+            select_button: button::State::new(),
+            close_button: button::State::new(),
+        });
+
+        cmd.map(move |m| Message::Doc(id, m))
+    }
+
+    fn doc(&self, id: Option<u64>) -> Option<&Document> {
+        self.tabs.iter().find(|t| Some(t.id) == id).map(|t| &t.doc)
+    }
+
+    /// Compare the maps of the chosen tabs again, if anything changed
+    fn refresh_compare(&mut self) {
+        let a = self.doc(self.compare.a).and_then(Document::source);
+        let b = self.doc(self.compare.b).and_then(Document::source);
+
+        self.compare.refresh(a, b);
+    }
+
+    /// Run an action from a shortcut or the palette
+    fn perform(&mut self, action: Action) -> Command<Message> {
+        let msg = match action {
+            Action::PanLeft => document::Message::Pan(-KEY_PAN, 0.0),
+            Action::PanRight => document::Message::Pan(KEY_PAN, 0.0),
+            Action::PanUp => document::Message::Pan(0.0, -KEY_PAN),
+            Action::PanDown => document::Message::Pan(0.0, KEY_PAN),
+            Action::ZoomIn => document::Message::Zoom(KEY_ZOOM.recip()),
+            Action::ZoomOut => document::Message::Zoom(KEY_ZOOM),
+            Action::ResetView => document::Message::ResetView,
+            Action::Render => document::Message::Restart,
+            Action::Cancel => document::Message::Cancel,
+            Action::Export => document::Message::Export,
+            Action::Pin => document::Message::Pin,
+            Action::Enqueue => document::Message::Enqueue,
+            Action::NewTab => return self.dispatch(Message::NewTab),
+            Action::CloseTab if self.tabs.len() > 1 => {
+                return self.dispatch(Message::CloseTab(self.active))
+            },
+            Action::CloseTab => return Command::none(),
+            Action::NextTab | Action::PreviousTab => {
+                let n = self.tabs.len();
+
+                if let Some(i) = self.tabs.iter().position(|t| t.id == self.active) {
+                    let step = if action == Action::NextTab { 1 } else { n - 1 };
+
+                    self.active = self.tabs[(i + step) % n].id;
+                }
+
+                return Command::none();
+            },
+            Action::SideBySide => return self.dispatch(Message::Split(!self.split)),
+            Action::LinkCursors => return self.dispatch(Message::Link(!self.linked)),
+            Action::Compare => return self.dispatch(Message::Comparing(!self.comparing)),
+            Action::Cache => return self.dispatch(Message::Browsing(!self.browsing)),
+            Action::Queue => return self.dispatch(Message::Queueing(!self.queueing)),
+        };
+
+        self.dispatch(Message::Doc(self.active, msg))
+    }
+
+    fn dispatch(&mut self, msg: Message) -> Command<Message> {
+        match msg {
+            Message::Doc(_, document::Message::Hover(hover)) if self.linked => {
+                for tab in &mut self.tabs {
+                    tab.doc.update(document::Message::Hover(hover));
+                }
+            },
+            Message::Doc(id, document::Message::Enqueue) => {
+                if let Some(request) = self.doc(Some(id)).and_then(Document::request) {
+                    return self.queue.push(&self.cache, request).map(Message::Queue);
+                }
+            },
+            Message::Doc(id, msg) => {
+                if let Some(tab) = self.tabs.iter_mut().find(|t| t.id == id) {
+                    return tab.doc.update(msg).map(move |m| Message::Doc(id, m));
+                }
+            },
+            Message::NewTab => return self.open(String::new(), None),
+            Message::SelectTab(id) => self.active = id,
+            Message::CloseTab(id) => {
+                let Some(idx) = self.tabs.iter().position(|t| t.id == id) else {
+                    return Command::none();
+                };
+
+                self.tabs.remove(idx);
+
+                if self.active == id {
+                    if let Some(t) = self.tabs.get(idx.min(self.tabs.len().saturating_sub(1))) {
+                        self.active = t.id;
+                    }
+                }
+            },
+            Message::Split(split) => self.split = split,
+            Message::Link(linked) => self.linked = linked,
+            Message::Comparing(comparing) => {
+                self.comparing = comparing;
+
+                // Start by comparing the tab shown with the next one
+                if comparing && self.compare.a.is_none() {
+                    let active = self.active;
+
+                    self.compare.a = Some(active);
+                    self.compare.b = self.tabs.iter().map(|t| t.id).find(|&i| i != active);
+                }
+            },
+            Message::Compare(msg) => self.compare.update(msg),
+            Message::Browsing(browsing) => {
+                self.browsing = browsing;
+
+                if browsing {
+                    return self.browser.refresh(&self.cache).map(Message::Browser);
+                }
+            },
+            Message::Browser(browser::Message::Load(hash)) => {
+                let (doc, cmd) = Document::cached(self.cache.clone(), self.progress.clone(), hash);
+
+                self.browsing = false;
+
+                return self.add(doc, cmd);
+            },
+            Message::Browser(msg) => {
+                return self.browser.update(&self.cache, msg).map(Message::Browser);
+            },
+            Message::Queueing(queueing) => self.queueing = queueing,
+            Message::Queue(queue::Message::Open(id)) => {
+                if let Some((request, rendered)) = self.queue.result(id) {
+                    let doc = Document::from_job(
+                        self.cache.clone(),
+                        self.progress.clone(),
+                        request,
+                        rendered,
+                    );
+
+                    self.queueing = false;
+
+                    return self.add(doc, Command::none());
+                }
+            },
+            Message::Queue(msg) => return self.queue.update(&self.cache, msg).map(Message::Queue),
+            Message::Palette(msg) => {
+                if let Some(action) = self.palette.update(msg) {
+                    return self.perform(action);
+                }
+            },
+            Message::Key(key, mods) => {
+                if palette::toggles(key, mods) {
+                    self.palette.toggle();
+                } else if key == KeyCode::Escape && self.palette.is_open() {
+                    self.palette.close();
+                } else if let Some(action) = Action::from_key(key, mods) {
+                    return self.perform(action);
+                }
+            },
+            Message::FileDropped(path) => {
+                return self.dispatch(Message::Doc(self.active, document::Message::Dropped(path)));
+            },
+        }
+
+        Command::none()
+    }
+}
 
 impl Application for Gui {
     type Executor = executor::Default;
-    type Flags = ();
+    type Flags = (GuiCache, GuiOpts);
     type Message = Message;
 
-    fn new((): ()) -> (Self, Command<Message>) { (Self, Command::none()) }
+    fn new((cache, opts): Self::Flags) -> (Self, Command<Message>) {
+        let mut gui = Self {
+            cache,
+            progress: Progress::track(),
+            tabs: vec![],
+            active: 0,
+            next_id: 0,
+            split: false,
+            linked: false,
+            comparing: false,
+            compare: Compare::default(),
+            browsing: false,
+            browser: Browser::default(),
+            queueing: false,
+            queue: Queue::default(),
+            palette: Palette::default(),
+            new_button: button::State::new(),
+        };
+        let config = opts
+            .config
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let cmd = gui.open(config, opts.preset);
+
+        (gui, cmd)
+    }
+
+    fn title(&self) -> String {
+        match self.tabs.iter().find(|t| t.id == self.active) {
+            Some(t) => format!("{} - disson", t.doc.title()),
+            None => "disson".into(),
+        }
+    }
+
+    fn update(&mut self, msg: Message) -> Command<Message> {
+        let cmd = self.dispatch(msg);
+
+        if self.comparing {
+            self.refresh_compare();
+        }
 
-    fn title(&self) -> String { "disson".into() }
+        cmd
+    }
 
-    fn update(&mut self, msg: Message) -> Command<Message> { match msg {} }
+    fn subscription(&self) -> Subscription<Message> {
+        // Keys pressed while a widget has focus, e.g. typing into a text
+        // field, aren't shortcuts
+        subscription::events_with(|event, status| match (event, status) {
+            (Event::Window(window::Event::FileDropped(path)), _) => {
+                Some(Message::FileDropped(path))
+            },
+            (
+                Event::Keyboard(keyboard::Event::KeyPressed {
+                    key_code,
+                    modifiers,
+                }),
+                event::Status::Ignored,
+            ) => Some(Message::Key(key_code, modifiers)),
+            _ => None,
+        })
+    }
 
-    fn view(&mut self) -> Element<Message> { iced::Column::new().into() }
+    fn view(&mut self) -> Element<'_, Message> {
+        let (active, closable) = (self.active, self.tabs.len() > 1);
+        let choices: Vec<_> = self
+            .tabs
+            .iter()
+            .map(|t| Choice {
+                id: t.id,
+                title: t.doc.title(),
+            })
+            .collect();
+        let mut tab_bar = Row::new().spacing(5).align_items(Align::Center);
+        let mut docs = Row::new().spacing(20);
+
+        for tab in &mut self.tabs {
+            let id = tab.id;
+            let mut select = Button::new(&mut tab.select_button, Text::new(tab.doc.title()));
+
+            if id != active {
+                select = select.on_press(Message::SelectTab(id));
+            }
+
+            let mut close = Button::new(&mut tab.close_button, Text::new("x"));
+
+            if closable {
+                close = close.on_press(Message::CloseTab(id));
+            }
+
+            tab_bar = tab_bar.push(select).push(close);
+
+            if !(self.comparing || self.browsing || self.queueing) && (self.split || id == active) {
+                docs = docs.push(tab.doc.view(self.split).map(move |m| Message::Doc(id, m)));
+            }
+        }
+
+        let tab_bar = tab_bar
+            .push(Button::new(&mut self.new_button, Text::new("+")).on_press(Message::NewTab))
+            .push(Checkbox::new(self.split, "Side by side", Message::Split))
+            .push(Checkbox::new(self.linked, "Link cursors", Message::Link))
+            .push(Checkbox::new(self.comparing, "Compare", Message::Comparing))
+            .push(Checkbox::new(self.browsing, "Cache", Message::Browsing))
+            .push(Checkbox::new(
+                self.queueing,
+                format!("Queue ({})", self.queue.pending()),
+                Message::Queueing,
+            ));
+        let tab_bar = match self.queue.notice() {
+            Some(n) => tab_bar.push(Text::new(n).size(16)),
+            None => tab_bar,
+        };
+
+        let body: Element<_> = if self.browsing {
+            self.browser.view().map(Message::Browser)
+        } else if self.queueing {
+            self.queue.view(&self.progress).map(Message::Queue)
+        } else if self.comparing {
+            self.compare.view(choices).map(Message::Compare)
+        } else {
+            docs.into()
+        };
+
+        let col = Column::new().padding(10).spacing(10).push(tab_bar);
+        let col = if self.palette.is_open() {
+            col.push(self.palette.view().map(Message::Palette))
+        } else {
+            col
+        };
+
+        col.push(body).into()
+    }
 }
 
-pub fn run(cache_mode: CacheMode) -> Result<()> {
+pub fn run(cache_opts: CacheOpts, opts: GuiOpts) -> Result<()> {
+    let memory_entries = cache_opts.memory_entries;
+    let cache = Arc::new(MemoryCache::new(cache::from_opts(cache_opts), memory_entries));
+
     Gui::run(Settings {
         antialiasing: true,
-        ..Settings::default()
+        ..Settings::with_flags((cache, opts))
     })
     .map_err(|e| anyhow!("iced failed to initialize: {}", e))?;
 
@@ -0,0 +1,265 @@
+//! Keyboard shortcuts, and a palette listing every command the GUI can run
+//! from them, searchable by name
+
+use iced::{
+    button, scrollable, text_input, Align, Button, Column, Element, Length, Row, Scrollable, Text,
+    TextInput,
+};
+use iced_native::keyboard::{KeyCode, Modifiers};
+
+/// Most matches listed in the palette at once
+const MAX_MATCHES: usize = 10;
+/// Height of the list of matches
+const LIST_HEIGHT: u16 = 240;
+
+/// Something to do to the GUI or the document shown, from a shortcut or the
+/// palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    ZoomIn,
+    ZoomOut,
+    ResetView,
+    Render,
+    Cancel,
+    Export,
+    Enqueue,
+    Pin,
+    NewTab,
+    CloseTab,
+    NextTab,
+    PreviousTab,
+    SideBySide,
+    LinkCursors,
+    Compare,
+    Cache,
+    Queue,
+}
+
+impl Action {
+    const ALL: [Self; 21] = [
+        Self::Render,
+        Self::Cancel,
+        Self::Export,
+        Self::Enqueue,
+        Self::Pin,
+        Self::ResetView,
+        Self::ZoomIn,
+        Self::ZoomOut,
+        Self::PanLeft,
+        Self::PanRight,
+        Self::PanUp,
+        Self::PanDown,
+        Self::NewTab,
+        Self::CloseTab,
+        Self::NextTab,
+        Self::PreviousTab,
+        Self::SideBySide,
+        Self::LinkCursors,
+        Self::Compare,
+        Self::Cache,
+        Self::Queue,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::PanLeft => "Pan left",
+            Self::PanRight => "Pan right",
+            Self::PanUp => "Pan up",
+            Self::PanDown => "Pan down",
+            Self::ZoomIn => "Zoom in",
+            Self::ZoomOut => "Zoom out",
+            Self::ResetView => "Reset the view to the config's",
+            Self::Render => "Render the map again",
+            Self::Cancel => "Cancel the render",
+            Self::Export => "Export the map as a PNG",
+            Self::Enqueue => "Queue a render of the map",
+            Self::Pin => "Pin the probe under the cursor",
+            Self::NewTab => "New tab",
+            Self::CloseTab => "Close tab",
+            Self::NextTab => "Next tab",
+            Self::PreviousTab => "Previous tab",
+            Self::SideBySide => "Toggle showing tabs side by side",
+            Self::LinkCursors => "Toggle linking cursors",
+            Self::Compare => "Toggle comparing tabs",
+            Self::Cache => "Toggle the cache browser",
+            Self::Queue => "Toggle the render queue",
+        }
+    }
+
+    /// A description of the shortcut running the action, if it has one
+    fn shortcut(self) -> Option<&'static str> {
+        Some(match self {
+            Self::PanLeft => "Left",
+            Self::PanRight => "Right",
+            Self::PanUp => "Up",
+            Self::PanDown => "Down",
+            Self::ZoomIn => "+",
+            Self::ZoomOut => "-",
+            Self::ResetView => "0",
+            Self::Render => "Ctrl+R",
+            Self::Cancel => "Esc",
+            Self::Export => "Ctrl+E",
+            Self::Pin => "P",
+            Self::NewTab => "Ctrl+T",
+            Self::CloseTab => "Ctrl+W",
+            Self::NextTab => "Ctrl+Tab",
+            Self::PreviousTab => "Ctrl+Shift+Tab",
+            _ => return None,
+        })
+    }
+
+    /// The action a key press is a shortcut for, if any
+    pub fn from_key(key: KeyCode, mods: Modifiers) -> Option<Self> {
+        if mods.alt || mods.logo {
+            return None;
+        }
+
+        Some(match (mods.control, mods.shift, key) {
+            (false, _, KeyCode::Left) => Self::PanLeft,
+            (false, _, KeyCode::Right) => Self::PanRight,
+            (false, _, KeyCode::Up) => Self::PanUp,
+            (false, _, KeyCode::Down) => Self::PanDown,
+            (false, _, KeyCode::Equals | KeyCode::Plus | KeyCode::NumpadAdd) => Self::ZoomIn,
+            (false, _, KeyCode::Minus | KeyCode::NumpadSubtract) => Self::ZoomOut,
+            (false, _, KeyCode::Key0 | KeyCode::Numpad0) => Self::ResetView,
+            (false, _, KeyCode::Escape) => Self::Cancel,
+            (false, false, KeyCode::P) => Self::Pin,
+            (true, false, KeyCode::R) => Self::Render,
+            (true, false, KeyCode::E) => Self::Export,
+            (true, false, KeyCode::T) => Self::NewTab,
+            (true, false, KeyCode::W) => Self::CloseTab,
+            (true, false, KeyCode::Tab) => Self::NextTab,
+            (true, true, KeyCode::Tab) => Self::PreviousTab,
+            _ => return None,
+        })
+    }
+}
+
+/// Whether a key press opens or closes the palette
+pub fn toggles(key: KeyCode, mods: Modifiers) -> bool {
+    key == KeyCode::P && mods.control && !(mods.shift || mods.alt || mods.logo)
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Query(String),
+    /// Run the first action matching the query
+    Submit,
+    Run(Action),
+    Close,
+}
+
+#[derive(Debug, Default)]
+pub struct Palette {
+    open: bool,
+    query: String,
+    input: text_input::State,
+    /// The state of the button of each action in the list, in the order of
+    /// `Action::ALL`
+    buttons: Vec<button::State>,
+    close_button: button::State,
+    scroll: scrollable::State,
+}
+
+impl Palette {
+    pub fn is_open(&self) -> bool { self.open }
+
+    /// Open the palette with an empty query, ready to type into, or close it
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+
+        if self.open {
+            self.query.clear();
+            self.input = text_input::State::focused();
+        }
+    }
+
+    pub fn close(&mut self) { self.open = false; }
+
+    /// Whether the label of an action contains every word of the query
+    fn matches(&self, action: Action) -> bool {
+        let label = action.label().to_lowercase();
+
+        self.query.to_lowercase().split_whitespace().all(|w| label.contains(w))
+    }
+
+    /// Apply a message, returning the action it chose to run, if any
+    pub fn update(&mut self, msg: Message) -> Option<Action> {
+        let action = match msg {
+            Message::Query(query) => {
+                self.query = query;
+                return None;
+            },
+            Message::Submit => Action::ALL.iter().copied().find(|&a| self.matches(a)),
+            Message::Run(action) => Some(action),
+            Message::Close => None,
+        };
+
+        self.open = false;
+
+        action
+    }
+
+    pub fn view(&mut self) -> Element<'_, Message> {
+        let hits: Vec<_> = Action::ALL.iter().map(|&a| self.matches(a)).collect();
+
+        self.buttons.resize_with(Action::ALL.len(), button::State::new);
+
+        let mut list = Scrollable::new(&mut self.scroll)
+            .width(Length::Fill)
+            .max_height(u32::from(LIST_HEIGHT))
+            .spacing(2);
+
+        if !hits.contains(&true) {
+            list = list.push(Text::new("No commands match").size(14));
+        }
+
+        for ((state, &action), _) in self
+            .buttons
+            .iter_mut()
+            .zip(Action::ALL.iter())
+            .zip(hits)
+            .filter(|(_, hit)| *hit)
+            .take(MAX_MATCHES)
+        {
+            let row = Row::new()
+                .spacing(10)
+                .push(Text::new(action.label()).size(14).width(Length::Fill))
+                .push(Text::new(action.shortcut().unwrap_or("")).size(14));
+
+            list = list.push(
+                Button::new(state, row)
+                    .width(Length::Fill)
+                    .on_press(Message::Run(action)),
+            );
+        }
+
+        Column::new()
+            .spacing(5)
+            .push(
+                Row::new()
+                    .spacing(10)
+                    .align_items(Align::Center)
+                    .push(
+                        TextInput::new(
+                            &mut self.input,
+                            "Type a command, then press Enter",
+                            &self.query,
+                            Message::Query,
+                        )
+                        .on_submit(Message::Submit)
+                        .padding(5),
+                    )
+                    .push(
+                        Button::new(&mut self.close_button, Text::new("Close"))
+                            .on_press(Message::Close),
+                    ),
+            )
+            .push(list)
+            .into()
+    }
+}
@@ -0,0 +1,390 @@
+//! The side panel for editing the map settings of the loaded config
+
+use std::fmt;
+
+use iced::{
+    pick_list, text_input, Color, Column, Element, Length, PickList, Row, Text, TextInput,
+};
+
+use super::PANEL_WIDTH;
+use crate::{
+    config::{Frequency, MapConfig},
+    disson::{
+        algo::{OverlapCurve, PitchCurve},
+        timbre::{Rolloff, Timbre},
+    },
+};
+
+pub const INVALID_COLOR: Color = Color {
+    r: 0.8,
+    g: 0.2,
+    b: 0.2,
+    a: 1.0,
+};
+
+/// Pitch curves selectable in the panel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PitchChoice {
+    Logarithmic,
+    ErbRate,
+    Bark,
+}
+
+/// Overlap curves selectable in the panel.  Blends can only be set in the
+/// config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlapChoice {
+    ExponentialDissonance,
+    TrapezoidDissonance,
+    TriangleConsonance,
+    TrapezoidConsonance,
+    Sethares,
+}
+
+/// Rolloffs selectable in the panel, with the rate of exponential decay
+/// entered separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RolloffChoice {
+    Flat,
+    Inverse,
+    InverseSquare,
+    ExponentialDecay,
+}
+
+impl PitchChoice {
+    const ALL: [Self; 3] = [Self::Logarithmic, Self::ErbRate, Self::Bark];
+
+    fn of(curve: PitchCurve) -> Self {
+        match curve {
+            PitchCurve::Edo => Self::Logarithmic,
+            PitchCurve::Erb => Self::ErbRate,
+            PitchCurve::Bark => Self::Bark,
+        }
+    }
+
+    fn curve(self) -> PitchCurve {
+        match self {
+            Self::Logarithmic => PitchCurve::Edo,
+            Self::ErbRate => PitchCurve::Erb,
+            Self::Bark => PitchCurve::Bark,
+        }
+    }
+}
+
+impl OverlapChoice {
+    const ALL: [Self; 5] = [
+        Self::ExponentialDissonance,
+        Self::TrapezoidDissonance,
+        Self::TriangleConsonance,
+        Self::TrapezoidConsonance,
+        Self::Sethares,
+    ];
+
+    fn of(curve: &OverlapCurve) -> Option<Self> {
+        Some(match curve {
+            OverlapCurve::ExpDiss => Self::ExponentialDissonance,
+            OverlapCurve::TrapDiss => Self::TrapezoidDissonance,
+            OverlapCurve::TriCons => Self::TriangleConsonance,
+            OverlapCurve::TrapCons => Self::TrapezoidConsonance,
+            OverlapCurve::Sethares => Self::Sethares,
+            OverlapCurve::Blend(_) => return None,
+        })
+    }
+
+    fn curve(self) -> OverlapCurve {
+        match self {
+            Self::ExponentialDissonance => OverlapCurve::ExpDiss,
+            Self::TrapezoidDissonance => OverlapCurve::TrapDiss,
+            Self::TriangleConsonance => OverlapCurve::TriCons,
+            Self::TrapezoidConsonance => OverlapCurve::TrapCons,
+            Self::Sethares => OverlapCurve::Sethares,
+        }
+    }
+}
+
+impl RolloffChoice {
+    const ALL: [Self; 4] = [
+        Self::Flat,
+        Self::Inverse,
+        Self::InverseSquare,
+        Self::ExponentialDecay,
+    ];
+
+    fn of(rolloff: Rolloff) -> Self {
+        match rolloff {
+            Rolloff::Flat => Self::Flat,
+            Rolloff::Inv => Self::Inverse,
+            Rolloff::InvSq => Self::InverseSquare,
+            Rolloff::Exp(_) => Self::ExponentialDecay,
+        }
+    }
+}
+
+macro_rules! debug_display {
+    ($($ty:ty),*) => {
+        $(
+            impl fmt::Display for $ty {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    fmt::Debug::fmt(self, f)
+                }
+            }
+        )*
+    };
+}
+
+debug_display!(PitchChoice, OverlapChoice, RolloffChoice);
+
+/// Changes made in the panel to the map settings of the loaded config
+#[derive(Debug, Clone, Default)]
+pub struct Edits {
+    width: Option<u32>,
+    height: Option<u32>,
+    base_frequency: Option<Frequency>,
+    pitch_curve: Option<PitchCurve>,
+    overlap_curve: Option<OverlapCurve>,
+    rolloff: Option<Rolloff>,
+    timbre: Option<Timbre>,
+}
+
+impl Edits {
+    /// Replace the config's timbre as well, e.g. with one from the timbre
+    /// editor
+    pub fn with_timbre(self, timbre: Option<Timbre>) -> Self { Self { timbre, ..self } }
+
+    /// Whether the config's timbre is replaced
+    pub fn replaces_timbre(&self) -> bool { self.timbre.is_some() }
+
+    /// Apply the changes over the settings read from the config
+    pub fn apply(&self, map: &mut MapConfig) {
+        if let Some(w) = self.width {
+            map.width = w;
+            map.x_step = None;
+        }
+
+        if let Some(h) = self.height {
+            map.height = h;
+            map.y_step = None;
+        }
+
+        if let Some(f) = self.base_frequency {
+            map.base_frequency = f;
+        }
+
+        if let Some(p) = self.pitch_curve {
+            map.pitch_curve = p;
+        }
+
+        if let Some(ref o) = self.overlap_curve {
+            map.overlap_curve = o.clone();
+        }
+
+        if let Some(ref t) = self.timbre {
+            map.timbre = t.clone();
+        }
+
+        if let (Some(r), Timbre::Harmonic { rolloff, .. }) = (self.rolloff, &mut map.timbre) {
+            *rolloff = r;
+        }
+    }
+}
+
+fn parse_frequency(s: &str) -> Option<Frequency> {
+    let s = s.trim();
+
+    s.parse()
+        .ok()
+        .filter(|hz: &f64| *hz > 0.0)
+        .map(Frequency::Hz)
+        .or_else(|| s.parse().ok().map(Frequency::Note))
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Width(String),
+    Height(String),
+    BaseFrequency(String),
+    PitchCurve(PitchChoice),
+    OverlapCurve(OverlapChoice),
+    Rolloff(RolloffChoice),
+    Decay(String),
+}
+
+/// The values entered in the panel, along with the state of its widgets
+#[derive(Debug, Default)]
+pub struct Panel {
+    width: String,
+    height: String,
+    base_frequency: String,
+    pitch_curve: Option<PitchChoice>,
+    overlap_curve: Option<OverlapChoice>,
+    /// The rolloff of a harmonic timbre, or `None` for any other timbre
+    rolloff: Option<RolloffChoice>,
+    decay: String,
+    width_input: text_input::State,
+    height_input: text_input::State,
+    base_frequency_input: text_input::State,
+    pitch_curve_list: pick_list::State<PitchChoice>,
+    overlap_curve_list: pick_list::State<OverlapChoice>,
+    rolloff_list: pick_list::State<RolloffChoice>,
+    decay_input: text_input::State,
+}
+
+impl Panel {
+    /// Fill in the panel with the settings of a freshly loaded config
+    pub fn new(map: &MapConfig) -> Self {
+        let (rolloff, decay) = match map.timbre {
+            Timbre::Harmonic { rolloff, .. } => (
+                Some(RolloffChoice::of(rolloff)),
+                match rolloff {
+                    Rolloff::Exp(k) => k,
+                    _ => 1.0,
+                },
+            ),
+            _ => (None, 1.0),
+        };
+
+        Self {
+            width: map.width.to_string(),
+            height: map.height.to_string(),
+            base_frequency: match map.base_frequency {
+                Frequency::Hz(hz) => hz.to_string(),
+                Frequency::Note(n) => n.to_string(),
+            },
+            pitch_curve: Some(PitchChoice::of(map.pitch_curve)),
+            overlap_curve: OverlapChoice::of(&map.overlap_curve),
+            rolloff,
+            decay: decay.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn update(&mut self, msg: Message) {
+        match msg {
+            Message::Width(s) => self.width = s,
+            Message::Height(s) => self.height = s,
+            Message::BaseFrequency(s) => self.base_frequency = s,
+            Message::PitchCurve(c) => self.pitch_curve = Some(c),
+            Message::OverlapCurve(c) => self.overlap_curve = Some(c),
+            Message::Rolloff(r) => self.rolloff = Some(r),
+            Message::Decay(s) => self.decay = s,
+        }
+    }
+
+    fn size(s: &str) -> Option<u32> { s.trim().parse().ok().filter(|n| *n > 0) }
+
+    fn decay(&self) -> Option<f64> {
+        self.decay.trim().parse().ok().filter(|k: &f64| *k >= 0.0)
+    }
+
+    /// The settings entered, leaving out any that can't be parsed
+    pub fn edits(&self) -> Edits {
+        Edits {
+            width: Self::size(&self.width),
+            height: Self::size(&self.height),
+            base_frequency: parse_frequency(&self.base_frequency),
+            pitch_curve: self.pitch_curve.map(PitchChoice::curve),
+            overlap_curve: self.overlap_curve.map(OverlapChoice::curve),
+            rolloff: self.rolloff.and_then(|r| {
+                Some(match r {
+                    RolloffChoice::Flat => Rolloff::Flat,
+                    RolloffChoice::Inverse => Rolloff::Inv,
+                    RolloffChoice::InverseSquare => Rolloff::InvSq,
+                    RolloffChoice::ExponentialDecay => Rolloff::Exp(self.decay()?),
+                })
+            }),
+            timbre: None,
+        }
+    }
+
+    pub fn view(&mut self) -> Element<'_, Message> {
+        let invalid = |ok: bool| {
+            if ok {
+                Text::new("")
+            } else {
+                Text::new("invalid").size(14).color(INVALID_COLOR)
+            }
+        };
+        let label = |s: &str| Text::new(s).size(16);
+
+        let size_ok = Self::size(&self.width).is_some() && Self::size(&self.height).is_some();
+        let base_ok = parse_frequency(&self.base_frequency).is_some();
+        let decay_ok = self.decay().is_some();
+
+        let mut col = Column::new()
+            .width(Length::Units(PANEL_WIDTH))
+            .spacing(5)
+            .push(label("Size"))
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(
+                        TextInput::new(&mut self.width_input, "Width", &self.width, Message::Width)
+                            .padding(5),
+                    )
+                    .push(Text::new("x"))
+                    .push(
+                        TextInput::new(
+                            &mut self.height_input,
+                            "Height",
+                            &self.height,
+                            Message::Height,
+                        )
+                        .padding(5),
+                    ),
+            )
+            .push(invalid(size_ok))
+            .push(label("Base frequency"))
+            .push(
+                TextInput::new(
+                    &mut self.base_frequency_input,
+                    "Hz or note, e.g. C4",
+                    &self.base_frequency,
+                    Message::BaseFrequency,
+                )
+                .padding(5),
+            )
+            .push(invalid(base_ok))
+            .push(label("Pitch curve"))
+            .push(PickList::new(
+                &mut self.pitch_curve_list,
+                &PitchChoice::ALL[..],
+                self.pitch_curve,
+                Message::PitchCurve,
+            ))
+            .push(label("Overlap curve"))
+            .push(PickList::new(
+                &mut self.overlap_curve_list,
+                &OverlapChoice::ALL[..],
+                self.overlap_curve,
+                Message::OverlapCurve,
+            ))
+            .push(label("Timbre rolloff"));
+
+        if let Some(rolloff) = self.rolloff {
+            col = col.push(PickList::new(
+                &mut self.rolloff_list,
+                &RolloffChoice::ALL[..],
+                Some(rolloff),
+                Message::Rolloff,
+            ));
+
+            if rolloff == RolloffChoice::ExponentialDecay {
+                col = col
+                    .push(
+                        TextInput::new(
+                            &mut self.decay_input,
+                            "Decay per partial",
+                            &self.decay,
+                            Message::Decay,
+                        )
+                        .padding(5),
+                    )
+                    .push(invalid(decay_ok));
+            }
+        } else {
+            col = col.push(Text::new("Only harmonic timbres have a rolloff").size(14));
+        }
+
+        col.into()
+    }
+}
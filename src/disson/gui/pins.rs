@@ -0,0 +1,120 @@
+//! Probes pinned to points of a map, which keep reading out the map as it's
+//! rendered again
+
+use iced::{button, text_input, Align, Button, Column, Element, Length, Row, Text, TextInput};
+use nalgebra::Point2;
+
+use super::{readout::Probe, render::Rendered, PANEL_WIDTH};
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    /// Pin a point, in octaves above the base frequency
+    Add(Point2<f64>),
+    Note(usize, String),
+    Remove(usize),
+    Clear,
+}
+
+/// One pinned point, along with the state of its widgets
+#[derive(Debug)]
+struct PinRow {
+    octaves: Point2<f64>,
+    note: String,
+    note_input: text_input::State,
+    remove_button: button::State,
+}
+
+#[derive(Debug, Default)]
+pub struct Panel {
+    rows: Vec<PinRow>,
+    clear_button: button::State,
+}
+
+impl Panel {
+    /// The label of each pin, which is its note, or its number if it has none,
+    /// along with the point it's pinned to
+    pub fn labels(&self) -> impl Iterator<Item = (String, Point2<f64>)> + '_ {
+        self.rows.iter().enumerate().map(|(i, r)| {
+            let label = match r.note.trim() {
+                "" => format!("#{}", i + 1),
+                n => n.into(),
+            };
+
+            (label, r.octaves)
+        })
+    }
+
+    pub fn update(&mut self, msg: Message) {
+        match msg {
+            Message::Add(octaves) => self.rows.push(PinRow {
+                octaves,
+                note: String::new(),
+                note_input: text_input::State::new(),
+                remove_button: button::State::new(),
+            }),
+            Message::Note(i, note) => self.rows[i].note = note,
+            Message::Remove(i) => {
+                self.rows.remove(i);
+            },
+            Message::Clear => self.rows.clear(),
+        }
+    }
+
+    pub fn view(&mut self, rendered: &Rendered) -> Element<'_, Message> {
+        let empty = self.rows.is_empty();
+        let mut col = Column::new()
+            .width(Length::Units(PANEL_WIDTH))
+            .spacing(5)
+            .push(Text::new("Pinned probes").size(16));
+
+        if empty {
+            col = col.push(
+                Text::new("Middle-click the map or press P to pin the point under the cursor")
+                    .size(14),
+            );
+        }
+
+        for (i, row) in self.rows.iter_mut().enumerate() {
+            let probe = Probe::at(rendered, row.octaves);
+            let value = match probe.value {
+                Some(v) => format!("{v:.6}"),
+                None => "not rendered".into(),
+            };
+
+            col = col
+                .push(
+                    Row::new()
+                        .spacing(5)
+                        .align_items(Align::Center)
+                        .push(Text::new(format!("#{}", i + 1)).size(14))
+                        .push(
+                            TextInput::new(&mut row.note_input, "Note", &row.note, move |s| {
+                                Message::Note(i, s)
+                            })
+                            .padding(3),
+                        )
+                        .push(
+                            Button::new(&mut row.remove_button, Text::new("x").size(14))
+                                .on_press(Message::Remove(i)),
+                        ),
+                )
+                .push(
+                    Text::new(format!(
+                        "X {:+.1}¢, Y {:+.1}¢: {}",
+                        probe.octaves.x * 1200.0,
+                        probe.octaves.y * 1200.0,
+                        value
+                    ))
+                    .size(12),
+                );
+        }
+
+        let mut clear = Button::new(&mut self.clear_button, Text::new("Clear pins"));
+
+        if !empty {
+            clear = clear.on_press(Message::Clear);
+        }
+
+        col.push(clear).into()
+    }
+}
@@ -0,0 +1,305 @@
+//! Renders queued from the tabs, which run one after another in the
+//! background
+
+use std::{fmt::Write as _, sync::Arc};
+
+use iced::{
+    button, scrollable, Align, Button, Column, Command, Element, Length, ProgressBar, Row,
+    Scrollable, Text,
+};
+use nalgebra::Vector2;
+
+use super::{
+    params::Edits,
+    render::{self, GuiCache, Outcome, Progress, Rendered},
+    viewer::View,
+    PROGRESS_INTERVAL,
+};
+use crate::cancel::CancelToken;
+
+/// Everything needed to render the map of a tab as it is when queued
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub title: String,
+    pub config: String,
+    pub preset: Option<String>,
+    pub view: Option<View>,
+    pub size: Option<Vector2<u32>>,
+    pub edits: Edits,
+}
+
+impl Request {
+    /// A short description of what the request renders
+    fn describe(&self) -> String {
+        let mut s = self.title.clone();
+
+        if let Some(size) = self.size {
+            write!(s, ", {}x{}", size.x, size.y).unwrap();
+        }
+
+        if let Some(View { x, y }) = self.view {
+            write!(
+                s,
+                ", x {:+.0}..{:+.0}¢, y {:+.0}..{:+.0}¢",
+                x.0 * 1200.0,
+                x.1 * 1200.0,
+                y.0 * 1200.0,
+                y.1 * 1200.0
+            )
+            .unwrap();
+        }
+
+        s
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    Queued,
+    Running(Arc<CancelToken>),
+    Done(Arc<Rendered>),
+    Cancelled,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Finished(u64, Outcome),
+    Tick(u64),
+    /// Cancel a job if it's running, or remove it from the queue otherwise
+    Cancel(u64),
+    /// Open the map of a finished job in a new tab, which is up to the GUI
+    Open(u64),
+    /// Remove every job that's no longer queued or running
+    Clear,
+}
+
+/// One render in the queue, along with the state of its buttons
+#[derive(Debug)]
+struct Job {
+    id: u64,
+    request: Request,
+    state: State,
+    open_button: button::State,
+    cancel_button: button::State,
+}
+
+impl Job {
+    fn view(&mut self, progress: &Progress) -> Element<'_, Message> {
+        let id = self.id;
+        let mut row = Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(
+                Text::new(self.request.describe())
+                    .size(14)
+                    .width(Length::Fill),
+            );
+
+        let status = match self.state {
+            State::Queued => "Queued".into(),
+            State::Running(_) => {
+                row = row.push(
+                    ProgressBar::new(0.0..=1.0, progress.fraction().unwrap_or(0.0))
+                        .width(Length::Units(160)),
+                );
+
+                "Rendering".into()
+            },
+            State::Done(ref r) => format!("Done, {}x{}", r.map.size.x, r.map.size.y),
+            State::Cancelled => "Cancelled".into(),
+            State::Failed(ref e) => format!("Failed: {e}"),
+        };
+
+        row = row.push(Text::new(status).size(14));
+
+        if let State::Done(_) = self.state {
+            row = row.push(
+                Button::new(&mut self.open_button, Text::new("Open").size(14))
+                    .on_press(Message::Open(id)),
+            );
+        }
+
+        if let State::Queued | State::Running(_) = self.state {
+            row = row.push(
+                Button::new(&mut self.cancel_button, Text::new("Cancel").size(14))
+                    .on_press(Message::Cancel(id)),
+            );
+        }
+
+        row.into()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Queue {
+    jobs: Vec<Job>,
+    next_id: u64,
+    /// What happened to the latest job to finish
+    notice: Option<String>,
+    clear_button: button::State,
+    scroll: scrollable::State,
+}
+
+impl Queue {
+    /// Add a render to the end of the queue, starting it if nothing else is
+    /// running
+    pub fn push(&mut self, cache: &GuiCache, request: Request) -> Command<Message> {
+        self.jobs.push(Job {
+            id: self.next_id,
+            request,
+            state: State::Queued,
+            open_button: button::State::new(),
+            cancel_button: button::State::new(),
+        });
+        self.next_id += 1;
+
+        self.start(cache)
+    }
+
+    /// The number of jobs queued or running
+    pub fn pending(&self) -> usize {
+        self.jobs
+            .iter()
+            .filter(|j| matches!(j.state, State::Queued | State::Running(_)))
+            .count()
+    }
+
+    /// What happened to the latest job to finish, until finished jobs are
+    /// cleared
+    pub fn notice(&self) -> Option<&str> { self.notice.as_deref() }
+
+    /// The request and map of a finished job
+    pub fn result(&self, id: u64) -> Option<(Request, Arc<Rendered>)> {
+        self.jobs.iter().find(|j| j.id == id).and_then(|j| match j.state {
+            State::Done(ref r) => Some((j.request.clone(), r.clone())),
+            _ => None,
+        })
+    }
+
+    /// Start the next queued job, unless one is already running
+    fn start(&mut self, cache: &GuiCache) -> Command<Message> {
+        if self.jobs.iter().any(|j| matches!(j.state, State::Running(_))) {
+            return Command::none();
+        }
+
+        let Some(job) = self.jobs.iter_mut().find(|j| matches!(j.state, State::Queued)) else {
+            return Command::none();
+        };
+
+        let Request {
+            ref config,
+            ref preset,
+            view,
+            size,
+            ref edits,
+            ..
+        } = job.request;
+        let cancel = Arc::new(CancelToken::new());
+        let id = job.id;
+
+        job.state = State::Running(cancel.clone());
+
+        Command::batch(vec![
+            Command::perform(
+                render::render(
+                    cache.clone(),
+                    render::config_opts(config, preset.clone(), size),
+                    view,
+                    edits.clone(),
+                    cancel,
+                ),
+                move |o| Message::Finished(id, o),
+            ),
+            Self::tick(id),
+        ])
+    }
+
+    /// Redraw the progress of a running job after a short wait
+    fn tick(id: u64) -> Command<Message> {
+        Command::perform(render::delay(PROGRESS_INTERVAL), move |()| Message::Tick(id))
+    }
+
+    pub fn update(&mut self, cache: &GuiCache, msg: Message) -> Command<Message> {
+        match msg {
+            Message::Finished(id, outcome) => {
+                if let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) {
+                    let title = job.request.title.clone();
+
+                    let (state, notice) = match outcome {
+                        Outcome::Done(r) => (State::Done(r), format!("Finished {title}")),
+                        Outcome::Cancelled => (State::Cancelled, format!("Cancelled {title}")),
+                        Outcome::Failed(e) => (
+                            State::Failed(format!("{e:#}")),
+                            format!("Failed to render {title}"),
+                        ),
+                    };
+
+                    job.state = state;
+                    self.notice = Some(notice);
+                }
+
+                return self.start(cache);
+            },
+            // Ticking only continues while the job is running
+            Message::Tick(id)
+                if self
+                    .jobs
+                    .iter()
+                    .any(|j| j.id == id && matches!(j.state, State::Running(_))) =>
+            {
+                return Self::tick(id)
+            },
+            Message::Tick(_) | Message::Open(_) => (),
+            Message::Cancel(id) => {
+                if let Some(i) = self.jobs.iter().position(|j| j.id == id) {
+                    match self.jobs[i].state {
+                        State::Running(ref cancel) => cancel.set(),
+                        State::Queued => {
+                            self.jobs.remove(i);
+                        },
+                        _ => (),
+                    }
+                }
+            },
+            Message::Clear => {
+                self.jobs
+                    .retain(|j| matches!(j.state, State::Queued | State::Running(_)));
+                self.notice = None;
+            },
+        }
+
+        Command::none()
+    }
+
+    pub fn view(&mut self, progress: &Progress) -> Element<'_, Message> {
+        let header = if self.jobs.is_empty() {
+            "Queue renders from the toolbar of a tab to run them here one at a time"
+        } else {
+            "Jobs run one at a time, in the order they were queued"
+        };
+
+        let list = self.jobs.iter_mut().fold(
+            Scrollable::new(&mut self.scroll)
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .spacing(5),
+            |s, j| s.push(j.view(progress)),
+        );
+
+        Column::new()
+            .spacing(10)
+            .push(
+                Row::new()
+                    .spacing(10)
+                    .align_items(Align::Center)
+                    .push(
+                        Button::new(&mut self.clear_button, Text::new("Clear finished"))
+                            .on_press(Message::Clear),
+                    )
+                    .push(Text::new(header).size(16)),
+            )
+            .push(list)
+            .into()
+    }
+}
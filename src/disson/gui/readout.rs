@@ -0,0 +1,119 @@
+//! Readouts of the intervals and dissonance at a point of a map
+
+use std::fmt;
+
+use nalgebra::Point2;
+use serde::Serialize;
+
+use super::render::Rendered;
+
+/// The largest denominator tried when approximating an interval as a ratio
+const MAX_DENOMINATOR: u32 = 32;
+/// How far, in cents, a ratio may be from an interval to approximate it
+const RATIO_TOLERANCE: f64 = 10.0;
+
+/// A ratio approximating an interval, along with its error in cents
+#[derive(Debug, Clone, Copy)]
+pub struct Ratio {
+    pub num: u32,
+    pub den: u32,
+    pub error: f64,
+}
+
+impl Ratio {
+    /// Find the ratio with the smallest denominator within tolerance of an
+    /// interval, or the closest ratio if none is
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn nearest(octaves: f64) -> Self {
+        let cents = octaves * 1200.0;
+        let mut best: Option<Self> = None;
+
+        for den in 1..=MAX_DENOMINATOR {
+            let num = (octaves.exp2() * f64::from(den)).round().max(1.0) as u32;
+            let error = (f64::from(num) / f64::from(den)).log2() * 1200.0 - cents;
+            let ratio = Self { num, den, error };
+
+            if error.abs() <= RATIO_TOLERANCE {
+                return ratio;
+            }
+
+            if best.is_none_or(|b| error.abs() < b.error.abs()) {
+                best = Some(ratio);
+            }
+        }
+
+        best.unwrap()
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "~{}/{} ({:+.1}¢)", self.num, self.den, self.error)
+    }
+}
+
+/// The tones and dissonance at one point of a map
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Probe {
+    /// The point, in octaves above the base frequency
+    pub octaves: Point2<f64>,
+    /// Frequencies of the X and Y tones, in Hz
+    pub hz: Point2<f64>,
+    /// The value of the nearest pixel, if the point lies on the map and the
+    /// pixel was rendered
+    pub value: Option<f64>,
+}
+
+impl Probe {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn at(rendered: &Rendered, octaves: Point2<f64>) -> Self {
+        let map = &rendered.map;
+        let base_hz = rendered.base_hz;
+
+        let value = map.pixel(octaves).and_then(|px| {
+            let px = px.map(f64::round);
+            let size = map.size.cast::<f64>();
+
+            if px.x < 0.0 || px.y < 0.0 || px.x >= size.x || px.y >= size.y {
+                return None;
+            }
+
+            Some(map.data[px.y as usize * map.size.x as usize + px.x as usize])
+                .filter(|v| !v.is_nan())
+        });
+
+        Self {
+            octaves,
+            hz: octaves.map(|o| base_hz * o.exp2()),
+            value,
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tone = |f: &mut fmt::Formatter, name, hz, oct: f64| {
+            write!(
+                f,
+                "{} {:.2} Hz ({:+.1}¢, {})",
+                name,
+                hz,
+                oct * 1200.0,
+                Ratio::nearest(oct)
+            )
+        };
+
+        tone(f, "X", self.hz.x, self.octaves.x)?;
+        f.write_str(", ")?;
+        tone(f, "Y", self.hz.y, self.octaves.y)?;
+
+        let between = self.octaves.y - self.octaves.x;
+
+        write!(f, ", Y/X {:+.1}¢ ({})", between * 1200.0, Ratio::nearest(between))?;
+
+        match self.value {
+            Some(v) => write!(f, ", dissonance {v:.6}"),
+            None => f.write_str(", not rendered"),
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Computing maps in the background for the GUI
+
+use std::{
+    fmt, iter,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use futures::{channel::oneshot, prelude::*};
+use log::trace;
+use nalgebra::Vector2;
+
+use super::{
+    colormap,
+    minima::{self, Minimum},
+    params::Edits,
+    viewer::View,
+};
+use crate::{
+    cache::{self, file::FileCache, AccessCache, DynamicCache, KeyInfo, Listing, MemoryCache},
+    cancel::{prelude::*, CancelError},
+    cli::{ConfigOpts, SizeOverride},
+    config::GenerateConfig,
+    disson::{
+        self,
+        map::DissonMap,
+        spectrum::{self, Spectrum},
+        stats::Stats,
+    },
+    error::prelude::*,
+    message,
+};
+
+/// How many bins the histogram of a map is counted in
+const HISTOGRAM_BINS: usize = 128;
+
+/// The cache shared by every render of the GUI
+pub type GuiCache = Arc<MemoryCache<AccessCache<DynamicCache>>>;
+
+/// A finished map, along with the config it was rendered from
+pub struct Rendered {
+    /// The config, unless the map was loaded straight from the cache
+    pub cfg: Option<GenerateConfig>,
+    pub map: DissonMap,
+    pub base_hz: f64,
+    /// The least and greatest values of the map
+    pub range: (f64, f64),
+    /// The deepest local minima of the map
+    pub minima: Vec<Minimum>,
+    /// Counts of values in equal-width bins across the range of the map
+    pub histogram: Vec<usize>,
+}
+
+impl Rendered {
+    /// Prepare a finished map for display
+    fn new(cfg: Option<GenerateConfig>, base_hz: f64, map: DissonMap) -> Self {
+        let range = colormap::range(&map);
+
+        trace!("Finding minima...");
+
+        let minima = minima::find(&map);
+        let histogram =
+            Stats::of(&map, &[], HISTOGRAM_BINS).map_or_else(Vec::new, |s| s.histogram);
+
+        Self {
+            cfg,
+            map,
+            base_hz,
+            range,
+            minima,
+            histogram,
+        }
+    }
+}
+
+impl fmt::Debug for Rendered {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Rendered")
+            .field("cfg", &self.cfg)
+            .field("size", &self.map.size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The progress of the latest render, in tiles
+#[derive(Debug, Default)]
+pub struct Progress {
+    done: AtomicU64,
+    total: AtomicU64,
+}
+
+impl Progress {
+    /// Track the progress of every map rendered from now on
+    pub fn track() -> Arc<Self> {
+        let progress = Arc::new(Self::default());
+        let tracked = progress.clone();
+
+        message::on_progress(move |done, total| {
+            tracked.done.store(done, Ordering::Relaxed);
+            tracked.total.store(total, Ordering::Relaxed);
+        });
+
+        progress
+    }
+
+    /// Forget the progress of the last render, before starting another
+    pub fn reset(&self) {
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(0, Ordering::Relaxed);
+    }
+
+    /// The fraction of tiles done, or `None` if rendering hasn't begun
+    #[allow(clippy::cast_precision_loss)]
+    pub fn fraction(&self) -> Option<f32> {
+        let total = self.total.load(Ordering::Relaxed);
+
+        if total == 0 {
+            return None;
+        }
+
+        Some(self.done.load(Ordering::Relaxed) as f32 / total as f32)
+    }
+}
+
+/// How a render ended
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Done(Arc<Rendered>),
+    Cancelled,
+    Failed(Arc<Error>),
+}
+
+fn run(
+    cache: &GuiCache,
+    opts: &ConfigOpts,
+    view: Option<View>,
+    edits: &Edits,
+    cancel: &CancelToken,
+) -> CancelResult<Rendered> {
+    trace!("Reading config...");
+
+    let mut cfg = GenerateConfig::read(opts).context("failed to get config")?;
+    let maps = iter::once(&mut cfg.map).chain(cfg.components.iter_mut().map(|(_, m)| m));
+
+    for map in maps {
+        edits.apply(map);
+
+        if let Some(view) = view {
+            map.set_view(view.x, view.y);
+        }
+    }
+
+    cfg.validate().context("invalid settings")?;
+
+    trace!("Computing map...");
+
+    let map = disson::compute_view(cache, &cfg, cancel)?;
+    let base_hz = cfg.map.base_frequency.hz(cfg.map.tuning_reference);
+
+    Ok(Rendered::new(Some(cfg), base_hz, map))
+}
+
+/// Borrow the files of the GUI's cache, if it's kept on disk
+fn local(cache: &GuiCache) -> Result<&FileCache> { cache::local_ref(&cache.inner().inner) }
+
+fn load_entry(cache: &GuiCache, hash: &str, cancel: &CancelToken) -> CancelResult<Rendered> {
+    let entry = cache::find(local(cache)?, hash)?;
+
+    let key = match entry.read_key().context("failed to read cache key")? {
+        KeyInfo::Map(k) => k,
+        KeyInfo::MapLayouts(_) => {
+            return Err(anyhow!("entry lists the layouts of a map, not the map itself").into())
+        },
+    };
+
+    let (map, base_hz) = disson::load_cached(cache, &key, cancel)?;
+
+    Ok(Rendered::new(None, base_hz, map))
+}
+
+/// Options for reading a config to render in the GUI, optionally at a size
+/// other than the config's
+pub fn config_opts(config: &str, preset: Option<String>, size: Option<Vector2<u32>>) -> ConfigOpts {
+    ConfigOpts {
+        config: config.into(),
+        preset,
+        size: size.map(|s| SizeOverride::Exact(s.x, s.y)),
+        tile_width: None,
+        tile_height: None,
+        threads: None,
+        precision: None,
+    }
+}
+
+/// Read a config and compute its map on a separate thread, so the GUI stays
+/// responsive.  The edits made in the GUI are applied over the config, and if
+/// a view is given, the map covers it instead of the ranges in the config.
+pub fn render(
+    cache: GuiCache,
+    opts: ConfigOpts,
+    view: Option<View>,
+    edits: Edits,
+    cancel: Arc<CancelToken>,
+) -> impl Future<Output = Outcome> {
+    finish(move || run(&cache, &opts, view, &edits, &cancel))
+}
+
+/// Load the map of the cache entry with the given hash on a separate thread,
+/// rendering whatever tiles of it are missing
+pub fn load(
+    cache: GuiCache,
+    hash: String,
+    cancel: Arc<CancelToken>,
+) -> impl Future<Output = Outcome> {
+    finish(move || load_entry(&cache, &hash, &cancel))
+}
+
+/// Produce a map on a separate thread, reporting how it ended
+fn finish(
+    f: impl FnOnce() -> CancelResult<Rendered> + Send + 'static,
+) -> impl Future<Output = Outcome> {
+    background(move || match f() {
+        Ok(r) => Outcome::Done(Arc::new(r)),
+        Err(Cancelled) => Outcome::Cancelled,
+        Err(CancelError::Failed(e)) => Outcome::Failed(Arc::new(e)),
+    })
+    .map(|o| o.unwrap_or_else(|| Outcome::Failed(Arc::new(anyhow!("render thread panicked")))))
+}
+
+/// List the entries of the cache on a separate thread
+pub fn list(cache: GuiCache) -> impl Future<Output = Result<Arc<Vec<Listing>>, Arc<Error>>> {
+    background(move || {
+        local(&cache)
+            .and_then(cache::list)
+            .context("failed to list cache entries")
+            .map(Arc::new)
+            .map_err(Arc::new)
+    })
+    .map(|r| r.unwrap_or_else(|| Err(Arc::new(anyhow!("listing thread panicked")))))
+}
+
+/// Delete the cache entry with the given hash on a separate thread
+pub fn delete(cache: GuiCache, hash: String) -> impl Future<Output = Result<(), Arc<Error>>> {
+    background(move || -> Result<()> {
+        cache.inner().check_writable()?;
+        cache::find(local(&cache)?, &hash)?.delete()?;
+
+        // Don't keep serving the deleted entry from memory
+        cache.forget();
+
+        Ok(())
+    })
+    .map(|r| {
+        r.unwrap_or_else(|| Err(anyhow!("deleting thread panicked")))
+            .map_err(Arc::new)
+    })
+}
+
+/// Find the spectrum of a WAV file on a separate thread
+pub fn analyze(path: PathBuf) -> impl Future<Output = Result<Arc<Spectrum>, Arc<Error>>> {
+    background(move || {
+        spectrum::analyze_spectrum(&path)
+            .with_context(|| format!("failed to analyze {}", path.display()))
+            .map(Arc::new)
+            .map_err(Arc::new)
+    })
+    .map(|r| r.unwrap_or_else(|| Err(Arc::new(anyhow!("analysis thread panicked")))))
+}
+
+/// Run a function on a separate thread, resolving to its result, or `None` if
+/// it panicked
+pub fn background<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> impl Future<Output = Option<T>> {
+    let (tx, rx) = oneshot::channel();
+
+    thread::spawn(move || {
+        tx.send(f()).ok();
+    });
+
+    rx.map(Result::ok)
+}
+
+/// Resolve after the given time has passed, without blocking the GUI
+pub fn delay(time: Duration) -> impl Future<Output = ()> {
+    background(move || thread::sleep(time)).map(|_| ())
+}
@@ -0,0 +1,100 @@
+//! A plot of the spectrum of an imported sound, marking the peaks picked from
+//! it as partials
+
+use std::sync::Arc;
+
+use iced::{
+    canvas::{Cursor, Frame, Geometry, Path, Program, Stroke},
+    Color, Point, Rectangle,
+};
+
+use crate::disson::spectrum::Spectrum;
+
+const SPECTRUM_COLOR: Color = Color {
+    r: 0.5,
+    g: 0.5,
+    b: 0.5,
+    a: 1.0,
+};
+const PEAK_COLOR: Color = Color {
+    r: 0.9,
+    g: 0.55,
+    b: 0.1,
+    a: 1.0,
+};
+/// How far past the highest peak the plot extends, as a ratio of its
+/// frequency
+const HEADROOM: f64 = 1.25;
+/// The quietest level plotted, in natural log units below the loudest bin
+const FLOOR: f64 = 12.0;
+
+#[derive(Debug)]
+pub struct Plot(pub Arc<Spectrum>);
+
+impl<Message> Program<Message> for Plot {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn draw(&self, bounds: Rectangle, _cursor: Cursor) -> Vec<Geometry> {
+        let Spectrum {
+            ref mags,
+            bin_hz,
+            fundamental,
+            ref wave,
+        } = *self.0;
+        let mut frame = Frame::new(bounds.size());
+
+        let top_hz = wave.iter().map(|p| p.pitch * fundamental).fold(0.0, f64::max) * HEADROOM;
+        let bins = ((top_hz / bin_hz).ceil() as usize).max(2).min(mags.len());
+        let loudest = mags[..bins].iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+        let x = |hz: f64| (hz / (bins as f64 * bin_hz)) as f32 * bounds.width;
+        let y = |mag: f64| ((loudest - mag) / FLOOR).clamp(0.0, 1.0) as f32 * bounds.height;
+
+        // Keep the loudest bin of each column, so narrow peaks aren't lost
+        let columns = bounds.width.max(1.0) as usize;
+        let curve = Path::new(|b| {
+            for c in 0..columns {
+                let lo = c * bins / columns;
+                let hi = ((c + 1) * bins / columns).max(lo + 1).min(bins);
+                let mag = mags[lo..hi].iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                let p = Point::new(c as f32, y(mag));
+
+                if c == 0 {
+                    b.move_to(p);
+                } else {
+                    b.line_to(p);
+                }
+            }
+        });
+
+        for p in wave.iter() {
+            let px = x(p.pitch * fundamental);
+
+            frame.stroke(
+                &Path::line(
+                    Point::new(px, bounds.height),
+                    Point::new(px, bounds.height * (1.0 - p.amp as f32)),
+                ),
+                Stroke {
+                    color: PEAK_COLOR,
+                    width: 2.0,
+                    ..Stroke::default()
+                },
+            );
+        }
+
+        frame.stroke(
+            &curve,
+            Stroke {
+                color: SPECTRUM_COLOR,
+                width: 1.0,
+                ..Stroke::default()
+            },
+        );
+
+        vec![frame.into_geometry()]
+    }
+}
@@ -0,0 +1,298 @@
+//! An editor for the partials of the timbre, which replaces the timbre of the
+//! loaded config once it's used
+
+use std::{fmt, sync::Arc};
+
+use iced::{
+    button, pick_list, slider, text_input, Align, Button, Canvas, Column, Element, Length,
+    PickList, Row, Slider, Text, TextInput,
+};
+
+use super::{params::INVALID_COLOR, spectrum::Plot, PANEL_WIDTH};
+use crate::{
+    config::MapConfig,
+    disson::{
+        spectrum::Spectrum,
+        timbre::{Rolloff, Shape, Timbre},
+        wave::Partial,
+    },
+};
+
+/// The most partials the editor holds
+const MAX_PARTIALS: usize = 64;
+/// Height of the plot of an imported spectrum
+const PLOT_HEIGHT: u16 = 80;
+
+/// Amplitudes that can be applied to every partial at once, by harmonic number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    Flat,
+    Inverse,
+    InverseSquare,
+    Square,
+    Triangle,
+    Clarinet,
+}
+
+impl Preset {
+    const ALL: [Self; 6] = [
+        Self::Flat,
+        Self::Inverse,
+        Self::InverseSquare,
+        Self::Square,
+        Self::Triangle,
+        Self::Clarinet,
+    ];
+
+    /// Amplitude of the nth harmonic (1-indexed) relative to the fundamental
+    fn amp(self, n: u32) -> f64 {
+        match self {
+            Self::Flat => Rolloff::Flat.amp(n),
+            Self::Inverse => Rolloff::Inv.amp(n),
+            Self::InverseSquare => Rolloff::InvSq.amp(n),
+            Self::Square => Shape::Square.amp(n),
+            Self::Triangle => Shape::Triangle.amp(n),
+            Self::Clarinet => Shape::Clarinet.amp(n),
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt::Debug::fmt(self, f) }
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Pitch(usize, String),
+    Amp(usize, f32),
+    Remove(usize),
+    Add,
+    Preset(Preset),
+    Reset,
+}
+
+/// One partial in the editor, along with the state of its widgets
+#[derive(Debug)]
+struct PartialRow {
+    pitch: String,
+    amp: f32,
+    pitch_input: text_input::State,
+    amp_slider: slider::State,
+    remove_button: button::State,
+}
+
+impl PartialRow {
+    fn new(p: Partial) -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        let amp = p.amp.clamp(0.0, 1.0) as f32;
+
+        Self {
+            pitch: p.pitch.to_string(),
+            amp,
+            pitch_input: text_input::State::new(),
+            amp_slider: slider::State::new(),
+            remove_button: button::State::new(),
+        }
+    }
+
+    fn pitch(&self) -> Option<f64> {
+        self.pitch.trim().parse().ok().filter(|p: &f64| p.is_finite() && *p > 0.0)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Editor {
+    /// The partials of the config's timbre, restored when the editor is reset
+    seed: Vec<Partial>,
+    rows: Vec<PartialRow>,
+    /// Whether the partials were changed, and so replace the config's timbre
+    edited: bool,
+    /// The file the config's timbre is analyzed from, if any, which the
+    /// editor can't show
+    file: Option<String>,
+    /// The spectrum of the last sound imported, if the partials came from one
+    spectrum: Option<Arc<Spectrum>>,
+    preset: Option<Preset>,
+    add_button: button::State,
+    reset_button: button::State,
+    preset_list: pick_list::State<Preset>,
+}
+
+impl Editor {
+    /// Fill in the editor with the timbre of a freshly loaded config
+    pub fn new(map: &MapConfig) -> Self {
+        let (seed, file): (Vec<_>, _) = match map.timbre {
+            Timbre::FromFile(ref f) => (vec![], Some(f.to_string_lossy().into_owned())),
+            ref t => (
+                t.wave()
+                    .map(|w| w.iter().take(MAX_PARTIALS).copied().collect())
+                    .unwrap_or_default(),
+                None,
+            ),
+        };
+
+        Self {
+            rows: seed.iter().copied().map(PartialRow::new).collect(),
+            seed,
+            file,
+            ..Self::default()
+        }
+    }
+
+    /// Keep replacing the config's timbre with the partials, e.g. when they
+    /// were filled in from a config that already had them applied
+    pub fn keep(&mut self) { self.edited = true; }
+
+    /// Replace the partials with the peaks of a sound's spectrum
+    pub fn import(&mut self, spectrum: Arc<Spectrum>) {
+        self.rows = spectrum
+            .wave
+            .iter()
+            .take(MAX_PARTIALS)
+            .copied()
+            .map(PartialRow::new)
+            .collect();
+        self.spectrum = Some(spectrum);
+        self.preset = None;
+        self.edited = true;
+    }
+
+    pub fn update(&mut self, msg: Message) {
+        // Only imported partials match the plot of the spectrum
+        if !matches!(msg, Message::Amp(..)) {
+            self.spectrum = None;
+        }
+
+        match msg {
+            Message::Pitch(i, s) => self.rows[i].pitch = s,
+            Message::Amp(i, a) => self.rows[i].amp = a,
+            Message::Remove(i) => {
+                self.rows.remove(i);
+            },
+            Message::Add => {
+                // Continue the harmonic series past the highest partial
+                let top = self.rows.iter().filter_map(PartialRow::pitch).fold(0.0, f64::max);
+
+                self.rows.push(PartialRow::new(Partial {
+                    pitch: top.floor() + 1.0,
+                    amp: 0.5,
+                }));
+            },
+            Message::Preset(p) => {
+                self.preset = Some(p);
+
+                for (n, row) in (1..).zip(&mut self.rows) {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let amp = p.amp(n) as f32;
+
+                    row.amp = amp;
+                }
+            },
+            Message::Reset => {
+                self.rows = self.seed.iter().copied().map(PartialRow::new).collect();
+                self.edited = false;
+                self.preset = None;
+                return;
+            },
+        }
+
+        self.edited = true;
+    }
+
+    /// The timbre in the editor, if it was edited and every partial is valid
+    pub fn timbre(&self) -> Option<Timbre> {
+        if !self.edited || self.rows.is_empty() {
+            return None;
+        }
+
+        self.rows
+            .iter()
+            .map(|r| {
+                Some(Partial {
+                    pitch: r.pitch()?,
+                    amp: f64::from(r.amp),
+                })
+            })
+            .collect::<Option<_>>()
+            .map(Timbre::Partials)
+    }
+
+    pub fn view(&mut self) -> Element<'_, Message> {
+        let label = |s: String| Text::new(s).size(16);
+
+        let mut col = Column::new()
+            .width(Length::Units(PANEL_WIDTH))
+            .spacing(5)
+            .push(label("Timbre".into()));
+
+        if let (Some(ref f), false) = (&self.file, self.edited) {
+            col = col
+                .push(Text::new(format!("Analyzed from {f}; add partials to replace it")).size(14));
+        }
+
+        col = match self.spectrum {
+            Some(ref s) => col.push(
+                Canvas::new(Plot(s.clone()))
+                    .width(Length::Units(PANEL_WIDTH))
+                    .height(Length::Units(PLOT_HEIGHT)),
+            ),
+            None => col.push(Text::new("Drop a WAV file to import its partials").size(14)),
+        };
+
+        if self.rows.iter().any(|r| r.pitch().is_none()) {
+            col = col.push(
+                Text::new("Pitches must be positive ratios")
+                    .size(14)
+                    .color(INVALID_COLOR),
+            );
+        }
+
+        let full = self.rows.len() >= MAX_PARTIALS;
+
+        for (i, row) in self.rows.iter_mut().enumerate() {
+            col = col.push(
+                Row::new()
+                    .spacing(5)
+                    .align_items(Align::Center)
+                    .push(
+                        TextInput::new(&mut row.pitch_input, "Ratio", &row.pitch, move |s| {
+                            Message::Pitch(i, s)
+                        })
+                        .width(Length::Units(60))
+                        .padding(3),
+                    )
+                    .push(
+                        Slider::new(&mut row.amp_slider, 0.0..=1.0, row.amp, move |a| {
+                            Message::Amp(i, a)
+                        })
+                        .step(0.01),
+                    )
+                    .push(
+                        Button::new(&mut row.remove_button, Text::new("x").size(14))
+                            .on_press(Message::Remove(i)),
+                    ),
+            );
+        }
+
+        let mut add = Button::new(&mut self.add_button, Text::new("Add partial"));
+
+        if !full {
+            add = add.on_press(Message::Add);
+        }
+
+        let mut reset = Button::new(&mut self.reset_button, Text::new("Use config"));
+
+        if self.edited {
+            reset = reset.on_press(Message::Reset);
+        }
+
+        col.push(Row::new().spacing(5).push(add).push(reset))
+            .push(PickList::new(
+                &mut self.preset_list,
+                &Preset::ALL[..],
+                self.preset,
+                Message::Preset,
+            ))
+            .into()
+    }
+}
@@ -0,0 +1,510 @@
+//! A pannable, zoomable display of rendered maps
+
+use std::{any::TypeId, fmt, hash::Hash};
+
+use iced_graphics::{Backend, Defaults, Primitive, Renderer};
+use iced_native::{
+    event, image, layout, mouse, Background, Clipboard, Color, Element, Event, Font, Hasher,
+    HorizontalAlignment, Layout, Length, Point, Rectangle, Size, Vector, VerticalAlignment, Widget,
+};
+use nalgebra::{Point2, Vector2};
+use serde::Serialize;
+
+use crate::disson::map::DissonMap;
+
+/// How much one line of scrolling zooms in or out
+const ZOOM_PER_LINE: f64 = 2.0;
+/// Scrolled pixels counted as one line, for touchpads
+const PIXELS_PER_LINE: f32 = 50.0;
+/// Diameter of the dots drawn at markers
+const MARKER_SIZE: f32 = 8.0;
+const MARKER_TEXT_SIZE: f32 = 14.0;
+/// The smallest region that can be selected, in screen pixels, so that a
+/// stray click doesn't select anything
+const MIN_SELECTION: f32 = 8.0;
+const SELECTION_COLOR: Color = Color {
+    r: 1.0,
+    g: 1.0,
+    b: 1.0,
+    a: 0.2,
+};
+
+/// The intervals covered by a map, in octaves above the base frequency
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct View {
+    pub x: (f64, f64),
+    pub y: (f64, f64),
+}
+
+impl View {
+    pub fn of(map: &DissonMap) -> Self {
+        let m = map.view.matrix();
+
+        Self {
+            x: (m[(0, 2)], m[(0, 2)] + m[(0, 0)]),
+            y: (m[(1, 2)], m[(1, 2)] + m[(1, 1)]),
+        }
+    }
+
+    /// Shift the view by a fraction of its width and height
+    pub fn pan(self, dx: f64, dy: f64) -> Self {
+        let shift = |(lo, hi): (f64, f64), d: f64| (lo + (hi - lo) * d, hi + (hi - lo) * d);
+
+        Self {
+            x: shift(self.x, dx),
+            y: shift(self.y, dy),
+        }
+    }
+
+    /// Scale the view by `factor` around a point, given as a fraction of its
+    /// width and height
+    pub fn zoom(self, at: (f64, f64), factor: f64) -> Self {
+        let scale = |(lo, hi): (f64, f64), t: f64| {
+            let c = lo + (hi - lo) * t;
+
+            (c + (lo - c) * factor, c + (hi - c) * factor)
+        };
+
+        Self {
+            x: scale(self.x, at.0),
+            y: scale(self.y, at.1),
+        }
+    }
+
+    /// Shift the view onto the pixel grid of a map covering `grid` with the
+    /// given size, so that the tiles they share can be reused
+    pub fn snap(self, grid: Self, size: Vector2<u32>) -> Self {
+        let snap = |(lo, hi): (f64, f64), (g0, g1): (f64, f64), n: u32| {
+            let step = (g1 - g0) / f64::from(n.saturating_sub(1).max(1));
+
+            if step == 0.0 {
+                return (lo, hi);
+            }
+
+            let d = g0 + ((lo - g0) / step).round() * step - lo;
+
+            (lo + d, hi + d)
+        };
+
+        Self {
+            x: snap(self.x, grid.x, size.x),
+            y: snap(self.y, grid.y, size.y),
+        }
+    }
+
+    /// Find the point at a fraction of the view's width and height
+    fn at(&self, (fx, fy): (f64, f64)) -> Point2<f64> {
+        Point2::new(
+            self.x.0 + (self.x.1 - self.x.0) * fx,
+            self.y.0 + (self.y.1 - self.y.0) * fy,
+        )
+    }
+
+    /// Find where a point lands within `frame`, the area of the screen showing
+    /// this view
+    #[allow(clippy::cast_possible_truncation)]
+    fn locate(&self, frame: Rectangle, p: Point2<f64>) -> Point {
+        let axis = |(v0, v1): (f64, f64), o: f64, start: f32, len: f32| {
+            start + ((o - v0) / (v1 - v0) * f64::from(len)) as f32
+        };
+
+        Point::new(
+            axis(self.x, p.x, frame.x, frame.width),
+            axis(self.y, p.y, frame.y, frame.height),
+        )
+    }
+
+    /// Find where a map covering `other` lands within `frame`, the area of
+    /// the screen showing this view
+    #[allow(clippy::cast_possible_truncation)]
+    fn project(&self, other: &Self, frame: Rectangle) -> Rectangle {
+        let axis = |(v0, v1): (f64, f64), (o0, o1): (f64, f64), start: f32, len: f32| {
+            #[allow(clippy::float_cmp)]
+            let flat = v1 == v0;
+
+            if flat {
+                (start, len)
+            } else {
+                let len = f64::from(len);
+
+                (
+                    start + ((o0 - v0) / (v1 - v0) * len) as f32,
+                    ((o1 - o0) / (v1 - v0) * len) as f32,
+                )
+            }
+        };
+
+        let (x, width) = axis(self.x, other.x, frame.x, frame.width);
+        let (y, height) = axis(self.y, other.y, frame.y, frame.height);
+
+        Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// The largest area of `bounds` with the aspect ratio of a map of the given
+/// size, centered within it
+#[allow(clippy::cast_precision_loss)]
+fn frame(bounds: Rectangle, size: Vector2<u32>) -> Rectangle {
+    let (w, h) = (size.x.max(1) as f32, size.y.max(1) as f32);
+    let scale = (bounds.width / w).min(bounds.height / h);
+    let (width, height) = (w * scale, h * scale);
+
+    Rectangle {
+        x: bounds.x + (bounds.width - width) / 2.0,
+        y: bounds.y + (bounds.height - height) / 2.0,
+        width,
+        height,
+    }
+}
+
+/// The rectangle with the given opposite corners
+fn corners(a: Point, b: Point) -> Rectangle {
+    Rectangle {
+        x: a.x.min(b.x),
+        y: a.y.min(b.y),
+        width: (a.x - b.x).abs(),
+        height: (a.y - b.y).abs(),
+    }
+}
+
+/// Find where a point lies within `frame`, as a fraction of its width and
+/// height
+fn fraction(frame: Rectangle, p: Point) -> (f64, f64) {
+    (
+        f64::from((p.x - frame.x) / frame.width),
+        f64::from((p.y - frame.y) / frame.height),
+    )
+}
+
+/// Interaction state of a [`Viewer`]
+#[derive(Debug, Default)]
+pub struct State {
+    /// The last cursor position of a drag in progress
+    drag: Option<Point>,
+    /// Whether the cursor was over the map when it last moved
+    hovering: bool,
+    /// Where a selection in progress was started, and where the cursor has
+    /// dragged it to
+    select: Option<(Point, Point)>,
+}
+
+/// See [`Viewer::on_hover`]
+type HoverHandler<'a, Message> = Box<dyn Fn(Option<Point2<f64>>) -> Message + 'a>;
+/// See [`Viewer::on_select`]
+type SelectHandler<'a, Message> = Box<dyn Fn(View, Vector2<u32>) -> Message + 'a>;
+
+/// Displays rendered maps within a view, which is panned by dragging and
+/// zoomed by scrolling.  Regions can be selected by dragging with the right
+/// mouse button, and points pinned by clicking with the middle one.
+pub struct Viewer<'a, Message> {
+    state: &'a mut State,
+    view: View,
+    /// Size of the map being viewed, in pixels
+    size: Vector2<u32>,
+    /// Rendered images and the views they cover, drawn in order
+    layers: Vec<(image::Handle, View)>,
+    /// Labeled points drawn over the images, in octaves above the base
+    /// frequency
+    markers: Vec<(Point2<f64>, String)>,
+    on_change: Box<dyn Fn(View) -> Message + 'a>,
+    on_hover: Option<HoverHandler<'a, Message>>,
+    on_select: Option<SelectHandler<'a, Message>>,
+    on_pin: Option<Box<dyn Fn(Point2<f64>) -> Message + 'a>>,
+}
+
+impl<Message> fmt::Debug for Viewer<'_, Message> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Viewer")
+            .field("state", &self.state)
+            .field("view", &self.view)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl<'a, Message> Viewer<'a, Message> {
+    pub fn new(
+        state: &'a mut State,
+        view: View,
+        size: Vector2<u32>,
+        on_change: impl Fn(View) -> Message + 'a,
+    ) -> Self {
+        Self {
+            state,
+            view,
+            size,
+            layers: vec![],
+            markers: vec![],
+            on_change: Box::new(on_change),
+            on_hover: None,
+            on_select: None,
+            on_pin: None,
+        }
+    }
+
+    /// Report the point under the cursor as it moves, in octaves above the
+    /// base frequency, or `None` once it leaves the map
+    pub fn on_hover(mut self, f: impl Fn(Option<Point2<f64>>) -> Message + 'a) -> Self {
+        self.on_hover = Some(Box::new(f));
+        self
+    }
+
+    /// Allow selecting a region of the view, reporting the view it covers and
+    /// the size in pixels that renders it as large as the viewer will fit
+    pub fn on_select(mut self, f: impl Fn(View, Vector2<u32>) -> Message + 'a) -> Self {
+        self.on_select = Some(Box::new(f));
+        self
+    }
+
+    /// Allow pinning the point under the cursor, in octaves above the base
+    /// frequency
+    pub fn on_pin(mut self, f: impl Fn(Point2<f64>) -> Message + 'a) -> Self {
+        self.on_pin = Some(Box::new(f));
+        self
+    }
+
+    /// Draw an image of the map covering the given view
+    pub fn layer(mut self, image: image::Handle, view: View) -> Self {
+        self.layers.push((image, view));
+        self
+    }
+
+    /// Mark a point over the map with a dot and a label
+    pub fn marker(mut self, at: Point2<f64>, label: String) -> Self {
+        self.markers.push((at, label));
+        self
+    }
+}
+
+impl<Message, B: Backend> Widget<Message, Renderer<B>> for Viewer<'_, Message> {
+    fn width(&self) -> Length { Length::Fill }
+
+    fn height(&self) -> Length { Length::Fill }
+
+    fn layout(&self, _renderer: &Renderer<B>, limits: &layout::Limits) -> layout::Node {
+        layout::Node::new(limits.width(Length::Fill).height(Length::Fill).resolve(Size::ZERO))
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn draw(
+        &self,
+        _renderer: &mut Renderer<B>,
+        _defaults: &Defaults,
+        layout: Layout<'_>,
+        cursor: Point,
+        _viewport: &Rectangle,
+    ) -> (Primitive, mouse::Interaction) {
+        let frame = frame(layout.bounds(), self.size);
+        let mut primitives: Vec<_> = self
+            .layers
+            .iter()
+            .map(|(handle, view)| Primitive::Image {
+                handle: handle.clone(),
+                bounds: self.view.project(view, frame),
+            })
+            .collect();
+
+        for (at, label) in &self.markers {
+            let p = self.view.locate(frame, *at);
+
+            if !frame.contains(p) {
+                continue;
+            }
+
+            primitives.push(Primitive::Quad {
+                bounds: Rectangle {
+                    x: p.x - MARKER_SIZE / 2.0,
+                    y: p.y - MARKER_SIZE / 2.0,
+                    width: MARKER_SIZE,
+                    height: MARKER_SIZE,
+                },
+                background: Background::Color(Color::WHITE),
+                border_radius: MARKER_SIZE / 2.0,
+                border_width: 1.0,
+                border_color: Color::BLACK,
+            });
+
+            // Shadowed so the label stays legible over any color
+            for (offset, color) in &[(1.0, Color::BLACK), (0.0, Color::WHITE)] {
+                primitives.push(Primitive::Text {
+                    content: label.clone(),
+                    bounds: Rectangle {
+                        x: p.x + MARKER_SIZE + offset,
+                        y: p.y - MARKER_TEXT_SIZE / 2.0 + offset,
+                        width: frame.width,
+                        height: MARKER_TEXT_SIZE,
+                    },
+                    color: *color,
+                    size: MARKER_TEXT_SIZE,
+                    font: Font::Default,
+                    horizontal_alignment: HorizontalAlignment::Left,
+                    vertical_alignment: VerticalAlignment::Top,
+                });
+            }
+        }
+
+        if let Some((start, end)) = self.state.select {
+            primitives.push(Primitive::Quad {
+                bounds: corners(start, end),
+                background: Background::Color(SELECTION_COLOR),
+                border_radius: 0.0,
+                border_width: 1.0,
+                border_color: Color::WHITE,
+            });
+        }
+
+        let interaction = if self.state.select.is_some() {
+            mouse::Interaction::Crosshair
+        } else if self.state.drag.is_some() {
+            mouse::Interaction::Grabbing
+        } else if frame.contains(cursor) {
+            mouse::Interaction::Grab
+        } else {
+            mouse::Interaction::default()
+        };
+
+        (
+            Primitive::Clip {
+                bounds: frame,
+                offset: Vector::new(0, 0),
+                content: Box::new(Primitive::Group { primitives }),
+            },
+            interaction,
+        )
+    }
+
+    fn hash_layout(&self, state: &mut Hasher) {
+        struct Marker;
+
+        TypeId::of::<Marker>().hash(state);
+    }
+
+    fn on_event(
+        &mut self,
+        event: Event,
+        layout: Layout<'_>,
+        cursor: Point,
+        messages: &mut Vec<Message>,
+        _renderer: &Renderer<B>,
+        _clipboard: Option<&dyn Clipboard>,
+    ) -> event::Status {
+        let frame = frame(layout.bounds(), self.size);
+        let Event::Mouse(evt) = event else {
+            return event::Status::Ignored;
+        };
+
+        match evt {
+            mouse::Event::ButtonPressed(mouse::Button::Left) if frame.contains(cursor) => {
+                self.state.drag = Some(cursor);
+            },
+            mouse::Event::ButtonReleased(mouse::Button::Left) if self.state.drag.is_some() => {
+                self.state.drag = None;
+            },
+            mouse::Event::ButtonPressed(mouse::Button::Middle) if frame.contains(cursor) => {
+                match self.on_pin {
+                    Some(ref on_pin) => {
+                        messages.push(on_pin(self.view.at(fraction(frame, cursor))));
+                    },
+                    None => return event::Status::Ignored,
+                }
+            },
+            mouse::Event::ButtonPressed(mouse::Button::Right)
+                if self.on_select.is_some() && frame.contains(cursor) =>
+            {
+                self.state.select = Some((cursor, cursor));
+            },
+            mouse::Event::ButtonReleased(mouse::Button::Right) if self.state.select.is_some() => {
+                let (start, end) = self.state.select.take().unwrap();
+                let region = corners(start, end);
+
+                if region.width < MIN_SELECTION || region.height < MIN_SELECTION {
+                    return event::Status::Captured;
+                }
+
+                let lo = self.view.at(fraction(frame, Point::new(region.x, region.y)));
+                let hi = self.view.at(fraction(
+                    frame,
+                    Point::new(region.x + region.width, region.y + region.height),
+                ));
+                let bounds = layout.bounds();
+                let scale = (bounds.width / region.width).min(bounds.height / region.height);
+
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let size = Vector2::new(
+                    (region.width * scale).round() as u32,
+                    (region.height * scale).round() as u32,
+                );
+
+                if let Some(ref on_select) = self.on_select {
+                    messages.push(on_select(
+                        View {
+                            x: (lo.x, hi.x),
+                            y: (lo.y, hi.y),
+                        },
+                        size,
+                    ));
+                }
+            },
+            mouse::Event::CursorMoved { .. } => {
+                let hovering = frame.contains(cursor);
+
+                if let Some(ref on_hover) = self.on_hover {
+                    if hovering {
+                        messages.push(on_hover(Some(self.view.at(fraction(frame, cursor)))));
+                    } else if self.state.hovering {
+                        messages.push(on_hover(None));
+                    }
+                }
+
+                self.state.hovering = hovering;
+
+                if let Some((start, _)) = self.state.select {
+                    let end = Point::new(
+                        cursor.x.max(frame.x).min(frame.x + frame.width),
+                        cursor.y.max(frame.y).min(frame.y + frame.height),
+                    );
+
+                    self.state.select = Some((start, end));
+
+                    return event::Status::Captured;
+                }
+
+                let Some(last) = self.state.drag else {
+                    return event::Status::Ignored;
+                };
+
+                self.state.drag = Some(cursor);
+
+                let dx = f64::from((cursor.x - last.x) / frame.width);
+                let dy = f64::from((cursor.y - last.y) / frame.height);
+
+                messages.push((self.on_change)(self.view.pan(-dx, -dy)));
+            },
+            mouse::Event::WheelScrolled { delta } if frame.contains(cursor) => {
+                let lines = match delta {
+                    mouse::ScrollDelta::Lines { y, .. } => y,
+                    mouse::ScrollDelta::Pixels { y, .. } => y / PIXELS_PER_LINE,
+                };
+                let at = fraction(frame, cursor);
+
+                messages.push((self.on_change)(
+                    self.view.zoom(at, ZOOM_PER_LINE.powf(-f64::from(lines))),
+                ));
+            },
+            _ => return event::Status::Ignored,
+        }
+
+        event::Status::Captured
+    }
+}
+
+impl<'a, Message: 'a, B: Backend + 'a> From<Viewer<'a, Message>>
+    for Element<'a, Message, Renderer<B>>
+{
+    fn from(viewer: Viewer<'a, Message>) -> Self { Element::new(viewer) }
+}
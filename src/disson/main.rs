@@ -4,6 +4,7 @@
 
 use cli::{GlobalOpts, Opts, Subcommand};
 use log::{error, LevelFilter};
+use message::exit;
 
 mod cache;
 mod cancel;
@@ -11,7 +12,9 @@ mod cli;
 mod config;
 mod disson;
 mod error;
+#[cfg(feature = "gui")]
 mod gui;
+mod message;
 mod tile_renderer;
 
 const VERBOSITY: [LevelFilter; 3] = [LevelFilter::Info, LevelFilter::Debug, LevelFilter::Trace];
@@ -23,15 +26,22 @@ const DEFAULT_V: usize = 0;
 fn main() {
     let Opts { opts: global, cmd } = cli::parse();
     let GlobalOpts {
-        cache_mode,
+        cache: cache_opts,
         quiet,
         no_quiet,
         verbose,
+        message_format,
     } = global;
 
+    message::init(message_format);
+
     {
         let mut b = env_logger::builder();
 
+        if message::is_json() {
+            b.format(|buf, rec| message::log(buf, rec));
+        }
+
         if !(no_quiet || verbose != 0 || atty::is(atty::Stream::Stderr)) || quiet {
             b.filter_level(LevelFilter::Warn);
         } else {
@@ -42,18 +52,37 @@ fn main() {
     }
 
     let result = match cmd {
-        Subcommand::Clean => cache::clean(cache_mode),
-        Subcommand::Gui => gui::run(cache_mode),
-        Subcommand::Generate(g) => disson::generate(cache_mode, g),
-        Subcommand::PrintDefaults => config::print_defaults(),
-        Subcommand::Watch(g) => disson::watch(cache_mode, g),
+        Subcommand::Cache(c) => cache::run(cache_opts, c),
+        Subcommand::Clean(c) => cache::clean(cache_opts, &c),
+        Subcommand::Explain(e) => disson::explain(&e),
+        #[cfg(feature = "gui")]
+        Subcommand::Gui(g) => gui::run(cache_opts, g),
+        #[cfg(not(feature = "gui"))]
+        Subcommand::Gui(_) => Err(error::prelude::anyhow!(
+            "disson was built without the GUI; rebuild it with the gui feature enabled"
+        )),
+        Subcommand::Generate(g) => disson::generate(cache_opts, g),
+        Subcommand::PrintDefaults(p) => config::print_defaults(&p),
+        Subcommand::RenderAudio(r) => disson::render_audio(&r),
+        Subcommand::RenderSweep(r) => disson::render_sweep(cache_opts, r),
+        Subcommand::Stats(s) => disson::stats(cache_opts, s),
+        Subcommand::UpgradeConfig(u) => config::upgrade_config(u),
+        Subcommand::Watch(g) => disson::watch(cache_opts, g),
     };
 
-    match result {
-        Ok(()) => (),
-        Err(e) => {
-            error!("Program exited with error: {:?}", e);
-            std::process::exit(-1);
+    let code = match result {
+        Ok(()) if cancel::interrupted() => exit::INTERRUPTED,
+        Ok(()) => exit::SUCCESS,
+        Err(ref e) => {
+            if !message::is_json() {
+                error!("Program exited with error: {e:?}");
+            }
+
+            exit::FAILURE
         },
-    }
+    };
+
+    message::finish(code, result.err().as_ref());
+
+    std::process::exit(code);
 }
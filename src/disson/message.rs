@@ -0,0 +1,113 @@
+//! Machine-readable output for editor plugins and build systems driving
+//! disson.  With `--message-format json`, every message is printed to stderr
+//! as one JSON object per line, tagged by a `type` of `log`, `progress`,
+//! `error`, or `result`.
+
+use std::{
+    io::{self, prelude::*},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+
+use log::Record;
+use serde_json::{json, Value};
+
+use crate::{cli::MessageFormat, error::Error};
+
+/// The exit codes of the disson process
+pub mod exit {
+    /// The command finished successfully
+    pub const SUCCESS: i32 = 0;
+    /// The command failed
+    pub const FAILURE: i32 = 1;
+    /// The command line couldn't be parsed
+    pub const USAGE: i32 = 2;
+    /// The command was stopped with ^C before it finished
+    pub const INTERRUPTED: i32 = 130;
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Called with the done and total counts of every progress message
+type ProgressListener = Box<dyn Fn(u64, u64) + Send + Sync>;
+
+static PROGRESS_LISTENER: RwLock<Option<ProgressListener>> = RwLock::new(None);
+
+pub fn init(format: MessageFormat) {
+    JSON.store(matches!(format, MessageFormat::Json), Ordering::Relaxed);
+}
+
+/// Whether messages are being printed as JSON
+pub fn is_json() -> bool { JSON.load(Ordering::Relaxed) }
+
+fn emit(record: &Value) {
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+
+    // There's nowhere left to report a failure to write to stderr
+    writeln!(stderr, "{record}").ok();
+}
+
+/// Format a log record as a JSON message, for use as the logger format
+pub fn log(buf: &mut dyn Write, record: &Record) -> io::Result<()> {
+    writeln!(
+        buf,
+        "{}",
+        json!({
+            "type": "log",
+            "level": record.level().as_str().to_lowercase(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        })
+    )
+}
+
+/// Pass the progress of every map rendered from now on to the given function,
+/// as the number of tiles done and the total.  It may be called from any
+/// render thread.
+#[cfg(feature = "gui")]
+pub fn on_progress(f: impl Fn(u64, u64) + Send + Sync + 'static) {
+    *PROGRESS_LISTENER.write().unwrap() = Some(Box::new(f));
+}
+
+/// Report that `done` of `total` tiles of the current map are rendered
+pub fn progress(done: u64, total: u64) {
+    if let Some(ref f) = *PROGRESS_LISTENER.read().unwrap() {
+        f(done, total);
+    }
+
+    if is_json() {
+        emit(&json!({
+            "type": "progress",
+            "done": done,
+            "total": total,
+        }));
+    }
+}
+
+/// Report how the command finished, along with the exit code it will return
+pub fn finish(code: i32, error: Option<&Error>) {
+    if !is_json() {
+        return;
+    }
+
+    if let Some(e) = error {
+        emit(&json!({
+            "type": "error",
+            "message": e.to_string(),
+            "causes": e.chain().skip(1).map(ToString::to_string).collect::<Vec<_>>(),
+        }));
+    }
+
+    emit(&json!({
+        "type": "result",
+        "status": match code {
+            exit::SUCCESS => "success",
+            exit::INTERRUPTED => "interrupted",
+            _ => "failure",
+        },
+        "exit_code": code,
+    }));
+}
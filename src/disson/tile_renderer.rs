@@ -1,12 +1,21 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+};
 
 use backbuf::BackBuffer;
 use log::trace;
 use nalgebra::Vector2;
-use rayon::prelude::*;
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
 use serde::{Deserialize, Serialize};
 
-use crate::cancel::prelude::*;
+use crate::{
+    cancel::{prelude::*, CancelError},
+    error::prelude::*,
+};
 
 mod backbuf {
     use std::{mem, ptr, ptr::NonNull, slice, sync::RwLock};
@@ -26,15 +35,15 @@ mod backbuf {
     unsafe impl<T: Sync> Sync for Slice<T> {}
 
     impl<T: Default + Copy + Sync> BackBuffer<T> {
-        pub fn new(size: Vector2<u32>) -> Self {
+        /// Allocate a buffer with every element set to `fill`
+        pub fn new(size: Vector2<u32>, fill: T) -> Self {
             let size = size.cast::<usize>();
             // TODO: eventually box literals will be a thing, I think...
             Self(Disposable::new(Inner(
                 size,
                 RwLock::new(Slice(
                     NonNull::new(
-                        Box::leak(vec![Default::default(); size.x * size.y].into_boxed_slice())
-                            .as_mut_ptr(),
+                        Box::leak(vec![fill; size.x * size.y].into_boxed_slice()).as_mut_ptr(),
                     )
                     .expect("back buffer slice was null"),
                 )),
@@ -95,7 +104,9 @@ pub trait TileRenderFunction: Send + Sync {
     type Input;
     type Output: Copy + Default + Send + Sync;
 
-    fn process(&self, tile: Tile<Self::Input, Self::Output>);
+    /// Render one tile.  If this fails, the tile is left unrendered and the
+    /// renderer stops as if cancelled, returning the error if there was one.
+    fn process(&self, tile: Tile<Self::Input, Self::Output>) -> CancelResult<()>;
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -132,16 +143,104 @@ impl<'a, I, O> Tile<'a, I, O> {
     }
 }
 
-pub struct TileRenderer<F: Send + Sync, const TW: u32, const TH: u32>(F);
+/// Split an image of the given size into tiles, left to right and top to
+/// bottom
+pub fn tile_ranges(size: Vector2<u32>, tile_size: Vector2<u32>) -> Vec<TileRange> {
+    let (tw, th) = (tile_size.x, tile_size.y);
+    let tiles_x = size.x / tw + (size.x % tw).min(1);
+    let tiles_y = size.y / th + (size.y % th).min(1);
+
+    (0..tiles_y)
+        .flat_map(|r| {
+            (0..tiles_x).map(move |c| {
+                let pos = Vector2::new(c * tw, r * th);
+                let max = size - pos;
+                TileRange {
+                    pos,
+                    size: Vector2::new(tw.min(max.x), th.min(max.y)),
+                }
+            })
+        })
+        .collect()
+}
+
+/// Render thread pools by thread count, kept alive so every map rendered in
+/// one run shares the same workers
+static POOLS: LazyLock<Mutex<HashMap<usize, Arc<ThreadPool>>>> = LazyLock::new(Mutex::default);
+
+fn thread_pool(threads: usize) -> Result<Arc<ThreadPool>> {
+    let mut pools = POOLS.lock().unwrap();
+
+    if let Some(pool) = pools.get(&threads) {
+        return Ok(pool.clone());
+    }
+
+    let pool = Arc::new(
+        ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .context("failed to create render thread pool")?,
+    );
+
+    pools.insert(threads, pool.clone());
+
+    Ok(pool)
+}
+
+/// A progress notification from a running renderer
+#[derive(Debug, Clone, Copy)]
+pub enum TileEvent {
+    /// Rendering began with `total` tiles, `preloaded` of which will be copied
+    /// from the preload map instead of being rendered
+    Started { total: usize, preloaded: usize },
+    /// A tile missing from the preload map finished rendering
+    Rendered,
+}
+
+pub struct TileRenderer<F: Send + Sync> {
+    f: F,
+    tile_size: Vector2<u32>,
+    threads: usize,
+    on_event: Option<Box<dyn Fn(TileEvent) + Send + Sync>>,
+}
 
 pub const DEFAULT_TILE_WIDTH: u32 = 128;
 pub const DEFAULT_TILE_HEIGHT: u32 = 128;
-pub type DefaultTileRenderer<F> = TileRenderer<F, DEFAULT_TILE_WIDTH, DEFAULT_TILE_HEIGHT>;
 
-impl<F: TileRenderFunction, const TW: u32, const TH: u32> TileRenderer<F, TW, TH> {
-    pub fn new(f: F) -> Self { Self(f) }
+impl<F: TileRenderFunction> TileRenderer<F> {
+    /// Construct a new renderer.  If `threads` is zero, the renderer will
+    /// pick a thread count based on the number of available cores.
+    pub fn new(f: F, tile_size: Vector2<u32>, threads: usize) -> Self {
+        assert!(
+            tile_size.x > 0 && tile_size.y > 0,
+            "Tile size must be non-zero"
+        );
+
+        Self {
+            f,
+            tile_size,
+            threads,
+            on_event: None,
+        }
+    }
+
+    /// Report the progress of each run to the given function.  It may be called
+    /// from any render thread.
+    pub fn on_event(mut self, f: impl Fn(TileEvent) + Send + Sync + 'static) -> Self {
+        self.on_event = Some(Box::new(f));
+        self
+    }
+
+    fn emit(&self, evt: TileEvent) {
+        if let Some(ref f) = self.on_event {
+            f(evt);
+        }
+    }
 
-    pub fn run<
+    /// Render every tile, but if cancelled, keep whatever finished in time.
+    /// Pixels of tiles that weren't rendered are set to `fill`, and the number
+    /// of such tiles is returned alongside the buffer.
+    pub fn run_partial<
         I: AsRef<[F::Input]> + Sync,
         P: AsRef<[F::Output]> + Sync,
         C: std::borrow::Borrow<CancelToken> + Sync,
@@ -151,32 +250,42 @@ impl<F: TileRenderFunction, const TW: u32, const TH: u32> TileRenderer<F, TW, TH
         buf_in: I,
         preload: &HashMap<TileRange, P>,
         cancel: C,
-    ) -> CancelResult<Box<[F::Output]>> {
+        fill: F::Output,
+    ) -> Result<(Box<[F::Output]>, usize)> {
+        let pool = thread_pool(self.threads)?;
+
+        pool.install(|| self.run_impl(size, &buf_in, preload, &cancel, fill))
+    }
+
+    fn run_impl<
+        I: AsRef<[F::Input]> + Sync,
+        P: AsRef<[F::Output]> + Sync,
+        C: std::borrow::Borrow<CancelToken> + Sync,
+    >(
+        &self,
+        size: Vector2<u32>,
+        buf_in: &I,
+        preload: &HashMap<TileRange, P>,
+        cancel: &C,
+        fill: F::Output,
+    ) -> Result<(Box<[F::Output]>, usize)> {
         assert_eq!(
             buf_in.as_ref().len(),
             size.x as usize * size.y as usize,
             "Input buffer size mismatch"
         );
 
-        let tiles_x = size.x / TW + (size.x % TW).min(1);
-        let tiles_y = size.y / TH + (size.y % TH).min(1);
-
-        let mut tiles: Vec<_> = (0..tiles_x)
-            .into_iter()
-            .flat_map(|r| {
-                (0..tiles_y).into_iter().map(move |c| {
-                    let pos = Vector2::new(c * TW, r * TH);
-                    let max = size - pos;
-                    TileRange {
-                        pos: Vector2::new(c * TW, r * TH),
-                        size: Vector2::new(TW.min(max.x), TH.min(max.y)),
-                    }
-                })
-            })
-            .collect();
+        let mut tiles = tile_ranges(size, self.tile_size);
+
+        self.emit(TileEvent::Started {
+            total: tiles.len(),
+            preloaded: tiles.iter().filter(|t| preload.contains_key(t)).count(),
+        });
 
         let ctr = size / 2;
-        let bbuf = BackBuffer::new(size);
+        let bbuf = BackBuffer::new(size, fill);
+        let left = AtomicUsize::new(tiles.len());
+        let failed = Mutex::new(None);
 
         tiles.par_sort_by(|a, b| {
             let ca = a.pos + a.size / 2;
@@ -206,23 +315,41 @@ impl<F: TileRenderFunction, const TW: u32, const TH: u32> TileRenderer<F, TW, TH
                     let mut buf_out =
                         vec![Default::default(); range.size.x as usize * range.size.y as usize];
 
-                    self.0.process(Tile {
+                    let res = self.f.process(Tile {
                         range,
                         in_stride: size.x as usize,
                         buf_in: buf_in.as_ref(),
                         buf_out: buf_out.as_mut(),
                     });
 
+                    match res {
+                        Ok(()) => (),
+                        Err(CancelError::Cancelled) => return None,
+                        Err(CancelError::Failed(e)) => {
+                            failed.lock().unwrap().get_or_insert(e);
+                            return None;
+                        },
+                    }
+
                     unsafe {
                         bbuf.blit(&range, buf_out);
                     }
+
+                    self.emit(TileEvent::Rendered);
                 }
 
+                left.fetch_sub(1, Ordering::Relaxed);
+
                 cancel.borrow().try_weak().ok()
             })
             .while_some()
             .for_each(|()| ());
 
-        cancel.borrow().try_strong().map(|()| bbuf.into_inner())
+        let data = bbuf.into_inner();
+
+        match failed.into_inner().unwrap() {
+            Some(e) => Err(e),
+            None => Ok((data, left.into_inner())),
+        }
     }
 }
@@ -10,6 +10,12 @@ use std::{
 
 use rand::{prelude::*, rngs::StdRng};
 
+// Shared with disson so the render kernels can be timed against the scalar
+// loops below
+#[allow(dead_code)]
+#[path = "../disson/disson/simd.rs"]
+mod simd;
+
 //// Test harness functions and plumbing code
 
 macro_rules! time {
@@ -121,6 +127,41 @@ fn run_test_slice(
     times
 }
 
+/// Time summing `f` over every ordered pair of `len` random partials, packed
+/// the way disson packs them for its render kernels
+fn run_test_pairs(
+    len: usize,
+    tries: usize,
+    seed: <StdRng as SeedableRng>::Seed,
+    f: impl Fn(&[f64], &[f64]) -> f64,
+) -> (Vec<Duration>, Vec<f64>) {
+    let mut times = Vec::with_capacity(tries);
+    let mut sums = Vec::with_capacity(tries);
+    let mut cerr = stderr();
+    let mut rng = StdRng::from_seed(seed);
+    let padded = (len + 7) / 8 * 8;
+
+    for run in 0..tries {
+        write!(cerr, "\r\x1b[2K  Run {}...", run + 1).unwrap();
+        cerr.flush().unwrap();
+
+        let mut pitch = vec![0_f64; padded];
+        let mut gain = vec![0_f64; padded];
+
+        rng.fill(&mut pitch[..len]);
+        rng.fill(&mut gain[..len]);
+
+        let sum;
+
+        times.push(time! { sum = f(black_box(&pitch), black_box(&gain)); });
+        sums.push(sum);
+    }
+
+    writeln!(cerr).unwrap();
+
+    (times, sums)
+}
+
 fn time_fmt(d: impl Borrow<Duration>) -> String {
     let d = d.borrow();
     let nanos = d.subsec_nanos();
@@ -178,6 +219,18 @@ fn expon_tup((a, b): (f64, f64)) -> f64 { expon(a, b) }
 
 fn linear_tup((a, b): (f64, f64)) -> f64 { linear(a, b) }
 
+fn sum_pairs(f: impl Fn(f64, f64) -> f64, pitch: &[f64], gain: &[f64]) -> f64 {
+    let mut sum = 0.0;
+
+    for (&a, &ga) in pitch.iter().zip(gain) {
+        for (&b, &gb) in pitch.iter().zip(gain) {
+            sum += f(a, b) * ga * gb;
+        }
+    }
+
+    sum
+}
+
 fn map_slice<
     'a,
     F: FnOnce(std::iter::Copied<std::slice::Iter<'a, (f64, f64)>>) -> J,
@@ -271,6 +324,17 @@ enum FunctionEnum {
 }
 
 impl FunctionEnum {
+    fn kernel(self) -> simd::Kernel<f64> {
+        match self {
+            Self::Expon => simd::Kernel::ExpDiss { scale: 1.0 },
+            Self::Linear => simd::Kernel::TrapDiss {
+                scale: 1.0,
+                rise: 3.0,
+                end: 2.0,
+            },
+        }
+    }
+
     fn into_dyn(self) -> Box<dyn DynFunction> {
         match self {
             Self::Expon => Box::new(Expon),
@@ -433,4 +497,46 @@ fn main() {
             }
         }
     }
+
+    for len in IntoIter::new([8, 64, 512]) {
+        eprintln!("//// PARTIALS: {}", len);
+
+        for ty in IntoIter::new([FunctionEnum::Linear, FunctionEnum::Expon]) {
+            let ty = black_box(ty);
+            let kernel = ty.kernel();
+
+            eprintln!("//// TYPE: {:?}", ty);
+
+            {
+                eprintln!("Running scalar pairs...");
+                let (times_scalar, _) = match ty {
+                    FunctionEnum::Expon => {
+                        run_test_pairs(len, TRIES, seed, |p, g| sum_pairs(expon, p, g))
+                    },
+                    FunctionEnum::Linear => {
+                        run_test_pairs(len, TRIES, seed, |p, g| sum_pairs(linear, p, g))
+                    },
+                };
+                print_stats("Scalar (pairs)", len, ty, times_scalar);
+            }
+
+            eprintln!("Running portable kernel...");
+            let (times_portable, sums_portable) = run_test_pairs(len, TRIES, seed, |p, g| {
+                simd::sum_f64(simd::Backend::Portable, kernel, p, g)
+            });
+            print_stats("Kernel (portable)", len, ty, times_portable);
+
+            if simd::Backend::Avx2.is_supported() {
+                eprintln!("Running AVX2 kernel...");
+                let (times_avx2, sums_avx2) = run_test_pairs(len, TRIES, seed, |p, g| {
+                    simd::sum_f64(simd::Backend::Avx2, kernel, p, g)
+                });
+                print_stats("Kernel (AVX2)", len, ty, times_avx2);
+
+                for (i, (p, a)) in sums_portable.iter().zip(&sums_avx2).enumerate() {
+                    assert_eq!(p.to_bits(), a.to_bits(), "backends differ in run {}", i);
+                }
+            }
+        }
+    }
 }